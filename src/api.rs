@@ -1,9 +1,16 @@
+use base64::Engine;
+use chrono::{DateTime, Utc};
 use futures::StreamExt;
-use reqwest::Client;
+use reqwest::header::HeaderMap;
+use reqwest::{Client, ClientBuilder};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::sync::RwLock;
+use std::time::Duration;
 use tokio::sync::mpsc;
 
+use crate::aws::{self, AwsCredentials};
+use crate::config::{Config, CustomProvider, NetworkConfig};
 use crate::event::Event;
 use crate::tools;
 
@@ -21,19 +28,259 @@ pub enum MessageContent {
     Blocks(Vec<Value>),
 }
 
-#[derive(Clone)]
+/// Token counts a provider reports for one response.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TokenUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+impl TokenUsage {
+    /// Pick up counts from a response body or stream event in any provider's
+    /// shape. Counts are cumulative within a response, so later values
+    /// replace earlier ones. Returns whether the event carried any.
+    pub fn update(&mut self, event: &Value) -> bool {
+        // Anthropic reports input on message_start and output on message_delta
+        let usage = if event["message"]["usage"].is_object() { &event["message"]["usage"] } else { &event["usage"] };
+        let metrics = &event["amazon-bedrock-invocationMetrics"];
+        let pairs = [
+            (&usage["input_tokens"], &usage["output_tokens"]),
+            (&usage["prompt_tokens"], &usage["completion_tokens"]),
+            (&event["usageMetadata"]["promptTokenCount"], &event["usageMetadata"]["candidatesTokenCount"]),
+            (&event["prompt_eval_count"], &event["eval_count"]),
+            (&metrics["inputTokenCount"], &metrics["outputTokenCount"]),
+        ];
+        let mut found = false;
+        for (input, output) in pairs {
+            if let Some(n) = input.as_u64() {
+                self.input_tokens = n;
+                found = true;
+            }
+            if let Some(n) = output.as_u64() {
+                self.output_tokens = n;
+                found = true;
+            }
+        }
+        found
+    }
+
+    pub fn add(&mut self, other: TokenUsage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.input_tokens == 0 && self.output_tokens == 0
+    }
+}
+
+/// One rate-limited quantity (requests or tokens) from response headers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub limit: u64,
+    pub remaining: u64,
+    pub reset_at: Option<DateTime<Utc>>,
+}
+
+impl RateLimit {
+    /// Less than a tenth of the window left.
+    pub fn is_low(&self) -> bool {
+        self.remaining * 10 < self.limit
+    }
+}
+
+/// Rate limits a provider reported with its last response.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RateLimits {
+    pub requests: Option<RateLimit>,
+    pub tokens: Option<RateLimit>,
+}
+
+impl RateLimits {
+    /// Read Anthropic's `anthropic-ratelimit-*` headers (resets are
+    /// timestamps) or the OpenAI-style `x-ratelimit-*` ones (resets are
+    /// durations like `6m0s`). `None` when there are neither.
+    pub fn from_headers(headers: &HeaderMap, now: DateTime<Utc>) -> Option<Self> {
+        let get = |name: String| headers.get(name).and_then(|v| v.to_str().ok());
+        let read = |kind: &str| {
+            if let Some(limit) = get(format!("anthropic-ratelimit-{kind}-limit")) {
+                Some(RateLimit {
+                    limit: limit.parse().ok()?,
+                    remaining: get(format!("anthropic-ratelimit-{kind}-remaining"))?.parse().ok()?,
+                    reset_at: get(format!("anthropic-ratelimit-{kind}-reset"))
+                        .and_then(|r| DateTime::parse_from_rfc3339(r).ok())
+                        .map(|r| r.with_timezone(&Utc)),
+                })
+            } else {
+                Some(RateLimit {
+                    limit: get(format!("x-ratelimit-limit-{kind}"))?.parse().ok()?,
+                    remaining: get(format!("x-ratelimit-remaining-{kind}"))?.parse().ok()?,
+                    reset_at: get(format!("x-ratelimit-reset-{kind}"))
+                        .and_then(parse_reset_duration)
+                        .and_then(|d| chrono::Duration::from_std(d).ok())
+                        .map(|d| now + d),
+                })
+            }
+        };
+        let limits = Self { requests: read("requests"), tokens: read("tokens") };
+        (limits != Self::default()).then_some(limits)
+    }
+
+    /// The first limit that is nearly used up, labelled.
+    pub fn low(&self) -> Option<(&'static str, RateLimit)> {
+        [("requests", self.requests), ("tokens", self.tokens)]
+            .into_iter()
+            .find_map(|(name, limit)| limit.filter(RateLimit::is_low).map(|l| (name, l)))
+    }
+}
+
+/// A Go-style duration as OpenAI sends for rate limit resets: `1s`, `6m0s`,
+/// `1h2m3.5s`, `20ms`.
+fn parse_reset_duration(text: &str) -> Option<Duration> {
+    let mut total = 0.0;
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let split = rest.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+        let value: f64 = rest[..split].parse().ok()?;
+        rest = &rest[split..];
+        let unit_len = rest.find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len());
+        total += value * match &rest[..unit_len] {
+            "ms" => 0.001,
+            "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            _ => return None,
+        };
+        rest = &rest[unit_len..];
+    }
+    Some(Duration::from_secs_f64(total))
+}
+
+/// Tell the app about the rate limits on a provider response, if it sent any.
+fn send_rate_limits(response: &reqwest::Response, tx: &mpsc::UnboundedSender<Event>) {
+    if let Some(limits) = RateLimits::from_headers(response.headers(), Utc::now()) {
+        let _ = tx.send(Event::RateLimits(limits));
+    }
+}
+
+/// Build a header map from configured name/value pairs, naming the bad one
+/// instead of failing the request with an opaque builder error.
+fn header_map(headers: &[(String, String)]) -> anyhow::Result<HeaderMap> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        let name = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| anyhow::anyhow!("Invalid header name '{name}'"))?;
+        let value = reqwest::header::HeaderValue::from_str(value)
+            .map_err(|_| anyhow::anyhow!("Invalid value for header '{name}'"))?;
+        map.append(name, value);
+    }
+    Ok(map)
+}
+
+/// How long an idle pooled connection is kept for reuse.
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+/// HTTP client for all provider calls. The app builds exactly one and shares
+/// it behind an `Arc`, so every request and tool continuation reuses pooled
+/// connections instead of paying for a fresh TLS handshake.
 pub struct ApiClient {
     client: Client,
+    /// Longest gap between stream chunks before giving up
+    stall_timeout: Duration,
+}
+
+/// `[network]` settings for the clients built outside [`ApiClient`] (GitHub
+/// tools, ticket tracker, embeddings). Global because those are created deep
+/// inside tools that never see the config.
+static NETWORK: RwLock<Option<NetworkConfig>> = RwLock::new(None);
+
+/// Apply the connect timeout, proxy and TLS settings from `[network]`.
+fn client_builder(network: &NetworkConfig) -> anyhow::Result<ClientBuilder> {
+    let mut builder = Client::builder().connect_timeout(Duration::from_secs(network.connect_timeout));
+
+    if let Some(ref url) = network.proxy {
+        let proxy = reqwest::Proxy::all(url)
+            .map_err(|e| anyhow::anyhow!("Invalid network.proxy {url}: {e}"))?;
+        builder = builder.proxy(proxy);
+    }
+    if let Some(ref path) = network.ca_bundle {
+        let pem = std::fs::read(path)
+            .map_err(|e| anyhow::anyhow!("Cannot read network.ca_bundle {}: {e}", path.display()))?;
+        for cert in reqwest::Certificate::from_pem_bundle(&pem)? {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    if network.danger_accept_invalid_certs {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    Ok(builder)
+}
+
+/// Remember `network` for the clients made by [`http_client`].
+pub fn use_network(network: &NetworkConfig) {
+    if let Ok(mut global) = NETWORK.write() {
+        *global = Some(network.clone());
+    }
+}
+
+/// A client for one-off requests outside the chat API, with the `[network]`
+/// proxy and TLS settings and an overall `timeout`.
+pub fn http_client(timeout: Duration) -> anyhow::Result<Client> {
+    let network = NETWORK.read().ok().and_then(|n| n.clone()).unwrap_or_default();
+    Ok(client_builder(&network)?.timeout(timeout).build()?)
+}
+
+/// Like [`http_client`], but always verifying certificates, even when
+/// `danger_accept_invalid_certs` is set: for downloads that get executed.
+pub fn verified_http_client(timeout: Duration) -> anyhow::Result<Client> {
+    let mut network = NETWORK.read().ok().and_then(|n| n.clone()).unwrap_or_default();
+    network.danger_accept_invalid_certs = false;
+    Ok(client_builder(&network)?.timeout(timeout).build()?)
+}
+
+/// A client with the `[network]` proxy and TLS settings but no overall
+/// timeout, for event streams that stay open indefinitely.
+pub fn streaming_http_client() -> anyhow::Result<Client> {
+    let network = NETWORK.read().ok().and_then(|n| n.clone()).unwrap_or_default();
+    Ok(client_builder(&network)?.build()?)
 }
 
 impl ApiClient {
-    pub fn new() -> Self {
-        Self {
-            client: Client::new(),
+    /// Build the shared client, and remember `network` for [`http_client`].
+    pub fn new(network: &NetworkConfig) -> anyhow::Result<Self> {
+        let builder = client_builder(network)?
+            .read_timeout(Duration::from_secs(network.read_timeout))
+            // Keep connections warm across the pauses between messages
+            .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+            .tcp_keepalive(TCP_KEEPALIVE);
+        let client = builder.build()?;
+        use_network(network);
+
+        Ok(Self {
+            client,
+            stall_timeout: Duration::from_secs(network.stall_timeout),
+        })
+    }
+
+    /// Next chunk of a response stream, or an error once nothing has arrived
+    /// for `stall_timeout` so a dead connection can't hang the UI forever.
+    async fn next_chunk<S, T>(&self, stream: &mut S) -> anyhow::Result<Option<T>>
+    where
+        S: futures::Stream<Item = reqwest::Result<T>> + Unpin,
+    {
+        match tokio::time::timeout(self.stall_timeout, stream.next()).await {
+            Ok(Some(chunk)) => Ok(Some(chunk?)),
+            Ok(None) => Ok(None),
+            Err(_) => anyhow::bail!(
+                "Stream stalled: no data for {}s (network.stall_timeout)",
+                self.stall_timeout.as_secs()
+            ),
         }
     }
 
     /// Stream an Anthropic API call (text-only, no tools).
+    #[allow(clippy::too_many_arguments)]
     pub async fn stream_anthropic(
         &self,
         api_key: &str,
@@ -41,26 +288,31 @@ impl ApiClient {
         messages: &[Message],
         system_prompt: Option<&str>,
         max_tokens: u32,
-        temperature: f32,
+        sampling: &Sampling,
         tx: mpsc::UnboundedSender<Event>,
+        thinking_budget: Option<u32>,
+        extra_headers: &[(String, String)],
     ) -> anyhow::Result<()> {
         let mut body = json!({
             "model": model,
             "max_tokens": max_tokens,
-            "temperature": temperature,
+            "temperature": sampling.temperature,
             "stream": true,
             "messages": messages,
         });
+        sampling.apply(&mut body, "top_p", Some("top_k"), "stop_sequences");
 
         if let Some(sys) = system_prompt {
             body["system"] = json!(sys);
         }
+        apply_thinking(&mut body, thinking_budget);
 
         let response = self.client
             .post("https://api.anthropic.com/v1/messages")
             .header("x-api-key", api_key)
             .header("anthropic-version", "2023-06-01")
             .header("content-type", "application/json")
+            .headers(header_map(extra_headers)?)
             .json(&body)
             .send()
             .await?;
@@ -71,30 +323,32 @@ impl ApiClient {
             let _ = tx.send(Event::ApiError(format!("API error {status}: {text}")));
             return Ok(());
         }
+        send_rate_limits(&response, &tx);
 
         let mut stream = response.bytes_stream();
         let mut buffer = String::new();
+        let mut usage = TokenUsage::default();
 
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk?;
+        while let Some(chunk) = self.next_chunk(&mut stream).await? {
             buffer.push_str(&String::from_utf8_lossy(&chunk));
 
             while let Some(line_end) = buffer.find('\n') {
                 let line = buffer[..line_end].trim().to_string();
                 buffer = buffer[line_end + 1..].to_string();
 
-                if line.starts_with("data: ") {
-                    let data = &line[6..];
+                if let Some(data) = line.strip_prefix("data: ") {
                     if data == "[DONE]" {
                         let _ = tx.send(Event::ApiDone);
                         return Ok(());
                     }
 
                     if let Ok(event) = serde_json::from_str::<Value>(data) {
-                        if event["type"] == "content_block_delta" {
-                            if let Some(text) = event["delta"]["text"].as_str() {
-                                let _ = tx.send(Event::ApiChunk(text.to_string()));
-                            }
+                        if usage.update(&event) {
+                            let _ = tx.send(Event::Usage(usage));
+                        }
+                        send_anthropic_delta(&event, &tx);
+                        if let Some(reason) = event["delta"]["stop_reason"].as_str() {
+                            let _ = tx.send(Event::StopReason(reason.to_string()));
                         }
                         if event["type"] == "message_stop" {
                             let _ = tx.send(Event::ApiDone);
@@ -112,6 +366,7 @@ impl ApiClient {
     /// Non-streaming Anthropic call with tool definitions.
     /// Returns the full response body if it contains tool_use blocks,
     /// otherwise streams the text content via events.
+    #[allow(clippy::too_many_arguments)]
     pub async fn call_anthropic_with_tools(
         &self,
         api_key: &str,
@@ -119,28 +374,34 @@ impl ApiClient {
         messages: &[Message],
         system_prompt: Option<&str>,
         max_tokens: u32,
-        temperature: f32,
+        sampling: &Sampling,
         tx: mpsc::UnboundedSender<Event>,
+        thinking_budget: Option<u32>,
+        extra_headers: &[(String, String)],
+        tool_set: tools::ToolSet,
     ) -> anyhow::Result<()> {
-        let tool_defs = tools::format_tool_definitions();
+        let tool_defs = tools::format_tool_definitions(tool_set);
 
         let mut body = json!({
             "model": model,
             "max_tokens": max_tokens,
-            "temperature": temperature,
+            "temperature": sampling.temperature,
             "messages": messages,
             "tools": tool_defs,
         });
+        sampling.apply(&mut body, "top_p", Some("top_k"), "stop_sequences");
 
         if let Some(sys) = system_prompt {
             body["system"] = json!(sys);
         }
+        apply_thinking(&mut body, thinking_budget);
 
         let response = self.client
             .post("https://api.anthropic.com/v1/messages")
             .header("x-api-key", api_key)
             .header("anthropic-version", "2023-06-01")
             .header("content-type", "application/json")
+            .headers(header_map(extra_headers)?)
             .json(&body)
             .send()
             .await?;
@@ -151,47 +412,118 @@ impl ApiClient {
             let _ = tx.send(Event::ApiError(format!("API error {status}: {text}")));
             return Ok(());
         }
+        send_rate_limits(&response, &tx);
+
+        send_anthropic_response(response.text().await?, &tx)
+    }
+
+    /// Call Claude through AWS Bedrock. Streams text with
+    /// InvokeModelWithResponseStream; with tools the non-streaming InvokeModel
+    /// is used so the response can be handed to the tool loop, as for
+    /// [`Self::call_anthropic_with_tools`].
+    #[allow(clippy::too_many_arguments)]
+    pub async fn call_bedrock(
+        &self,
+        creds: &AwsCredentials,
+        region: &str,
+        model: &str,
+        messages: &[Message],
+        system_prompt: Option<&str>,
+        max_tokens: u32,
+        sampling: &Sampling,
+        tx: mpsc::UnboundedSender<Event>,
+        tools: Option<tools::ToolSet>,
+        thinking_budget: Option<u32>,
+        extra_headers: &[(String, String)],
+    ) -> anyhow::Result<()> {
+        let mut body = json!({
+            "anthropic_version": BEDROCK_ANTHROPIC_VERSION,
+            "max_tokens": max_tokens,
+            "temperature": sampling.temperature,
+            "messages": messages,
+        });
+        sampling.apply(&mut body, "top_p", Some("top_k"), "stop_sequences");
+        if let Some(sys) = system_prompt {
+            body["system"] = json!(sys);
+        }
+        if let Some(set) = tools {
+            body["tools"] = json!(tools::format_tool_definitions(set));
+        }
+        apply_thinking(&mut body, thinking_budget);
+        let body = serde_json::to_vec(&body)?;
+
+        let host = format!("bedrock-runtime.{region}.amazonaws.com");
+        let action = if tools.is_some() { "invoke" } else { "invoke-with-response-stream" };
+        let path = format!("/model/{}/{action}", aws::uri_encode(model));
+        let mut headers = vec![("content-type", "application/json")];
+        headers.extend(extra_headers.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        let signed = aws::sign(
+            "POST", &host, &path, &headers, &body, creds, region, "bedrock", chrono::Utc::now(),
+        );
+
+        let mut request = self.client
+            .post(format!("https://{host}{path}"))
+            .header("content-type", "application/json")
+            .headers(header_map(extra_headers)?)
+            .body(body);
+        for (name, value) in signed {
+            request = request.header(name, value);
+        }
+        let response = request.send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            let _ = tx.send(Event::ApiError(format!("Bedrock error {status}: {text}")));
+            return Ok(());
+        }
+        if tools.is_some() {
+            return send_anthropic_response(response.text().await?, &tx);
+        }
 
-        let body_text = response.text().await?;
-        let response_json: Value = serde_json::from_str(&body_text)?;
+        let mut stream = response.bytes_stream();
+        let mut buffer = Vec::new();
+        let mut usage = TokenUsage::default();
 
-        // Check if response contains tool_use blocks
-        let has_tool_use = response_json["content"]
-            .as_array()
-            .map(|arr| arr.iter().any(|b| b["type"] == "tool_use"))
-            .unwrap_or(false);
+        while let Some(chunk) = self.next_chunk(&mut stream).await? {
+            buffer.extend_from_slice(&chunk);
 
-        if has_tool_use {
-            // Extract any text content first and send it
-            if let Some(content) = response_json["content"].as_array() {
-                for block in content {
-                    if block["type"] == "text" {
-                        if let Some(text) = block["text"].as_str() {
-                            let _ = tx.send(Event::ApiChunk(text.to_string()));
-                        }
-                    }
+            while let Some(message) = aws::decode_message(&mut buffer)? {
+                let payload: Value = serde_json::from_slice(&message.payload).unwrap_or_default();
+                if message.header(":message-type") == Some("exception") {
+                    let kind = message.header(":exception-type").unwrap_or("exception");
+                    let text = payload["message"].as_str().unwrap_or_default();
+                    let _ = tx.send(Event::ApiError(format!("Bedrock {kind}: {text}")));
+                    return Ok(());
                 }
-            }
-            // Send the full response for tool processing
-            let _ = tx.send(Event::ToolUseRequest(body_text));
-        } else {
-            // No tools - just send the text content
-            if let Some(content) = response_json["content"].as_array() {
-                for block in content {
-                    if block["type"] == "text" {
-                        if let Some(text) = block["text"].as_str() {
-                            let _ = tx.send(Event::ApiChunk(text.to_string()));
-                        }
-                    }
+                // Each chunk wraps one Anthropic streaming event, base64 encoded
+                let Some(event) = payload["bytes"]
+                    .as_str()
+                    .and_then(|b| base64::engine::general_purpose::STANDARD.decode(b).ok())
+                    .and_then(|b| serde_json::from_slice::<Value>(&b).ok())
+                else {
+                    continue;
+                };
+                if usage.update(&event) {
+                    let _ = tx.send(Event::Usage(usage));
+                }
+                send_anthropic_delta(&event, &tx);
+                if let Some(reason) = event["delta"]["stop_reason"].as_str() {
+                    let _ = tx.send(Event::StopReason(reason.to_string()));
+                }
+                if event["type"] == "message_stop" {
+                    let _ = tx.send(Event::ApiDone);
+                    return Ok(());
                 }
             }
-            let _ = tx.send(Event::ApiDone);
         }
 
+        let _ = tx.send(Event::ApiDone);
         Ok(())
     }
 
     /// Stream an OpenAI-compatible API call (works for OpenAI, OpenRouter, xAI, etc.).
+    #[allow(clippy::too_many_arguments)]
     pub async fn stream_openai_compatible(
         &self,
         api_key: &str,
@@ -199,37 +531,47 @@ impl ApiClient {
         messages: &[Message],
         system_prompt: Option<&str>,
         max_tokens: u32,
-        temperature: f32,
+        sampling: &Sampling,
         tx: mpsc::UnboundedSender<Event>,
         base_url: &str,
-        extra_headers: &[(&str, &str)],
+        extra_headers: &[(String, String)],
+        response_format: Option<&Value>,
+        tools: Option<tools::ToolSet>,
     ) -> anyhow::Result<()> {
         let mut msgs = Vec::new();
         if let Some(sys) = system_prompt {
             msgs.push(json!({"role": "system", "content": sys}));
         }
         for msg in messages {
-            msgs.push(json!({"role": msg.role, "content": msg.content}));
+            msgs.extend(openai_messages(msg));
         }
 
-        let body = json!({
+        let mut body = json!({
             "model": model,
             "max_tokens": max_tokens,
-            "temperature": temperature,
+            "temperature": sampling.temperature,
             "stream": true,
+            "stream_options": {"include_usage": true},
             "messages": msgs,
         });
+        // OpenAI itself rejects top_k; compatible servers mostly accept it
+        let top_k = (!base_url.starts_with("https://api.openai.com/")).then_some("top_k");
+        sampling.apply(&mut body, "top_p", top_k, "stop");
+        if let Some(format) = response_format {
+            body["response_format"] = format.clone();
+        }
+        if let Some(set) = tools {
+            body["tools"] = tools::format_openai_tool_definitions(set);
+        }
 
-        let mut req = self.client
+        let response = self.client
             .post(base_url)
             .header("Authorization", format!("Bearer {api_key}"))
-            .header("content-type", "application/json");
-
-        for (key, value) in extra_headers {
-            req = req.header(*key, *value);
-        }
-
-        let response = req.json(&body).send().await?;
+            .header("content-type", "application/json")
+            .headers(header_map(extra_headers)?)
+            .json(&body)
+            .send()
+            .await?;
 
         if !response.status().is_success() {
             let status = response.status();
@@ -237,36 +579,1076 @@ impl ApiClient {
             let _ = tx.send(Event::ApiError(format!("API error {status}: {text}")));
             return Ok(());
         }
+        send_rate_limits(&response, &tx);
 
         let mut stream = response.bytes_stream();
         let mut buffer = String::new();
+        let mut tool_calls = OpenAiToolCalls::default();
+        let mut usage = TokenUsage::default();
 
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk?;
+        'stream: while let Some(chunk) = self.next_chunk(&mut stream).await? {
             buffer.push_str(&String::from_utf8_lossy(&chunk));
 
             while let Some(line_end) = buffer.find('\n') {
                 let line = buffer[..line_end].trim().to_string();
                 buffer = buffer[line_end + 1..].to_string();
 
-                if line.starts_with("data: ") {
-                    let data = &line[6..];
+                if let Some(data) = line.strip_prefix("data: ") {
                     if data == "[DONE]" {
-                        let _ = tx.send(Event::ApiDone);
-                        return Ok(());
+                        break 'stream;
                     }
 
                     if let Ok(event) = serde_json::from_str::<Value>(data) {
-                        if let Some(content) = event["choices"][0]["delta"]["content"].as_str() {
+                        if usage.update(&event) {
+                            let _ = tx.send(Event::Usage(usage));
+                        }
+                        let delta = &event["choices"][0]["delta"];
+                        if let Some(content) = delta["content"].as_str() {
+                            tool_calls.text.push_str(content);
                             let _ = tx.send(Event::ApiChunk(content.to_string()));
                         }
+                        tool_calls.apply_delta(delta);
                     }
                 }
             }
         }
 
+        // Tool calls go through the same flow as Anthropic tool_use responses
+        match tool_calls.into_response() {
+            Some(response) => { let _ = tx.send(Event::ToolUseRequest(response.to_string())); }
+            None => { let _ = tx.send(Event::ApiDone); }
+        }
+        Ok(())
+    }
+
+    /// Stream a Google Gemini call via the `streamGenerateContent` SSE endpoint.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn stream_gemini(
+        &self,
+        api_key: &str,
+        model: &str,
+        messages: &[Message],
+        system_prompt: Option<&str>,
+        max_tokens: u32,
+        sampling: &Sampling,
+        tx: mpsc::UnboundedSender<Event>,
+        extra_headers: &[(String, String)],
+    ) -> anyhow::Result<()> {
+        let mut body = json!({
+            "contents": gemini_contents(messages),
+            "generationConfig": {
+                "maxOutputTokens": max_tokens,
+                "temperature": sampling.temperature,
+            },
+        });
+        sampling.apply(&mut body["generationConfig"], "topP", Some("topK"), "stopSequences");
+        if let Some(sys) = system_prompt {
+            body["systemInstruction"] = json!({"parts": [{"text": sys}]});
+        }
+
+        let url = format!("{GEMINI_API_URL}/models/{model}:streamGenerateContent?alt=sse");
+        let response = self.client
+            .post(url)
+            .header("x-goog-api-key", api_key)
+            .header("content-type", "application/json")
+            .headers(header_map(extra_headers)?)
+            .json(&body)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            let _ = tx.send(Event::ApiError(format!("API error {status}: {text}")));
+            return Ok(());
+        }
+        send_rate_limits(&response, &tx);
+
+        let mut stream = response.bytes_stream();
+        let mut buffer = String::new();
+        let mut usage = TokenUsage::default();
+
+        while let Some(chunk) = self.next_chunk(&mut stream).await? {
+            buffer.push_str(&String::from_utf8_lossy(&chunk));
+
+            while let Some(line_end) = buffer.find('\n') {
+                let line = buffer[..line_end].trim().to_string();
+                buffer = buffer[line_end + 1..].to_string();
+
+                if let Some(data) = line.strip_prefix("data: ")
+                    && let Ok(event) = serde_json::from_str::<Value>(data)
+                {
+                    if let Some(message) = event["error"]["message"].as_str() {
+                        let _ = tx.send(Event::ApiError(format!("API error: {message}")));
+                        return Ok(());
+                    }
+                    if usage.update(&event) {
+                        let _ = tx.send(Event::Usage(usage));
+                    }
+                    let text = gemini_text(&event);
+                    if !text.is_empty() {
+                        let _ = tx.send(Event::ApiChunk(text));
+                    }
+                }
+            }
+        }
+
+        let _ = tx.send(Event::ApiDone);
+        Ok(())
+    }
+
+    /// Stream a chat from a local Ollama server. Responses are newline
+    /// delimited JSON objects rather than SSE.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn stream_ollama(
+        &self,
+        host: &str,
+        model: &str,
+        messages: &[Message],
+        system_prompt: Option<&str>,
+        max_tokens: u32,
+        sampling: &Sampling,
+        tx: mpsc::UnboundedSender<Event>,
+        extra_headers: &[(String, String)],
+    ) -> anyhow::Result<()> {
+        let mut body = json!({
+            "model": model,
+            "messages": ollama_messages(messages, system_prompt),
+            "stream": true,
+            "options": {
+                "num_predict": max_tokens,
+                "temperature": sampling.temperature,
+            },
+        });
+        sampling.apply(&mut body["options"], "top_p", Some("top_k"), "stop");
+
+        let response = self.client
+            .post(format!("{}/api/chat", host.trim_end_matches('/')))
+            .headers(header_map(extra_headers)?)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("Cannot reach Ollama at {host} (is `ollama serve` running?): {e}"))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            let _ = tx.send(Event::ApiError(format!("API error {status}: {text}")));
+            return Ok(());
+        }
+        send_rate_limits(&response, &tx);
+
+        let mut stream = response.bytes_stream();
+        let mut buffer = String::new();
+        let mut usage = TokenUsage::default();
+
+        while let Some(chunk) = self.next_chunk(&mut stream).await? {
+            buffer.push_str(&String::from_utf8_lossy(&chunk));
+
+            while let Some(line_end) = buffer.find('\n') {
+                let line = buffer[..line_end].trim().to_string();
+                buffer = buffer[line_end + 1..].to_string();
+
+                let Ok(event) = serde_json::from_str::<Value>(&line) else { continue };
+                if let Some(message) = event["error"].as_str() {
+                    let _ = tx.send(Event::ApiError(format!("Ollama: {message}")));
+                    return Ok(());
+                }
+                if usage.update(&event) {
+                    let _ = tx.send(Event::Usage(usage));
+                }
+                if let Some(content) = event["message"]["content"].as_str()
+                    && !content.is_empty()
+                {
+                    let _ = tx.send(Event::ApiChunk(content.to_string()));
+                }
+                if event["done"] == true {
+                    let _ = tx.send(Event::ApiDone);
+                    return Ok(());
+                }
+            }
+        }
+
         let _ = tx.send(Event::ApiDone);
         Ok(())
     }
 
+    /// Model ids offered at a provider's model-list endpoint.
+    pub async fn list_models(&self, url: &str, headers: &[(String, String)]) -> anyhow::Result<Vec<String>> {
+        let response = self.client
+            .get(url)
+            .headers(header_map(headers)?)
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("Cannot reach {}: {e}", url.split('?').next().unwrap_or(url)))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            anyhow::bail!("Listing models failed ({status}): {}", text.trim());
+        }
+        Ok(model_ids(&response.json().await?))
+    }
+
+}
+
+/// Whether an API error means the request didn't fit the model's context
+/// window. Providers word this differently and don't share an error code.
+pub fn is_context_length_error(err: &str) -> bool {
+    let err = err.to_lowercase();
+    [
+        "prompt is too long",
+        "context_length_exceeded",
+        "maximum context length",
+        "context window",
+        "input is too long",
+        "too many tokens",
+        "input token count",
+    ]
+    .iter()
+    .any(|pattern| err.contains(pattern))
+}
+
+/// The HTTP status in an error from the request functions, e.g.
+/// `429 Too Many Requests` from `API error 429 Too Many Requests: {...}`.
+pub fn error_status(err: &str) -> Option<&str> {
+    let head = err.split_once(": ").map_or(err, |(head, _)| head);
+    let (_, status) = head.split_once(" error ")?;
+    status.as_bytes().first().is_some_and(u8::is_ascii_digit).then_some(status)
+}
+
+/// The human-readable part of an error: the `message` from a JSON error
+/// body when there is one, else the text after the status.
+pub fn error_detail(err: &str) -> &str {
+    let body = match error_status(err) {
+        Some(_) => err.split_once(": ").map_or(err, |(_, body)| body),
+        None => err,
+    };
+    let message = body.find('{').and_then(|start| {
+        let json: Value = serde_json::from_str(&body[start..]).ok()?;
+        let message = json["error"]["message"].as_str().or(json["message"].as_str())?;
+        body.find(message).map(|at| &body[at..at + message.len()])
+    });
+    message.unwrap_or(body).trim()
+}
+
+/// Ids from a model list: `{"data": [{"id"}]}` from OpenAI, Anthropic and
+/// compatible servers, or `{"models": [{"name"}]}` from Ollama and Gemini
+/// (whose names carry a `models/` prefix, and which also lists embedding
+/// models that cannot chat).
+fn model_ids(body: &Value) -> Vec<String> {
+    let data = body["data"].as_array().into_iter().flatten()
+        .filter_map(|m| m["id"].as_str());
+    let models = body["models"].as_array().into_iter().flatten()
+        .filter(|m| {
+            m["supportedGenerationMethods"]
+                .as_array()
+                .is_none_or(|methods| methods.iter().any(|x| x == "generateContent"))
+        })
+        .filter_map(|m| m["name"].as_str())
+        .map(|name| name.strip_prefix("models/").unwrap_or(name));
+    data.chain(models).map(String::from).collect()
+}
+
+/// One chat request, with everything needed to send it to any provider
+/// captured up front so it can run on its own task.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub provider: String,
+    pub model: String,
+    pub api_key: String,
+    pub system: Option<String>,
+    pub messages: Vec<Message>,
+    pub max_tokens: u32,
+    pub sampling: Sampling,
+    /// Set when `provider` names a `[[providers]]` entry
+    pub custom: Option<CustomProvider>,
+    pub headers: Vec<(String, String)>,
+    pub response_format: Option<Value>,
+    pub tools: Option<tools::ToolSet>,
+    pub ollama_host: String,
+    pub bedrock_region: String,
+    pub thinking_budget: Option<u32>,
+}
+
+impl Request {
+    /// A request to the configured provider and model with the configured
+    /// system prompt and sampling settings, without tools.
+    pub fn new(config: &Config, api_key: String, messages: Vec<Message>) -> Self {
+        Request {
+            provider: config.provider.clone(),
+            model: config.model.clone(),
+            api_key,
+            system: config.system_prompt.clone(),
+            messages,
+            max_tokens: config.effective_max_tokens(),
+            sampling: Sampling {
+                temperature: config.effective_temperature(),
+                top_p: config.top_p,
+                top_k: config.top_k,
+                stop_sequences: config.stop_sequences.clone(),
+            },
+            custom: config.custom_provider(&config.provider).cloned(),
+            headers: config.request_headers(&config.provider),
+            response_format: None,
+            tools: None,
+            ollama_host: config.ollama_host(),
+            bedrock_region: config.bedrock_region(),
+            thinking_budget: config.thinking_budget,
+        }
+    }
+}
+
+/// A whole response gathered from the stream, for commands that print
+/// results instead of drawing them.
+#[derive(Debug, Clone, Default)]
+pub struct Completion {
+    pub text: String,
+    pub usage: TokenUsage,
+    /// When the first text or thinking arrived
+    pub first_token: Option<Duration>,
+    pub elapsed: Duration,
+}
+
+impl ApiClient {
+    /// Send `request` and wait for the whole response.
+    pub async fn complete(&self, request: &Request) -> anyhow::Result<Completion> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let start = std::time::Instant::now();
+        let collect = async {
+            let mut completion = Completion::default();
+            let mut error = None;
+            while let Some(event) = rx.recv().await {
+                match event {
+                    Event::ApiChunk(text) => {
+                        completion.first_token.get_or_insert_with(|| start.elapsed());
+                        completion.text.push_str(&text);
+                    }
+                    Event::ThinkingChunk(_) => {
+                        completion.first_token.get_or_insert_with(|| start.elapsed());
+                    }
+                    Event::Usage(usage) => completion.usage = usage,
+                    Event::ApiError(e) => error = Some(e),
+                    _ => {}
+                }
+            }
+            (completion, error)
+        };
+        let (sent, (mut completion, error)) = tokio::join!(self.send(request, tx), collect);
+        sent?;
+        if let Some(e) = error {
+            anyhow::bail!(e);
+        }
+        completion.elapsed = start.elapsed();
+        Ok(completion)
+    }
+
+    /// Send `request` to its provider, streaming events to `tx`.
+    pub async fn send(&self, request: &Request, tx: mpsc::UnboundedSender<Event>) -> anyhow::Result<()> {
+        let Request { provider, model, api_key, messages, max_tokens, sampling, headers, tools, .. } = request;
+        let (model, max_tokens, system) = (model.as_str(), *max_tokens, request.system.as_deref());
+        let response_format = request.response_format.as_ref();
+        match provider.as_str() {
+            _ if let Some(custom) = &request.custom => {
+                self.stream_openai_compatible(
+                    api_key, model, messages, system, max_tokens, sampling, tx,
+                    &custom.chat_url(), headers, response_format, *tools,
+                ).await
+            }
+            "openai" => {
+                self.stream_openai_compatible(
+                    api_key, model, messages, system, max_tokens, sampling, tx,
+                    "https://api.openai.com/v1/chat/completions",
+                    headers, response_format, *tools,
+                ).await
+            }
+            "openrouter" => {
+                self.stream_openai_compatible(
+                    api_key, model, messages, system, max_tokens, sampling, tx,
+                    OPENROUTER_URL, headers, response_format, None,
+                ).await
+            }
+            "ollama" => {
+                self.stream_ollama(
+                    &request.ollama_host, model, messages, system, max_tokens, sampling, tx, headers,
+                ).await
+            }
+            "bedrock" => match AwsCredentials::load() {
+                Some(creds) => self.call_bedrock(
+                    &creds, &request.bedrock_region, model, messages, system, max_tokens, sampling, tx,
+                    *tools, request.thinking_budget, headers,
+                ).await,
+                None => Err(anyhow::anyhow!(
+                    "No AWS credentials: set AWS_ACCESS_KEY_ID/AWS_SECRET_ACCESS_KEY or AWS_PROFILE"
+                )),
+            },
+            "mock" => stream_mock(messages, tx).await,
+            "gemini" => {
+                self.stream_gemini(api_key, model, messages, system, max_tokens, sampling, tx, headers).await
+            }
+            "xai" => {
+                self.stream_openai_compatible(
+                    api_key, model, messages, system, max_tokens, sampling, tx,
+                    "https://api.x.ai/v1/chat/completions",
+                    headers, response_format, None,
+                ).await
+            }
+            // Default: anthropic
+            _ => match tools {
+                Some(set) => {
+                    self.call_anthropic_with_tools(
+                        api_key, model, messages, system, max_tokens, sampling, tx,
+                        request.thinking_budget, headers, *set,
+                    ).await
+                }
+                None => {
+                    self.stream_anthropic(
+                        api_key, model, messages, system, max_tokens, sampling, tx,
+                        request.thinking_budget, headers,
+                    ).await
+                }
+            },
+        }
+    }
+}
+
+/// Sampling settings for a request. Only `temperature` is always sent; the
+/// rest are left to the provider's defaults unless configured.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sampling {
+    pub temperature: f32,
+    pub top_p: Option<f32>,
+    pub top_k: Option<u32>,
+    pub stop_sequences: Vec<String>,
+}
+
+impl Sampling {
+    /// Add the optional settings to `target` under the provider's field
+    /// names; `top_k` is `None` for providers that reject it.
+    fn apply(&self, target: &mut Value, top_p: &str, top_k: Option<&str>, stop: &str) {
+        if let Some(p) = self.top_p {
+            target[top_p] = json!(p);
+        }
+        if let (Some(k), Some(name)) = (self.top_k, top_k) {
+            target[name] = json!(k);
+        }
+        if !self.stop_sequences.is_empty() {
+            target[stop] = json!(self.stop_sequences);
+        }
+    }
+}
+
+/// Turn on extended thinking in an Anthropic request body. `max_tokens`
+/// counts thinking too, so the budget is added on top of it, and the API
+/// only accepts the default temperature and top_k alongside thinking.
+fn apply_thinking(body: &mut Value, budget: Option<u32>) {
+    let Some(budget) = budget else { return };
+    let max_tokens = body["max_tokens"].as_u64().unwrap_or_default();
+    body["max_tokens"] = json!(max_tokens + u64::from(budget));
+    body["thinking"] = json!({"type": "enabled", "budget_tokens": budget});
+    if let Some(fields) = body.as_object_mut() {
+        fields.remove("temperature");
+        fields.remove("top_k");
+    }
+}
+
+/// Forward the text or thinking carried by an Anthropic `content_block_delta`.
+fn send_anthropic_delta(event: &Value, tx: &mpsc::UnboundedSender<Event>) {
+    if event["type"] != "content_block_delta" {
+        return;
+    }
+    let delta = &event["delta"];
+    if let Some(text) = delta["text"].as_str() {
+        let _ = tx.send(Event::ApiChunk(text.to_string()));
+    } else if let Some(thinking) = delta["thinking"].as_str() {
+        let _ = tx.send(Event::ThinkingChunk(thinking.to_string()));
+    }
+}
+
+/// Emit the text of a complete Anthropic Messages response, then hand it to
+/// the tool loop if it asks for tools.
+fn send_anthropic_response(body_text: String, tx: &mpsc::UnboundedSender<Event>) -> anyhow::Result<()> {
+    let response_json: Value = serde_json::from_str(&body_text)?;
+    let mut usage = TokenUsage::default();
+    if usage.update(&response_json) {
+        let _ = tx.send(Event::Usage(usage));
+    }
+    if let Some(reason) = response_json["stop_reason"].as_str() {
+        let _ = tx.send(Event::StopReason(reason.to_string()));
+    }
+
+    if let Some(content) = response_json["content"].as_array() {
+        for block in content {
+            if block["type"] == "thinking"
+                && let Some(thinking) = block["thinking"].as_str()
+            {
+                let _ = tx.send(Event::ThinkingChunk(thinking.to_string()));
+            }
+            if block["type"] == "text"
+                && let Some(text) = block["text"].as_str()
+            {
+                let _ = tx.send(Event::ApiChunk(text.to_string()));
+            }
+        }
+    }
+
+    // Check if response contains tool_use blocks
+    let has_tool_use = response_json["content"]
+        .as_array()
+        .map(|arr| arr.iter().any(|b| b["type"] == "tool_use"))
+        .unwrap_or(false);
+
+    if has_tool_use {
+        // Send the full response for tool processing
+        let _ = tx.send(Event::ToolUseRequest(body_text));
+    } else {
+        let _ = tx.send(Event::ApiDone);
+    }
+    Ok(())
+}
+
+/// Delay between words of a `mock` reply, so it streams like a real one.
+const MOCK_WORD_DELAY: Duration = Duration::from_millis(15);
+
+const MOCK_REPLY: &str = "This reply comes from the built-in mock provider, so nothing left your machine.\n\n\
+Here is a code block to practice yanking:\n\n```rust\nfn main() {\n    println!(\"Hello from pro tutor!\");\n}\n```\n\n\
+Ask me to run a command to see how tool calls work.";
+
+const MOCK_TOOL_DONE: &str = "The command ran and its output is in the tool card above. That is the whole tool loop: \
+the model asks, you confirm, and the result goes back to the model.";
+
+/// The `mock` provider: scripted local replies for `pro tutor` and demos.
+/// Asking it to run something produces an `execute` tool call; anything
+/// else gets a short reply with a code block.
+pub async fn stream_mock(messages: &[Message], tx: mpsc::UnboundedSender<Event>) -> anyhow::Result<()> {
+    let (prompt, after_tool) = match messages.last().map(|m| &m.content) {
+        Some(MessageContent::Text(text)) => (text.to_lowercase(), false),
+        Some(MessageContent::Blocks(blocks)) => (
+            blocks.iter().map(block_text).collect::<String>().to_lowercase(),
+            blocks.iter().any(|b| b["type"] == "tool_result"),
+        ),
+        None => (String::new(), false),
+    };
+    if !after_tool && ["run", "command", "tool"].iter().any(|w| prompt.contains(w)) {
+        let body = json!({
+            "content": [
+                {"type": "text", "text": "Sure, I'll run a harmless command.\n"},
+                {
+                    "type": "tool_use",
+                    "id": format!("toolu_mock_{}", messages.len()),
+                    "name": "execute",
+                    "input": {"command": "echo 'Hello from pro tutor'"},
+                },
+            ],
+            "stop_reason": "tool_use",
+        });
+        return send_anthropic_response(body.to_string(), &tx);
+    }
+    let reply = if after_tool { MOCK_TOOL_DONE } else { MOCK_REPLY };
+    for word in reply.split_inclusive(' ') {
+        let _ = tx.send(Event::ApiChunk(word.to_string()));
+        tokio::time::sleep(MOCK_WORD_DELAY).await;
+    }
+    let _ = tx.send(Event::ApiDone);
+    Ok(())
+}
+
+/// Messages API version Bedrock expects in the request body in place of the
+/// `anthropic-version` header.
+const BEDROCK_ANTHROPIC_VERSION: &str = "bedrock-2023-05-31";
+
+/// OpenRouter's OpenAI-compatible chat endpoint.
+pub const OPENROUTER_URL: &str = "https://openrouter.ai/api/v1/chat/completions";
+
+/// App attribution OpenRouter shows on its model rankings and activity pages.
+pub const OPENROUTER_HEADERS: &[(&str, &str)] =
+    &[("HTTP-Referer", "https://github.com/pro-chat"), ("X-Title", "Pro Chat")];
+
+/// Ollama's address when neither `ollama_host` nor `OLLAMA_HOST` is set.
+pub const DEFAULT_OLLAMA_HOST: &str = "http://localhost:11434";
+
+pub const GEMINI_API_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+
+/// A `document` content block carrying a base64-encoded PDF. Anthropic and
+/// Bedrock take it as is; the other providers' message mappers translate it.
+pub fn document_block(title: &str, data: &str) -> Value {
+    json!({
+        "type": "document",
+        "title": title,
+        "source": {"type": "base64", "media_type": "application/pdf", "data": data},
+    })
+}
+
+/// Map messages to Gemini `contents`: the assistant role is `model`, and
+/// Anthropic content blocks become `parts`. Tool exchanges (only present if
+/// the provider was switched mid-conversation) are kept as plain text.
+fn gemini_contents(messages: &[Message]) -> Vec<Value> {
+    messages
+        .iter()
+        .map(|msg| {
+            let role = if msg.role == "assistant" { "model" } else { "user" };
+            let parts: Vec<Value> = match &msg.content {
+                MessageContent::Text(text) => vec![json!({"text": text})],
+                MessageContent::Blocks(blocks) => blocks.iter().map(gemini_part).collect(),
+            };
+            json!({"role": role, "parts": parts})
+        })
+        .collect()
+}
+
+fn gemini_part(block: &Value) -> Value {
+    match block["type"].as_str() {
+        Some("image" | "document") => json!({"inline_data": {
+            "mime_type": block["source"]["media_type"],
+            "data": block["source"]["data"],
+        }}),
+        _ => json!({"text": block_text(block)}),
+    }
+}
+
+/// Plain-text form of a non-image content block, for providers without tool
+/// support.
+fn block_text(block: &Value) -> String {
+    match block["type"].as_str() {
+        Some("tool_use") => format!("[tool call {} {}]", block["name"].as_str().unwrap_or("?"), block["input"]),
+        Some("tool_result") => {
+            let content: String = match &block["content"] {
+                Value::String(s) => s.clone(),
+                Value::Array(parts) => parts.iter().filter_map(|p| p["text"].as_str()).collect(),
+                other => other.to_string(),
+            };
+            format!("[tool result]\n{content}")
+        }
+        Some("document") => format!("[PDF attachment {} not readable by this model]", block["title"].as_str().unwrap_or("?")),
+        _ => block["text"].as_str().unwrap_or("").to_string(),
+    }
+}
+
+/// Map messages to Ollama's chat format: text content plus base64 `images`.
+fn ollama_messages(messages: &[Message], system_prompt: Option<&str>) -> Vec<Value> {
+    let mut out = Vec::new();
+    if let Some(sys) = system_prompt {
+        out.push(json!({"role": "system", "content": sys}));
+    }
+    for msg in messages {
+        match &msg.content {
+            MessageContent::Text(text) => out.push(json!({"role": msg.role, "content": text})),
+            MessageContent::Blocks(blocks) => {
+                let (images, rest): (Vec<&Value>, Vec<&Value>) =
+                    blocks.iter().partition(|b| b["type"] == "image");
+                let content = rest.into_iter().map(block_text).collect::<Vec<_>>().join("\n\n");
+                let mut message = json!({"role": msg.role, "content": content});
+                if !images.is_empty() {
+                    message["images"] = images.iter().map(|b| b["source"]["data"].clone()).collect();
+                }
+                out.push(message);
+            }
+        }
+    }
+    out
+}
+
+/// Text of the first candidate in a streamed Gemini response chunk.
+fn gemini_text(event: &Value) -> String {
+    event["candidates"][0]["content"]["parts"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|p| p["text"].as_str())
+        .collect()
+}
+
+/// Convert a stored message to OpenAI chat format. Tool exchanges are kept in
+/// Anthropic block form, so tool_use blocks become assistant `tool_calls` and
+/// each tool_result becomes its own `role: "tool"` message.
+fn openai_messages(msg: &Message) -> Vec<Value> {
+    let MessageContent::Blocks(blocks) = &msg.content else {
+        return vec![json!({"role": msg.role, "content": msg.content})];
+    };
+
+    if msg.role == "assistant" {
+        let text: String = blocks.iter()
+            .filter(|b| b["type"] == "text")
+            .filter_map(|b| b["text"].as_str())
+            .collect();
+        let calls: Vec<Value> = blocks.iter()
+            .filter(|b| b["type"] == "tool_use")
+            .map(|b| json!({
+                "id": b["id"],
+                "type": "function",
+                "function": { "name": b["name"], "arguments": b["input"].to_string() },
+            }))
+            .collect();
+        if calls.is_empty() {
+            return vec![json!({"role": "assistant", "content": text})];
+        }
+        let content = if text.is_empty() { Value::Null } else { json!(text) };
+        return vec![json!({"role": "assistant", "content": content, "tool_calls": calls})];
+    }
+
+    let mut out = Vec::new();
+    let mut rest = Vec::new();
+    for block in blocks {
+        if block["type"] == "document" {
+            rest.push(json!({"type": "file", "file": {
+                "filename": block["title"],
+                "file_data": format!("data:application/pdf;base64,{}", block["source"]["data"].as_str().unwrap_or("")),
+            }}));
+            continue;
+        }
+        if block["type"] != "tool_result" {
+            rest.push(block.clone());
+            continue;
+        }
+        let content = match &block["content"] {
+            Value::String(s) => s.clone(),
+            Value::Array(parts) => parts.iter().filter_map(|p| p["text"].as_str()).collect(),
+            other => other.to_string(),
+        };
+        out.push(json!({"role": "tool", "tool_call_id": block["tool_use_id"], "content": content}));
+    }
+    if !rest.is_empty() {
+        out.push(json!({"role": msg.role, "content": rest}));
+    }
+    out
+}
+
+/// Accumulates streamed OpenAI `tool_calls` deltas. The id and name arrive in
+/// the first delta for each index; the JSON arguments arrive in fragments.
+#[derive(Debug, Default)]
+struct OpenAiToolCalls {
+    text: String,
+    /// (id, name, arguments) by tool call index
+    calls: Vec<(String, String, String)>,
+}
+
+impl OpenAiToolCalls {
+    fn apply_delta(&mut self, delta: &Value) {
+        let Some(calls) = delta["tool_calls"].as_array() else { return };
+        for call in calls {
+            let index = call["index"].as_u64().unwrap_or(0) as usize;
+            if self.calls.len() <= index {
+                self.calls.resize_with(index + 1, Default::default);
+            }
+            let entry = &mut self.calls[index];
+            if let Some(id) = call["id"].as_str() {
+                entry.0 = id.to_string();
+            }
+            if let Some(name) = call["function"]["name"].as_str() {
+                entry.1.push_str(name);
+            }
+            if let Some(args) = call["function"]["arguments"].as_str() {
+                entry.2.push_str(args);
+            }
+        }
+    }
+
+    /// An Anthropic-shaped response body for `tools::parse_tool_calls`, or
+    /// `None` when the reply had no tool calls.
+    fn into_response(self) -> Option<Value> {
+        if self.calls.iter().all(|(_, name, _)| name.is_empty()) {
+            return None;
+        }
+        let mut content = Vec::new();
+        if !self.text.is_empty() {
+            content.push(json!({"type": "text", "text": self.text}));
+        }
+        for (id, name, args) in self.calls.into_iter().filter(|(_, name, _)| !name.is_empty()) {
+            let input = if args.trim().is_empty() {
+                json!({})
+            } else {
+                serde_json::from_str(&args).unwrap_or_else(|_| json!({}))
+            };
+            content.push(json!({"type": "tool_use", "id": id, "name": name, "input": input}));
+        }
+        Some(json!({"content": content, "stop_reason": "tool_use"}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_thinking() {
+        let mut body = json!({"max_tokens": 4096, "temperature": 0.7});
+        apply_thinking(&mut body, None);
+        assert_eq!(body["temperature"], 0.7);
+
+        apply_thinking(&mut body, Some(8000));
+        assert_eq!(body["max_tokens"], 12096);
+        assert_eq!(body["thinking"], json!({"type": "enabled", "budget_tokens": 8000}));
+        assert!(body.get("temperature").is_none());
+    }
+
+    #[test]
+    fn test_sampling_field_names() {
+        let sampling = Sampling {
+            temperature: 0.2,
+            top_p: Some(0.9),
+            top_k: Some(40),
+            stop_sequences: vec!["END".into()],
+        };
+        let mut body = json!({"temperature": 0.2});
+        sampling.apply(&mut body, "top_p", None, "stop");
+        assert_eq!(body, json!({"temperature": 0.2, "top_p": 0.9f32, "stop": ["END"]}));
+
+        let mut config = json!({});
+        sampling.apply(&mut config, "topP", Some("topK"), "stopSequences");
+        assert_eq!(config["topK"], 40);
+        assert_eq!(config["stopSequences"], json!(["END"]));
+
+        // Unset options fall back to the provider's defaults
+        let mut body = json!({});
+        Sampling::default().apply(&mut body, "top_p", Some("top_k"), "stop_sequences");
+        assert_eq!(body, json!({}));
+
+        let mut body = json!({"max_tokens": 1024, "temperature": 0.2, "top_k": 40});
+        apply_thinking(&mut body, Some(2048));
+        assert!(body.get("top_k").is_none());
+    }
+
+    #[test]
+    fn test_client_builder_rejects_bad_network_settings() {
+        let proxy = NetworkConfig { proxy: Some("not a url".into()), ..Default::default() };
+        let err = client_builder(&proxy).unwrap_err().to_string();
+        assert!(err.contains("network.proxy"), "{err}");
+        let ca = NetworkConfig { ca_bundle: Some("/nonexistent/ca.pem".into()), ..Default::default() };
+        assert!(client_builder(&ca).unwrap_err().to_string().contains("network.ca_bundle"));
+        assert!(client_builder(&NetworkConfig::default()).is_ok());
+    }
+
+    #[test]
+    fn test_rate_limits_from_headers() {
+        let now = DateTime::parse_from_rfc3339("2025-01-01T00:00:00Z").unwrap().with_timezone(&Utc);
+        let headers = |pairs: &[(&'static str, &'static str)]| {
+            let mut map = HeaderMap::new();
+            for (k, v) in pairs {
+                map.insert(*k, v.parse().unwrap());
+            }
+            map
+        };
+
+        let anthropic = RateLimits::from_headers(&headers(&[
+            ("anthropic-ratelimit-requests-limit", "50"),
+            ("anthropic-ratelimit-requests-remaining", "3"),
+            ("anthropic-ratelimit-requests-reset", "2025-01-01T00:00:30Z"),
+            ("anthropic-ratelimit-tokens-limit", "80000"),
+            ("anthropic-ratelimit-tokens-remaining", "79000"),
+        ]), now).unwrap();
+        let requests = anthropic.requests.unwrap();
+        assert_eq!((requests.limit, requests.remaining), (50, 3));
+        assert_eq!(requests.reset_at, Some(now + chrono::Duration::seconds(30)));
+        assert_eq!(anthropic.low().map(|(name, _)| name), Some("requests"));
+        assert!(!anthropic.tokens.unwrap().is_low());
+
+        let openai = RateLimits::from_headers(&headers(&[
+            ("x-ratelimit-limit-tokens", "150000"),
+            ("x-ratelimit-remaining-tokens", "149000"),
+            ("x-ratelimit-reset-tokens", "6m0s"),
+        ]), now).unwrap();
+        assert_eq!(openai.requests, None);
+        assert_eq!(openai.tokens.unwrap().reset_at, Some(now + chrono::Duration::minutes(6)));
+        assert_eq!(openai.low(), None);
+
+        assert_eq!(RateLimits::from_headers(&HeaderMap::new(), now), None);
+        assert_eq!(parse_reset_duration("1h2m3.5s"), Some(Duration::from_secs_f64(3723.5)));
+        assert_eq!(parse_reset_duration("20ms"), Some(Duration::from_millis(20)));
+        assert_eq!(parse_reset_duration("soon"), None);
+    }
+
+    #[test]
+    fn test_error_status_and_detail() {
+        let err = r#"API error 429 Too Many Requests: {"type":"error","error":{"type":"rate_limit_error","message":"Number of requests has exceeded your rate limit"}}"#;
+        assert_eq!(error_status(err), Some("429 Too Many Requests"));
+        assert_eq!(error_detail(err), "Number of requests has exceeded your rate limit");
+        assert_eq!(error_status("Bedrock error 403 Forbidden: denied"), Some("403 Forbidden"));
+        assert_eq!(error_detail("Bedrock error 403 Forbidden: denied"), "denied");
+        assert_eq!(error_status("Stream stalled: no data for 60s"), None);
+        assert_eq!(error_detail("Stream stalled: no data for 60s"), "Stream stalled: no data for 60s");
+    }
+
+    #[test]
+    fn test_is_context_length_error() {
+        assert!(is_context_length_error(
+            r#"API error 400 Bad Request: {"type":"error","error":{"type":"invalid_request_error","message":"prompt is too long: 208310 tokens > 200000 maximum"}}"#
+        ));
+        assert!(is_context_length_error(r#"API error 400: {"error":{"code":"context_length_exceeded"}}"#));
+        assert!(is_context_length_error("API error: The input token count (1200000) exceeds the maximum number of tokens allowed (1048576)."));
+        assert!(!is_context_length_error("API error 429 Too Many Requests: rate limited"));
+    }
+
+    #[test]
+    fn test_token_usage_shapes() {
+        let mut usage = TokenUsage::default();
+        // Anthropic streaming: input on message_start, output on message_delta
+        assert!(usage.update(&json!({"type": "message_start", "message": {"usage": {"input_tokens": 25, "output_tokens": 1}}})));
+        assert!(!usage.update(&json!({"type": "content_block_delta", "delta": {"text": "hi"}})));
+        usage.update(&json!({"type": "message_delta", "usage": {"output_tokens": 15}}));
+        assert_eq!(usage, TokenUsage { input_tokens: 25, output_tokens: 15 });
+
+        let cases = [
+            json!({"choices": [], "usage": {"prompt_tokens": 10, "completion_tokens": 4}}),
+            json!({"usageMetadata": {"promptTokenCount": 10, "candidatesTokenCount": 4}}),
+            json!({"done": true, "prompt_eval_count": 10, "eval_count": 4}),
+            json!({"amazon-bedrock-invocationMetrics": {"inputTokenCount": 10, "outputTokenCount": 4}}),
+        ];
+        for event in cases {
+            let mut usage = TokenUsage::default();
+            assert!(usage.update(&event), "{event}");
+            assert_eq!(usage, TokenUsage { input_tokens: 10, output_tokens: 4 });
+        }
+        // OpenAI sends `"usage": null` on every chunk before the last
+        assert!(!TokenUsage::default().update(&json!({"usage": null})));
+
+        let mut total = TokenUsage::default();
+        total.add(usage);
+        total.add(usage);
+        assert_eq!(total.output_tokens, 30);
+    }
+
+    #[test]
+    fn model_ids_from_each_list_format() {
+        let openai = json!({"data": [{"id": "gpt-4o"}, {"id": "claude-sonnet-4-20250514"}]});
+        assert_eq!(model_ids(&openai), ["gpt-4o", "claude-sonnet-4-20250514"]);
+
+        let ollama = json!({"models": [{"name": "llama3.2:latest"}]});
+        assert_eq!(model_ids(&ollama), ["llama3.2:latest"]);
+
+        let gemini = json!({"models": [
+            {"name": "models/gemini-2.5-pro", "supportedGenerationMethods": ["generateContent"]},
+            {"name": "models/text-embedding-004", "supportedGenerationMethods": ["embedContent"]},
+        ]});
+        assert_eq!(model_ids(&gemini), ["gemini-2.5-pro"]);
+    }
+
+    #[test]
+    fn tool_call_deltas_become_tool_use_blocks() {
+        let mut calls = OpenAiToolCalls { text: "Checking.".into(), ..Default::default() };
+        calls.apply_delta(&json!({"tool_calls": [
+            {"index": 0, "id": "call_1", "type": "function", "function": {"name": "read_file", "arguments": ""}}
+        ]}));
+        calls.apply_delta(&json!({"tool_calls": [{"index": 0, "function": {"arguments": "{\"path\":"}}]}));
+        calls.apply_delta(&json!({"tool_calls": [{"index": 0, "function": {"arguments": "\"a.rs\"}"}}]}));
+
+        let response = calls.into_response().unwrap();
+        assert_eq!(response["content"][0]["text"], "Checking.");
+        let tool_calls = tools::parse_tool_calls(&response);
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].id, "call_1");
+        assert!(matches!(&tool_calls[0].tool, tools::Tool::ReadFile { path } if path == "a.rs"));
+    }
+
+    #[test]
+    fn no_tool_calls_means_plain_reply() {
+        let mut calls = OpenAiToolCalls::default();
+        calls.apply_delta(&json!({"content": "hi"}));
+        assert!(calls.into_response().is_none());
+    }
+
+    #[test]
+    fn messages_map_to_gemini_contents() {
+        let messages = vec![
+            Message { role: "user".into(), content: MessageContent::Text("hi".into()) },
+            Message { role: "assistant".into(), content: MessageContent::Text("hello".into()) },
+            Message {
+                role: "user".into(),
+                content: MessageContent::Blocks(vec![
+                    json!({"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "AAA"}}),
+                    json!({"type": "text", "text": "what is this?"}),
+                ]),
+            },
+        ];
+        let contents = gemini_contents(&messages);
+        assert_eq!(contents[0], json!({"role": "user", "parts": [{"text": "hi"}]}));
+        assert_eq!(contents[1]["role"], "model");
+        assert_eq!(contents[2]["parts"][0]["inline_data"]["mime_type"], "image/png");
+        assert_eq!(contents[2]["parts"][1]["text"], "what is this?");
+
+        let chunk = json!({"candidates": [{"content": {"role": "model", "parts": [{"text": "Hel"}, {"text": "lo"}]}}]});
+        assert_eq!(gemini_text(&chunk), "Hello");
+        assert_eq!(gemini_text(&json!({"usageMetadata": {}})), "");
+    }
+
+    #[test]
+    fn messages_map_to_ollama_chat() {
+        let messages = vec![Message {
+            role: "user".into(),
+            content: MessageContent::Blocks(vec![
+                json!({"type": "text", "text": "describe"}),
+                json!({"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "AAA"}}),
+            ]),
+        }];
+        let out = ollama_messages(&messages, Some("be brief"));
+        assert_eq!(out[0], json!({"role": "system", "content": "be brief"}));
+        assert_eq!(out[1], json!({"role": "user", "content": "describe", "images": ["AAA"]}));
+    }
+
+    #[test]
+    fn tool_exchange_converts_to_openai_messages() {
+        let assistant = Message {
+            role: "assistant".into(),
+            content: MessageContent::Blocks(vec![
+                json!({"type": "tool_use", "id": "call_1", "name": "list_files", "input": {"path": "."}}),
+            ]),
+        };
+        let converted = openai_messages(&assistant);
+        assert_eq!(converted.len(), 1);
+        assert!(converted[0]["content"].is_null());
+        assert_eq!(converted[0]["tool_calls"][0]["function"]["arguments"], r#"{"path":"."}"#);
+
+        let results = Message {
+            role: "user".into(),
+            content: MessageContent::Blocks(vec![
+                json!({"type": "tool_result", "tool_use_id": "call_1", "content": "src/"}),
+            ]),
+        };
+        let converted = openai_messages(&results);
+        assert_eq!(converted, vec![json!({"role": "tool", "tool_call_id": "call_1", "content": "src/"})]);
+    }
+    #[tokio::test]
+    async fn mock_provider_replies_and_calls_tools() {
+        let text = |t: &str| Message { role: "user".into(), content: MessageContent::Text(t.into()) };
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        stream_mock(&[text("hello")], tx).await.unwrap();
+        let mut reply = String::new();
+        while let Ok(event) = rx.try_recv() {
+            match event {
+                Event::ApiChunk(chunk) => reply.push_str(&chunk),
+                Event::ApiDone => break,
+                _ => {}
+            }
+        }
+        assert!(reply.contains("```rust\n"));
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        stream_mock(&[text("Run a command please")], tx).await.unwrap();
+        let body = std::iter::from_fn(|| rx.try_recv().ok())
+            .find_map(|e| match e {
+                Event::ToolUseRequest(body) => Some(body),
+                _ => None,
+            })
+            .unwrap();
+        let calls = tools::parse_tool_calls(&serde_json::from_str(&body).unwrap());
+        assert_eq!(calls[0].tool.name(), "execute");
+    }
+
+    #[test]
+    fn pdf_document_maps_to_each_provider() {
+        let message = Message {
+            role: "user".into(),
+            content: MessageContent::Blocks(vec![
+                document_block("report.pdf", "JVBERi0="),
+                json!({"type": "text", "text": "summarize"}),
+            ]),
+        };
+        let openai = openai_messages(&message);
+        assert_eq!(openai[0]["content"][0]["file"]["filename"], "report.pdf");
+        assert_eq!(openai[0]["content"][0]["file"]["file_data"], "data:application/pdf;base64,JVBERi0=");
+
+        let gemini = gemini_contents(std::slice::from_ref(&message));
+        assert_eq!(gemini[0]["parts"][0]["inline_data"]["mime_type"], "application/pdf");
+
+        let ollama = ollama_messages(&[message], None);
+        assert_eq!(ollama[0]["content"], "[PDF attachment report.pdf not readable by this model]\n\nsummarize");
+    }
 }
@@ -1,16 +1,41 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::Arc;
+
 use crossterm::event::MouseEventKind;
 use ratatui::prelude::*;
 use serde_json::Value;
 use tokio::sync::mpsc;
 
-use crate::api::{ApiClient, Message, MessageContent};
-use crate::config::{Config, ThemeColors, clamp_temperature, get_theme};
+use crate::api::{self, ApiClient, Message, MessageContent, TokenUsage};
+use crate::config::{Autonomy, Config, Cue, NetworkConfig, PrintOnExit, ProjectSettings, Recipe, SandboxConfig, StartupMode, ThemeColors, get_theme};
 use crate::event::{Event, EventHandler};
-use crate::history::Conversation;
+use crate::history::{self, Bookmark, Conversation, ConversationLock, ResponseMeta};
 use crate::keybinds::{handle_key, KeyAction};
+use crate::mcp;
 use crate::neovim::NeovimClient;
-use crate::tools::{self, ToolCall, ToolExecutor, ToolPermission, ToolResult};
+use crate::tools::{self, ToolCache, ToolCall, ToolExecutor, ToolPermission, ToolResult, ToolStats};
+use crate::rag;
+use crate::review;
+use crate::structured;
+use crate::tracker;
 use crate::ui;
+use crate::usage::{self, UsageEvent};
+
+/// Minimum gap between conversation writes; saves requested sooner are
+/// coalesced and flushed on a later tick.
+const SAVE_DEBOUNCE: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Slash commands that work in the read-only viewer.
+const VIEWER_COMMANDS: &[&str] = &["/export", "/goto", "/stats", "/help", "/?", "/quit", "/q"];
+
+/// Number of recent conversations offered by the startup picker.
+const PICKER_RECENT: usize = 10;
+
+/// Number of recent conversations listed on the welcome screen.
+const WELCOME_RECENT: usize = 5;
+
+/// How often an in-progress response is snapshotted to disk.
+const AUTOSAVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3);
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum InputMode {
@@ -29,6 +54,98 @@ pub enum Overlay {
     Settings,
     ToolConfirm,
     Setup,
+    Stats,
+    /// Recent conversations shown at launch (`startup = "picker"`)
+    Picker,
+    Bookmarks,
+    ResponseDiff,
+    /// Usage analytics across all history (`/dashboard`)
+    Dashboard,
+    /// Multi-line system prompt editor (`/system` with no argument)
+    SystemPrompt,
+    /// What the next request would send (`/preview`, Ctrl+o)
+    RequestPreview,
+    Recall,
+    /// Models offered by the current provider (`/models`)
+    Models,
+    /// Files changed by tools this session (`/review`)
+    Review,
+    /// A running command is waiting for the user to answer a prompt
+    ToolInput,
+    /// A pack recipe's commands and files, waiting for the user's go-ahead
+    RecipeConfirm,
+}
+
+/// A recipe from a pack, held until the user approves exactly what it runs
+/// and reads.
+pub struct RecipeConfirm {
+    pub name: String,
+    pub pack: String,
+    pub recipe: Recipe,
+    extra: String,
+}
+
+/// A prompt a running command stopped at, and the answer being typed.
+pub struct ToolInputPrompt {
+    pub prompt: String,
+    pub answer: String,
+    reply: tokio::sync::oneshot::Sender<Option<String>>,
+}
+
+/// Where the current turn is between sending a request and being ready for
+/// the next prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SessionState {
+    #[default]
+    Idle,
+    /// A reply is streaming in
+    Streaming,
+    /// A tool call is waiting for the user to allow or deny it
+    AwaitingToolConfirm,
+    /// The model's tool calls are executing
+    RunningTools,
+    /// Tool results were sent and the model's follow-up is streaming in
+    Continuing,
+}
+
+impl SessionState {
+    /// Status bar text; empty when idle.
+    pub fn label(self) -> &'static str {
+        match self {
+            SessionState::Idle => "",
+            SessionState::Streaming => "streaming",
+            SessionState::AwaitingToolConfirm => "awaiting confirmation",
+            SessionState::RunningTools => "running tools",
+            SessionState::Continuing => "continuing after tools",
+        }
+    }
+
+    /// A request is in flight and its events are expected.
+    pub fn is_streaming(self) -> bool {
+        matches!(self, SessionState::Streaming | SessionState::Continuing)
+    }
+
+    /// Whether `next` can follow this state. Any state may return to `Idle`
+    /// (done, cancelled or failed).
+    fn can_become(self, next: SessionState) -> bool {
+        use SessionState::*;
+        matches!(
+            (self, next),
+            (_, Idle)
+                | (Idle, Streaming)
+                | (Streaming | Continuing, RunningTools)
+                | (RunningTools, AwaitingToolConfirm)
+                | (AwaitingToolConfirm, RunningTools)
+                | (RunningTools, Continuing)
+        )
+    }
+}
+
+/// Bulk operations on the conversations selected in the History overlay.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HistoryBulkAction {
+    Delete,
+    Export,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -63,6 +180,9 @@ impl SetupState {
             ("openai", "OpenAI (GPT)", "◎"),
             ("openrouter", "OpenRouter (Any model)", "⬡"),
             ("xai", "xAI (Grok)", "✕"),
+            ("gemini", "Google (Gemini)", "◆"),
+            ("ollama", "Ollama (Local models)", "◉"),
+            ("bedrock", "AWS Bedrock (Claude)", "▣"),
         ]
     }
 
@@ -88,6 +208,22 @@ impl SetupState {
                 ("grok-3-mini", "fast"),
                 ("grok-2", "balanced"),
             ],
+            "gemini" => vec![
+                ("gemini-2.5-pro", "powerful"),
+                ("gemini-2.5-flash", "fast"),
+            ],
+            // Suggestions only; /models lists what is actually pulled
+            "ollama" => vec![
+                ("llama3.2", "general"),
+                ("qwen2.5-coder", "code"),
+                ("gemma3", "small"),
+            ],
+            // Cross-region inference profiles; plain model ids work too
+            "bedrock" => vec![
+                ("us.anthropic.claude-sonnet-4-20250514-v1:0", "balanced"),
+                ("us.anthropic.claude-opus-4-20250514-v1:0", "powerful"),
+                ("us.anthropic.claude-3-5-haiku-20241022-v1:0", "fast"),
+            ],
             _ => vec![],
         }
     }
@@ -98,10 +234,19 @@ impl SetupState {
             "openai" => "platform.openai.com",
             "openrouter" => "openrouter.ai/keys",
             "xai" => "console.x.ai",
+            "gemini" => "aistudio.google.com/apikey",
+            "ollama" => "ollama.com/download",
+            "bedrock" => "console.aws.amazon.com/bedrock",
             _ => "",
         }
     }
 
+    /// Ollama and the mock provider run locally and Bedrock signs requests
+    /// with AWS credentials, so none of them asks for a key.
+    pub fn needs_api_key(provider: &str) -> bool {
+        !matches!(provider, "ollama" | "bedrock" | "mock")
+    }
+
     pub fn current_provider_id(&self) -> &'static str {
         Self::providers().get(self.selected_provider)
             .map(|(id, _, _)| *id)
@@ -109,6 +254,509 @@ impl SetupState {
     }
 }
 
+/// Step-through playback of a conversation (`/replay`).
+#[derive(Debug, Clone)]
+pub struct ReplayState {
+    /// Every message of the conversation being replayed
+    pub all: Vec<ChatMessage>,
+    /// How many messages have been revealed so far
+    pub shown: usize,
+    /// Reveal assistant replies gradually, as if streaming
+    pub simulate_stream: bool,
+    /// Characters of the last revealed message typed out so far
+    pub typed: Option<usize>,
+}
+
+/// A failed request that left nothing to resend (it broke off mid-stream, or
+/// during a tool loop), shown as a card in the transcript. Display-only:
+/// cards are neither saved nor sent.
+#[derive(Debug, Clone)]
+pub struct ErrorCard {
+    /// Id of the message the card follows
+    pub after: Option<String>,
+    pub error: String,
+    pub failed_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Largest PDF `/attach` accepts. Base64 grows it by a third, and Anthropic
+/// caps a whole request at 32 MB.
+const MAX_PDF_BYTES: usize = 20 * 1024 * 1024;
+
+/// A PDF added with `/attach`, sent as a document block with the next message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attachment {
+    pub name: String,
+    /// Base64-encoded file contents
+    pub data: String,
+}
+
+impl Attachment {
+    /// Read `path`, checking that it is a PDF within the size limit.
+    fn load_pdf(path: &std::path::Path) -> Result<Self, String> {
+        use base64::Engine;
+        let bytes = std::fs::read(path).map_err(|e| format!("Cannot read {}: {e}", path.display()))?;
+        if !bytes.starts_with(b"%PDF-") {
+            return Err(format!("{} is not a PDF (use /file for text files)", path.display()));
+        }
+        if bytes.len() > MAX_PDF_BYTES {
+            return Err(format!(
+                "{} is {}; PDFs are limited to {}",
+                path.display(), format_size(bytes.len()), format_size(MAX_PDF_BYTES)
+            ));
+        }
+        Ok(Self {
+            name: path.file_name().map_or_else(|| path.display().to_string(), |n| n.to_string_lossy().to_string()),
+            data: base64::engine::general_purpose::STANDARD.encode(bytes),
+        })
+    }
+
+    /// Decoded size in bytes.
+    pub fn size(&self) -> usize {
+        self.data.len() / 4 * 3 - self.data.bytes().rev().take_while(|&b| b == b'=').count()
+    }
+
+    /// Name and size, e.g. `report.pdf (1.2 MB)`.
+    pub fn label(&self) -> String {
+        format!("{} ({})", self.name, format_size(self.size()))
+    }
+}
+
+/// Split a user turn sent with attachments back into its text and PDFs, or
+/// `None` if the blocks are anything else (e.g. tool results).
+fn split_attachments(blocks: &[Value]) -> Option<(String, Vec<Attachment>)> {
+    let (text, documents) = blocks.split_last()?;
+    if text["type"] != "text" || documents.iter().any(|b| b["type"] != "document") {
+        return None;
+    }
+    let attachments = documents
+        .iter()
+        .map(|b| Attachment {
+            name: b["title"].as_str().unwrap_or("document.pdf").to_string(),
+            data: b["source"]["data"].as_str().unwrap_or("").to_string(),
+        })
+        .collect();
+    Some((text["text"].as_str().unwrap_or("").to_string(), attachments))
+}
+
+/// Human-readable byte count: `512 B`, `1.5 KB`, `2.0 MB`.
+fn format_size(bytes: usize) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    } else if bytes >= 1024 {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    } else {
+        format!("{bytes} B")
+    }
+}
+
+/// A user message whose request failed, kept so it can be resent.
+#[derive(Debug, Clone)]
+pub struct FailedSend {
+    pub content: String,
+    /// PDFs that went with it, re-attached on resend
+    pub attachments: Vec<Attachment>,
+    pub error: String,
+    pub failed_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Multi-line text being edited in the system prompt overlay.
+#[derive(Debug, Clone, Default)]
+pub struct PromptEditor {
+    pub text: String,
+    /// Byte offset of the cursor in `text`
+    pub cursor: usize,
+    /// Show the fully assembled prompt instead of the editable text
+    pub preview: bool,
+}
+
+impl PromptEditor {
+    pub fn new(text: &str) -> Self {
+        Self { text: text.to_string(), cursor: text.len(), preview: false }
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    pub fn backspace(&mut self) {
+        if let Some(c) = self.text[..self.cursor].chars().next_back() {
+            self.cursor -= c.len_utf8();
+            self.text.remove(self.cursor);
+        }
+    }
+
+    pub fn left(&mut self) {
+        if let Some(c) = self.text[..self.cursor].chars().next_back() {
+            self.cursor -= c.len_utf8();
+        }
+    }
+
+    pub fn right(&mut self) {
+        if let Some(c) = self.text[self.cursor..].chars().next() {
+            self.cursor += c.len_utf8();
+        }
+    }
+
+    /// Cursor position as (line, column in chars).
+    pub fn line_col(&self) -> (usize, usize) {
+        let before = &self.text[..self.cursor];
+        let line = before.matches('\n').count();
+        let col = before.rsplit('\n').next().unwrap_or("").chars().count();
+        (line, col)
+    }
+
+    fn move_to(&mut self, line: usize, col: usize) {
+        let mut offset = 0;
+        for (i, l) in self.text.split('\n').enumerate() {
+            if i == line {
+                self.cursor = offset + l.char_indices().nth(col).map_or(l.len(), |(b, _)| b);
+                return;
+            }
+            offset += l.len() + 1;
+        }
+    }
+
+    pub fn up(&mut self) {
+        let (line, col) = self.line_col();
+        if line > 0 {
+            self.move_to(line - 1, col);
+        }
+    }
+
+    pub fn down(&mut self) {
+        let (line, col) = self.line_col();
+        if line < self.text.matches('\n').count() {
+            self.move_to(line + 1, col);
+        }
+    }
+
+    pub fn home(&mut self) {
+        self.cursor = self.text[..self.cursor].rfind('\n').map_or(0, |i| i + 1);
+    }
+
+    pub fn end(&mut self) {
+        self.cursor += self.text[self.cursor..].find('\n').unwrap_or(self.text.len() - self.cursor);
+    }
+}
+
+/// One message as it would appear in the next request.
+#[derive(Debug, Clone)]
+pub struct PreviewMessage {
+    pub role: String,
+    pub tokens: usize,
+    /// Leading lines of the content, each cut to `PREVIEW_LINE_CHARS`
+    pub excerpt: Vec<String>,
+    /// Lines not shown in `excerpt`
+    pub hidden_lines: usize,
+}
+
+/// Summary of the request the next send would make.
+#[derive(Debug, Clone, Default)]
+pub struct RequestPreview {
+    pub provider: String,
+    pub model: String,
+    pub max_tokens: u32,
+    pub temperature: f32,
+    pub system: Option<String>,
+    pub messages: Vec<PreviewMessage>,
+    /// Names of tool definitions sent, empty when tools are off
+    pub tools: Vec<&'static str>,
+    pub system_tokens: usize,
+    pub tool_tokens: usize,
+}
+
+impl RequestPreview {
+    pub fn total_tokens(&self) -> usize {
+        self.system_tokens + self.tool_tokens + self.messages.iter().map(|m| m.tokens).sum::<usize>()
+    }
+}
+
+const PREVIEW_LINES: usize = 4;
+const PREVIEW_LINE_CHARS: usize = 160;
+
+/// Fenced code blocks in `content` as (language, code), in order. An
+/// unterminated trailing fence is ignored.
+pub fn fenced_blocks(content: &str) -> Vec<(String, String)> {
+    let mut blocks = Vec::new();
+    let mut search_from = 0;
+    while let Some(fence_start) = content[search_from..].find("```") {
+        let after_backticks = search_from + fence_start + 3;
+        // Extract language from the opening fence line
+        let line_end = content[after_backticks..]
+            .find('\n')
+            .map(|i| after_backticks + i)
+            .unwrap_or(content.len());
+        let lang = content[after_backticks..line_end].trim().to_string();
+        let code_start = if line_end < content.len() { line_end + 1 } else { line_end };
+        // Find closing fence
+        let Some(close_pos) = content[code_start..].find("```") else { break };
+        let abs_close = code_start + close_pos;
+        // Strip trailing newline from code content
+        let code = content[code_start..abs_close].trim_end_matches('\n').to_string();
+        blocks.push((lang, code));
+        // Skip past the closing fence
+        search_from = abs_close + 3;
+    }
+    blocks
+}
+
+/// The opening line of a code fence left open at the end of `text`, e.g.
+/// "```rust", if the text stops inside a code block.
+fn open_fence(text: &str) -> Option<&str> {
+    let mut open = None;
+    for line in text.lines().map(str::trim) {
+        if line.starts_with("```") {
+            open = if open.is_some() { None } else { Some(line) };
+        }
+    }
+    open
+}
+
+/// What to ask a provider that can't continue a prefilled reply.
+fn continuation_prompt(partial: &str) -> String {
+    let mut prompt = String::from(
+        "Your previous reply was cut off. Continue exactly where it stopped, \
+         without repeating anything and without any preamble.",
+    );
+    if let Some(fence) = open_fence(partial) {
+        prompt.push_str(&format!(
+            " It stopped inside a {fence} code block: continue the code directly, without opening a new block."
+        ));
+    }
+    prompt
+}
+
+/// The continuation of `partial` with what models tend to add at the seam
+/// removed: a repeat of the line it stopped on, or a fresh fence opener when
+/// it stopped inside a code block.
+fn stitch_continuation(partial: &str, continuation: &str) -> String {
+    let body = continuation.trim_start_matches('\n');
+    let first_line = body.lines().next().unwrap_or("").trim();
+    // A bare ``` would close the block, so only a tagged opener is dropped
+    let reopened = open_fence(partial).is_some_and(|fence| fence.len() > 3 && first_line == fence);
+    let repeated = !first_line.is_empty() && partial.lines().last().is_some_and(|l| l.trim() == first_line);
+    if reopened || repeated {
+        let rest = body.split_once('\n').map_or("", |(_, after)| after);
+        return format!("\n{rest}");
+    }
+    continuation.to_string()
+}
+
+/// Assemble a recipe's message: the prompt, any extra text typed after
+/// `/recipe <name>`, then one fenced block per (heading, lang, body) attachment.
+fn recipe_message(prompt: &str, extra: &str, attachments: &[(String, String, String)]) -> String {
+    let mut text = prompt.trim().to_string();
+    if !extra.trim().is_empty() {
+        if !text.is_empty() {
+            text.push_str("\n\n");
+        }
+        text.push_str(extra.trim());
+    }
+    for (heading, lang, body) in attachments {
+        if !text.is_empty() {
+            text.push_str("\n\n");
+        }
+        text.push_str(&format!("{heading}:\n```{lang}\n{}\n```", body.trim_end()));
+    }
+    text
+}
+
+/// Run a recipe's `commands` in turn under `sh -c`, each killed after
+/// `timeout`, and return one (heading, lang, output) attachment per command.
+async fn recipe_outputs(
+    commands: &[String],
+    env: &crate::config::ToolEnvConfig,
+    timeout: std::time::Duration,
+) -> Result<Vec<(String, String, String)>, String> {
+    let mut attachments = Vec::new();
+    for cmd in commands {
+        let mut command = std::process::Command::new("sh");
+        env.apply(&mut command);
+        let mut command = tokio::process::Command::from(command);
+        command.arg("-c").arg(cmd).stdin(std::process::Stdio::null()).kill_on_drop(true);
+        tools::detach_from_terminal(&mut command);
+        let output = match tokio::time::timeout(timeout, command.output()).await {
+            Ok(Ok(output)) => output,
+            Ok(Err(e)) => return Err(format!("failed to run `{cmd}`: {e}")),
+            Err(_) => return Err(format!("`{cmd}` timed out after {} seconds", timeout.as_secs())),
+        };
+        let mut text = String::from_utf8_lossy(&output.stdout).to_string();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !stderr.trim().is_empty() {
+            text.push_str(&format!("\nstderr: {stderr}"));
+        }
+        attachments.push((format!("Output of `{cmd}`"), String::new(), text.trim().to_string()));
+    }
+    Ok(attachments)
+}
+
+/// Whether `message` opens a turn: a user message that isn't carrying tool
+/// results back, so history can be cut before it without orphaning a
+/// `tool_result` from its `tool_use`.
+fn starts_turn(message: &Message) -> bool {
+    message.role == "user"
+        && match &message.content {
+            MessageContent::Text(_) => true,
+            MessageContent::Blocks(blocks) => !blocks.iter().any(|b| b["type"] == "tool_result"),
+        }
+}
+
+/// Drop whole turns from the front of `messages` until at most half of the
+/// (serialized) context is left, always keeping the current turn. Returns the
+/// number of messages dropped and their estimated tokens, or `None` when
+/// there is nothing before the current turn to drop.
+fn trim_oldest_turns(messages: &mut Vec<Message>) -> Option<(usize, usize)> {
+    let sizes: Vec<usize> = messages
+        .iter()
+        .map(|m| serde_json::to_string(m).map_or(0, |s| s.len()))
+        .collect();
+    let total: usize = sizes.iter().sum();
+    let current = messages.iter().rposition(starts_turn)?;
+
+    let mut cut = 0;
+    let mut dropped = 0;
+    for (i, message) in messages.iter().enumerate().take(current + 1).skip(1) {
+        if starts_turn(message) {
+            cut = i;
+            dropped = sizes[..i].iter().sum();
+            if total - dropped <= total / 2 {
+                break;
+            }
+        }
+    }
+    if cut == 0 {
+        return None;
+    }
+    messages.drain(..cut);
+    Some((cut, dropped / 4))
+}
+
+/// Readable text for one API message, with tool blocks summarized.
+fn preview_text(content: &MessageContent) -> String {
+    match content {
+        MessageContent::Text(text) => text.clone(),
+        MessageContent::Blocks(blocks) => blocks
+            .iter()
+            .map(|b| match b["type"].as_str() {
+                Some("text") => b["text"].as_str().unwrap_or("").to_string(),
+                Some("tool_use") => format!("⚙ tool_use {} {}", b["name"].as_str().unwrap_or("?"), b["input"]),
+                Some("document") => format!("📎 {}", b["title"].as_str().unwrap_or("document")),
+                Some("tool_result") => {
+                    let body = match &b["content"] {
+                        Value::String(s) => s.clone(),
+                        other => other.to_string(),
+                    };
+                    format!("↩ tool_result {}", body)
+                }
+                Some(other) => format!("[{other}]"),
+                None => b.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+/// Settings for `/code` mode.
+#[derive(Debug, Clone, Default)]
+pub struct CodeMode {
+    /// Also write each snippet here (`/code file <path>`)
+    pub output_file: Option<std::path::PathBuf>,
+}
+
+/// Smallest extended thinking budget the Anthropic API accepts.
+const MIN_THINKING_BUDGET: u32 = 1024;
+
+/// How long (in ticks) a flash cue highlights the status bar.
+const FLASH_TICKS: u64 = 3;
+
+/// How often (in ticks) watched files are checked for changes.
+const FILE_WATCH_TICKS: u64 = 8;
+
+/// Files the model has seen (attached with `/file` or touched by a tool),
+/// with the modification time and content hash of the version it saw.
+#[derive(Debug, Default)]
+pub struct WatchedFiles {
+    files: BTreeMap<std::path::PathBuf, (Option<std::time::SystemTime>, Option<String>)>,
+    /// Files modified on disk since the model last saw them
+    pub changed: Vec<std::path::PathBuf>,
+}
+
+impl WatchedFiles {
+    fn mtime(path: &std::path::Path) -> Option<std::time::SystemTime> {
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    fn hash(path: &std::path::Path) -> Option<String> {
+        use sha2::{Digest, Sha256};
+        std::fs::read(path).ok().map(|bytes| hex::encode(Sha256::digest(bytes)))
+    }
+
+    fn version(path: &std::path::Path) -> (Option<std::time::SystemTime>, Option<String>) {
+        (Self::mtime(path), Self::hash(path))
+    }
+
+    /// Start watching `path`, or mark its current version as seen.
+    pub fn watch(&mut self, path: &std::path::Path) {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.changed.retain(|p| *p != path);
+        self.files.insert(path.clone(), Self::version(&path));
+    }
+
+    /// True if `path` is watched and was modified after the model last saw
+    /// it. The content is compared too, since a write within the
+    /// filesystem's timestamp granularity (or a restored mtime) leaves the
+    /// mtime as it was.
+    pub fn is_stale(&self, path: &std::path::Path) -> bool {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.files.get(&path).is_some_and(|(mtime, hash)| {
+            Self::mtime(&path) != *mtime || Self::hash(&path) != *hash
+        })
+    }
+
+    /// Check watched files, returning true if any changed since the last poll.
+    /// Only mtimes are compared, to keep polling cheap.
+    pub fn poll(&mut self) -> bool {
+        let mut found = false;
+        for (path, (seen, _)) in &self.files {
+            if Self::mtime(path) != *seen && !self.changed.contains(path) {
+                self.changed.push(path.clone());
+                found = true;
+            }
+        }
+        found
+    }
+
+    /// Accept the changed files' current versions as seen.
+    pub fn take_changed(&mut self) -> Vec<std::path::PathBuf> {
+        let changed = std::mem::take(&mut self.changed);
+        for path in &changed {
+            self.files.insert(path.clone(), Self::version(path));
+        }
+        changed
+    }
+}
+
+/// Open `/tee` target that streamed assistant output is appended to.
+#[derive(Debug)]
+pub struct Tee {
+    pub path: std::path::PathBuf,
+    file: std::fs::File,
+}
+
+/// Settings for `/json` mode.
+#[derive(Debug, Clone, Default)]
+pub struct JsonMode {
+    /// Schema loaded with `/json schema <path>`
+    pub schema: Option<Value>,
+    pub schema_path: Option<String>,
+    /// Nesting depth beyond which replies are folded; 0 shows everything
+    pub fold_depth: usize,
+}
+
+/// Characters revealed per tick when simulating streaming.
+const REPLAY_CHARS_PER_TICK: usize = 48;
+
 /// Represents a tool invocation displayed in the chat.
 #[derive(Debug, Clone)]
 pub struct ToolInvocation {
@@ -116,8 +764,17 @@ pub struct ToolInvocation {
     pub tool_args: String,
     pub result: Option<ToolResult>,
     pub collapsed: bool,
+    /// Wall-clock execution time; `None` for denied or pending calls.
+    pub duration: Option<std::time::Duration>,
+    /// The result came from the tool cache rather than a fresh run.
+    pub cached: bool,
+    /// Output streamed so far while the call runs (`execute` only).
+    pub live_output: String,
 }
 
+/// Most streamed output kept on a running tool row; older text is dropped.
+const LIVE_OUTPUT_MAX_BYTES: usize = 16 * 1024;
+
 pub struct App {
     pub config: Config,
     pub input: String,
@@ -125,7 +782,8 @@ pub struct App {
     pub cursor_pos: usize,
     pub messages: Vec<ChatMessage>,
     pub scroll_offset: usize,
-    pub streaming: bool,
+    /// Request and tool lifecycle of the current turn
+    pub session: SessionState,
     pub stream_buffer: String,
     pub command_input: String,
     pub overlay: Overlay,
@@ -133,6 +791,85 @@ pub struct App {
     pub status_message: Option<String>,
     pub conversation: Conversation,
     pub history_list: Vec<Conversation>,
+    /// Recent conversations listed on the welcome screen
+    pub welcome_recent: Vec<Conversation>,
+    pub welcome_selected: usize,
+    /// Launches so far, to show a different welcome tip each time
+    pub launch_count: usize,
+    /// Model ids shown in the Models overlay
+    pub model_list: Vec<String>,
+    /// Restrict the history overlay to conversations from the current project
+    pub history_project_only: bool,
+    /// Show trashed conversations in the History overlay (`/history trash`)
+    pub history_trash: bool,
+    /// Read-only transcript viewer (`pro view`): nothing is sent or saved
+    pub viewer: bool,
+    /// Conversation ids marked in the History overlay (Space)
+    pub history_marked: BTreeSet<String>,
+    /// Anchor row of a visual range in the History overlay (`v`)
+    pub history_visual: Option<usize>,
+    /// Bulk action waiting for y/n in the History overlay
+    pub history_confirm: Option<HistoryBulkAction>,
+    /// Bookmarked messages across all conversations, newest first
+    pub bookmarks: Vec<Bookmark>,
+    /// Message last jumped to via search or /goto; target of `b`
+    pub focused_message: Option<usize>,
+    /// Assistant reply discarded by the last retry, kept for /diffresp
+    pub discarded_response: Option<String>,
+    /// Word-level diff shown in the ResponseDiff overlay
+    pub response_diff: Vec<(similar::ChangeTag, String)>,
+    /// Pre-edit snapshots of files written by tools this session
+    pub session_changes: review::SessionChanges,
+    /// Changed files shown in the Review overlay; `overlay_scroll` selects one
+    pub review_files: Vec<review::FileReview>,
+    /// Selected hunk of the selected file in the Review overlay
+    pub review_hunk: usize,
+    /// Waiting for y/n before reverting the selected file
+    pub review_revert_pending: bool,
+    /// Active conversation replay, if any
+    pub replay: Option<ReplayState>,
+    /// Prefix key (e.g. `g`) pressed in Normal mode, awaiting its second key
+    pub pending_prefix: Option<char>,
+    /// Progress through `pro tutor`, when running it
+    pub tutor: Option<crate::tutor::Tutor>,
+    /// Analytics shown in the Dashboard overlay, computed on open
+    pub dashboard: usage::Dashboard,
+    /// Advisory lock on the current conversation's file
+    conversation_lock: Option<ConversationLock>,
+    /// (conversation id, PID) when another instance holds that conversation
+    locked_by: Option<(String, u32)>,
+    /// Messages whose request failed, oldest first (`R` resends the last)
+    pub failed_sends: Vec<FailedSend>,
+    /// Errors shown inline in the transcript for this session
+    pub error_cards: Vec<ErrorCard>,
+    /// PDFs added with `/attach`, sent with the next message
+    pub pending_attachments: Vec<Attachment>,
+    /// Prompts added with `/queue`, sent one by one as responses complete
+    pub prompt_queue: VecDeque<String>,
+    /// Draft in the system prompt editor overlay
+    pub prompt_editor: PromptEditor,
+    /// Project file listing added by `/context`, appended to the system prompt
+    pub project_context: Option<String>,
+    /// `.prochat/settings.toml` of the project in the working directory
+    pub project_settings: Option<ProjectSettings>,
+    /// The user trusted that file with `/trust`; until then it can only
+    /// make tools stricter
+    pub project_trusted: bool,
+    /// "Always allow"/"Always deny" and /tools choices made this session,
+    /// kept when permissions are rebuilt for another project
+    session_permissions: BTreeMap<String, ToolPermission>,
+    /// Snapshot shown in the RequestPreview overlay
+    pub request_preview: RequestPreview,
+    /// Request strict JSON replies (`/json on`)
+    pub json_mode: Option<JsonMode>,
+    /// Request a single code block per reply (`/code on`)
+    pub code_mode: Option<CodeMode>,
+    /// Results of the last `/recall`, shown in the Recall overlay
+    pub recall_hits: Vec<rag::RecallHit>,
+    /// File receiving assistant output as it streams (`/tee <path>`)
+    pub tee: Option<Tee>,
+    /// Files in context, checked for changes on disk
+    pub watched_files: WatchedFiles,
     pub input_history: Vec<String>,
     pub input_history_idx: Option<usize>,
     pub should_quit: bool,
@@ -141,13 +878,25 @@ pub struct App {
     pub tool_executor: ToolExecutor,
     pub pending_tool_calls: Vec<ToolCall>,
     pub pending_tool_confirm_idx: usize,
+    /// The prompt shown in the ToolInput overlay
+    pub tool_input: Option<ToolInputPrompt>,
+    /// The pack recipe shown in the RecipeConfirm overlay
+    pub recipe_confirm: Option<RecipeConfirm>,
+    /// How often ask-first tools pause for confirmation (`/autonomy`)
+    pub autonomy: Autonomy,
+    /// Ask-first calls run without asking since the last confirmation
+    unconfirmed_tool_calls: usize,
     pub tool_invocations: Vec<ToolInvocation>,
+    /// Per-tool execution telemetry for this session, keyed by tool name
+    pub tool_stats: BTreeMap<String, ToolStats>,
+    /// Reused results of unchanged `read_file`/`list_files` calls
+    pub tool_cache: ToolCache,
     /// Full API message history (includes tool_use and tool_result blocks)
     pub api_messages: Vec<Message>,
     /// Whether tools are enabled for this session
     pub tools_enabled: bool,
     /// Shared HTTP client for connection pooling across API calls.
-    api_client: ApiClient,
+    api_client: Arc<ApiClient>,
     /// Whether we're in visual selection mode (for code block picking)
     pub visual_mode: bool,
     /// Extracted code blocks: (message_index, language, content)
@@ -160,10 +909,52 @@ pub struct App {
     pub search_match_idx: usize,
     /// Tick counter for animations
     pub tick_count: u64,
+    /// When a key was last pressed, for `idle_lock_minutes`
+    last_activity: std::time::Instant,
+    /// The screen is blanked until the next key press
+    pub locked: bool,
     /// When the current stream started
     pub stream_start_time: Option<std::time::Instant>,
+    /// When the in-flight response was last persisted to disk
+    last_autosave: Option<std::time::Instant>,
+    /// When the conversation was last written to disk
+    last_save: Option<std::time::Instant>,
+    /// A save was requested but deferred by the debounce window
+    save_pending: bool,
     /// Duration of the last completed response
     pub last_response_time: Option<std::time::Duration>,
+    /// Tokens the provider reported for this conversation's responses
+    pub token_usage: TokenUsage,
+    /// The same, split by the model that produced each response
+    pub model_usage: BTreeMap<String, TokenUsage>,
+    /// Counts reported so far for the response in flight
+    response_usage: Option<TokenUsage>,
+    /// Counts for the last finished response
+    last_usage: Option<TokenUsage>,
+    /// Rate limits the provider reported with its last response
+    pub rate_limits: Option<api::RateLimits>,
+    /// Configured MCP servers that finished connecting: tool count or error
+    pub mcp_status: BTreeMap<String, Result<usize, String>>,
+    /// The request in flight is already a retry with trimmed context
+    context_trim_retried: bool,
+    /// Parameters (provider, model, system prompt, tools, ...) of the last
+    /// user turn's request. Follow-ups with tool results reuse them, so a
+    /// `/model` or `:set` during a tool loop waits for the next turn.
+    chain_request: Option<api::Request>,
+    /// Stop reason reported for the response in flight
+    response_stop_reason: Option<String>,
+    /// The response in flight continues the last assistant message, which
+    /// was this many bytes long
+    continuing: Option<usize>,
+    /// Expand extended thinking above answers instead of a one-line summary
+    pub show_thinking: bool,
+    /// Tick until which a flash cue highlights the status bar
+    flash_until: Option<u64>,
+    /// The request task whose reply is streaming, aborted on cancel
+    stream_task: Option<tokio::task::AbortHandle>,
+    /// Whether the terminal has focus; `None` until it reports focus
+    /// changes (not every terminal does, and tmux needs `focus-events on`)
+    pub focused: Option<bool>,
     /// Whether to auto-scroll to bottom on new content
     pub auto_scroll: bool,
     /// Undo stack for input field: (input_text, cursor_pos)
@@ -171,36 +962,63 @@ pub struct App {
     /// Redo stack for input field: (input_text, cursor_pos)
     pub redo_stack: Vec<(String, usize)>,
     pub setup_state: SetupState,
+    /// Recipe whose commands are running: (name, recipe, extra text)
+    running_recipe: Option<(String, Recipe, String)>,
     event_tx: Option<mpsc::UnboundedSender<Event>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct ChatMessage {
+    /// Stable ID shared with the saved `SavedMessage`
+    pub id: String,
     pub role: String,
     pub content: String,
     /// When this message was created
     pub timestamp: chrono::DateTime<chrono::Utc>,
     /// Optional tool invocations associated with this message
     pub tool_invocations: Vec<ToolInvocation>,
+    /// Private annotation; shown in the UI but never sent to the API
+    pub note: Option<String>,
+    /// Why the model stopped (`max_tokens`, `stop_sequence`, `tool_use`),
+    /// when the provider said and it wasn't a normal end of turn
+    pub stop_reason: Option<String>,
+    /// Extended thinking that preceded the answer
+    pub thinking: Option<String>,
+    /// Model, tokens and latency of the response, for the footer
+    pub meta: Option<ResponseMeta>,
+    /// Labels of the PDFs sent with a user message
+    pub attachments: Vec<String>,
 }
 
 impl App {
     pub fn new(config: Config) -> Self {
+        // A bad proxy or CA setting shouldn't stop the app from starting;
+        // fall back to default settings and say why.
+        let (api_client, network_error) = match ApiClient::new(&config.network) {
+            Ok(client) => (client, None),
+            Err(e) => (
+                ApiClient::new(&NetworkConfig::default()).expect("default network config"),
+                Some(format!("Network config ignored: {e}")),
+            ),
+        };
         let neovim = if config.neovim.auto_connect {
             config.neovim.socket_path.as_deref()
-                .map(|s| NeovimClient::new(s))
+                .map(NeovimClient::new)
                 .or_else(|| NeovimClient::discover().map(|s| NeovimClient::new(&s)))
         } else {
             None
         };
 
+        // Permissions and the sandbox are set by load_project_settings below
         let mut tool_executor = ToolExecutor::new();
-        // Auto-allow read-only tools
-        tool_executor.set_permission("read_file", ToolPermission::AutoAllow);
-        tool_executor.set_permission("list_files", ToolPermission::AutoAllow);
-        tool_executor.set_permission("search_files", ToolPermission::AutoAllow);
+        tool_executor.set_env(config.tool_env.clone());
+        tool_executor.set_github(config.github.clone());
+        tool_executor.set_tracker(config.tracker.clone());
+        tool_executor.set_embeddings(config.embeddings());
+        tool_executor.set_validate(config.validate.clone());
 
         let last_conversation_id = config.last_conversation_id.clone();
+        let autonomy = config.autonomy;
 
         let mut app = Self {
             config,
@@ -209,14 +1027,53 @@ impl App {
             cursor_pos: 0,
             messages: Vec::new(),
             scroll_offset: 0,
-            streaming: false,
+            session: SessionState::Idle,
             stream_buffer: String::new(),
             command_input: String::new(),
             overlay: Overlay::None,
             overlay_scroll: 0,
-            status_message: None,
+            status_message: network_error,
             conversation: Conversation::new(),
             history_list: Vec::new(),
+            welcome_recent: Vec::new(),
+            welcome_selected: 0,
+            launch_count: 0,
+            model_list: Vec::new(),
+            history_project_only: false,
+            history_trash: false,
+            viewer: false,
+            history_marked: BTreeSet::new(),
+            history_visual: None,
+            history_confirm: None,
+            bookmarks: Bookmark::load_all().unwrap_or_default(),
+            focused_message: None,
+            discarded_response: None,
+            response_diff: Vec::new(),
+            session_changes: review::SessionChanges::default(),
+            review_files: Vec::new(),
+            review_hunk: 0,
+            review_revert_pending: false,
+            replay: None,
+            pending_prefix: None,
+            tutor: None,
+            dashboard: usage::Dashboard::default(),
+            conversation_lock: None,
+            locked_by: None,
+            failed_sends: Vec::new(),
+            error_cards: Vec::new(),
+            pending_attachments: Vec::new(),
+            prompt_queue: VecDeque::new(),
+            prompt_editor: PromptEditor::default(),
+            project_context: None,
+            project_settings: None,
+            project_trusted: false,
+            session_permissions: BTreeMap::new(),
+            request_preview: RequestPreview::default(),
+            recall_hits: Vec::new(),
+            tee: None,
+            watched_files: WatchedFiles::default(),
+            json_mode: None,
+            code_mode: None,
             input_history: Vec::new(),
             input_history_idx: None,
             should_quit: false,
@@ -225,9 +1082,15 @@ impl App {
             tool_executor,
             pending_tool_calls: Vec::new(),
             pending_tool_confirm_idx: 0,
+            tool_input: None,
+            recipe_confirm: None,
+            autonomy,
+            unconfirmed_tool_calls: 0,
             tool_invocations: Vec::new(),
+            tool_stats: BTreeMap::new(),
+            tool_cache: ToolCache::default(),
             api_messages: Vec::new(),
-            api_client: ApiClient::new(),
+            api_client: Arc::new(api_client),
             tools_enabled: true,
             visual_mode: false,
             code_blocks: Vec::new(),
@@ -235,19 +1098,53 @@ impl App {
             search_matches: Vec::new(),
             search_match_idx: 0,
             tick_count: 0,
+            last_activity: std::time::Instant::now(),
+            locked: false,
             stream_start_time: None,
+            last_autosave: None,
+            last_save: None,
+            save_pending: false,
             last_response_time: None,
+            token_usage: TokenUsage::default(),
+            model_usage: BTreeMap::new(),
+            response_usage: None,
+            last_usage: None,
+            rate_limits: None,
+            mcp_status: BTreeMap::new(),
+            context_trim_retried: false,
+            chain_request: None,
+            response_stop_reason: None,
+            continuing: None,
+            show_thinking: false,
+            flash_until: None,
+            stream_task: None,
+            focused: None,
             auto_scroll: true,
             undo_stack: Vec::new(),
             redo_stack: Vec::new(),
             setup_state: SetupState::new(),
+            running_recipe: None,
             event_tx: None,
         };
+        if let Some(warning) = app.load_project_settings() {
+            app.status_message = Some(warning);
+        }
 
-        // Auto-restore last conversation if configured
-        if let Some(ref id) = last_conversation_id {
-            if app.load_conversation(id).is_ok() {
-                app.status_message = Some("Restored previous session".into());
+        match app.config.startup {
+            StartupMode::Restore => {
+                if let Some(ref id) = last_conversation_id
+                    && app.load_conversation(id).is_ok()
+                {
+                    app.status_message = Some("Restored previous session".into());
+                }
+            }
+            StartupMode::New => {}
+            StartupMode::Picker => {
+                app.load_history_list();
+                app.history_list.truncate(PICKER_RECENT);
+                if !app.history_list.is_empty() {
+                    app.overlay = Overlay::Picker;
+                }
             }
         }
 
@@ -267,12 +1164,22 @@ impl App {
         total_chars / 4
     }
 
+    /// Tokens the conversation occupies in the context window: what the last
+    /// response reported, else the estimate.
+    pub fn context_tokens(&self) -> usize {
+        self.last_usage
+            .map(|u| (u.input_tokens + u.output_tokens) as usize)
+            .unwrap_or_else(|| self.estimate_tokens())
+    }
+
     pub fn set_model(&mut self, model: &str) {
         self.config.model = model.to_string();
     }
 
-    pub fn set_provider(&mut self, provider: &str) {
+    pub fn set_provider(&mut self, provider: &str) -> Result<(), String> {
+        self.config.check_provider(provider)?;
         self.config.provider = provider.to_string();
+        Ok(())
     }
 
     pub fn set_nvim_socket(&mut self, socket: &str) {
@@ -290,27 +1197,170 @@ impl App {
     }
 
     pub fn load_conversation(&mut self, id: &str) -> anyhow::Result<()> {
-        let conv = Conversation::load(id)?;
+        let conv = Conversation::load(&self.config.history_dir(), id)?;
+        self.flush_conversation();
+        self.tool_executor.close_edit_batch();
+        if self.overlay == Overlay::Picker {
+            self.overlay = Overlay::None;
+        }
+        self.show_conversation(conv);
+        self.scroll_to_bottom();
+        if !self.ensure_conversation_lock()
+            && let Some(pid) = self.read_only_pid()
+        {
+            self.status_message = Some(format!("Opened read-only (locked by PID {pid})"));
+        }
+        Ok(())
+    }
+
+    /// Open `conv` in the read-only viewer: scrolling, search, yank and
+    /// /export work, but nothing is sent to a provider or written back.
+    pub fn open_viewer(&mut self, conv: Conversation) {
+        self.viewer = true;
+        self.conversation_lock = None;
+        self.overlay = Overlay::None;
+        self.status_message = Some(format!(
+            "Viewing \"{}\" ({} messages) read-only. / search  y yank  /export  q quit",
+            conv.title,
+            conv.messages.len()
+        ));
+        self.show_conversation(conv);
+        self.scroll_to_top();
+    }
+
+    fn show_conversation(&mut self, conv: Conversation) {
+        self.focused_message = None;
         self.messages = conv.messages.iter().map(|m| ChatMessage {
+            id: m.id.clone(),
             role: m.role.clone(),
             content: m.content.clone(),
             timestamp: m.timestamp,
             tool_invocations: Vec::new(),
+            note: m.note.clone(),
+            stop_reason: None,
+            thinking: None,
+            meta: m.meta.clone(),
+            attachments: m.attachments.clone(),
         }).collect();
         self.conversation = conv;
-        self.scroll_to_bottom();
-        Ok(())
+        self.error_cards.clear();
+        self.token_usage = TokenUsage::default();
+        self.model_usage.clear();
+        self.last_usage = None;
+    }
+
+    /// Take the lock for the current conversation if we don't hold it yet.
+    /// Returns false when another live `pro` instance holds it.
+    fn ensure_conversation_lock(&mut self) -> bool {
+        if self.conversation_lock.as_ref().is_some_and(|l| l.id() == self.conversation.id) {
+            return true;
+        }
+        self.conversation_lock = None;
+        match ConversationLock::acquire(&self.config.history_dir(), &self.conversation.id) {
+            Ok(lock) => {
+                self.conversation_lock = Some(lock);
+                self.locked_by = None;
+                true
+            }
+            Err(pid) => {
+                self.locked_by = Some((self.conversation.id.clone(), pid));
+                false
+            }
+        }
+    }
+
+    /// PID of the other instance holding the current conversation, if it is
+    /// open read-only here.
+    pub fn read_only_pid(&self) -> Option<u32> {
+        self.locked_by.as_ref()
+            .filter(|(id, _)| *id == self.conversation.id)
+            .map(|(_, pid)| *pid)
+    }
+
+    /// Periodically write the partial response to disk while streaming, so a
+    /// crash loses at most `AUTOSAVE_INTERVAL` of output. The final save at
+    /// `ApiDone` overwrites the snapshot.
+    fn autosave_stream(&mut self) {
+        if !self.is_streaming() || self.stream_buffer.is_empty() {
+            return;
+        }
+        if self.last_autosave.is_some_and(|t| t.elapsed() < AUTOSAVE_INTERVAL) {
+            return;
+        }
+        self.last_autosave = Some(std::time::Instant::now());
+        if self.ensure_conversation_lock() {
+            let _ = self.stream_snapshot().save(&self.config.history_dir(), self.config.compress_history);
+        }
+    }
+
+    /// ID of the assistant message currently being streamed into.
+    fn streaming_message_id(&self) -> String {
+        match self.messages.last() {
+            Some(last) if last.role == "assistant" => last.id.clone(),
+            _ => history::new_message_id(),
+        }
+    }
+
+    /// The conversation as it would be saved if the current stream ended now.
+    fn stream_snapshot(&self) -> Conversation {
+        let mut snapshot = self.conversation.clone();
+        if !self.stream_buffer.is_empty() {
+            snapshot.set_message(&self.streaming_message_id(), "assistant", &self.stream_buffer);
+        }
+        snapshot
     }
 
     /// Save the current conversation and update the config to track it as the last session.
+    /// Writes are debounced: if the last one was under `SAVE_DEBOUNCE` ago the
+    /// save is deferred to `flush_conversation` on a later tick.
     fn save_and_track_conversation(&mut self) {
-        let _ = self.conversation.save();
-        self.config.last_conversation_id = Some(self.conversation.id.clone());
-        let _ = self.config.save();
+        self.save_pending = true;
+        if self.last_save.is_none_or(|t| t.elapsed() >= SAVE_DEBOUNCE) {
+            self.flush_conversation();
+        }
+    }
+
+    /// Write out a pending save immediately. Must run before the current
+    /// conversation is replaced or the app exits.
+    fn flush_conversation(&mut self) {
+        if !self.save_pending || self.viewer {
+            return;
+        }
+        self.save_pending = false;
+        self.last_save = Some(std::time::Instant::now());
+        if !self.ensure_conversation_lock() {
+            return;
+        }
+        let _ = self.conversation.save(&self.config.history_dir(), self.config.compress_history);
+        if self.config.last_conversation_id.as_deref() != Some(self.conversation.id.as_str()) {
+            self.config.last_conversation_id = Some(self.conversation.id.clone());
+            let _ = self.config.save();
+        }
+    }
+
+    /// After `idle_lock_minutes` without a key press, save the conversation
+    /// and blank the screen until the next key.
+    fn check_idle_lock(&mut self) {
+        let minutes = u64::from(self.config.idle_lock_minutes);
+        if minutes == 0 || self.locked || self.last_activity.elapsed().as_secs() < minutes * 60 {
+            return;
+        }
+        self.flush_conversation();
+        self.locked = true;
     }
 
     pub fn is_streaming(&self) -> bool {
-        self.streaming
+        self.session.is_streaming()
+    }
+
+    /// Move the turn to `next`. A step the lifecycle doesn't allow is a bug
+    /// elsewhere; it is reported rather than refused, so the UI can't get
+    /// stuck in a state nothing leaves.
+    fn set_session(&mut self, next: SessionState) {
+        if !self.session.can_become(next) {
+            self.status_message = Some(format!("Unexpected state change: {:?} -> {:?}", self.session, next));
+        }
+        self.session = next;
     }
 
     pub fn visible_height(&self) -> usize {
@@ -323,8 +1373,12 @@ impl App {
         mut events: EventHandler,
     ) -> anyhow::Result<()> {
         self.event_tx = Some(events.sender());
+        if !self.viewer {
+            mcp::start(&self.config.mcp, events.sender());
+        }
 
         loop {
+            crate::tutor::advance(self);
             terminal.draw(|f| {
                 self.terminal_height = f.area().height;
                 ui::draw(f, self);
@@ -332,9 +1386,30 @@ impl App {
 
             if let Some(event) = events.next().await {
                 match event {
+                    // Sent by a request that was cancelled before it got
+                    // aborted; the reply they belong to is gone
+                    Event::ApiChunk(_)
+                    | Event::ThinkingChunk(_)
+                    | Event::Usage(_)
+                    | Event::StopReason(_)
+                    | Event::ApiDone
+                    | Event::ApiError(_)
+                    | Event::ToolUseRequest(_)
+                        if !self.is_streaming() => {}
+                    Event::Key(_) if std::mem::take(&mut self.locked) => {
+                        // The key that unlocks the screen does nothing else
+                        self.last_activity = std::time::Instant::now();
+                    }
+                    Event::Mouse(_) if self.locked => {}
                     Event::Key(key) => {
+                        self.last_activity = std::time::Instant::now();
                         self.status_message = None;
 
+                        if self.overlay == Overlay::ToolInput {
+                            self.handle_tool_input_key(key);
+                            continue;
+                        }
+
                         // Handle tool confirmation overlay keys
                         if self.overlay == Overlay::ToolConfirm {
                             self.handle_tool_confirm_key(key).await;
@@ -347,11 +1422,17 @@ impl App {
                             continue;
                         }
 
+                        if self.replay.is_some() && self.overlay == Overlay::None {
+                            self.handle_replay_key(key);
+                            continue;
+                        }
+
                         match handle_key(self, key) {
                             KeyAction::Quit => {
                                 if !self.messages.is_empty() {
                                     self.save_and_track_conversation();
                                 }
+                                self.flush_conversation();
                                 return Ok(());
                             }
                             KeyAction::SendMessage => {
@@ -366,60 +1447,172 @@ impl App {
                             KeyAction::EditLastMessage => {
                                 self.edit_last_message();
                             }
+                            KeyAction::ResendFailed => {
+                                self.resend_failed();
+                            }
+                            KeyAction::ContinueResponse => {
+                                self.continue_response(true);
+                            }
                             _ => {}
                         }
                     }
+                    Event::ThinkingChunk(text) => {
+                        if let Some(last) = self.messages.last_mut()
+                            && last.role == "assistant"
+                        {
+                            last.thinking.get_or_insert_default().push_str(&text);
+                        }
+                        if self.auto_scroll {
+                            self.scroll_to_bottom();
+                        }
+                    }
                     Event::ApiChunk(text) => {
+                        if self.stream_buffer.is_empty() {
+                            self.cue(self.config.cues.stream_start);
+                        }
+                        self.tee_write(&text);
                         self.stream_buffer.push_str(&text);
-                        if let Some(last) = self.messages.last_mut() {
-                            if last.role == "assistant" {
-                                last.content = self.stream_buffer.clone();
-                            }
+                        if let Some(last) = self.messages.last_mut()
+                            && last.role == "assistant"
+                        {
+                            last.content = self.stream_buffer.clone();
                         }
                         if self.auto_scroll {
                             self.scroll_to_bottom();
                         }
                     }
-                    Event::ApiDone => {
-                        self.streaming = false;
+                    Event::Usage(usage) => {
+                        self.response_usage = Some(usage);
+                    }
+                    Event::StopReason(reason) => {
+                        self.response_stop_reason = Some(reason);
+                    }
+                    Event::RateLimits(limits) => self.update_rate_limits(limits),
+                    Event::McpConnected(server, result) => {
+                        self.status_message = Some(match &result {
+                            Ok(count) => format!("MCP {server}: {count} tools"),
+                            Err(e) => format!("MCP {server} failed: {e}"),
+                        });
+                        self.mcp_status.insert(server, result);
+                    }
+                    Event::ApiDone => {
+                        self.set_session(SessionState::Idle);
+                        self.context_trim_retried = false;
                         if let Some(start) = self.stream_start_time.take() {
                             self.last_response_time = Some(start.elapsed());
                         }
                         if !self.stream_buffer.is_empty() {
+                            self.tee_write("\n\n");
+                            self.record_response_usage();
+                            crate::bugreport::record_outcome(Ok(&self.stream_buffer));
+                            if let Some(start) = self.continuing.take() {
+                                self.stitch_continuation(start);
+                                // Replaced below by the stitched message
+                                self.api_messages.pop();
+                            }
+                            self.format_json_reply();
+                            self.format_code_reply();
                             // Keep api_messages in sync for streamed responses
                             self.api_messages.push(Message {
                                 role: "assistant".into(),
                                 content: MessageContent::Text(self.stream_buffer.clone()),
                             });
-                            self.conversation.add_message("assistant", &self.stream_buffer);
+                            let id = self.streaming_message_id();
+                            self.conversation.set_message(&id, "assistant", &self.stream_buffer);
+                            let meta = self.response_meta();
+                            self.conversation.set_meta(&id, meta.clone());
+                            if let Some(last) = self.messages.last_mut()
+                                && last.id == id
+                            {
+                                last.meta = Some(meta);
+                            }
                             self.save_and_track_conversation();
                         }
+                        self.continuing = None;
+                        self.take_stop_reason();
                         self.stream_buffer.clear();
+                        self.finish_response_usage();
                         // Ring terminal bell to notify user the response is complete
-                        if self.config.notify_on_complete {
+                        if self.config.notify_on_complete && self.focused != Some(true) {
                             eprint!("\x07");
                         }
+                        self.send_next_queued();
                     }
                     Event::ApiError(err) => {
-                        self.streaming = false;
+                        crate::bugreport::record_outcome(Err(&err));
+                        self.cue(self.config.cues.error);
+                        self.set_session(SessionState::Idle);
                         self.stream_start_time = None;
-                        self.stream_buffer.clear();
-                        if let Some(last) = self.messages.last() {
-                            if last.role == "assistant" && last.content.is_empty() {
+                        self.finish_response_usage();
+                        self.response_stop_reason = None;
+                        if !self.stream_buffer.is_empty() {
+                            // Failed mid-stream (e.g. stalled): keep what arrived
+                            self.tee_write("\n\n");
+                            self.keep_partial_response();
+                            self.stream_buffer.clear();
+                            self.push_error_card(&err);
+                            self.status_message = Some("Request failed, partial response kept (Ctrl+R to retry)".into());
+                        } else if !self.retry_with_trimmed_context(&err) {
+                            if let Some(last) = self.messages.last()
+                                && last.role == "assistant" && last.content.is_empty()
+                            {
                                 self.messages.pop();
                             }
+                            self.queue_failed_send(&err);
                         }
-                        self.status_message = Some(format!("Error: {err}"));
                     }
                     Event::ToolUseRequest(response_body) => {
-                        self.streaming = false;
+                        crate::bugreport::record_outcome(Ok(&response_body));
+                        self.context_trim_retried = false;
+                        self.continuing = None;
+                        self.take_stop_reason();
+                        self.finish_response_usage();
                         self.handle_tool_use_response(&response_body).await;
                     }
+                    // Only the call being waited for moves the loop on
+                    Event::ToolStarted(idx)
+                    | Event::ToolOutputChunk(idx, _)
+                    | Event::ToolInput(idx, ..)
+                    | Event::ToolFinished(idx, ..)
+                        if self.session != SessionState::RunningTools || idx != self.pending_tool_confirm_idx => {}
+                    Event::ToolStarted(idx) => self.show_tool_running(idx),
+                    Event::ToolOutputChunk(_, chunk) => self.append_tool_output(&chunk),
+                    Event::ToolInput(_, prompt, reply) => {
+                        self.tool_input = Some(ToolInputPrompt { prompt, answer: String::new(), reply });
+                        self.overlay = Overlay::ToolInput;
+                    }
+                    Event::ToolFinished(idx, result, elapsed) => {
+                        self.finish_tool_at_index(idx, result, elapsed, false);
+                        self.pending_tool_confirm_idx += 1;
+                        self.process_next_tool_call().await;
+                    }
+                    Event::RecipeOutput(name, outputs) => {
+                        if let Some((_, recipe, extra)) = self.running_recipe.take_if(|(running, ..)| *running == name) {
+                            self.finish_recipe(&name, &recipe, &extra, outputs);
+                        }
+                    }
+                    Event::Focus(focused) => {
+                        self.focused = Some(focused);
+                    }
                     Event::Resize(_, h) => {
                         self.terminal_height = h;
                     }
                     Event::Tick => {
                         self.tick_count = self.tick_count.wrapping_add(1);
+                        self.autosave_stream();
+                        self.replay_tick();
+                        self.check_idle_lock();
+                        if self.tick_count.is_multiple_of(FILE_WATCH_TICKS) && self.watched_files.poll() {
+                            self.status_message = Some(format!(
+                                "{} file(s) changed on disk since the model read them: /refresh to update it, /refresh dismiss to ignore",
+                                self.watched_files.changed.len()
+                            ));
+                        }
+                        if self.save_pending
+                            && self.last_save.is_none_or(|t| t.elapsed() >= SAVE_DEBOUNCE)
+                        {
+                            self.flush_conversation();
+                        }
                     }
                     Event::Mouse(mouse) => {
                         match mouse.kind {
@@ -432,6 +1625,7 @@ impl App {
             }
 
             if self.should_quit {
+                self.flush_conversation();
                 return Ok(());
             }
         }
@@ -443,6 +1637,7 @@ impl App {
             Ok(v) => v,
             Err(e) => {
                 self.status_message = Some(format!("Failed to parse tool response: {e}"));
+                self.set_session(SessionState::Idle);
                 return;
             }
         };
@@ -458,21 +1653,22 @@ impl App {
         // Parse tool calls
         let tool_calls = tools::parse_tool_calls(&response);
         if tool_calls.is_empty() {
+            self.set_session(SessionState::Idle);
             return;
         }
 
         // Save current stream text to the last assistant message
-        if !self.stream_buffer.is_empty() {
-            if let Some(last) = self.messages.last_mut() {
-                if last.role == "assistant" {
-                    last.content = self.stream_buffer.clone();
-                }
-            }
+        if !self.stream_buffer.is_empty()
+            && let Some(last) = self.messages.last_mut()
+            && last.role == "assistant"
+        {
+            last.content = self.stream_buffer.clone();
         }
         self.stream_buffer.clear();
 
         self.pending_tool_calls = tool_calls;
         self.pending_tool_confirm_idx = 0;
+        self.set_session(SessionState::RunningTools);
 
         // Process tool calls - auto-allow or prompt
         self.process_next_tool_call().await;
@@ -483,37 +1679,57 @@ impl App {
     async fn process_next_tool_call(&mut self) {
         while self.pending_tool_confirm_idx < self.pending_tool_calls.len() {
             let call = &self.pending_tool_calls[self.pending_tool_confirm_idx];
-            let perm = self.tool_executor.permission(call.tool.name());
+            let perm = self.tool_executor.permission(&call.tool.permission_key());
 
             match perm {
                 ToolPermission::AutoAllow => {
-                    self.execute_tool_at_index(self.pending_tool_confirm_idx).await;
+                    if self.execute_tool_at_index(self.pending_tool_confirm_idx).await {
+                        return;
+                    }
+                    self.pending_tool_confirm_idx += 1;
+                }
+                ToolPermission::AskFirst if !self.autonomy_requires_confirm(&call.tool) => {
+                    self.unconfirmed_tool_calls += 1;
+                    if self.execute_tool_at_index(self.pending_tool_confirm_idx).await {
+                        return;
+                    }
                     self.pending_tool_confirm_idx += 1;
                 }
                 ToolPermission::AskFirst => {
                     // Show confirmation overlay
+                    self.unconfirmed_tool_calls = 0;
+                    self.set_session(SessionState::AwaitingToolConfirm);
                     self.overlay = Overlay::ToolConfirm;
+                    self.cue(self.config.cues.confirm);
                     return;
                 }
                 ToolPermission::Deny => {
                     // Add a denied result
-                    let call = &self.pending_tool_calls[self.pending_tool_confirm_idx];
+                    let call = self.pending_tool_calls[self.pending_tool_confirm_idx].clone();
+                    let mut result = ToolResult::err("Tool execution denied by user");
+                    self.rollback_edit_batch(&call.tool, &mut result);
                     let invocation = ToolInvocation {
                         tool_name: call.tool.name().to_string(),
                         tool_args: format_tool_args(&call.tool),
-                        result: Some(ToolResult::err("Tool execution denied by user")),
+                        result: Some(result),
                         collapsed: false,
+                        duration: None,
+                        cached: false,
+                        live_output: String::new(),
                     };
                     self.tool_invocations.push(invocation);
-                    if let Some(last) = self.messages.last_mut() {
-                        if last.role == "assistant" {
-                            last.tool_invocations.push(ToolInvocation {
-                                tool_name: call.tool.name().to_string(),
-                                tool_args: format_tool_args(&call.tool),
-                                result: Some(ToolResult::err("Denied")),
-                                collapsed: false,
-                            });
-                        }
+                    if let Some(last) = self.messages.last_mut()
+                        && last.role == "assistant"
+                    {
+                        last.tool_invocations.push(ToolInvocation {
+                            tool_name: call.tool.name().to_string(),
+                            tool_args: format_tool_args(&call.tool),
+                            result: Some(ToolResult::err("Denied")),
+                            collapsed: false,
+                            duration: None,
+                            cached: false,
+                            live_output: String::new(),
+                        });
                     }
                     self.pending_tool_confirm_idx += 1;
                 }
@@ -524,63 +1740,459 @@ impl App {
         self.send_tool_results().await;
     }
 
-    async fn execute_tool_at_index(&mut self, idx: usize) {
-        let call = &self.pending_tool_calls[idx];
-        let result = self.tool_executor.execute(&call.tool).await;
+    /// Run the pending call at `idx`. Returns true when it was spawned off
+    /// the event loop, so the TUI keeps drawing while it runs and
+    /// `Event::ToolFinished` carries on with the remaining calls; false when
+    /// it finished here (cached, refused, or there is no event loop, as in
+    /// tests).
+    async fn execute_tool_at_index(&mut self, idx: usize) -> bool {
+        let call = self.pending_tool_calls[idx].clone();
+        if let Some(result) = self.tool_cache.get(&call.tool) {
+            self.finish_tool_at_index(idx, result, std::time::Duration::ZERO, true);
+            return false;
+        }
+        // Don't clobber edits made on disk after the model read the file
+        if call.tool.written_paths().iter().any(|path| self.watched_files.is_stale(std::path::Path::new(path))) {
+            let result = ToolResult::err(
+                "File changed since read: it was modified on disk after you last read it. \
+                 Re-read it with read_file before editing.",
+            );
+            self.finish_tool_at_index(idx, result, std::time::Duration::ZERO, false);
+            return false;
+        }
+        for path in call.tool.written_paths() {
+            self.session_changes.record(std::path::Path::new(&path));
+        }
+
+        let mut executor = self.tool_executor.clone();
+        let Some(tx) = self.event_tx.clone() else {
+            let started = std::time::Instant::now();
+            let result = executor.execute(&call.tool).await;
+            self.finish_tool_at_index(idx, result, started.elapsed(), false);
+            return false;
+        };
+        // Show command output as it arrives rather than only at the end
+        let chunk_tx = tx.clone();
+        executor.set_output_sink(Some(Arc::new(move |chunk: &str| {
+            let _ = chunk_tx.send(Event::ToolOutputChunk(idx, chunk.to_string()));
+        })));
+        // A prompt is put to the user, not the model
+        let input_tx = tx.clone();
+        executor.set_input_handler(Some(Arc::new(move |prompt| {
+            let (reply, answer) = tokio::sync::oneshot::channel();
+            let _ = input_tx.send(Event::ToolInput(idx, prompt, reply));
+            answer
+        })));
+        // Slow commands and big searches block; keep them off the UI thread
+        let runtime = tokio::runtime::Handle::current();
+        tokio::task::spawn_blocking(move || {
+            let _ = tx.send(Event::ToolStarted(idx));
+            let started = std::time::Instant::now();
+            let result = runtime.block_on(executor.execute(&call.tool));
+            let _ = tx.send(Event::ToolFinished(idx, result, started.elapsed()));
+        });
+        true
+    }
+
+    /// Show the call at `idx` as running until its result comes back.
+    fn show_tool_running(&mut self, idx: usize) {
+        let Some(call) = self.pending_tool_calls.get(idx) else { return };
+        let invocation = ToolInvocation {
+            tool_name: call.tool.name().to_string(),
+            tool_args: format_tool_args(&call.tool),
+            result: None,
+            collapsed: false,
+            duration: None,
+            cached: false,
+            live_output: String::new(),
+        };
+        if let Some(last) = self.messages.last_mut()
+            && last.role == "assistant"
+        {
+            last.tool_invocations.push(invocation.clone());
+        }
+        self.tool_invocations.push(invocation);
+        if self.auto_scroll {
+            self.scroll_to_bottom();
+        }
+    }
+
+    /// Add streamed output to the running row.
+    fn append_tool_output(&mut self, chunk: &str) {
+        let append = |invs: &mut [ToolInvocation]| {
+            let Some(inv) = invs.iter_mut().rev().find(|inv| inv.result.is_none()) else { return };
+            inv.live_output.push_str(chunk);
+            if inv.live_output.len() > LIVE_OUTPUT_MAX_BYTES {
+                let mut cut = inv.live_output.len() - LIVE_OUTPUT_MAX_BYTES;
+                while !inv.live_output.is_char_boundary(cut) {
+                    cut += 1;
+                }
+                inv.live_output.drain(..cut);
+            }
+        };
+        if let Some(last) = self.messages.last_mut()
+            && last.role == "assistant"
+        {
+            append(&mut last.tool_invocations);
+        }
+        append(&mut self.tool_invocations);
+        if self.auto_scroll {
+            self.scroll_to_bottom();
+        }
+    }
+
+    /// Record the result of the call at `idx` and fill in its row.
+    fn finish_tool_at_index(&mut self, idx: usize, mut result: ToolResult, elapsed: std::time::Duration, cached: bool) {
+        let call = self.pending_tool_calls[idx].clone();
+        if !cached {
+            self.tool_cache.insert(&call.tool, &result);
+        }
+        if !result.success {
+            self.rollback_edit_batch(&call.tool, &mut result);
+        }
+        if result.success {
+            if let tools::Tool::ReadFile { path } = &call.tool {
+                self.watched_files.watch(std::path::Path::new(path));
+            }
+            for path in call.tool.written_paths() {
+                self.watched_files.watch(std::path::Path::new(&path));
+            }
+        }
+
+        self.tool_stats
+            .entry(call.tool.name().to_string())
+            .or_default()
+            .record(&result, elapsed);
+        usage::record(UsageEvent::Tool {
+            name: call.tool.name().to_string(),
+            success: result.success,
+            duration_ms: elapsed.as_millis() as u64,
+        });
 
         let invocation = ToolInvocation {
             tool_name: call.tool.name().to_string(),
             tool_args: format_tool_args(&call.tool),
             result: Some(result.clone()),
             collapsed: result.output.lines().count() > 10,
+            duration: Some(elapsed),
+            cached,
+            live_output: String::new(),
         };
 
-        // Add to the current assistant message's tool invocations
-        if let Some(last) = self.messages.last_mut() {
-            if last.role == "assistant" {
-                last.tool_invocations.push(invocation.clone());
+        // Add to the current assistant message's tool invocations, in place
+        // of the running row if there is one
+        let running = |invs: &[ToolInvocation]| {
+            invs.iter().rposition(|inv| inv.result.is_none() && inv.tool_name == invocation.tool_name)
+        };
+        if let Some(last) = self.messages.last_mut()
+            && last.role == "assistant"
+        {
+            match running(&last.tool_invocations) {
+                Some(i) => last.tool_invocations[i] = invocation.clone(),
+                None => last.tool_invocations.push(invocation.clone()),
             }
         }
-        self.tool_invocations.push(invocation);
+        match running(&self.tool_invocations) {
+            Some(i) => self.tool_invocations[i] = invocation,
+            None => self.tool_invocations.push(invocation),
+        }
         if self.auto_scroll {
             self.scroll_to_bottom();
         }
     }
 
+    /// Whether an ask-first call has to wait for the user at the current
+    /// autonomy level.
+    fn autonomy_requires_confirm(&self, tool: &tools::Tool) -> bool {
+        match self.autonomy {
+            Autonomy::Low => true,
+            _ if tool.is_dangerous() => true,
+            Autonomy::Medium => self.unconfirmed_tool_calls + 1 >= self.config.autonomy_interval,
+            Autonomy::High => false,
+        }
+    }
+
+    /// `/autonomy [low|medium|high]`: show or set how often tools pause for
+    /// confirmation, remembering the choice for the current project.
+    fn set_autonomy(&mut self, arg: &str) {
+        if arg.is_empty() {
+            self.status_message = Some(format!("Autonomy: {} ({})", self.autonomy.as_str(), self.autonomy_summary()));
+            return;
+        }
+        let Some(level) = Autonomy::parse(arg) else {
+            self.status_message = Some(format!("Unknown autonomy level: {arg} (low, medium, high)"));
+            return;
+        };
+        self.autonomy = level;
+        self.unconfirmed_tool_calls = 0;
+        let saved = match history::current_project() {
+            Some(project) => match level.save_for_project(&project) {
+                Ok(()) => " for this project".to_string(),
+                Err(e) => format!("; not saved: {e}"),
+            },
+            None => String::new(),
+        };
+        self.status_message = Some(format!("Autonomy {}{saved}: {}", level.as_str(), self.autonomy_summary()));
+    }
+
+    fn autonomy_summary(&self) -> String {
+        match self.autonomy {
+            Autonomy::Low => "asks before every tool call".into(),
+            Autonomy::Medium => format!(
+                "asks every {} tool calls and before dangerous ones",
+                self.config.autonomy_interval
+            ),
+            Autonomy::High => "asks only before dangerous tool calls".into(),
+        }
+    }
+
+    /// Read `.prochat/settings.toml` for the project in the working directory
+    /// and rebuild permissions, sandbox and autonomy from it. Runs at startup
+    /// and after `/cd`; returns a warning if the file could not be used.
+    pub fn load_project_settings(&mut self) -> Option<String> {
+        let mut warning = None;
+        let project = history::current_project();
+        self.project_settings = match project.as_deref().map(|p| ProjectSettings::load(p.as_ref())) {
+            Some(Ok(settings)) => settings,
+            Some(Err(e)) => {
+                warning = Some(format!("Project settings ignored: {e}"));
+                None
+            }
+            None => None,
+        };
+        let trusted_hash = project.as_deref().and_then(ProjectSettings::trusted_hash);
+        self.project_trusted = self.project_settings.as_ref()
+            .zip(trusted_hash.as_ref())
+            .is_some_and(|(settings, hash)| settings.hash == *hash);
+        if !self.project_trusted && self.project_settings.as_ref().is_some_and(ProjectSettings::needs_trust) {
+            warning = Some(match trusted_hash {
+                Some(_) => "This project's .prochat/settings.toml changed since you trusted it; only stricter settings apply until you /trust it again".into(),
+                None => "This project's .prochat/settings.toml loosens tool settings; only stricter ones apply until you /trust it".into(),
+            });
+        }
+        self.apply_project_settings();
+        warning
+    }
+
+    /// `/trust`: apply all of the current project's settings file, now and
+    /// whenever the project is opened again with the file unchanged.
+    fn trust_project(&mut self) {
+        let Some((project, hash)) = history::current_project()
+            .zip(self.project_settings.as_ref().map(|s| s.hash.clone()))
+        else {
+            self.status_message = Some("No .prochat/settings.toml in this project".into());
+            return;
+        };
+        if let Err(e) = ProjectSettings::trust(&project, &hash) {
+            self.status_message = Some(format!("Cannot save trust: {e}"));
+            return;
+        }
+        self.project_trusted = true;
+        self.apply_project_settings();
+        self.status_message = Some(format!("Trusted {project}; its .prochat/settings.toml now applies in full"));
+    }
+
+    fn apply_project_settings(&mut self) {
+        let executor = &mut self.tool_executor;
+        executor.clear_permissions();
+        // Auto-allow read-only tools
+        for name in [
+            "read_file", "list_files", "search_files", "gh_issue_view", "gh_pr_diff", "fetch_ticket",
+            "git_status", "git_log", "git_show", "retrieve", "start_edit_batch", "commit_edit_batch",
+        ] {
+            executor.set_permission(name, ToolPermission::AutoAllow);
+        }
+        // The clipboard may hold secrets, so the model only gets it once the user opts in
+        executor.set_permission("read_clipboard", ToolPermission::Deny);
+        // Choices remembered from "Always allow", "Always deny" and /tools
+        for (name, permission) in &self.config.tools.permissions {
+            executor.set_permission(name, *permission);
+        }
+        // Then the project's own; an untrusted one may only tighten them
+        for (name, permission) in self.project_settings.iter().flat_map(|p| &p.tools.permissions) {
+            let permission = match self.project_trusted {
+                true => *permission,
+                false => permission.stricter(executor.permission(name)),
+            };
+            executor.set_permission(name, permission);
+        }
+        // And what the user chose this session, whatever the project says
+        for (name, permission) in &self.session_permissions {
+            executor.set_permission(name, *permission);
+        }
+        self.tool_executor.set_sandbox(tools::Sandbox::from_config(&self.sandbox_config()));
+        self.autonomy = history::current_project()
+            .and_then(|p| Autonomy::load_for_project(&p))
+            .unwrap_or(self.config.autonomy);
+    }
+
+    /// The sandbox settings in effect, with the project's applied.
+    fn sandbox_config(&self) -> SandboxConfig {
+        match &self.project_settings {
+            Some(project) => project.sandbox(&self.config.sandbox, self.project_trusted),
+            None => self.config.sandbox.clone(),
+        }
+    }
+
+    /// `/cd [dir]`: show or change the working directory, picking up the
+    /// new project's settings.
+    fn change_dir(&mut self, arg: &str) {
+        if !arg.is_empty() {
+            if self.session != SessionState::Idle {
+                self.status_message = Some("Wait for the response to finish before changing directory".into());
+                return;
+            }
+            let dir = match arg.strip_prefix("~/").or((arg == "~").then_some("")) {
+                Some(rest) => dirs::home_dir().unwrap_or_default().join(rest),
+                None => std::path::PathBuf::from(arg),
+            };
+            if let Err(e) = std::env::set_current_dir(&dir) {
+                self.status_message = Some(format!("Cannot change to {arg}: {e}"));
+                return;
+            }
+            if let Some(warning) = self.load_project_settings() {
+                self.status_message = Some(warning);
+                return;
+            }
+        }
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let project = match (&self.project_settings, self.project_trusted) {
+            (Some(_), true) => " (using .prochat/settings.toml)",
+            (Some(_), false) => " (using the stricter parts of .prochat/settings.toml)",
+            (None, _) => "",
+        };
+        self.status_message = Some(format!("Working directory: {}{project}", cwd.display()));
+    }
+
+    /// `/sandbox [on|off|add <dir>|remove <dir>]`: show or change where the
+    /// file tools may read and write. Changes last until `/save`.
+    fn sandbox_command(&mut self, arg: &str) {
+        let (action, dir) = arg.split_once(' ').map_or((arg, ""), |(a, d)| (a, d.trim()));
+        match (action, dir) {
+            ("", _) => {}
+            ("on", "") => self.config.sandbox.enabled = true,
+            ("off", "") => self.config.sandbox.enabled = false,
+            ("add", dir) if !dir.is_empty() => {
+                let path = match dir.strip_prefix("~/") {
+                    Some(rest) => dirs::home_dir().map(|home| home.join(rest)).unwrap_or_else(|| dir.into()),
+                    None => std::path::PathBuf::from(dir),
+                };
+                if !path.is_dir() {
+                    self.status_message = Some(format!("Not a directory: {dir}"));
+                    return;
+                }
+                if !self.config.sandbox.allow.iter().any(|d| d == dir) {
+                    self.config.sandbox.allow.push(dir.to_string());
+                }
+            }
+            ("remove", dir) if !dir.is_empty() => {
+                let before = self.config.sandbox.allow.len();
+                self.config.sandbox.allow.retain(|d| d != dir);
+                if self.config.sandbox.allow.len() == before {
+                    self.status_message = Some(format!("{dir} is not in the sandbox allowlist"));
+                    return;
+                }
+            }
+            _ => {
+                self.status_message = Some("Usage: /sandbox [on|off|add <dir>|remove <dir>]".into());
+                return;
+            }
+        }
+        if !arg.is_empty() {
+            self.tool_executor.set_sandbox(tools::Sandbox::from_config(&self.sandbox_config()));
+        }
+        let state = match self.tool_executor.sandbox() {
+            Some(sandbox) => {
+                let roots: Vec<String> = sandbox.roots().iter().map(|r| r.display().to_string()).collect();
+                format!("Sandbox: file tools limited to {}", roots.join(", "))
+            }
+            None => "Sandbox off: file tools may use any path".into(),
+        };
+        self.status_message = Some(if arg.is_empty() { state } else { format!("{state} (/save to keep)") });
+    }
+
+    /// A failed or denied write inside an edit batch undoes the whole batch.
+    fn rollback_edit_batch(&mut self, tool: &tools::Tool, result: &mut ToolResult) {
+        if tool.written_paths().is_empty() {
+            return;
+        }
+        let Some((restored, note)) = self.tool_executor.rollback_edit_batch() else {
+            return;
+        };
+        // The restored contents are ours, not an outside change to flag
+        for path in &restored {
+            self.watched_files.watch(path);
+        }
+        result.output.push_str(&format!("\n\n{note}"));
+        self.status_message = Some(format!("Edit batch rolled back ({} file(s) restored)", restored.len()));
+    }
+
+    /// Type the answer to a running command's prompt: Enter sends it to the
+    /// command, Esc declines.
+    fn handle_tool_input_key(&mut self, key: crossterm::event::KeyEvent) {
+        use crossterm::event::{KeyCode, KeyModifiers};
+
+        let Some(input) = &mut self.tool_input else {
+            self.overlay = Overlay::None;
+            return;
+        };
+        let answer = match key.code {
+            KeyCode::Enter => Some(std::mem::take(&mut input.answer)),
+            KeyCode::Esc => None,
+            KeyCode::Backspace => {
+                input.answer.pop();
+                return;
+            }
+            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                input.answer.push(c);
+                return;
+            }
+            _ => return,
+        };
+        if let Some(input) = self.tool_input.take() {
+            let _ = input.reply.send(answer);
+        }
+        self.overlay = Overlay::None;
+    }
+
     async fn handle_tool_confirm_key(&mut self, key: crossterm::event::KeyEvent) {
         use crossterm::event::KeyCode;
 
+        if matches!(key.code, KeyCode::Char('y' | 'a' | 'n' | 'd') | KeyCode::Enter | KeyCode::Esc) {
+            self.set_session(SessionState::RunningTools);
+        }
         match key.code {
             KeyCode::Char('y') | KeyCode::Enter => {
                 // Allow this tool
                 self.overlay = Overlay::None;
-                self.execute_tool_at_index(self.pending_tool_confirm_idx).await;
-                self.pending_tool_confirm_idx += 1;
-                self.process_next_tool_call().await;
+                self.run_confirmed_tool().await;
             }
             KeyCode::Char('a') => {
                 // Always allow this tool type
                 let tool_name = self.pending_tool_calls[self.pending_tool_confirm_idx]
-                    .tool.name().to_string();
-                self.tool_executor.set_permission(&tool_name, ToolPermission::AutoAllow);
+                    .tool.permission_key();
+                self.remember_tool_permission(&tool_name, ToolPermission::AutoAllow);
                 self.overlay = Overlay::None;
-                self.execute_tool_at_index(self.pending_tool_confirm_idx).await;
-                self.pending_tool_confirm_idx += 1;
-                self.process_next_tool_call().await;
+                self.run_confirmed_tool().await;
             }
             KeyCode::Char('n') | KeyCode::Esc => {
                 // Deny this tool
-                let call = &self.pending_tool_calls[self.pending_tool_confirm_idx];
+                let call = self.pending_tool_calls[self.pending_tool_confirm_idx].clone();
+                let mut result = ToolResult::err("Denied by user");
+                self.rollback_edit_batch(&call.tool, &mut result);
                 let invocation = ToolInvocation {
                     tool_name: call.tool.name().to_string(),
                     tool_args: format_tool_args(&call.tool),
-                    result: Some(ToolResult::err("Denied by user")),
+                    result: Some(result),
                     collapsed: false,
+                    duration: None,
+                    cached: false,
+                    live_output: String::new(),
                 };
-                if let Some(last) = self.messages.last_mut() {
-                    if last.role == "assistant" {
-                        last.tool_invocations.push(invocation.clone());
-                    }
+                if let Some(last) = self.messages.last_mut()
+                    && last.role == "assistant"
+                {
+                    last.tool_invocations.push(invocation.clone());
                 }
                 self.tool_invocations.push(invocation);
                 self.overlay = Overlay::None;
@@ -590,19 +2202,24 @@ impl App {
             KeyCode::Char('d') => {
                 // Deny all of this type
                 let tool_name = self.pending_tool_calls[self.pending_tool_confirm_idx]
-                    .tool.name().to_string();
-                self.tool_executor.set_permission(&tool_name, ToolPermission::Deny);
-                let call = &self.pending_tool_calls[self.pending_tool_confirm_idx];
+                    .tool.permission_key();
+                self.remember_tool_permission(&tool_name, ToolPermission::Deny);
+                let call = self.pending_tool_calls[self.pending_tool_confirm_idx].clone();
+                let mut result = ToolResult::err("Denied by user");
+                self.rollback_edit_batch(&call.tool, &mut result);
                 let invocation = ToolInvocation {
                     tool_name: call.tool.name().to_string(),
                     tool_args: format_tool_args(&call.tool),
-                    result: Some(ToolResult::err("Denied by user")),
+                    result: Some(result),
                     collapsed: false,
+                    duration: None,
+                    cached: false,
+                    live_output: String::new(),
                 };
-                if let Some(last) = self.messages.last_mut() {
-                    if last.role == "assistant" {
-                        last.tool_invocations.push(invocation.clone());
-                    }
+                if let Some(last) = self.messages.last_mut()
+                    && last.role == "assistant"
+                {
+                    last.tool_invocations.push(invocation.clone());
                 }
                 self.tool_invocations.push(invocation);
                 self.overlay = Overlay::None;
@@ -613,6 +2230,24 @@ impl App {
         }
     }
 
+    /// Set a tool's permission for this and future sessions.
+    fn remember_tool_permission(&mut self, tool: &str, permission: ToolPermission) {
+        self.tool_executor.set_permission(tool, permission);
+        self.session_permissions.insert(tool.to_string(), permission);
+        self.status_message = Some(match self.config.save_tool_permission(tool, permission) {
+            Ok(()) => format!("{tool}: {permission:?} (saved to config)"),
+            Err(e) => format!("{tool}: {permission:?} for this session; not saved: {e}"),
+        });
+    }
+
+    /// Run the call the user just allowed, then carry on with the rest.
+    async fn run_confirmed_tool(&mut self) {
+        if !self.execute_tool_at_index(self.pending_tool_confirm_idx).await {
+            self.pending_tool_confirm_idx += 1;
+            self.process_next_tool_call().await;
+        }
+    }
+
     fn handle_setup_key(&mut self, key: crossterm::event::KeyEvent) {
         use crossterm::event::KeyCode;
 
@@ -629,9 +2264,15 @@ impl App {
                             self.setup_state.selected_provider.saturating_sub(1);
                     }
                     KeyCode::Enter => {
-                        self.setup_state.step = SetupStep::EnterApiKey;
                         self.setup_state.key_input.clear();
-                        self.setup_state.key_confirmed = false;
+                        if !SetupState::needs_api_key(self.setup_state.current_provider_id()) {
+                            self.setup_state.key_confirmed = true;
+                            self.setup_state.step = SetupStep::PickModel;
+                            self.setup_state.selected_model = 0;
+                        } else {
+                            self.setup_state.step = SetupStep::EnterApiKey;
+                            self.setup_state.key_confirmed = false;
+                        }
                     }
                     KeyCode::Esc => {
                         self.overlay = Overlay::None;
@@ -641,12 +2282,10 @@ impl App {
             }
             SetupStep::EnterApiKey => {
                 match (key.modifiers, key.code) {
-                    (_, KeyCode::Enter) => {
-                        if !self.setup_state.key_input.trim().is_empty() {
-                            self.setup_state.key_confirmed = true;
-                            self.setup_state.step = SetupStep::PickModel;
-                            self.setup_state.selected_model = 0;
-                        }
+                    (_, KeyCode::Enter) if !self.setup_state.key_input.trim().is_empty() => {
+                        self.setup_state.key_confirmed = true;
+                        self.setup_state.step = SetupStep::PickModel;
+                        self.setup_state.selected_model = 0;
                     }
                     (_, KeyCode::Esc) => {
                         self.setup_state.step = SetupStep::PickProvider;
@@ -655,10 +2294,10 @@ impl App {
                         self.setup_state.key_input.pop();
                     }
                     (crossterm::event::KeyModifiers::CONTROL, KeyCode::Char('v')) => {
-                        if let Ok(mut clipboard) = arboard::Clipboard::new() {
-                            if let Ok(text) = clipboard.get_text() {
-                                self.setup_state.key_input.push_str(text.trim());
-                            }
+                        if let Ok(mut clipboard) = arboard::Clipboard::new()
+                            && let Ok(text) = clipboard.get_text()
+                        {
+                            self.setup_state.key_input.push_str(text.trim());
                         }
                     }
                     (_, KeyCode::Char(c)) => {
@@ -682,6 +2321,9 @@ impl App {
                     KeyCode::Enter => {
                         self.complete_setup();
                     }
+                    KeyCode::Esc if !SetupState::needs_api_key(provider_id) => {
+                        self.setup_state.step = SetupStep::PickProvider;
+                    }
                     KeyCode::Esc => {
                         self.setup_state.step = SetupStep::EnterApiKey;
                     }
@@ -724,12 +2366,13 @@ impl App {
             tool_results.push(serde_json::json!({
                 "type": "tool_result",
                 "tool_use_id": call.id,
-                "content": result.output,
+                "content": result.api_content(),
                 "is_error": !result.success,
             }));
         }
 
         if tool_results.is_empty() {
+            self.set_session(SessionState::Idle);
             return;
         }
 
@@ -743,96 +2386,311 @@ impl App {
         self.pending_tool_confirm_idx = 0;
 
         // Continue the conversation - make another API call
-        self.streaming = true;
+        self.set_session(SessionState::Continuing);
         self.stream_start_time = Some(std::time::Instant::now());
+        self.last_autosave = self.stream_start_time;
         self.stream_buffer.clear();
 
         // Add a new assistant placeholder for the continuation
         self.messages.push(ChatMessage {
+            id: history::new_message_id(),
             role: "assistant".into(),
             content: String::new(),
             timestamp: chrono::Utc::now(),
             tool_invocations: Vec::new(),
+            note: None,
+            stop_reason: None,
+            thinking: None,
+            meta: None,
+            attachments: Vec::new(),
         });
 
-        let api_key = match self.config.api_key_from_env() {
-            Some(key) => key,
-            None => return,
+        if self.settings_changed_mid_chain() {
+            self.status_message =
+                Some("Settings change applies from your next message; this tool loop keeps its model and tools".into());
+        }
+        self.spawn_chain_call();
+    }
+
+    /// Request parameters for a new turn from the current settings, without
+    /// messages.
+    fn build_request(&self, api_key: String) -> api::Request {
+        api::Request {
+            system: self.assembled_system_prompt(self.system_prompt()),
+            sampling: self.sampling(),
+            response_format: self.json_mode.as_ref()
+                .map(|j| structured::openai_response_format(j.schema.as_ref())),
+            tools: self.config.tool_set().filter(|_| self.tools_enabled),
+            // Thinking can't be combined with a prefilled assistant turn
+            thinking_budget: self.config.thinking_budget.filter(|_| self.continuing.is_none()),
+            ..api::Request::new(&self.config, api_key, Vec::new())
+        }
+    }
+
+    /// Whether a request built now would differ from the one the current
+    /// tool loop started with.
+    fn settings_changed_mid_chain(&self) -> bool {
+        let Some(frozen) = &self.chain_request else { return false };
+        let now = self.build_request(String::new());
+        (&now.provider, &now.model, &now.system, &now.sampling, &now.tools, &now.max_tokens)
+            != (&frozen.provider, &frozen.model, &frozen.system, &frozen.sampling, &frozen.tools, &frozen.max_tokens)
+            || now.thinking_budget != frozen.thinking_budget
+            || now.response_format != frozen.response_format
+    }
+
+    /// The model that produced (or is producing) the latest response.
+    fn request_model(&self) -> (&str, &str) {
+        match &self.chain_request {
+            Some(request) => (&request.provider, &request.model),
+            None => (&self.config.provider, &self.config.model),
+        }
+    }
+
+    /// Spawn an API call on a background task based on the current provider,
+    /// starting a new turn with the current settings.
+    fn spawn_api_call(&mut self, api_key: String) {
+        self.chain_request = Some(self.build_request(api_key));
+        self.spawn_chain_call();
+    }
+
+    /// Send `api_messages` with the parameters the current turn started with.
+    fn spawn_chain_call(&mut self) {
+        let Some(params) = self.chain_request.clone() else {
+            self.set_session(SessionState::Idle);
+            return;
         };
+        let tx = self.event_tx.clone().unwrap();
+        let mut messages = self.api_messages.clone();
+        if self.continuing.is_some()
+            && !self.config.supports_prefill()
+            && let Some(Message { content: MessageContent::Text(partial), .. }) = messages.last()
+        {
+            let prompt = continuation_prompt(partial);
+            messages.push(Message { role: "user".into(), content: MessageContent::Text(prompt) });
+        }
+        let request = api::Request { messages, ..params };
+        crate::bugreport::record_request(
+            &request.provider, &request.model, request.system.as_deref(), &request.messages,
+            request.max_tokens, request.tools.is_some(),
+        );
+        let client = Arc::clone(&self.api_client);
 
-        self.spawn_api_call(api_key);
+        let task = tokio::spawn(async move {
+            if let Err(e) = client.send(&request, tx.clone()).await {
+                let _ = tx.send(Event::ApiError(e.to_string()));
+            }
+        });
+        self.stream_task = Some(task.abort_handle());
+    }
+
+    pub async fn send_message(&mut self) -> anyhow::Result<()> {
+        let input = self.input.trim().to_string();
+        if input.is_empty() {
+            return Ok(());
+        }
+
+        // Fetching is async, so /ticket is handled here rather than with the
+        // other slash commands
+        if let Some(key) = input.strip_prefix("/ticket")
+            && (key.is_empty() || key.starts_with(' '))
+        {
+            self.attach_ticket(key.trim()).await;
+            return Ok(());
+        }
+        if let Some(query) = input.strip_prefix("/recall")
+            && (query.is_empty() || query.starts_with(' '))
+        {
+            self.recall(query.trim()).await;
+            return Ok(());
+        }
+        // Model lists are fetched from the provider
+        if let Some(filter) = input.strip_prefix("/models")
+            && (filter.is_empty() || filter.starts_with(' '))
+        {
+            self.show_models(filter.trim()).await;
+            return Ok(());
+        }
+        if input.starts_with('/') {
+            return self.handle_slash_command(&input);
+        }
+        // A second turn can't start until the current reply (and any tool
+        // calls awaiting confirmation) has finished
+        if self.session != SessionState::Idle {
+            if self.config.queue_while_streaming {
+                self.prompt_queue.push_back(input.clone());
+                self.input_history.push(input);
+                self.input_history_idx = None;
+                self.input.clear();
+                self.cursor_pos = 0;
+                self.status_message = Some(format!(
+                    "Queued ({}), sends when the response completes",
+                    self.prompt_queue.len()
+                ));
+            } else {
+                self.status_message = Some("Still responding: wait for the reply to finish".into());
+            }
+            return Ok(());
+        }
+        if self.send_user_text(&input) {
+            self.input_history.push(input);
+            self.input_history_idx = None;
+            self.input.clear();
+            self.cursor_pos = 0;
+        }
+        Ok(())
+    }
+
+    /// Fetch a tracker ticket into the input as context, like `/file`.
+    async fn attach_ticket(&mut self, key: &str) {
+        let Some(config) = &self.config.tracker else {
+            self.status_message = Some("No issue tracker configured (add [tracker] to config.toml)".into());
+            return;
+        };
+        if key.is_empty() {
+            self.status_message = Some("Usage: /ticket <KEY>".into());
+            return;
+        }
+        match tracker::fetch(config, key).await {
+            Ok(ticket) => {
+                self.input = format!("Here is ticket {}:\n```\n{}\n```\n", ticket.key, ticket.render());
+                self.cursor_pos = 0;
+                self.status_message = Some(format!("Loaded {} into input", ticket.key));
+            }
+            Err(e) => self.status_message = Some(e),
+        }
+    }
+
+    /// Fetch the provider's models into the Models overlay, keeping those
+    /// containing `filter`. Falls back to the setup wizard's suggestions when
+    /// the provider can't be listed.
+    async fn show_models(&mut self, filter: &str) {
+        self.input.clear();
+        self.cursor_pos = 0;
+        let fetched = match self.models_endpoint() {
+            Some((url, headers)) => Some(self.api_client.list_models(&url, &headers).await),
+            None => None,
+        };
+        let suggestions = || -> Vec<String> {
+            SetupState::models_for_provider(&self.config.provider)
+                .into_iter()
+                .map(|(id, _)| id.to_string())
+                .collect()
+        };
+        let (mut models, note) = match fetched {
+            Some(Ok(models)) if !models.is_empty() => (models, None),
+            Some(Ok(_)) if self.config.provider == "ollama" => {
+                self.status_message = Some(format!(
+                    "No local models (run `ollama pull <model>`) at {}",
+                    self.config.ollama_host()
+                ));
+                return;
+            }
+            Some(Ok(_)) => (suggestions(), Some("Provider listed no models; showing suggestions".to_string())),
+            Some(Err(e)) => (suggestions(), Some(format!("{e}; showing suggestions"))),
+            None => (suggestions(), None),
+        };
+        let filter = filter.to_lowercase();
+        models.retain(|m| m.to_lowercase().contains(&filter));
+        if models.is_empty() {
+            self.status_message = Some(format!("No {} models match '{filter}'", self.config.provider));
+            return;
+        }
+        self.overlay_scroll = models.iter().position(|m| *m == self.config.model).unwrap_or(0);
+        self.model_list = models;
+        self.overlay = Overlay::Models;
+        self.status_message = note;
+    }
+
+    /// The current provider's model-list URL with its auth and configured
+    /// headers, or `None` if it has no listing endpoint.
+    fn models_endpoint(&self) -> Option<(String, Vec<(String, String)>)> {
+        let key = self.config.api_key_from_env().unwrap_or_default();
+        let header = |name: &str, value: &str| (name.to_string(), value.to_string());
+        let bearer = vec![header("authorization", &format!("Bearer {key}"))];
+        let (url, mut headers) = match self.config.provider.as_str() {
+            "anthropic" => (
+                "https://api.anthropic.com/v1/models?limit=1000".into(),
+                vec![header("x-api-key", &key), header("anthropic-version", "2023-06-01")],
+            ),
+            "openai" => ("https://api.openai.com/v1/models".into(), bearer),
+            "openrouter" => ("https://openrouter.ai/api/v1/models".into(), bearer),
+            "xai" => ("https://api.x.ai/v1/models".into(), bearer),
+            "gemini" => (
+                format!("{}/models?pageSize=1000", crate::api::GEMINI_API_URL),
+                vec![header("x-goog-api-key", &key)],
+            ),
+            "ollama" => (format!("{}/api/tags", self.config.ollama_host().trim_end_matches('/')), Vec::new()),
+            name => (self.config.custom_provider(name)?.models_url(), bearer),
+        };
+        headers.extend(self.config.request_headers(&self.config.provider));
+        Some((url, headers))
     }
 
-    /// Spawn an API call on a background task based on the current provider.
-    fn spawn_api_call(&self, api_key: String) {
-        let tx = self.event_tx.clone().unwrap();
-        let provider = self.config.provider.clone();
-        let model = self.config.model.clone();
-        let system = self.config.system_prompt.clone();
-        let max_tokens = self.config.max_tokens;
-        let temp = self.config.temperature;
-        let messages = self.api_messages.clone();
-        let tools_enabled = self.tools_enabled && provider == "anthropic";
-        let client = self.api_client.clone();
-
-        tokio::spawn(async move {
-            let result = match provider.as_str() {
-                "openai" => {
-                    client.stream_openai_compatible(
-                        &api_key, &model, &messages,
-                        system.as_deref(), max_tokens, temp, tx.clone(),
-                        "https://api.openai.com/v1/chat/completions",
-                        &[],
-                    ).await
-                }
-                "openrouter" => {
-                    client.stream_openai_compatible(
-                        &api_key, &model, &messages,
-                        system.as_deref(), max_tokens, temp, tx.clone(),
-                        "https://openrouter.ai/api/v1/chat/completions",
-                        &[("HTTP-Referer", "https://github.com/pro-chat"), ("X-Title", "Pro Chat")],
-                    ).await
-                }
-                "xai" => {
-                    client.stream_openai_compatible(
-                        &api_key, &model, &messages,
-                        system.as_deref(), max_tokens, temp, tx.clone(),
-                        "https://api.x.ai/v1/chat/completions",
-                        &[],
-                    ).await
-                }
-                _ => {
-                    // Default: anthropic
-                    if tools_enabled {
-                        client.call_anthropic_with_tools(
-                            &api_key, &model, &messages,
-                            system.as_deref(), max_tokens, temp, tx.clone(),
-                        ).await
-                    } else {
-                        client.stream_anthropic(
-                            &api_key, &model, &messages,
-                            system.as_deref(), max_tokens, temp, tx.clone(),
-                        ).await
-                    }
-                }
-            };
-
-            if let Err(e) = result {
-                let _ = tx.send(Event::ApiError(e.to_string()));
+    /// Search saved conversations by meaning and list them in the Recall overlay.
+    async fn recall(&mut self, query: &str) {
+        if query.is_empty() {
+            self.status_message = Some("Usage: /recall <query>".into());
+            return;
+        }
+        let conversations = match Conversation::list_all(&self.config.history_dir()) {
+            Ok(list) => list,
+            Err(e) => {
+                self.status_message = Some(format!("Cannot read history: {e}"));
+                return;
             }
-        });
+        };
+        self.status_message = Some("Searching history...".into());
+        match rag::recall(&self.config.embeddings(), &conversations, query, 10).await {
+            Ok(hits) if hits.is_empty() => {
+                self.status_message = Some("No saved conversations to search".into());
+            }
+            Ok(hits) => {
+                self.recall_hits = hits;
+                self.input.clear();
+                self.cursor_pos = 0;
+                self.overlay = Overlay::Recall;
+                self.overlay_scroll = 0;
+                self.status_message = None;
+            }
+            Err(e) => self.status_message = Some(format!("Recall failed: {e}")),
+        }
     }
 
-    pub async fn send_message(&mut self) -> anyhow::Result<()> {
-        let input = self.input.trim().to_string();
-        if input.is_empty() {
-            return Ok(());
+    /// Put the full text of the selected recall hit into the input as context.
+    pub fn attach_recall_hit(&mut self) {
+        let Some(hit) = self.recall_hits.get(self.overlay_scroll).cloned() else { return };
+        let content = Conversation::load(&self.config.history_dir(), &hit.conversation_id)
+            .ok()
+            .and_then(|c| c.messages.into_iter().find(|m| m.id == hit.message_id))
+            .map(|m| m.content);
+        match content {
+            Some(content) => {
+                self.input = format!(
+                    "From an earlier conversation (\"{}\", #{}):\n```\n{}\n```\n",
+                    hit.title, hit.message_id, content.trim()
+                );
+                self.cursor_pos = self.input.len();
+                self.overlay = Overlay::None;
+                self.overlay_scroll = 0;
+                self.status_message = Some(format!("Attached #{} to input", hit.message_id));
+            }
+            None => self.status_message = Some("That conversation no longer exists".into()),
         }
+    }
 
-        if input.starts_with('/') {
-            return self.handle_slash_command(&input);
+    /// Append `input` as a user turn and start streaming the reply.
+    /// Returns false (with a status) when nothing could be sent.
+    fn send_user_text(&mut self, input: &str) -> bool {
+        if self.viewer {
+            self.status_message = Some("Read-only viewer: nothing can be sent".into());
+            return false;
+        }
+        if let Some(pid) = self.read_only_pid() {
+            self.status_message = Some(format!(
+                "Read-only: this conversation is open in another pro (PID {pid}). /new starts a fresh one"
+            ));
+            return false;
         }
-
         let api_key = match self.config.api_key_from_env() {
             Some(key) => key,
             None => {
@@ -841,52 +2699,541 @@ impl App {
                     self.config.api_key_env_var(),
                     Config::path().display()
                 ));
-                return Ok(());
+                return false;
             }
         };
 
         // Add user message
+        let attachments = std::mem::take(&mut self.pending_attachments);
+        let labels: Vec<String> = attachments.iter().map(Attachment::label).collect();
+        let message_id = history::new_message_id();
         self.messages.push(ChatMessage {
+            id: message_id.clone(),
             role: "user".into(),
-            content: input.clone(),
+            content: input.to_string(),
             timestamp: chrono::Utc::now(),
             tool_invocations: Vec::new(),
+            note: None,
+            stop_reason: None,
+            thinking: None,
+            meta: None,
+            attachments: labels.clone(),
         });
-        self.conversation.add_message("user", &input);
-
-        // Add to API message history
-        self.api_messages.push(Message {
-            role: "user".into(),
-            content: MessageContent::Text(input.clone()),
-        });
+        self.conversation.add_message(&message_id, "user", input);
+        if !labels.is_empty() {
+            self.conversation.set_attachments(&message_id, labels);
+        }
+        // Persist the user turn right away so it survives a crash mid-stream
+        self.save_and_track_conversation();
 
-        self.input_history.push(input);
-        self.input_history_idx = None;
-        self.input.clear();
-        self.cursor_pos = 0;
+        // Add to API message history, PDFs first as Anthropic recommends
+        let content = if attachments.is_empty() {
+            MessageContent::Text(input.to_string())
+        } else {
+            let mut blocks: Vec<Value> = attachments.iter().map(|a| api::document_block(&a.name, &a.data)).collect();
+            blocks.push(serde_json::json!({"type": "text", "text": input}));
+            MessageContent::Blocks(blocks)
+        };
+        self.api_messages.push(Message { role: "user".into(), content });
 
         // Add placeholder for assistant
         self.messages.push(ChatMessage {
+            id: history::new_message_id(),
             role: "assistant".into(),
             content: String::new(),
             timestamp: chrono::Utc::now(),
             tool_invocations: Vec::new(),
+            note: None,
+            stop_reason: None,
+            thinking: None,
+            meta: None,
+            attachments: Vec::new(),
         });
 
-        self.streaming = true;
+        self.set_session(SessionState::Streaming);
         self.stream_start_time = Some(std::time::Instant::now());
         self.stream_buffer.clear();
         self.scroll_to_bottom();
 
         self.spawn_api_call(api_key);
+        true
+    }
 
-        Ok(())
+    /// After a "prompt too long" error, drop the oldest turns from the API
+    /// context and send again, once. The display and saved history keep
+    /// everything. Returns false when the error was not about context length,
+    /// this was already the retry, or there was nothing left to drop.
+    fn retry_with_trimmed_context(&mut self, err: &str) -> bool {
+        if std::mem::take(&mut self.context_trim_retried) || !api::is_context_length_error(err) {
+            return false;
+        }
+        if self.chain_request.is_none() {
+            return false;
+        }
+        let Some((dropped, tokens)) = trim_oldest_turns(&mut self.api_messages) else { return false };
+
+        self.context_trim_retried = true;
+        self.status_message = Some(format!(
+            "Context too long: dropped the {dropped} oldest messages (~{tokens} tokens) from the request and retried"
+        ));
+        self.set_session(SessionState::Streaming);
+        self.stream_start_time = Some(std::time::Instant::now());
+        self.spawn_chain_call();
+        true
+    }
+
+    /// `/thinking <budget>|off|show|hide`: set Claude's extended thinking
+    /// budget, or expand and collapse the thinking shown above answers.
+    fn handle_thinking_command(&mut self, arg: &str) {
+        match arg {
+            "" => {
+                self.status_message = Some(match self.config.thinking_budget {
+                    Some(budget) => format!("Thinking: {budget} token budget"),
+                    None => "Thinking: off (/thinking <budget> to enable)".into(),
+                });
+            }
+            "off" => {
+                self.config.thinking_budget = None;
+                self.status_message = Some("Thinking off".into());
+            }
+            "show" | "hide" => {
+                self.show_thinking = arg == "show";
+                self.status_message = Some(format!("Thinking {}", if self.show_thinking { "expanded" } else { "collapsed" }));
+            }
+            _ => match arg.parse::<u32>() {
+                Ok(budget) if budget >= MIN_THINKING_BUDGET => {
+                    self.config.thinking_budget = Some(budget);
+                    self.status_message = Some(if matches!(self.config.provider.as_str(), "anthropic" | "bedrock") {
+                        format!("Thinking budget set to {budget} tokens")
+                    } else {
+                        format!("Thinking budget set to {budget} tokens (used with Claude on anthropic or bedrock)")
+                    });
+                }
+                Ok(_) => {
+                    self.status_message = Some(format!("Thinking budget must be at least {MIN_THINKING_BUDGET} tokens"));
+                }
+                Err(_) => {
+                    self.status_message = Some("Usage: /thinking <budget>|off|show|hide".into());
+                }
+            },
+        }
+    }
+
+    /// Sampling settings for the next request.
+    fn sampling(&self) -> api::Sampling {
+        api::Sampling {
+            temperature: self.config.effective_temperature(),
+            top_p: self.config.top_p,
+            top_k: self.config.top_k,
+            stop_sequences: self.config.stop_sequences.clone(),
+        }
+    }
+
+    /// `/mcp`: each configured server's state and the tools it offers.
+    fn show_mcp_status(&mut self) {
+        if self.config.mcp.is_empty() {
+            self.status_message = Some("No MCP servers configured (add [mcp.<name>] to config.toml)".into());
+            return;
+        }
+        let tools = mcp::tools();
+        let lines: Vec<String> = self.config.mcp.keys().map(|name| match self.mcp_status.get(name) {
+            None => format!("  {name}: connecting..."),
+            Some(Err(e)) => format!("  {name}: failed: {e}"),
+            Some(Ok(count)) => {
+                let names: Vec<&str> = tools.iter()
+                    .filter(|(server, _)| server == name)
+                    .map(|(_, tool)| tool.name.as_str())
+                    .collect();
+                format!("  {name}: {count} tools: {}", names.join(", "))
+            }
+        }).collect();
+        self.status_message = Some(format!("MCP servers\n{}", lines.join("\n")));
+    }
+
+    /// `/attach <file.pdf>` queues a PDF for the next message, `/attach clear`
+    /// drops them, and a bare `/attach` lists what is queued.
+    fn handle_attach_command(&mut self, arg: &str) {
+        match arg {
+            "" if self.pending_attachments.is_empty() => {
+                self.status_message = Some("Usage: /attach <file.pdf> (sent with your next message)".into());
+            }
+            "" => {
+                let labels: Vec<String> = self.pending_attachments.iter().map(Attachment::label).collect();
+                self.status_message = Some(format!("Attached: {}", labels.join(", ")));
+            }
+            "clear" => {
+                let count = self.pending_attachments.len();
+                self.pending_attachments.clear();
+                self.status_message = Some(format!("Removed {count} attachment(s)"));
+            }
+            path => match Attachment::load_pdf(std::path::Path::new(path)) {
+                Ok(attachment) => {
+                    let label = attachment.label();
+                    self.pending_attachments.push(attachment);
+                    let note = if self.config.provider == "ollama" {
+                        "; Ollama can't read PDFs, only the name is sent"
+                    } else {
+                        ""
+                    };
+                    self.status_message = Some(format!("Attached {label}, sent with your next message{note}"));
+                }
+                Err(e) => self.status_message = Some(e),
+            },
+        }
+    }
+
+    /// `/top_p`, `/top_k` and `/stop` (also `:set top_p=...`): set a sampling
+    /// option, clear it with `off`, or show all of them with no value.
+    fn handle_sampling_command(&mut self, name: &str, value: &str) {
+        let value = value.trim();
+        match (name, value) {
+            (_, "") => {}
+            ("top_p", "off") => self.config.top_p = None,
+            ("top_k", "off") => self.config.top_k = None,
+            ("stop", "off") => self.config.stop_sequences.clear(),
+            ("top_p", _) => match value.parse::<f32>() {
+                Ok(p) if (0.0..=1.0).contains(&p) => self.config.top_p = Some(p),
+                _ => {
+                    self.status_message = Some(format!("Invalid top_p {value}: expected 0.0-1.0 or off"));
+                    return;
+                }
+            },
+            ("top_k", _) => match value.parse::<u32>() {
+                Ok(k) if k > 0 => self.config.top_k = Some(k),
+                _ => {
+                    self.status_message = Some(format!("Invalid top_k {value}: expected a positive integer or off"));
+                    return;
+                }
+            },
+            _ => self.config.stop_sequences = parse_stop_sequences(value),
+        }
+        let sampling = self.sampling();
+        let or_default = |v: Option<String>| v.unwrap_or_else(|| "default".into());
+        self.status_message = Some(format!(
+            "temperature {} · top_p {} · top_k {} · stop {}",
+            sampling.temperature,
+            or_default(sampling.top_p.map(|p| p.to_string())),
+            or_default(sampling.top_k.map(|k| k.to_string())),
+            if sampling.stop_sequences.is_empty() {
+                "none".to_string()
+            } else {
+                format!("{:?}", sampling.stop_sequences)
+            },
+        ));
+    }
+
+    /// Ring the bell and/or flash the status bar, as configured in `[cues]`.
+    /// The bell is skipped while the terminal is known to have focus, since
+    /// it's for noticing things from elsewhere.
+    fn cue(&mut self, cue: Cue) {
+        if matches!(cue, Cue::Bell | Cue::Both) && self.focused != Some(true) {
+            eprint!("\x07");
+        }
+        if matches!(cue, Cue::Flash | Cue::Both) {
+            self.flash_until = Some(self.tick_count + FLASH_TICKS);
+        }
+    }
+
+    /// Whether a flash cue is highlighting the status bar.
+    pub fn flashing(&self) -> bool {
+        self.flash_until.is_some_and(|until| self.tick_count < until)
+    }
+
+    /// Put the stop reason of the response that just finished on its message,
+    /// unless it ended normally.
+    fn take_stop_reason(&mut self) {
+        let reason = self.response_stop_reason.take().filter(|r| r != "end_turn");
+        if let Some(last) = self.messages.last_mut()
+            && last.role == "assistant"
+        {
+            if reason.as_deref() == Some("max_tokens") {
+                self.status_message = Some("Response cut off at max_tokens: press c to continue".into());
+            }
+            last.stop_reason = reason;
+        }
+    }
+
+    /// Ask for the rest of the last response and stitch it onto the same
+    /// message (`c` after a `max_tokens` cut-off, or `/continue` for any
+    /// reply). Providers that support it get the partial reply back as the
+    /// start of the assistant turn, so the model picks up mid-sentence;
+    /// others are asked to carry on from where it stopped.
+    pub fn continue_response(&mut self, only_if_cut_off: bool) {
+        if self.is_streaming() {
+            self.status_message = Some("Cannot continue while streaming".into());
+            return;
+        }
+        let Some(last) = self.messages.last().filter(|m| m.role == "assistant" && !m.content.is_empty()) else {
+            self.status_message = Some("No assistant message to continue".into());
+            return;
+        };
+        if only_if_cut_off && last.stop_reason.as_deref() != Some("max_tokens") {
+            self.status_message = Some("The last response was not cut off (/continue extends it anyway)".into());
+            return;
+        }
+        let Some(api_key) = self.config.api_key_from_env() else {
+            self.status_message = Some("No API key set".into());
+            return;
+        };
+        // The API rejects a final assistant turn ending in whitespace
+        let partial = last.content.trim_end().to_string();
+        match self.api_messages.last_mut() {
+            Some(Message { role, content: content @ MessageContent::Text(_) }) if role == "assistant" => {
+                *content = MessageContent::Text(partial.clone());
+            }
+            _ => {
+                self.status_message = Some("Only a plain text reply can be continued".into());
+                return;
+            }
+        }
+
+        if let Some(last) = self.messages.last_mut() {
+            last.stop_reason = None;
+        }
+        self.continuing = Some(partial.len());
+        self.set_session(SessionState::Streaming);
+        self.stream_start_time = Some(std::time::Instant::now());
+        self.stream_buffer = partial;
+        self.status_message = Some("Continuing...".into());
+        self.scroll_to_bottom();
+        self.spawn_api_call(api_key);
+    }
+
+    /// Clean up the seam between a continued reply and its continuation,
+    /// which starts at byte `start` of the stream buffer.
+    fn stitch_continuation(&mut self, start: usize) {
+        let continuation = self.stream_buffer.split_off(start);
+        let joined = stitch_continuation(&self.stream_buffer, &continuation);
+        self.stream_buffer.push_str(&joined);
+        if let Some(last) = self.messages.last_mut()
+            && last.role == "assistant"
+        {
+            last.content = self.stream_buffer.clone();
+        }
+    }
+
+    /// Move the user message whose request just failed out of the
+    /// conversation and into the failed queue, so the history stays
+    /// consistent and the text isn't lost. Failures after tool results have
+    /// no user text to keep and only report the error.
+    fn queue_failed_send(&mut self, err: &str) {
+        let (content, attachments) = match self.api_messages.last() {
+            Some(Message { role, content: MessageContent::Text(text) }) if role == "user" => (text.clone(), Vec::new()),
+            Some(Message { role, content: MessageContent::Blocks(blocks) })
+                if role == "user" && let Some(split) = split_attachments(blocks) => split,
+            _ => {
+                self.push_error_card(err);
+                self.status_message = Some("Request failed (Ctrl+R to retry)".into());
+                return;
+            }
+        };
+        self.api_messages.pop();
+        if let Some(last) = self.messages.last()
+            && last.role == "user" && last.content == content
+        {
+            let id = last.id.clone();
+            self.messages.pop();
+            self.conversation.messages.retain(|m| m.id != id);
+            self.save_and_track_conversation();
+        }
+        self.failed_sends.push(FailedSend {
+            content,
+            attachments,
+            error: err.to_string(),
+            failed_at: chrono::Utc::now(),
+        });
+        self.status_message = Some(if self.prompt_queue.is_empty() {
+            format!("Error: {err} (R to resend)")
+        } else {
+            format!("Error: {err} (R to resend; {} queued prompts paused)", self.prompt_queue.len())
+        });
+    }
+
+    fn push_error_card(&mut self, err: &str) {
+        self.error_cards.push(ErrorCard {
+            after: self.messages.last().map(|m| m.id.clone()),
+            error: err.to_string(),
+            failed_at: chrono::Utc::now(),
+        });
+        self.scroll_to_bottom();
+    }
+
+    /// Send the most recently failed message again (`R` / `/resend`).
+    pub fn resend_failed(&mut self) {
+        if self.is_streaming() {
+            self.status_message = Some("Cannot resend while streaming".into());
+            return;
+        }
+        if !self.input.trim().is_empty() {
+            self.status_message = Some("Clear the input to resend".into());
+            return;
+        }
+        let Some(failed) = self.failed_sends.pop() else {
+            self.status_message = Some("No failed messages".into());
+            return;
+        };
+        self.pending_attachments.extend(failed.attachments);
+        if !self.send_user_text(&failed.content) {
+            self.set_input(&failed.content);
+        }
+    }
+
+    /// Send the next queued prompt if the model is idle. Called when a
+    /// response completes, so a `/queue` list is worked through unattended.
+    fn send_next_queued(&mut self) {
+        if self.session != SessionState::Idle {
+            return;
+        }
+        let Some(prompt) = self.prompt_queue.pop_front() else { return };
+        if self.send_user_text(&prompt) {
+            if !self.prompt_queue.is_empty() {
+                self.status_message = Some(format!("{} prompts queued", self.prompt_queue.len()));
+            }
+        } else {
+            self.prompt_queue.push_front(prompt);
+        }
+    }
+
+    /// Run a `[recipes.<name>]` entry: switch profile, gather command output
+    /// and files, and send the recipe prompt with them attached. `extra` is
+    /// appended to the prompt. A pack's recipe first shows what it will run
+    /// and read, since whoever controls the pack wrote it.
+    fn run_recipe(&mut self, name: &str, extra: &str) {
+        let Some(recipe) = self.config.recipes.get(name).cloned() else {
+            self.status_message = Some(format!("Unknown recipe: {name}"));
+            return;
+        };
+        if self.is_streaming() {
+            self.status_message = Some("Cannot run a recipe while streaming".into());
+            return;
+        }
+        if let Some((running, ..)) = &self.running_recipe {
+            self.status_message = Some(format!("Recipe {running} is still running"));
+            return;
+        }
+        if let Some(pack) = self.config.pack_entries.recipes.get(name)
+            && !(recipe.run.is_empty() && recipe.files.is_empty())
+        {
+            self.recipe_confirm = Some(RecipeConfirm {
+                name: name.to_string(),
+                pack: pack.clone(),
+                recipe,
+                extra: extra.to_string(),
+            });
+            self.overlay = Overlay::RecipeConfirm;
+            return;
+        }
+        self.execute_recipe(name, &recipe, extra, false);
+    }
+
+    /// Answer the RecipeConfirm overlay: run the pack recipe or drop it.
+    pub fn confirm_recipe(&mut self, approved: bool) {
+        self.overlay = Overlay::None;
+        let Some(pending) = self.recipe_confirm.take() else { return };
+        if approved {
+            self.execute_recipe(&pending.name, &pending.recipe, &pending.extra, true);
+        } else {
+            self.status_message = Some(format!("Recipe {} not run", pending.name));
+        }
+    }
+
+    fn execute_recipe(&mut self, name: &str, recipe: &Recipe, extra: &str, from_pack: bool) {
+        // A pack's files are held to the sandbox even when tools aren't
+        let pack_sandbox = from_pack.then(|| {
+            tools::Sandbox::from_config(&SandboxConfig { enabled: true, ..self.sandbox_config() }).unwrap_or_default()
+        });
+        if let Some(sandbox) = &pack_sandbox
+            && let Some(file) = recipe.files.iter().find(|f| !sandbox.contains(std::path::Path::new(f)))
+        {
+            self.status_message = Some(format!("Recipe {name}: {file} is outside the sandbox"));
+            return;
+        }
+
+        if recipe.run.is_empty() {
+            self.finish_recipe(name, recipe, extra, Ok(Vec::new()));
+            return;
+        }
+        let commands = recipe.run.clone();
+        let env = self.config.tool_env.clone();
+        let timeout = self.tool_executor.command_timeout();
+        let Some(tx) = self.event_tx.clone() else {
+            // No event loop (tests): run them here
+            let outputs = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(|e| e.to_string())
+                .and_then(|runtime| runtime.block_on(recipe_outputs(&commands, &env, timeout)));
+            self.finish_recipe(name, recipe, extra, outputs);
+            return;
+        };
+        // Commands can take a while; keep them off the UI thread, as tools do
+        self.running_recipe = Some((name.to_string(), recipe.clone(), extra.to_string()));
+        self.status_message = Some(format!("Running recipe {name}…"));
+        let name = name.to_string();
+        let runtime = tokio::runtime::Handle::current();
+        tokio::task::spawn_blocking(move || {
+            let outputs = runtime.block_on(recipe_outputs(&commands, &env, timeout));
+            let _ = tx.send(Event::RecipeOutput(name, outputs));
+        });
+    }
+
+    /// Attach the recipe's files to its command `outputs`, switch profile
+    /// and send it.
+    fn finish_recipe(
+        &mut self,
+        name: &str,
+        recipe: &Recipe,
+        extra: &str,
+        outputs: Result<Vec<(String, String, String)>, String>,
+    ) {
+        let mut attachments = match outputs {
+            Ok(outputs) => outputs,
+            Err(e) => {
+                self.status_message = Some(format!("Recipe {name}: {e}"));
+                return;
+            }
+        };
+        if self.is_streaming() {
+            self.status_message = Some("Cannot run a recipe while streaming".into());
+            return;
+        }
+        for file in &recipe.files {
+            match std::fs::read_to_string(file) {
+                Ok(content) => {
+                    let ext = std::path::Path::new(file)
+                        .extension()
+                        .map(|e| e.to_string_lossy().to_string())
+                        .unwrap_or_default();
+                    attachments.push((format!("Contents of `{file}`"), ext, content));
+                }
+                Err(e) => {
+                    self.status_message = Some(format!("Recipe {name}: cannot read {file}: {e}"));
+                    return;
+                }
+            }
+        }
+
+        if let Some(profile) = &recipe.profile
+            && self.config.active_profile.as_ref() != Some(profile)
+        {
+            self.switch_profile(profile);
+            if self.config.active_profile.as_ref() != Some(profile) {
+                return;
+            }
+        }
+
+        let text = recipe_message(&recipe.prompt, extra, &attachments);
+        if text.trim().is_empty() {
+            self.status_message = Some(format!("Recipe {name} has nothing to send"));
+        } else if self.send_user_text(&text) {
+            self.status_message = Some(format!("Recipe: {name}"));
+        }
     }
 
     /// Retry/regenerate the last assistant response.
     /// Removes the last assistant message and re-sends to the API.
     pub async fn retry_last(&mut self) -> anyhow::Result<()> {
-        if self.streaming {
+        if self.is_streaming() {
             self.status_message = Some("Cannot retry while streaming".into());
             return Ok(());
         }
@@ -901,7 +3248,7 @@ impl App {
             self.status_message = Some("No messages to retry".into());
             return Ok(());
         }
-        self.messages.pop();
+        self.discarded_response = self.messages.pop().map(|m| m.content);
 
         // Remove the last assistant message from api_messages
         if let Some(pos) = self.api_messages.iter().rposition(|m| m.role == "assistant") {
@@ -931,13 +3278,19 @@ impl App {
 
         // Add placeholder for new assistant response
         self.messages.push(ChatMessage {
+            id: history::new_message_id(),
             role: "assistant".into(),
             content: String::new(),
             timestamp: chrono::Utc::now(),
             tool_invocations: Vec::new(),
+            note: None,
+            stop_reason: None,
+            thinking: None,
+            meta: None,
+            attachments: Vec::new(),
         });
 
-        self.streaming = true;
+        self.set_session(SessionState::Streaming);
         self.stream_start_time = Some(std::time::Instant::now());
         self.stream_buffer.clear();
         self.scroll_to_bottom();
@@ -949,7 +3302,7 @@ impl App {
 
     /// Edit the last user message: put it back in input, remove it and the following assistant response.
     pub fn edit_last_message(&mut self) {
-        if self.streaming {
+        if self.is_streaming() {
             self.status_message = Some("Cannot edit while streaming".into());
             return;
         }
@@ -1009,11 +3362,21 @@ impl App {
 
     fn handle_slash_command(&mut self, cmd: &str) -> anyhow::Result<()> {
         let parts: Vec<&str> = cmd.splitn(2, ' ').collect();
+        if self.viewer && !VIEWER_COMMANDS.contains(&parts[0]) {
+            self.status_message = Some(format!(
+                "Read-only viewer: {} is unavailable (try {})",
+                parts[0],
+                VIEWER_COMMANDS.join(" ")
+            ));
+            return Ok(());
+        }
         match parts[0] {
             "/clear" | "/c" => {
                 self.messages.clear();
                 self.api_messages.clear();
                 self.tool_invocations.clear();
+                self.flush_conversation();
+
                 self.conversation = Conversation::new();
                 self.status_message = Some("Conversation cleared".into());
             }
@@ -1029,50 +3392,114 @@ impl App {
                     self.status_message = Some(format!("Current model: {}", self.config.model));
                 }
             }
-            "/models" => {
-                self.status_message = Some(
-                    "Aliases: sonnet/s, opus/o, haiku/h, gpt4, gpt4m, grok/grok3, grok3m, grok2, deepseek, llama, mistral, gemini"
-                        .into(),
-                );
-            }
             "/provider" | "/p" => {
                 if let Some(provider) = parts.get(1) {
-                    self.config.provider = provider.to_string();
-                    self.status_message = Some(format!("Provider set to {provider}"));
+                    let provider = provider.trim();
+                    self.status_message = Some(match self.set_provider(provider) {
+                        Ok(()) => format!("Provider set to {provider}"),
+                        Err(e) => e,
+                    });
                 } else {
-                    self.status_message = Some(format!("Current provider: {}", self.config.provider));
+                    self.status_message = Some(format!(
+                        "Current provider: {} (available: {})",
+                        self.config.provider,
+                        self.config.provider_names().join(", ")
+                    ));
                 }
             }
             "/system" | "/s" => {
                 if let Some(prompt) = parts.get(1) {
-                    self.config.system_prompt = Some(prompt.to_string());
+                    self.set_system_prompt(Some(prompt.to_string()));
                     self.status_message = Some("System prompt updated".into());
                 } else {
-                    self.status_message = Some(
-                        self.config.system_prompt.as_deref()
-                            .unwrap_or("No system prompt set")
-                            .to_string()
-                    );
+                    self.prompt_editor = PromptEditor::new(self.system_prompt().unwrap_or(""));
+                    self.overlay = Overlay::SystemPrompt;
                 }
             }
             "/history" | "/h" => {
+                let arg = parts.get(1).map(|a| a.trim());
+                match arg {
+                    Some("here") => self.history_project_only = true,
+                    Some("all") => self.history_project_only = false,
+                    _ => {}
+                }
+                self.history_trash = arg == Some("trash");
                 self.overlay = Overlay::History;
                 self.load_history_list();
             }
             "/help" | "/?" => {
                 self.overlay = Overlay::Help;
             }
+            "/stats" => {
+                self.overlay = Overlay::Stats;
+                self.overlay_scroll = 0;
+            }
+            "/cost" => {
+                self.status_message = Some(self.cost_summary());
+            }
+            "/code" => self.handle_code_command(parts.get(1).map(|s| s.trim()).unwrap_or("")),
+            "/refresh" => {
+                if parts.get(1).map(|s| s.trim()) == Some("dismiss") {
+                    let n = self.watched_files.take_changed().len();
+                    self.status_message = Some(format!("Dismissed {n} changed file(s)"));
+                } else {
+                    self.refresh_changed_files();
+                    return Ok(());
+                }
+            }
+            "/cache" => match parts.get(1).map(|s| s.trim()) {
+                Some("clear") => {
+                    self.tool_cache.clear();
+                    self.status_message = Some("Tool cache cleared".into());
+                }
+                _ => {
+                    self.status_message = Some(format!(
+                        "Tool cache: {} entries, {} hits (/cache clear to empty)",
+                        self.tool_cache.len(),
+                        self.tool_cache.hits
+                    ));
+                }
+            },
+            "/tee" => self.handle_tee_command(parts.get(1).map(|s| s.trim()).unwrap_or("")),
+            "/json" => self.handle_json_command(parts.get(1).map(|s| s.trim()).unwrap_or("")),
+            "/preview" => {
+                self.input.clear();
+                self.cursor_pos = 0;
+                self.show_request_preview();
+                return Ok(());
+            }
+            "/dashboard" => {
+                let conversations = Conversation::list_all(&self.config.history_dir()).unwrap_or_default();
+                self.dashboard = usage::Dashboard::build(&usage::load_all(), &conversations, |m| {
+                    self.config.price_per_mtok(m)
+                });
+                self.overlay = Overlay::Dashboard;
+                self.overlay_scroll = 0;
+            }
             "/temp" | "/t" => {
                 if let Some(temp) = parts.get(1) {
                     if let Ok(t) = temp.parse::<f32>() {
-                        let t = clamp_temperature(t);
-                        self.config.temperature = t;
+                        let t = self.config.set_temperature(t);
                         self.status_message = Some(format!("Temperature set to {t}"));
                     }
                 } else {
-                    self.status_message = Some(format!("Temperature: {}", self.config.temperature));
+                    self.status_message = Some(format!("Temperature: {}", self.config.effective_temperature()));
                 }
             }
+            "/top_p" | "/top_k" | "/stop" => {
+                self.handle_sampling_command(&parts[0][1..], parts.get(1).copied().unwrap_or(""));
+            }
+            "/continue" => self.continue_response(false),
+            "/thinking" => self.handle_thinking_command(parts.get(1).map(|s| s.trim()).unwrap_or("")),
+            "/mcp" => self.show_mcp_status(),
+            "/attach" => self.handle_attach_command(parts.get(1).map(|s| s.trim()).unwrap_or("")),
+            "/footer" => {
+                self.config.message_footer = !self.config.message_footer;
+                self.status_message = Some(format!(
+                    "Message footers {}",
+                    if self.config.message_footer { "on" } else { "off" }
+                ));
+            }
             "/save" => {
                 self.config.save()?;
                 self.status_message = Some("Config saved".into());
@@ -1099,20 +3526,56 @@ impl App {
                             self.tools_enabled = false;
                             self.status_message = Some("Tools disabled".into());
                         }
-                        _ => {
-                            self.status_message = Some("Usage: /tools [on|off]".into());
+                        arg => {
+                            let permission = match arg.split_once(' ') {
+                                Some(("allow", name)) => Some((ToolPermission::AutoAllow, name.trim())),
+                                Some(("ask", name)) => Some((ToolPermission::AskFirst, name.trim())),
+                                Some(("deny", name)) => Some((ToolPermission::Deny, name.trim())),
+                                _ => None,
+                            };
+                            match permission {
+                                Some((ToolPermission::AutoAllow, "mcp")) => {
+                                    self.status_message =
+                                        Some("MCP tools are allowed one at a time: /tools allow <server>__<tool>".into());
+                                }
+                                Some((permission, name))
+                                    if tools::TOOL_NAMES.contains(&name)
+                                        || name == "mcp"
+                                        || mcp::resolve(name).is_some() =>
+                                {
+                                    self.remember_tool_permission(name, permission);
+                                }
+                                Some((_, name)) => self.status_message = Some(format!("Unknown tool: {name}")),
+                                None => {
+                                    self.status_message =
+                                        Some("Usage: /tools [on|off|allow <name>|ask <name>|deny <name>]".into());
+                                }
+                            }
                         }
                     }
                 } else {
                     let status = if self.tools_enabled { "on" } else { "off" };
-                    let perms: Vec<String> = ["read_file", "write_file", "edit_file", "list_files", "search_files", "execute"]
+                    let offered = match self.config.tool_set() {
+                        Some(tools::ToolSet::Full) => String::new(),
+                        Some(tools::ToolSet::Core) => format!(" (core set only for {})", self.config.model),
+                        None => format!(" (not offered to {})", self.config.model),
+                    };
+                    let mcp_tools: Vec<String> = match self.config.mcp.is_empty() {
+                        true => Vec::new(),
+                        false => std::iter::once("mcp".to_string())
+                            .chain(mcp::tools().into_iter().map(|(_, tool)| tool.exposed))
+                            .collect(),
+                    };
+                    let perms: Vec<String> = tools::TOOL_NAMES
                         .iter()
+                        .copied()
+                        .chain(mcp_tools.iter().map(String::as_str))
                         .map(|t| {
                             let p = self.tool_executor.permission(t);
                             format!("  {t}: {p:?}")
                         })
                         .collect();
-                    self.status_message = Some(format!("Tools: {status}\n{}", perms.join("\n")));
+                    self.status_message = Some(format!("Tools: {status}{offered}\n{}", perms.join("\n")));
                 }
             }
             "/file" | "/f" => {
@@ -1145,13 +3608,7 @@ impl App {
                                         false
                                     };
 
-                                    let size_display = if file_size >= 1024 * 1024 {
-                                        format!("{:.1} MB", file_size as f64 / (1024.0 * 1024.0))
-                                    } else if file_size >= 1024 {
-                                        format!("{:.1} KB", file_size as f64 / 1024.0)
-                                    } else {
-                                        format!("{} B", file_size)
-                                    };
+                                    let size_display = format_size(file_size);
 
                                     if truncated {
                                         self.input = format!(
@@ -1163,6 +3620,7 @@ impl App {
                                         );
                                     }
                                     self.cursor_pos = 0;
+                                    self.watched_files.watch(path);
                                     self.status_message = Some(format!(
                                         "Loaded {filename} ({size_display}) into input"
                                     ));
@@ -1194,7 +3652,7 @@ impl App {
                     }
                 } else {
                     // Fall back to the most recently updated conversation
-                    match Conversation::latest() {
+                    match Conversation::latest(&self.config.history_dir()) {
                         Ok(Some(conv)) => {
                             let id = conv.id.clone();
                             match self.load_conversation(&id) {
@@ -1229,6 +3687,66 @@ impl App {
                     }
                 }
             }
+            "/profile" => {
+                match parts.get(1).map(|a| a.trim()) {
+                    Some(name) if !name.is_empty() => self.switch_profile(name),
+                    _ => {
+                        let active = self.config.active_profile.as_deref().unwrap_or("default");
+                        let names: Vec<String> = self.config.profiles.keys()
+                            .map(|name| match self.config.pack_entries.profiles.get(name) {
+                                Some(pack) => format!("{name} [{pack}]"),
+                                None => name.clone(),
+                            })
+                            .collect();
+                        self.status_message = Some(if names.is_empty() {
+                            "No profiles defined (add [profiles.<name>] to config.toml)".into()
+                        } else {
+                            format!("Profile: {active} (available: default, {})", names.join(", "))
+                        });
+                    }
+                }
+            }
+            "/note" => self.set_note(parts.get(1).unwrap_or(&"")),
+            "/replay" => {
+                let mut simulate = false;
+                let mut id = None;
+                for arg in parts.get(1).unwrap_or(&"").split_whitespace() {
+                    match arg {
+                        "stream" => simulate = true,
+                        other => id = Some(other.to_string()),
+                    }
+                }
+                if let Some(id) = id
+                    && let Err(e) = self.load_conversation(&id)
+                {
+                    self.status_message = Some(format!("Cannot load conversation {id}: {e}"));
+                    return Ok(());
+                }
+                self.start_replay(simulate);
+            }
+            "/diffresp" => {
+                self.show_response_diff();
+            }
+            "/review" => {
+                self.open_review();
+            }
+            "/trust" => self.trust_project(),
+            "/cd" => {
+                self.change_dir(parts.get(1).map_or("", |a| a.trim()));
+            }
+            "/autonomy" => {
+                self.set_autonomy(parts.get(1).map_or("", |a| a.trim()));
+            }
+            "/sandbox" => {
+                self.sandbox_command(parts.get(1).map_or("", |a| a.trim()));
+            }
+            "/bookmarks" | "/bm" => {
+                self.overlay = Overlay::Bookmarks;
+                self.overlay_scroll = 0;
+            }
+            "/goto" | "/g" => {
+                self.goto_message(parts.get(1).copied().unwrap_or(""));
+            }
             "/export" => {
                 self.export_conversation(parts.get(1).map(|s| s.trim()));
             }
@@ -1257,13 +3775,13 @@ impl App {
                 // We cannot call async from here, so remove the last assistant
                 // message and set a flag via status_message that retry is needed.
                 // Instead, inline the sync part and leave it to the user to re-send.
-                if self.streaming {
+                if self.is_streaming() {
                     self.status_message = Some("Cannot retry while streaming".into());
-                } else if self.messages.last().map_or(true, |m| m.role != "assistant") {
+                } else if self.messages.last().is_none_or(|m| m.role != "assistant") {
                     self.status_message = Some("No assistant message to retry".into());
                 } else {
                     // Remove the last assistant message
-                    self.messages.pop();
+                    self.discarded_response = self.messages.pop().map(|m| m.content);
                     if let Some(pos) = self.api_messages.iter().rposition(|m| m.role == "assistant") {
                         self.api_messages.remove(pos);
                     }
@@ -1280,17 +3798,78 @@ impl App {
                 self.edit_last_message();
                 return Ok(());
             }
+            "/queue" => {
+                let arg = parts.get(1).map(|s| s.trim()).unwrap_or("");
+                match arg {
+                    "" => {
+                        self.status_message = Some(match self.prompt_queue.front() {
+                            None => "Queue is empty. Usage: /queue <prompt>".into(),
+                            Some(next) => format!(
+                                "{} queued, next: {}",
+                                self.prompt_queue.len(),
+                                next.chars().take(60).collect::<String>()
+                            ),
+                        });
+                    }
+                    "clear" => {
+                        self.prompt_queue.clear();
+                        self.status_message = Some("Queue cleared".into());
+                    }
+                    prompt => {
+                        self.prompt_queue.push_back(prompt.to_string());
+                        self.input.clear();
+                        self.cursor_pos = 0;
+                        if self.is_streaming() {
+                            self.status_message = Some(format!("{} prompts queued", self.prompt_queue.len()));
+                        } else {
+                            self.send_next_queued();
+                        }
+                        return Ok(());
+                    }
+                }
+            }
+            "/recipe" => {
+                let arg = parts.get(1).map(|s| s.trim()).unwrap_or("");
+                if arg.is_empty() {
+                    self.status_message = Some(if self.config.recipes.is_empty() {
+                        "No recipes defined (add [recipes.<name>] to config.toml)".into()
+                    } else {
+                        let list: Vec<String> = self.config.recipes.iter()
+                            .map(|(name, r)| {
+                                let pack = self.config.pack_entries.recipes.get(name)
+                                    .map(|pack| format!(" [{pack}]"))
+                                    .unwrap_or_default();
+                                match &r.description {
+                                    Some(d) => format!("{name}{pack} ({d})"),
+                                    None => format!("{name}{pack}"),
+                                }
+                            })
+                            .collect();
+                        format!("Recipes: {}", list.join(", "))
+                    });
+                } else {
+                    let (name, extra) = arg.split_once(char::is_whitespace).unwrap_or((arg, ""));
+                    self.input.clear();
+                    self.cursor_pos = 0;
+                    self.run_recipe(name, extra);
+                    return Ok(());
+                }
+            }
+            "/resend" => {
+                self.input.clear();
+                self.cursor_pos = 0;
+                self.resend_failed();
+                return Ok(());
+            }
             "/run" | "/!" => {
                 if let Some(cmd_str) = parts.get(1) {
                     let cmd_str = cmd_str.trim();
                     if cmd_str.is_empty() {
                         self.status_message = Some("Usage: /run <command>".into());
                     } else {
-                        match std::process::Command::new("sh")
-                            .arg("-c")
-                            .arg(cmd_str)
-                            .output()
-                        {
+                        let mut command = std::process::Command::new("sh");
+                        self.config.tool_env.apply(&mut command);
+                        match command.arg("-c").arg(cmd_str).output() {
                             Ok(output) => {
                                 let stdout = String::from_utf8_lossy(&output.stdout).to_string();
                                 let stderr = String::from_utf8_lossy(&output.stderr).to_string();
@@ -1350,6 +3929,28 @@ impl App {
         Ok(())
     }
 
+    /// Text printed to stdout after the terminal is restored, per
+    /// `print_on_exit`. `None` when there is nothing to print.
+    pub fn exit_output(&self, mode: PrintOnExit) -> Option<String> {
+        match mode {
+            PrintOnExit::None => None,
+            PrintOnExit::Last => self.messages.iter()
+                .rev()
+                .find(|m| m.role == "assistant" && !m.content.is_empty())
+                .map(|m| m.content.clone()),
+            PrintOnExit::Conversation => {
+                let text: Vec<String> = self.messages.iter()
+                    .filter(|m| !m.content.is_empty())
+                    .map(|m| {
+                        let label = if m.role == "user" { "You" } else { "Assistant" };
+                        format!("## {label}\n\n{}", m.content.trim_end())
+                    })
+                    .collect();
+                (!text.is_empty()).then(|| text.join("\n\n"))
+            }
+        }
+    }
+
     /// Export the current conversation to a markdown file.
     fn export_conversation(&mut self, path_arg: Option<&str>) {
         if self.messages.is_empty() {
@@ -1374,7 +3975,8 @@ impl App {
                 "assistant" => "Assistant",
                 _ => "System",
             };
-            content.push_str(&format!("## {label}\n\n"));
+            content.push_str(&format!("## {label} <a id=\"{}\"></a>\n\n", msg.id));
+            content.push_str(&format!("*#{}*\n\n", msg.id));
             content.push_str(&msg.content);
             content.push_str("\n\n");
 
@@ -1399,31 +4001,143 @@ impl App {
         }
     }
 
+    /// Log the just-finished response to the usage log. Tokens are the
+    /// provider's counts, or estimated from the request context and the
+    /// streamed reply when it reported none.
+    fn record_response_usage(&self) {
+        let tokens = self.response_usage.unwrap_or_else(|| {
+            let context_chars = serde_json::to_string(&self.api_messages).map_or(0, |s| s.len());
+            TokenUsage {
+                input_tokens: (context_chars / 4) as u64,
+                output_tokens: (self.stream_buffer.len() / 4) as u64,
+            }
+        });
+        let (provider, model) = self.request_model();
+        usage::record(UsageEvent::Response {
+            provider: provider.to_string(),
+            model: model.to_string(),
+            input_tokens: tokens.input_tokens,
+            output_tokens: tokens.output_tokens,
+            latency_ms: self.last_response_time.map(|d| d.as_millis() as u64),
+        });
+    }
+
+    /// Remember the latest rate limits, and warn when one is nearly used up
+    /// so a long tool loop doesn't run straight into a 429.
+    fn update_rate_limits(&mut self, limits: api::RateLimits) {
+        self.rate_limits = Some(limits);
+        if let Some((name, limit)) = limits.low() {
+            let reset = limit
+                .reset_at
+                .map(|at| format!(", resets in {}", ui::format_until(at, chrono::Utc::now())))
+                .unwrap_or_default();
+            self.status_message = Some(format!(
+                "⚠ Rate limit nearly reached: {}/{} {name} left{reset}",
+                limit.remaining, limit.limit
+            ));
+        }
+    }
+
+    /// Footer details for the response that just finished. Call before the
+    /// stop reason and usage are taken for the next one.
+    fn response_meta(&self) -> ResponseMeta {
+        ResponseMeta {
+            model: self.request_model().1.to_string(),
+            stop_reason: self.response_stop_reason.clone(),
+            input_tokens: self.response_usage.map(|u| u.input_tokens),
+            output_tokens: self.response_usage.map(|u| u.output_tokens),
+            latency_ms: self.last_response_time.map(|d| d.as_millis() as u64),
+        }
+    }
+
+    /// What this conversation's responses cost in USD, counting only models
+    /// with a known price. `None` until one of them has been used.
+    pub fn conversation_cost(&self) -> Option<f64> {
+        self.model_usage
+            .iter()
+            .filter_map(|(model, usage)| {
+                let (input, output) = self.config.price_per_mtok(model)?;
+                Some((usage.input_tokens as f64 * input + usage.output_tokens as f64 * output) / 1e6)
+            })
+            .reduce(|a, b| a + b)
+    }
+
+    fn cost_summary(&self) -> String {
+        if self.model_usage.is_empty() {
+            return "No token usage reported yet in this conversation".into();
+        }
+        let mut summary = format!(
+            "Cost: ${:.4} ({} in, {} out tokens)",
+            self.conversation_cost().unwrap_or(0.0),
+            self.token_usage.input_tokens,
+            self.token_usage.output_tokens
+        );
+        let unpriced: Vec<&str> = self
+            .model_usage
+            .keys()
+            .filter(|m| self.config.price_per_mtok(m).is_none())
+            .map(String::as_str)
+            .collect();
+        if !unpriced.is_empty() {
+            summary.push_str(&format!(
+                "; no price for {} (set input_price/output_price under [models.\"<id>\"])",
+                unpriced.join(", ")
+            ));
+        }
+        summary
+    }
+
+    /// Add the finished response's reported tokens to the conversation total.
+    fn finish_response_usage(&mut self) {
+        if let Some(usage) = self.response_usage.take() {
+            self.token_usage.add(usage);
+            let model = self.request_model().1.to_string();
+            self.model_usage.entry(model).or_default().add(usage);
+            self.last_usage = Some(usage);
+        }
+    }
+
     fn default_export_path(&self) -> std::path::PathBuf {
         let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
         std::path::PathBuf::from(format!("./chat-export-{timestamp}.md"))
     }
 
+    /// Keep a partial response in api_messages and history so context is
+    /// preserved after a cancelled or failed stream.
+    fn keep_partial_response(&mut self) {
+        if self.continuing.take().is_some() {
+            self.api_messages.pop();
+        }
+        self.api_messages.push(Message {
+            role: "assistant".into(),
+            content: MessageContent::Text(self.stream_buffer.clone()),
+        });
+        let id = self.streaming_message_id();
+        self.conversation.set_message(&id, "assistant", &self.stream_buffer);
+        self.save_and_track_conversation();
+    }
+
     pub fn cancel_stream(&mut self) {
-        self.streaming = false;
+        // Dropping the task drops the response body, closing the connection
+        // instead of downloading the rest of a reply nobody will see
+        if let Some(task) = self.stream_task.take() {
+            task.abort();
+        }
+        self.set_session(SessionState::Idle);
         self.stream_start_time = None;
         if !self.stream_buffer.is_empty() {
-            // Keep the partial response in api_messages so context is preserved
-            self.api_messages.push(Message {
-                role: "assistant".into(),
-                content: MessageContent::Text(self.stream_buffer.clone()),
-            });
-            self.conversation.add_message("assistant", &self.stream_buffer);
-            self.save_and_track_conversation();
+            self.keep_partial_response();
         } else {
             // Remove the empty assistant placeholder from display messages
-            if let Some(last) = self.messages.last() {
-                if last.role == "assistant" && last.content.is_empty() {
-                    self.messages.pop();
-                }
+            if let Some(last) = self.messages.last()
+                && last.role == "assistant" && last.content.is_empty()
+            {
+                self.messages.pop();
             }
         }
         self.stream_buffer.clear();
+        self.finish_response_usage();
+        self.tool_executor.close_edit_batch();
         self.status_message = Some("Stream cancelled".into());
     }
 
@@ -1627,82 +4341,636 @@ impl App {
         if self.search_matches.is_empty() {
             return;
         }
-        if self.search_match_idx == 0 {
-            self.search_match_idx = self.search_matches.len() - 1;
-        } else {
-            self.search_match_idx -= 1;
+        if self.search_match_idx == 0 {
+            self.search_match_idx = self.search_matches.len() - 1;
+        } else {
+            self.search_match_idx -= 1;
+        }
+        self.scroll_to_match(self.search_match_idx);
+        self.status_message = Some(format!(
+            "/{}: match {}/{}",
+            self.search_query, self.search_match_idx + 1, self.search_matches.len()
+        ));
+    }
+
+    fn scroll_to_match(&mut self, match_idx: usize) {
+        if let Some(&msg_idx) = self.search_matches.get(match_idx) {
+            self.scroll_to_message(msg_idx);
+        }
+    }
+
+    fn scroll_to_message(&mut self, msg_idx: usize) {
+        self.focused_message = Some(msg_idx);
+        let estimated_line = msg_idx * 4;
+        self.scroll_offset = estimated_line;
+        self.auto_scroll = false;
+    }
+
+    /// Hide the conversation and reveal it one message at a time.
+    pub fn start_replay(&mut self, simulate_stream: bool) {
+        if self.is_streaming() {
+            self.status_message = Some("Cannot replay while streaming".into());
+            return;
+        }
+        if self.messages.is_empty() {
+            self.status_message = Some("Nothing to replay".into());
+            return;
+        }
+        self.replay = Some(ReplayState {
+            all: std::mem::take(&mut self.messages),
+            shown: 0,
+            simulate_stream,
+            typed: None,
+        });
+        self.replay_advance();
+    }
+
+    /// Reveal the next message, or finish typing out the current one.
+    pub fn replay_advance(&mut self) {
+        let Some(replay) = self.replay.as_mut() else { return };
+        if replay.typed.take().is_some() {
+            if let (Some(last), Some(full)) = (self.messages.last_mut(), replay.all.get(replay.shown - 1)) {
+                last.content = full.content.clone();
+            }
+            return;
+        }
+        let Some(next) = replay.all.get(replay.shown).cloned() else {
+            self.status_message = Some("End of replay (q to exit)".into());
+            return;
+        };
+        replay.shown += 1;
+        if replay.simulate_stream && next.role == "assistant" {
+            replay.typed = Some(0);
+            self.messages.push(ChatMessage { content: String::new(), ..next });
+        } else {
+            self.messages.push(next);
+        }
+        self.scroll_to_bottom();
+    }
+
+    /// Type out the message being "streamed" during a replay.
+    fn replay_tick(&mut self) {
+        let Some(replay) = self.replay.as_mut() else { return };
+        let Some(typed) = replay.typed else { return };
+        let full = &replay.all[replay.shown - 1].content;
+        let end = full.char_indices()
+            .nth(typed + REPLAY_CHARS_PER_TICK)
+            .map_or(full.len(), |(i, _)| i);
+        if let Some(last) = self.messages.last_mut() {
+            last.content = full[..end].to_string();
+        }
+        replay.typed = if end >= full.len() { None } else { Some(typed + REPLAY_CHARS_PER_TICK) };
+        self.scroll_to_bottom();
+    }
+
+    /// Leave replay mode and show the whole conversation again.
+    pub fn stop_replay(&mut self) {
+        if let Some(replay) = self.replay.take() {
+            self.messages = replay.all;
+            self.scroll_to_bottom();
+            self.status_message = Some("Replay ended".into());
+        }
+    }
+
+    fn handle_replay_key(&mut self, key: crossterm::event::KeyEvent) {
+        use crossterm::event::KeyCode;
+
+        match key.code {
+            KeyCode::Char(' ') | KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => {
+                self.replay_advance();
+            }
+            KeyCode::Char('s') => {
+                if let Some(replay) = self.replay.as_mut() {
+                    replay.simulate_stream = !replay.simulate_stream;
+                }
+            }
+            KeyCode::Char('j') | KeyCode::Down => self.scroll_down(1),
+            KeyCode::Char('k') | KeyCode::Up => self.scroll_up(1),
+            KeyCode::Char('q') | KeyCode::Esc => self.stop_replay(),
+            _ => {}
+        }
+    }
+
+    /// In JSON mode, check the finished reply parses (and matches the schema,
+    /// if any) and show it pretty-printed. History keeps the raw reply.
+    fn format_json_reply(&mut self) {
+        let Some(ref json) = self.json_mode else { return };
+        let value = match structured::extract_json(&self.stream_buffer) {
+            Ok(value) => value,
+            Err(e) => {
+                self.status_message = Some(format!("Reply is not valid JSON: {e}"));
+                return;
+            }
+        };
+        let errors = json.schema.as_ref()
+            .map(|schema| structured::validate(&value, schema))
+            .unwrap_or_default();
+        self.status_message = Some(match errors.as_slice() {
+            [] if json.schema.is_some() => "JSON ✓ matches schema".to_string(),
+            [] => "JSON ✓".to_string(),
+            [first, rest @ ..] => format!("JSON schema: {first} (+{} more)", rest.len()),
+        });
+        let pretty = structured::pretty_folded(&value, json.fold_depth);
+        if let Some(last) = self.messages.last_mut()
+            && last.role == "assistant"
+        {
+            last.content = format!("```json\n{pretty}\n```");
+        }
+    }
+
+    /// In code mode, reduce the finished reply to its first code block, copy
+    /// it to the clipboard, and write it to the output file if one is set.
+    fn format_code_reply(&mut self) {
+        let Some(ref code) = self.code_mode else { return };
+        let (lang, snippet) = fenced_blocks(&self.stream_buffer)
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| (String::new(), self.stream_buffer.trim().to_string()));
+        if let Some(last) = self.messages.last_mut()
+            && last.role == "assistant"
+        {
+            last.content = format!("```{lang}\n{snippet}\n```");
+        }
+
+        let mut done = Vec::new();
+        if let Ok(mut clipboard) = arboard::Clipboard::new()
+            && clipboard.set_text(&snippet).is_ok()
+        {
+            done.push("copied".to_string());
+        }
+        if let Some(ref path) = code.output_file {
+            match std::fs::write(path, format!("{snippet}\n")) {
+                Ok(()) => done.push(format!("written to {}", path.display())),
+                Err(e) => done.push(format!("write to {} failed: {e}", path.display())),
+            }
+        }
+        let lines = snippet.lines().count();
+        self.status_message = Some(if done.is_empty() {
+            format!("Code: {lines} lines")
+        } else {
+            format!("Code: {lines} lines, {}", done.join(", "))
+        });
+    }
+
+    fn handle_code_command(&mut self, arg: &str) {
+        let (sub, rest) = arg.split_once(' ').map_or((arg, ""), |(a, b)| (a, b.trim()));
+        match sub {
+            "on" => {
+                self.code_mode.get_or_insert_with(CodeMode::default);
+                self.json_mode = None;
+                self.status_message = Some("Code mode on".into());
+            }
+            "off" => {
+                self.code_mode = None;
+                self.status_message = Some("Code mode off".into());
+            }
+            "file" => {
+                let code = self.code_mode.get_or_insert_with(CodeMode::default);
+                code.output_file = (!rest.is_empty()).then(|| rest.into());
+                self.json_mode = None;
+                self.status_message = Some(match rest {
+                    "" => "Code mode on, not writing to a file".into(),
+                    path => format!("Code mode on, writing snippets to {path}"),
+                });
+            }
+            _ => {
+                self.status_message = Some(match &self.code_mode {
+                    None => "Code mode off. Usage: /code on|off|file [path]".into(),
+                    Some(CodeMode { output_file: Some(path) }) => {
+                        format!("Code mode on, writing to {}", path.display())
+                    }
+                    Some(_) => "Code mode on".into(),
+                });
+            }
+        }
+    }
+
+    /// Put the new contents of files changed on disk into the input, so the
+    /// next message tells the model about them.
+    fn refresh_changed_files(&mut self) {
+        let changed = self.watched_files.take_changed();
+        if changed.is_empty() {
+            self.status_message = Some("No watched files have changed".into());
+            return;
+        }
+        let mut text = String::from("These files changed on disk since you last saw them:\n");
+        for path in &changed {
+            let ext = path.extension().map(|e| e.to_string_lossy().to_string()).unwrap_or_default();
+            match std::fs::read_to_string(path) {
+                Ok(content) => text.push_str(&format!(
+                    "\n`{}`:\n```{ext}\n{}\n```\n",
+                    path.display(),
+                    content.trim_end()
+                )),
+                Err(_) => text.push_str(&format!("\n`{}` was deleted.\n", path.display())),
+            }
+        }
+        self.input = text;
+        self.cursor_pos = self.input.len();
+        self.status_message = Some(format!("Loaded {} changed file(s) into input", changed.len()));
+    }
+
+    fn handle_tee_command(&mut self, arg: &str) {
+        match arg {
+            "" => {
+                self.status_message = Some(match &self.tee {
+                    Some(tee) => format!("Teeing output to {}", tee.path.display()),
+                    None => "Tee off. Usage: /tee <path>|off".into(),
+                });
+            }
+            "off" => {
+                self.status_message = Some(match self.tee.take() {
+                    Some(tee) => format!("Stopped teeing to {}", tee.path.display()),
+                    None => "Tee was not on".into(),
+                });
+            }
+            path => {
+                let path = std::path::PathBuf::from(path);
+                match std::fs::OpenOptions::new().create(true).append(true).open(&path) {
+                    Ok(file) => {
+                        self.status_message = Some(format!("Appending assistant output to {}", path.display()));
+                        self.tee = Some(Tee { path, file });
+                    }
+                    Err(e) => {
+                        self.status_message = Some(format!("Cannot open {}: {e}", path.display()));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Append streamed text to the `/tee` file, turning tee off if it fails.
+    fn tee_write(&mut self, text: &str) {
+        use std::io::Write;
+        let Some(tee) = &mut self.tee else { return };
+        if let Err(e) = tee.file.write_all(text.as_bytes()) {
+            self.status_message = Some(format!("Tee to {} stopped: {e}", tee.path.display()));
+            self.tee = None;
+        }
+    }
+
+    fn handle_json_command(&mut self, arg: &str) {
+        let (sub, rest) = arg.split_once(' ').map_or((arg, ""), |(a, b)| (a, b.trim()));
+        match sub {
+            "on" => {
+                self.json_mode.get_or_insert_with(JsonMode::default);
+                self.code_mode = None;
+                self.status_message = Some("JSON mode on".into());
+            }
+            "off" => {
+                self.json_mode = None;
+                self.status_message = Some("JSON mode off".into());
+            }
+            "schema" if rest.is_empty() => {
+                if let Some(ref mut json) = self.json_mode {
+                    json.schema = None;
+                    json.schema_path = None;
+                }
+                self.status_message = Some("JSON schema cleared".into());
+            }
+            "schema" => {
+                let loaded = std::fs::read_to_string(rest)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|text| Ok(serde_json::from_str::<Value>(&text)?));
+                match loaded {
+                    Ok(schema) => {
+                        let json = self.json_mode.get_or_insert_with(JsonMode::default);
+                        json.schema = Some(schema);
+                        json.schema_path = Some(rest.to_string());
+                        self.status_message = Some(format!("JSON mode on, validating against {rest}"));
+                    }
+                    Err(e) => self.status_message = Some(format!("Cannot load schema {rest}: {e}")),
+                }
+            }
+            "fold" => match rest.parse::<usize>() {
+                Ok(depth) => {
+                    self.json_mode.get_or_insert_with(JsonMode::default).fold_depth = depth;
+                    self.status_message = Some(if depth == 0 {
+                        "JSON folding off".into()
+                    } else {
+                        format!("Folding JSON deeper than {depth} levels")
+                    });
+                }
+                Err(_) => self.status_message = Some("Usage: /json fold <depth>".into()),
+            },
+            _ => {
+                self.status_message = Some(match &self.json_mode {
+                    None => "JSON mode off. Usage: /json on|off|schema [path]|fold <depth>".into(),
+                    Some(j) => format!(
+                        "JSON mode on{}",
+                        j.schema_path.as_deref().map(|p| format!(", schema {p}")).unwrap_or_default()
+                    ),
+                });
+            }
+        }
+    }
+
+    /// Describe what sending now would transmit: the assembled system prompt,
+    /// every message (plus the typed input as the next user turn), and tool
+    /// definitions, with rough token counts.
+    pub fn request_preview(&self) -> RequestPreview {
+        let system = self.assembled_system_prompt(self.system_prompt());
+        let mut messages: Vec<Message> = self.api_messages.clone();
+        let pending = self.input.trim();
+        if !pending.is_empty() && !pending.starts_with('/') {
+            messages.push(Message {
+                role: "user".into(),
+                content: MessageContent::Text(pending.to_string()),
+            });
+        }
+        let messages = messages
+            .iter()
+            .map(|m| {
+                let text = preview_text(&m.content);
+                let total_lines = text.lines().count();
+                PreviewMessage {
+                    role: m.role.clone(),
+                    tokens: serde_json::to_string(&m.content).map_or(0, |s| s.len()) / 4,
+                    excerpt: text
+                        .lines()
+                        .take(PREVIEW_LINES)
+                        .map(|l| {
+                            let mut line: String = l.chars().take(PREVIEW_LINE_CHARS).collect();
+                            if l.chars().count() > PREVIEW_LINE_CHARS {
+                                line.push('…');
+                            }
+                            line
+                        })
+                        .collect(),
+                    hidden_lines: total_lines.saturating_sub(PREVIEW_LINES),
+                }
+            })
+            .collect();
+
+        // Mirrors spawn_api_call: tool definitions only go to providers that support them
+        let tools_sent = self.config.tool_set().filter(|_| self.tools_enabled);
+        RequestPreview {
+            provider: self.config.provider.clone(),
+            model: self.config.model.clone(),
+            max_tokens: self.config.effective_max_tokens(),
+            temperature: self.config.effective_temperature(),
+            system_tokens: system.as_ref().map_or(0, |s| s.len() / 4),
+            system,
+            messages,
+            tools: tools_sent.map(tools::tool_names).unwrap_or_default(),
+            tool_tokens: tools_sent.map_or(0, |set| tools::format_tool_definitions(set).to_string().len() / 4),
+        }
+    }
+
+    pub fn show_request_preview(&mut self) {
+        self.request_preview = self.request_preview();
+        self.overlay = Overlay::RequestPreview;
+        self.overlay_scroll = 0;
+    }
+
+    /// Compare the reply discarded by the last retry with its regeneration.
+    pub fn show_response_diff(&mut self) {
+        let Some(old) = self.discarded_response.as_deref() else {
+            self.status_message = Some("No discarded response to compare (retry a response first)".into());
+            return;
+        };
+        let current = self.messages.last().filter(|m| m.role == "assistant" && !m.content.is_empty());
+        let Some(new) = current.filter(|_| !self.is_streaming()) else {
+            self.status_message = Some("Wait for the regenerated response to finish".into());
+            return;
+        };
+        self.response_diff = word_diff(old, &new.content);
+        self.overlay = Overlay::ResponseDiff;
+        self.overlay_scroll = 0;
+    }
+
+    /// Open the Review overlay over every file tools changed this session.
+    pub fn open_review(&mut self) {
+        self.review_files = self.session_changes.files();
+        if self.review_files.is_empty() {
+            self.status_message = Some("No files changed by tools this session".into());
+            return;
+        }
+        self.overlay = Overlay::Review;
+        self.overlay_scroll = 0;
+        self.review_hunk = 0;
+        self.review_revert_pending = false;
+    }
+
+    pub fn selected_review_file(&self) -> Option<&review::FileReview> {
+        self.review_files.get(self.overlay_scroll)
+    }
+
+    /// Select another file (j/k), starting at its first hunk.
+    pub fn review_select_file(&mut self, delta: isize) {
+        let last = self.review_files.len().saturating_sub(1);
+        self.overlay_scroll = self.overlay_scroll.saturating_add_signed(delta).min(last);
+        self.review_hunk = 0;
+    }
+
+    /// Step to the next (n) or previous (p) hunk, crossing into the
+    /// neighbouring file at either end.
+    pub fn review_step_hunk(&mut self, forward: bool) {
+        let hunks = self.selected_review_file().map_or(0, |f| f.hunks.len());
+        if forward {
+            if self.review_hunk + 1 < hunks {
+                self.review_hunk += 1;
+            } else if self.overlay_scroll + 1 < self.review_files.len() {
+                self.review_select_file(1);
+            }
+        } else if self.review_hunk > 0 {
+            self.review_hunk -= 1;
+        } else if self.overlay_scroll > 0 {
+            self.review_select_file(-1);
+            self.review_hunk = self.selected_review_file().map_or(0, |f| f.hunks.len().saturating_sub(1));
+        }
+    }
+
+    /// Keep the selected file's changes and drop it from the review.
+    pub fn review_accept(&mut self) {
+        let Some(path) = self.selected_review_file().map(|f| f.path.clone()) else {
+            return;
+        };
+        self.session_changes.accept(&path);
+        self.status_message = Some(format!("Accepted {}", path.display()));
+        self.refresh_review();
+    }
+
+    pub fn request_review_revert(&mut self) {
+        if let Some(file) = self.selected_review_file() {
+            self.status_message = Some(format!("Revert {} to its state before this session? (y/n)", file.path.display()));
+            self.review_revert_pending = true;
+        }
+    }
+
+    pub fn confirm_review_revert(&mut self, yes: bool) {
+        self.review_revert_pending = false;
+        let Some(path) = self.selected_review_file().map(|f| f.path.clone()).filter(|_| yes) else {
+            self.status_message = None;
+            return;
+        };
+        match self.session_changes.revert(&path) {
+            Ok(()) => {
+                // Our own write; don't report it as an outside change
+                self.watched_files.watch(&path);
+                self.tool_cache.clear();
+                self.status_message = Some(format!("Reverted {}", path.display()));
+            }
+            Err(e) => self.status_message = Some(format!("Cannot revert {}: {e}", path.display())),
+        }
+        self.refresh_review();
+    }
+
+    /// Recompute the review after a file was accepted or reverted, closing
+    /// the overlay once nothing is left.
+    fn refresh_review(&mut self) {
+        self.review_files = self.session_changes.files();
+        self.review_hunk = 0;
+        if self.review_files.is_empty() {
+            self.overlay = Overlay::None;
+            self.overlay_scroll = 0;
+        } else {
+            self.overlay_scroll = self.overlay_scroll.min(self.review_files.len() - 1);
+        }
+    }
+
+    /// The message acted on by bookmarks and notes: the focused message
+    /// (last search hit or /goto target), else the latest assistant reply.
+    fn target_message(&self) -> Option<usize> {
+        self.focused_message
+            .filter(|&i| i < self.messages.len())
+            .or_else(|| self.messages.iter().rposition(|m| m.role == "assistant"))
+    }
+
+    /// Toggle a bookmark on the target message.
+    pub fn toggle_bookmark(&mut self) {
+        let Some(msg) = self.target_message().map(|i| &self.messages[i]) else {
+            self.status_message = Some("No message to bookmark".into());
+            return;
+        };
+        let bookmark = Bookmark {
+            conversation_id: self.conversation.id.clone(),
+            conversation_title: self.conversation.title.clone(),
+            message_id: msg.id.clone(),
+            role: msg.role.clone(),
+            excerpt: msg.content.lines().find(|l| !l.trim().is_empty())
+                .unwrap_or("").chars().take(80).collect(),
+            created_at: chrono::Utc::now(),
+        };
+        let id = msg.id.clone();
+        let added = history::toggle_bookmark(&mut self.bookmarks, bookmark);
+        // Make sure the conversation exists on disk so the bookmark can be opened later
+        self.save_and_track_conversation();
+        self.status_message = Some(match Bookmark::save_all(&self.bookmarks) {
+            Err(e) => format!("Failed to save bookmarks: {e}"),
+            Ok(()) if added => format!("Bookmarked #{id}"),
+            Ok(()) => format!("Removed bookmark #{id}"),
+        });
+    }
+
+    /// Open the command line prefilled with the target message's note (`gn`).
+    pub fn begin_note(&mut self) {
+        if self.viewer {
+            self.status_message = Some("Read-only viewer: notes can't be added".into());
+            return;
+        }
+        let Some(idx) = self.target_message() else {
+            self.status_message = Some("No message to annotate".into());
+            return;
+        };
+        let existing = self.messages[idx].note.clone().unwrap_or_default();
+        self.command_input = format!("note {existing}");
+        self.input_mode = InputMode::Command;
+    }
+
+    /// Attach a private note to the target message; an empty note removes it.
+    pub fn set_note(&mut self, text: &str) {
+        let Some(idx) = self.target_message() else {
+            self.status_message = Some("No message to annotate".into());
+            return;
+        };
+        let text = text.trim();
+        let note = (!text.is_empty()).then(|| text.to_string());
+        let msg = &mut self.messages[idx];
+        msg.note = note.clone();
+        let id = msg.id.clone();
+        if let Some(saved) = self.conversation.messages.iter_mut().find(|m| m.id == id) {
+            saved.note = note.clone();
+        }
+        self.save_and_track_conversation();
+        self.status_message = Some(match note {
+            Some(_) => format!("Note added to #{id}"),
+            None => format!("Note removed from #{id}"),
+        });
+    }
+
+    pub fn is_bookmarked(&self, message_id: &str) -> bool {
+        self.bookmarks.iter().any(|b| {
+            b.message_id == message_id && b.conversation_id == self.conversation.id
+        })
+    }
+
+    /// Remove the bookmark selected in the bookmarks overlay.
+    pub fn delete_selected_bookmark(&mut self) {
+        if self.overlay_scroll < self.bookmarks.len() {
+            self.bookmarks.remove(self.overlay_scroll);
+            let _ = Bookmark::save_all(&self.bookmarks);
+            if self.overlay_scroll >= self.bookmarks.len() && self.overlay_scroll > 0 {
+                self.overlay_scroll -= 1;
+            }
         }
-        self.scroll_to_match(self.search_match_idx);
-        self.status_message = Some(format!(
-            "/{}: match {}/{}",
-            self.search_query, self.search_match_idx + 1, self.search_matches.len()
-        ));
     }
 
-    fn scroll_to_match(&mut self, match_idx: usize) {
-        if let Some(&msg_idx) = self.search_matches.get(match_idx) {
-            let estimated_line = msg_idx * 4;
-            self.scroll_offset = estimated_line;
+    /// Jump to the message whose ID starts with `id` (a leading `#` is ignored).
+    pub fn goto_message(&mut self, id: &str) {
+        let id = id.trim().trim_start_matches('#');
+        if id.is_empty() {
+            self.status_message = Some("Usage: /goto <message-id>".into());
+            return;
+        }
+        let found: Vec<usize> = self.messages.iter()
+            .enumerate()
+            .filter(|(_, m)| m.id.starts_with(id))
+            .map(|(i, _)| i)
+            .collect();
+        match found.as_slice() {
+            [idx] => {
+                self.scroll_to_message(*idx);
+                self.status_message = Some(format!("Message #{}", self.messages[*idx].id));
+            }
+            [] => self.status_message = Some(format!("No message with id #{id}")),
+            _ => self.status_message = Some(format!("Ambiguous id #{id}: {} matches", found.len())),
         }
     }
 
     pub fn paste_clipboard(&mut self) {
-        if let Ok(mut clipboard) = arboard::Clipboard::new() {
-            if let Ok(text) = clipboard.get_text() {
-                // save_undo_state is called by insert_char, but we save once
-                // here so the entire paste can be undone in a single step.
-                self.save_undo_state();
-                for c in text.chars() {
-                    self.input.insert(self.cursor_pos, c);
-                    self.cursor_pos += c.len_utf8();
-                }
+        if let Ok(mut clipboard) = arboard::Clipboard::new()
+            && let Ok(text) = clipboard.get_text()
+        {
+            // save_undo_state is called by insert_char, but we save once
+            // here so the entire paste can be undone in a single step.
+            self.save_undo_state();
+            for c in text.chars() {
+                self.input.insert(self.cursor_pos, c);
+                self.cursor_pos += c.len_utf8();
             }
         }
     }
 
     pub fn yank_last_response(&mut self) {
-        if let Some(last) = self.messages.iter().rev().find(|m| m.role == "assistant") {
-            if let Ok(mut clipboard) = arboard::Clipboard::new() {
-                let _ = clipboard.set_text(&last.content);
-                self.status_message = Some("Response copied to clipboard".into());
-            }
+        if let Some(last) = self.messages.iter().rev().find(|m| m.role == "assistant")
+            && let Ok(mut clipboard) = arboard::Clipboard::new()
+        {
+            let _ = clipboard.set_text(&last.content);
+            self.status_message = Some("Response copied to clipboard".into());
         }
     }
 
     /// Scan all assistant messages for fenced code blocks (```...```)
     /// and store them in self.code_blocks as (msg_idx, language, content).
     pub fn extract_code_blocks(&mut self) {
-        self.code_blocks.clear();
-        for (msg_idx, msg) in self.messages.iter().enumerate() {
-            if msg.role != "assistant" {
-                continue;
-            }
-            let content = &msg.content;
-            let mut search_from = 0;
-            while let Some(fence_start) = content[search_from..].find("```") {
-                let abs_fence_start = search_from + fence_start;
-                let after_backticks = abs_fence_start + 3;
-                // Extract language from the opening fence line
-                let line_end = content[after_backticks..]
-                    .find('\n')
-                    .map(|i| after_backticks + i)
-                    .unwrap_or(content.len());
-                let lang = content[after_backticks..line_end].trim().to_string();
-                let code_start = if line_end < content.len() { line_end + 1 } else { line_end };
-                // Find closing fence
-                if let Some(close_pos) = content[code_start..].find("```") {
-                    let abs_close = code_start + close_pos;
-                    let code_content = content[code_start..abs_close].to_string();
-                    // Strip trailing newline from code content
-                    let code_content = code_content.trim_end_matches('\n').to_string();
-                    self.code_blocks.push((msg_idx, lang, code_content));
-                    // Skip past the closing fence
-                    search_from = abs_close + 3;
-                } else {
-                    break;
-                }
-            }
-        }
+        self.code_blocks = self.messages.iter()
+            .enumerate()
+            .filter(|(_, m)| m.role == "assistant")
+            .flat_map(|(msg_idx, m)| {
+                fenced_blocks(&m.content).into_iter().map(move |(lang, code)| (msg_idx, lang, code))
+            })
+            .collect();
     }
 
     /// Copy the code block at the given index to the system clipboard.
@@ -1786,19 +5054,39 @@ impl App {
         }
 
         // Check if we should do file path completion instead of command completion
-        let file_cmd_prefixes = ["/file ", "/f ", "/export "];
+        let file_cmd_prefixes = ["/file ", "/f ", "/export ", "/attach "];
         for prefix in &file_cmd_prefixes {
             if self.input.starts_with(prefix) {
                 self.tab_complete_path(prefix);
                 return;
             }
         }
+        for prefix in ["/provider ", "/p "] {
+            if let Some(partial) = self.input.strip_prefix(prefix) {
+                let matches: Vec<String> = self.config.provider_names()
+                    .into_iter()
+                    .filter(|p| p.starts_with(partial))
+                    .collect();
+                if let [only] = matches.as_slice() {
+                    self.input = format!("{prefix}{only}");
+                    self.cursor_pos = self.input.len();
+                } else if !matches.is_empty() {
+                    self.status_message = Some(matches.join("  "));
+                }
+                return;
+            }
+        }
 
         let commands = [
             "/clear", "/new", "/model", "/models", "/provider", "/system",
             "/history", "/help", "/temp", "/save", "/nvim", "/tools", "/file",
             "/context", "/paste", "/resume", "/diff", "/export", "/theme",
             "/retry", "/edit", "/quit", "/run", "/undo", "/redo", "/setup",
+            "/stats", "/goto", "/profile", "/bookmarks", "/diffresp",
+            "/replay", "/note", "/dashboard", "/resend", "/queue",
+            "/preview", "/json", "/code", "/recipe", "/ticket", "/recall", "/tee",
+            "/cache", "/refresh", "/review", "/autonomy", "/sandbox", "/cd", "/trust", "/cost", "/thinking", "/continue", "/top_p", "/top_k", "/stop", "/footer",
+            "/attach", "/mcp",
         ];
         let matches: Vec<&&str> = commands.iter()
             .filter(|c| c.starts_with(&self.input))
@@ -1891,11 +5179,11 @@ impl App {
             self.status_message = Some(format!("{}{}", display.join("  "), suffix));
 
             // Auto-complete the common prefix among matches
-            if let Some(common) = common_prefix(&matches) {
-                if common.len() > partial.len() {
-                    self.input = format!("{}{}", prefix, common);
-                    self.cursor_pos = self.input.len();
-                }
+            if let Some(common) = common_prefix(&matches)
+                && common.len() > partial.len()
+            {
+                self.input = format!("{}{}", prefix, common);
+                self.cursor_pos = self.input.len();
             }
         }
     }
@@ -1905,12 +5193,23 @@ impl App {
         self.messages.clear();
         self.api_messages.clear();
         self.tool_invocations.clear();
+        self.flush_conversation();
+        self.tool_executor.close_edit_batch();
+
         self.conversation = Conversation::new();
+        self.error_cards.clear();
+        self.pending_attachments.clear();
         self.status_message = Some("Conversation cleared".into());
     }
 
     pub fn overlay_scroll_down(&mut self) {
         self.overlay_scroll = self.overlay_scroll.saturating_add(1);
+        if self.overlay == Overlay::Picker {
+            self.overlay_scroll = self.overlay_scroll.min(self.history_list.len());
+        }
+        if self.overlay == Overlay::Models {
+            self.overlay_scroll = self.overlay_scroll.min(self.model_list.len().saturating_sub(1));
+        }
     }
 
     pub fn overlay_scroll_up(&mut self) {
@@ -1919,6 +5218,52 @@ impl App {
 
     pub fn overlay_select(&mut self) {
         match self.overlay {
+            Overlay::Bookmarks => {
+                if let Some(b) = self.bookmarks.get(self.overlay_scroll).cloned() {
+                    self.overlay = Overlay::None;
+                    self.overlay_scroll = 0;
+                    if b.conversation_id != self.conversation.id
+                        && let Err(e) = self.load_conversation(&b.conversation_id)
+                    {
+                        self.status_message = Some(format!("Cannot open conversation: {e}"));
+                        return;
+                    }
+                    self.goto_message(&b.message_id);
+                }
+            }
+            Overlay::Recall => {
+                if let Some(hit) = self.recall_hits.get(self.overlay_scroll).cloned() {
+                    self.overlay = Overlay::None;
+                    self.overlay_scroll = 0;
+                    if hit.conversation_id != self.conversation.id
+                        && let Err(e) = self.load_conversation(&hit.conversation_id)
+                    {
+                        self.status_message = Some(format!("Cannot open conversation: {e}"));
+                        return;
+                    }
+                    self.goto_message(&hit.message_id);
+                }
+            }
+            Overlay::Models => {
+                if let Some(model) = self.model_list.get(self.overlay_scroll).cloned() {
+                    self.config.model = model.clone();
+                    self.overlay = Overlay::None;
+                    self.overlay_scroll = 0;
+                    self.status_message = Some(format!("Model set to {model}"));
+                }
+            }
+            Overlay::Picker => {
+                // Row 0 is "New chat"; conversations follow
+                let picked = self.overlay_scroll.checked_sub(1)
+                    .and_then(|i| self.history_list.get(i))
+                    .map(|c| c.id.clone());
+                self.overlay = Overlay::None;
+                self.overlay_scroll = 0;
+                if let Some(id) = picked {
+                    let _ = self.load_conversation(&id);
+                }
+            }
+            Overlay::History if self.history_trash => self.restore_history_entry(),
             Overlay::History => {
                 if let Some(conv) = self.history_list.get(self.overlay_scroll) {
                     let id = conv.id.clone();
@@ -1933,6 +5278,37 @@ impl App {
         }
     }
 
+    /// Switch to a named profile (or `default` for the top-level settings).
+    /// The current chat is saved under the old profile and a fresh one starts,
+    /// since the profile may use a different history directory.
+    pub fn switch_profile(&mut self, name: &str) {
+        if !self.messages.is_empty() {
+            self.save_and_track_conversation();
+        }
+        self.flush_conversation();
+
+        let result = if name == "default" {
+            self.config.clear_profile();
+            Ok(())
+        } else {
+            self.config.apply_profile(name)
+        };
+        match result {
+            Ok(()) => {
+                self.messages.clear();
+                self.api_messages.clear();
+                self.tool_invocations.clear();
+                self.conversation = Conversation::new();
+                self.scroll_offset = 0;
+                self.status_message = Some(format!(
+                    "Profile: {name} ({} / {})",
+                    self.config.provider, self.config.model
+                ));
+            }
+            Err(e) => self.status_message = Some(e.to_string()),
+        }
+    }
+
     pub fn new_conversation(&mut self) {
         if !self.messages.is_empty() {
             self.save_and_track_conversation();
@@ -1940,9 +5316,52 @@ impl App {
         self.messages.clear();
         self.api_messages.clear();
         self.tool_invocations.clear();
+        self.flush_conversation();
+        self.tool_executor.close_edit_batch();
+
         self.conversation = Conversation::new();
+        self.error_cards.clear();
+        self.pending_attachments.clear();
+        self.token_usage = TokenUsage::default();
+        self.model_usage.clear();
+        self.last_usage = None;
         self.scroll_offset = 0;
+        self.focused_message = None;
         self.status_message = Some("New conversation".into());
+        self.load_welcome_recent();
+    }
+
+    /// Refresh the recent conversations shown on the welcome screen.
+    pub fn load_welcome_recent(&mut self) {
+        self.welcome_recent = match (self.history_project_only, history::current_project()) {
+            (true, Some(project)) => Conversation::list_for_project(&self.config.history_dir(), &project),
+            _ => Conversation::list_all(&self.config.history_dir()),
+        }
+        .unwrap_or_default();
+        self.welcome_recent.truncate(WELCOME_RECENT);
+        self.welcome_selected = 0;
+    }
+
+    /// Whether the welcome screen with recent conversations is showing.
+    pub fn on_welcome(&self) -> bool {
+        self.messages.is_empty()
+            && self.failed_sends.is_empty()
+            && self.error_cards.is_empty()
+            && !self.welcome_recent.is_empty()
+            && !self.viewer
+    }
+
+    pub fn welcome_move(&mut self, delta: isize) {
+        let last = self.welcome_recent.len().saturating_sub(1);
+        self.welcome_selected = self.welcome_selected.saturating_add_signed(delta).min(last);
+    }
+
+    /// Open the conversation highlighted on the welcome screen.
+    pub fn open_welcome_selection(&mut self) {
+        let Some(id) = self.welcome_recent.get(self.welcome_selected).map(|c| c.id.clone()) else { return };
+        if let Err(e) = self.load_conversation(&id) {
+            self.status_message = Some(format!("Cannot open conversation: {e}"));
+        }
     }
 
     pub fn load_project_context(&mut self) {
@@ -1988,15 +5407,63 @@ impl App {
             cwd.display()
         );
 
-        // Prepend context to the system prompt
-        let existing_prompt = self.config.system_prompt.clone().unwrap_or_default();
-        self.config.system_prompt = Some(format!(
-            "{existing_prompt}\n\n--- Project Context ---\n{context}"
-        ));
+        // Kept apart from the configured prompt so `/save` doesn't persist it
+        self.project_context = Some(context);
 
         self.status_message = Some(format!("Loaded project context for '{dir_name}'"));
     }
 
+    /// The system prompt actually sent: `base` plus any loaded project context
+    /// and the JSON or code mode instruction.
+    pub fn assembled_system_prompt(&self, base: Option<&str>) -> Option<String> {
+        let mut prompt = match (base.filter(|b| !b.is_empty()), &self.project_context) {
+            (base, Some(context)) => Some(format!(
+                "{}\n\n--- Project Context ---\n{context}",
+                base.unwrap_or_default()
+            )),
+            (base, None) => base.map(str::to_string),
+        };
+        let instruction = match (&self.json_mode, &self.code_mode) {
+            (Some(json), _) => Some(structured::json_instruction(json.schema.as_ref())),
+            (None, Some(_)) => Some(structured::CODE_INSTRUCTION.to_string()),
+            (None, None) => None,
+        };
+        if let Some(instruction) = instruction {
+            prompt = Some(match prompt {
+                Some(p) => format!("{p}\n\n--- Output Format ---\n{instruction}"),
+                None => format!("--- Output Format ---\n{instruction}"),
+            });
+        }
+        prompt
+    }
+
+    /// The system prompt in effect: the project's if it sets one, otherwise
+    /// the configured one.
+    pub fn system_prompt(&self) -> Option<&str> {
+        self.project_settings
+            .as_ref()
+            .filter(|_| self.project_trusted)
+            .and_then(|p| p.system_prompt.as_deref())
+            .or(self.config.system_prompt.as_deref())
+    }
+
+    /// Replace the system prompt for this session, including one that came
+    /// from project settings.
+    fn set_system_prompt(&mut self, prompt: Option<String>) {
+        if let Some(project) = &mut self.project_settings {
+            project.system_prompt = None;
+        }
+        self.config.system_prompt = prompt;
+    }
+
+    /// Apply the draft from the system prompt editor.
+    pub fn save_prompt_editor(&mut self) {
+        let text = self.prompt_editor.text.trim_end();
+        self.set_system_prompt((!text.is_empty()).then(|| text.to_string()));
+        self.overlay = Overlay::None;
+        self.status_message = Some("System prompt updated".into());
+    }
+
     pub fn paste_clipboard_as_codeblock(&mut self) {
         if let Ok(mut clipboard) = arboard::Clipboard::new() {
             match clipboard.get_text() {
@@ -2033,6 +5500,9 @@ impl App {
             "grok" | "grok3" => "grok-3".into(),
             "grok3m" => "grok-3-mini".into(),
             "grok2" => "grok-2".into(),
+            // Google
+            "gemini-pro" => "gemini-2.5-pro".into(),
+            "gemini-flash" => "gemini-2.5-flash".into(),
             // OpenRouter popular models
             "deepseek" => "deepseek/deepseek-chat-v3-0324".into(),
             "llama" | "llama4" => "meta-llama/llama-4-maverick".into(),
@@ -2043,8 +5513,172 @@ impl App {
     }
 
     pub fn load_history_list(&mut self) {
-        self.history_list = Conversation::list_all().unwrap_or_default();
+        self.history_list = match (self.history_project_only, history::current_project()) {
+            _ if self.history_trash => Conversation::list_trash(&self.config.history_dir()),
+            (true, Some(project)) => Conversation::list_for_project(&self.config.history_dir(), &project),
+            _ => Conversation::list_all(&self.config.history_dir()),
+        }
+        .unwrap_or_default();
         self.overlay_scroll = 0;
+        self.clear_history_selection();
+    }
+
+    pub fn clear_history_selection(&mut self) {
+        self.history_marked.clear();
+        self.history_visual = None;
+        self.history_confirm = None;
+    }
+
+    pub fn has_history_selection(&self) -> bool {
+        !self.history_marked.is_empty() || self.history_visual.is_some()
+    }
+
+    /// Whether row `i` of the History overlay is marked or inside the visual range.
+    pub fn is_history_marked(&self, i: usize) -> bool {
+        let in_range = self.history_visual.is_some_and(|anchor| {
+            (anchor.min(self.overlay_scroll)..=anchor.max(self.overlay_scroll)).contains(&i)
+        });
+        in_range || self.history_list.get(i).is_some_and(|c| self.history_marked.contains(&c.id))
+    }
+
+    /// Ids of the marked conversations, in list order.
+    pub fn history_selection(&self) -> Vec<String> {
+        (0..self.history_list.len())
+            .filter(|&i| self.is_history_marked(i))
+            .map(|i| self.history_list[i].id.clone())
+            .collect()
+    }
+
+    /// Mark or unmark the highlighted conversation.
+    pub fn toggle_history_mark(&mut self) {
+        if let Some(conv) = self.history_list.get(self.overlay_scroll)
+            && !self.history_marked.remove(&conv.id)
+        {
+            self.history_marked.insert(conv.id.clone());
+        }
+        self.overlay_scroll_down();
+    }
+
+    /// Start a visual range at the cursor, or fold the current range into the marks.
+    pub fn toggle_history_visual(&mut self) {
+        if self.history_visual.is_some() {
+            self.history_marked.extend(self.history_selection());
+            self.history_visual = None;
+        } else if !self.history_list.is_empty() {
+            self.history_visual = Some(self.overlay_scroll);
+        }
+    }
+
+    /// Ask for confirmation before deleting or exporting the selection.
+    /// Without a selection, delete acts on the highlighted entry directly.
+    pub fn request_history_bulk(&mut self, action: HistoryBulkAction) {
+        let count = self.history_selection().len();
+        if count == 0 {
+            match action {
+                HistoryBulkAction::Delete => return self.delete_history_entry(),
+                HistoryBulkAction::Export if self.history_list.is_empty() => return,
+                HistoryBulkAction::Export => {}
+            }
+        }
+        let count = count.max(1);
+        let verb = match action {
+            HistoryBulkAction::Delete => "Delete",
+            HistoryBulkAction::Export => "Export",
+        };
+        self.history_confirm = Some(action);
+        self.status_message = Some(format!("{verb} {count} conversation(s)? (y/n)"));
+    }
+
+    /// Run (`yes`) or cancel the pending bulk action.
+    pub fn confirm_history_bulk(&mut self, yes: bool) {
+        let Some(action) = self.history_confirm.take() else { return };
+        if !yes {
+            self.status_message = Some("Cancelled".into());
+            return;
+        }
+        let mut ids = self.history_selection();
+        if ids.is_empty()
+            && let Some(conv) = self.history_list.get(self.overlay_scroll)
+        {
+            ids.push(conv.id.clone());
+        }
+        self.status_message = Some(match action {
+            HistoryBulkAction::Delete => {
+                let deleted = ids.iter().filter(|id| Conversation::delete(&self.config.history_dir(), id).is_ok()).count();
+                let scroll = self.overlay_scroll;
+                self.load_history_list();
+                self.overlay_scroll = scroll.min(self.history_list.len().saturating_sub(1));
+                match ids.len() - deleted {
+                    0 => format!("Moved {deleted} conversation(s) to trash"),
+                    failed => format!("Moved {deleted} conversation(s) to trash; {failed} failed"),
+                }
+            }
+            HistoryBulkAction::Export => {
+                let result = export_conversations(&self.config.history_dir(), &ids, &self.default_export_path().with_extension(""));
+                self.clear_history_selection();
+                match result {
+                    Ok((dir, n)) => format!("Exported {n} conversation(s) to {}", dir.display()),
+                    Err(e) => format!("Export failed: {e}"),
+                }
+            }
+        });
+    }
+
+    /// Toggle the history overlay between this project and all conversations.
+    pub fn toggle_history_project_filter(&mut self) {
+        self.history_project_only = !self.history_project_only;
+        self.load_history_list();
+    }
+
+    /// Scope the session to the current project (`pro --here`): history only
+    /// lists this project's conversations, and we resume the latest one from
+    /// here instead of whatever was open last.
+    pub fn scope_to_project(&mut self) {
+        self.history_project_only = true;
+        if self.overlay == Overlay::Picker {
+            // Let the user choose among this project's chats instead
+            self.load_history_list();
+            self.history_list.truncate(PICKER_RECENT);
+            return;
+        }
+        let Some(project) = history::current_project() else { return };
+        if self.conversation.project.as_deref() == Some(project.as_str()) {
+            return;
+        }
+        let latest = Conversation::list_for_project(&self.config.history_dir(), &project)
+            .ok()
+            .and_then(|convs| convs.into_iter().next());
+        match latest {
+            Some(conv) => {
+                let _ = self.load_conversation(&conv.id);
+                self.status_message = Some(format!("Resumed: {}", conv.title));
+            }
+            None => {
+                self.messages.clear();
+                self.api_messages.clear();
+                self.tool_invocations.clear();
+                self.flush_conversation();
+
+                self.conversation = Conversation::new();
+                self.status_message = None;
+            }
+        }
+    }
+
+    /// Restore the selected conversation in the trash view of the history overlay.
+    pub fn restore_history_entry(&mut self) {
+        let Some(conv) = self.history_list.get(self.overlay_scroll) else {
+            return;
+        };
+        let title = conv.title.clone();
+        match Conversation::restore(&self.config.history_dir(), &conv.id) {
+            Ok(()) => {
+                self.status_message = Some(format!("Restored: {title}"));
+                self.load_history_list();
+                self.overlay_scroll = self.overlay_scroll.min(self.history_list.len().saturating_sub(1));
+            }
+            Err(e) => self.status_message = Some(format!("Cannot restore conversation: {e}")),
+        }
     }
 
     /// Delete the currently selected conversation from the history overlay.
@@ -2052,8 +5686,10 @@ impl App {
         if let Some(conv) = self.history_list.get(self.overlay_scroll) {
             let title = conv.title.clone();
             let id = conv.id.clone();
-            if Conversation::delete(&id).is_ok() {
-                self.status_message = Some(format!("Deleted conversation: {title}"));
+            if Conversation::delete(&self.config.history_dir(), &id).is_ok() {
+                self.status_message = Some(format!(
+                    "Moved to trash: {title} (/history trash to restore)"
+                ));
                 self.load_history_list();
                 // Adjust scroll if we deleted the last item
                 if self.overlay_scroll >= self.history_list.len() && self.overlay_scroll > 0 {
@@ -2066,6 +5702,10 @@ impl App {
     }
 
     pub fn execute_command(&mut self, cmd: &str) {
+        if self.viewer && !matches!(cmd.trim(), "q" | "quit" | "help" | "h") {
+            self.status_message = Some("Read-only viewer: only :q and :help are available".into());
+            return;
+        }
         match cmd.trim() {
             "q" | "quit" => self.should_quit = true,
             "w" | "save" => {
@@ -2080,6 +5720,8 @@ impl App {
                 self.messages.clear();
                 self.api_messages.clear();
                 self.tool_invocations.clear();
+                self.flush_conversation();
+
                 self.conversation = Conversation::new();
             }
             "new" | "n" => self.new_conversation(),
@@ -2097,6 +5739,10 @@ impl App {
             _ => {
                 if let Some(rest) = cmd.strip_prefix("set ") {
                     self.handle_set_command(rest);
+                } else if cmd == "note" {
+                    self.set_note("");
+                } else if let Some(rest) = cmd.strip_prefix("note ") {
+                    self.set_note(rest);
                 } else if let Some(rest) = cmd.strip_prefix("model ") {
                     self.config.model = rest.trim().to_string();
                     self.status_message = Some(format!("Model: {}", self.config.model));
@@ -2117,14 +5763,16 @@ impl App {
                 }
             }
             "temp" | "temperature" => {
-                if let Some(val) = parts.get(1) {
-                    if let Ok(t) = val.trim().parse::<f32>() {
-                        let t = clamp_temperature(t);
-                        self.config.temperature = t;
-                        self.status_message = Some(format!("Temperature: {t}"));
-                    }
+                if let Some(val) = parts.get(1)
+                    && let Ok(t) = val.trim().parse::<f32>()
+                {
+                    let t = self.config.set_temperature(t);
+                    self.status_message = Some(format!("Temperature: {t}"));
                 }
             }
+            name @ ("top_p" | "top_k" | "stop") => {
+                self.handle_sampling_command(name, parts.get(1).copied().unwrap_or(""));
+            }
             "provider" => {
                 if let Some(val) = parts.get(1) {
                     self.config.provider = val.trim().to_string();
@@ -2141,6 +5789,16 @@ impl App {
                     "Tools: {}", if self.tools_enabled { "on" } else { "off" }
                 ));
             }
+            "clipboard" => {
+                let perm = if self.tool_executor.permission("read_clipboard") == ToolPermission::Deny {
+                    ToolPermission::AskFirst
+                } else {
+                    ToolPermission::Deny
+                };
+                self.tool_executor.set_permission("read_clipboard", perm);
+                self.session_permissions.insert("read_clipboard".into(), perm);
+                self.status_message = Some(format!("Clipboard tool: {perm:?}"));
+            }
             _ => {
                 self.status_message = Some(format!("Unknown setting: {}", parts[0]));
             }
@@ -2148,6 +5806,29 @@ impl App {
     }
 }
 
+/// Bump and return the launch counter kept in the data dir, which picks the
+/// welcome screen's tip.
+pub fn record_launch() -> usize {
+    let path = Config::data_dir().join("launches");
+    let count = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|s| s.trim().parse::<usize>().ok())
+        .unwrap_or(0)
+        + 1;
+    let _ = std::fs::create_dir_all(Config::data_dir())
+        .and_then(|_| history::write_atomic(&path, count.to_string().as_bytes()));
+    count
+}
+
+/// Whitespace-separated stop sequences, with `\n`, `\t` and `\s` standing
+/// for a newline, tab and space.
+fn parse_stop_sequences(value: &str) -> Vec<String> {
+    value
+        .split_whitespace()
+        .map(|s| s.replace("\\n", "\n").replace("\\t", "\t").replace("\\s", " "))
+        .collect()
+}
+
 /// Format tool arguments for display (public for use in UI).
 pub fn format_tool_args_public(tool: &tools::Tool) -> String {
     format_tool_args(tool)
@@ -2166,11 +5847,83 @@ fn format_tool_args(tool: &tools::Tool) -> String {
         tools::Tool::SearchFiles { pattern, path } => {
             format!("pattern: {pattern}{}", path.as_deref().map(|p| format!(", path: {p}")).unwrap_or_default())
         }
-        tools::Tool::Execute { command } => format!("$ {command}"),
+        tools::Tool::Execute { command, stdin: None } => format!("$ {command}"),
+        tools::Tool::Execute { command, stdin: Some(_) } => format!("$ {command}  (with stdin)"),
         tools::Tool::EditFile { path, old_text, new_text: _ } => {
             format!("path: {path}, replacing {} chars", old_text.len())
         }
+        tools::Tool::EditLines { path, start, end, .. } if end + 1 == *start => {
+            format!("path: {path}, inserting before line {start}")
+        }
+        tools::Tool::EditLines { path, start, end, .. } => {
+            format!("path: {path}, lines {start}-{end}")
+        }
+        tools::Tool::ApplyPatch { patch } => match crate::patch::parse(patch) {
+            Ok(files) => {
+                let hunks: usize = files.iter().map(|f| f.hunks.len()).sum();
+                let paths: Vec<&str> = files.iter().map(|f| f.path()).collect();
+                format!("{hunks} hunk(s) in {}", paths.join(", "))
+            }
+            Err(_) => format!("{} bytes (not a valid diff)", patch.len()),
+        },
+        tools::Tool::DeleteFile { path } => format!("path: {path}"),
+        tools::Tool::MoveFile { from, to } => format!("{from} → {to}"),
+        tools::Tool::ReadClipboard => "clipboard contents".to_string(),
+        tools::Tool::Screenshot { window } => {
+            if *window { "active window".to_string() } else { "full screen".to_string() }
+        }
+        tools::Tool::GhIssueView { repo, number } => format!("{repo}#{number}"),
+        tools::Tool::GhPrDiff { repo, number } => format!("{repo}#{number} diff"),
+        tools::Tool::GhPrComment { repo, number, body } => {
+            format!("{repo}#{number}, {} chars", body.chars().count())
+        }
+        tools::Tool::FetchTicket { key } => key.clone(),
+        tools::Tool::GitStatus { path } => path.clone().unwrap_or_default(),
+        tools::Tool::GitLog { path, max_count } => {
+            let count = max_count.map(|n| format!("last {n}")).unwrap_or_default();
+            [count, path.clone().unwrap_or_default()].join(" ").trim().to_string()
+        }
+        tools::Tool::GitShow { rev, path } => match path {
+            Some(path) => format!("{rev} -- {path}"),
+            None => rev.clone(),
+        },
+        tools::Tool::Retrieve { query, .. } => format!("query: {query}"),
+        tools::Tool::WebFetch { url, .. } => url.clone(),
+        tools::Tool::StartEditBatch | tools::Tool::CommitEditBatch => String::new(),
+        tools::Tool::Mcp { server, tool, input } => format!("{server}/{tool} {input}"),
+    }
+}
+
+/// Write each conversation saved in `history` to its own markdown file in `dir`.
+fn export_conversations(
+    history: &std::path::Path,
+    ids: &[String],
+    dir: &std::path::Path,
+) -> anyhow::Result<(std::path::PathBuf, usize)> {
+    std::fs::create_dir_all(dir)?;
+    for (n, id) in ids.iter().enumerate() {
+        let conv = Conversation::load(history, id)?;
+        let mut content = format!("# {}\n\n", conv.title);
+        for msg in &conv.messages {
+            let label = match msg.role.as_str() {
+                "user" => "You",
+                "assistant" => "Assistant",
+                _ => "System",
+            };
+            content.push_str(&format!("## {label} <a id=\"{}\"></a>\n\n{}\n\n", msg.id, msg.content));
+        }
+        let slug: String = conv.title
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+            .collect::<String>()
+            .split('-')
+            .filter(|s| !s.is_empty())
+            .take(8)
+            .collect::<Vec<_>>()
+            .join("-");
+        std::fs::write(dir.join(format!("{:02}-{slug}.md", n + 1)), content)?;
     }
+    Ok((dir.to_path_buf(), ids.len()))
 }
 
 /// Find the longest common prefix among a list of strings.
@@ -2189,17 +5942,257 @@ fn common_prefix(strings: &[String]) -> Option<String> {
             }
         }
     }
-    Some(first[..prefix_len].to_string())
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::config::Config;
+    Some(first[..prefix_len].to_string())
+}
+
+/// Word-level diff of two texts, with adjacent changes of the same kind merged.
+fn word_diff(old: &str, new: &str) -> Vec<(similar::ChangeTag, String)> {
+    let diff = similar::TextDiff::from_words(old, new);
+    let mut out: Vec<(similar::ChangeTag, String)> = Vec::new();
+    for change in diff.iter_all_changes() {
+        match out.last_mut() {
+            Some((tag, text)) if *tag == change.tag() => text.push_str(change.value()),
+            _ => out.push((change.tag(), change.value().to_string())),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    /// Create an App with default config for testing.
+    fn test_app() -> App {
+        App::new(Config::default())
+    }
+
+    #[test]
+    fn exit_output_modes() {
+        let mut app = test_app();
+        assert_eq!(app.exit_output(PrintOnExit::Last), None);
+        add_msg(&mut app, "user", "hi");
+        add_msg(&mut app, "assistant", "first");
+        add_msg(&mut app, "user", "again");
+        add_msg(&mut app, "assistant", "second\n");
+        assert_eq!(app.exit_output(PrintOnExit::None), None);
+        assert_eq!(app.exit_output(PrintOnExit::Last).as_deref(), Some("second\n"));
+        let all = app.exit_output(PrintOnExit::Conversation).unwrap();
+        assert!(all.starts_with("## You\n\nhi\n\n## Assistant\n\nfirst"));
+        assert!(all.ends_with("## Assistant\n\nsecond"));
+    }
+
+    #[tokio::test]
+    async fn send_while_streaming_queues_or_blocks() {
+        let mut app = test_app();
+        app.session = SessionState::Streaming;
+        app.set_input("next question");
+        app.send_message().await.unwrap();
+        assert_eq!(app.prompt_queue.front().map(String::as_str), Some("next question"));
+        assert!(app.input.is_empty());
+        assert!(app.messages.is_empty());
+
+        app.config.queue_while_streaming = false;
+        app.set_input("another");
+        app.send_message().await.unwrap();
+        assert_eq!(app.prompt_queue.len(), 1);
+        assert_eq!(app.input, "another");
+        assert!(app.status_message.as_deref().unwrap().starts_with("Still responding"));
+    }
+
+    #[test]
+    fn watched_files_detect_changes() {
+        let path = std::env::temp_dir().join(format!("pro-chat-watch-{}.txt", std::process::id()));
+        std::fs::write(&path, "v1").unwrap();
+        let mut app = test_app();
+        app.watched_files.watch(&path);
+        assert!(!app.watched_files.poll());
+
+        std::fs::write(&path, "v2").unwrap();
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(5);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+        assert!(app.watched_files.poll());
+        assert!(!app.watched_files.poll(), "reported once");
+
+        app.refresh_changed_files();
+        assert!(app.input.contains("```txt\nv2\n```"));
+        assert!(app.watched_files.changed.is_empty());
+        assert!(!app.watched_files.poll());
+
+        // A write that keeps the mtime is still caught before an edit
+        assert!(!app.watched_files.is_stale(&path));
+        std::fs::write(&path, "v3").unwrap();
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+        assert!(app.watched_files.is_stale(&path));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn edit_blocked_when_file_changed_since_read() {
+        let path = std::env::temp_dir().join(format!("pro-chat-conflict-{}.txt", std::process::id()));
+        std::fs::write(&path, "original").unwrap();
+        let mut app = test_app();
+        app.watched_files.watch(&path);
+
+        // Simulate a manual edit after the model read the file
+        std::fs::write(&path, "manual edit").unwrap();
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(5);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+
+        app.pending_tool_calls = vec![ToolCall {
+            id: "t1".into(),
+            tool: tools::Tool::EditFile {
+                path: path.display().to_string(),
+                old_text: "manual".into(),
+                new_text: "model".into(),
+            },
+        }];
+        app.execute_tool_at_index(0).await;
+        let result = app.tool_invocations[0].result.as_ref().unwrap();
+        assert!(!result.success);
+        assert!(result.output.starts_with("File changed since read"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "manual edit");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn history_multi_select() {
+        let mut app = test_app();
+        app.history_list = (0..5).map(|_| Conversation::new()).collect();
+        app.overlay = Overlay::History;
+
+        app.toggle_history_mark();
+        assert_eq!(app.overlay_scroll, 1);
+        app.overlay_scroll = 2;
+        app.toggle_history_visual();
+        app.overlay_scroll_down();
+        app.overlay_scroll_down();
+        assert!(app.is_history_marked(3));
+        assert!(!app.is_history_marked(1));
+        app.toggle_history_visual();
+        let ids: Vec<String> = [0, 2, 3, 4].iter().map(|&i| app.history_list[i].id.clone()).collect();
+        assert_eq!(app.history_selection(), ids);
+
+        app.request_history_bulk(HistoryBulkAction::Delete);
+        assert_eq!(app.status_message.as_deref(), Some("Delete 4 conversation(s)? (y/n)"));
+        app.confirm_history_bulk(false);
+        assert_eq!(app.history_confirm, None);
+        assert_eq!(app.history_list.len(), 5);
+        assert_eq!(app.history_selection().len(), 4);
+    }
+
+    #[test]
+    fn provider_resolves_against_registry() {
+        let mut app = test_app();
+        app.config.providers.push(crate::config::CustomProvider {
+            name: "groq".into(),
+            base_url: "https://api.groq.com/openai/v1".into(),
+            ..Default::default()
+        });
+        app.handle_slash_command("/provider nonsense").unwrap();
+        assert_eq!(app.config.provider, "anthropic");
+        assert!(app.status_message.as_deref().unwrap().starts_with("Unknown provider"));
+
+        app.input = "/provider gr".into();
+        app.tab_complete();
+        assert_eq!(app.input, "/provider groq");
+        app.handle_slash_command("/provider groq").unwrap();
+        assert_eq!(app.config.provider, "groq");
+    }
+
+    #[tokio::test]
+    async fn failed_edit_rolls_back_edit_batch() {
+        let dir = std::env::temp_dir().join(format!("pro-chat-batch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a.rs"), dir.join("new.rs"));
+        std::fs::write(&a, "fn old() {}").unwrap();
+        let _ = std::fs::remove_file(&b);
+
+        let mut app = test_app();
+        app.config.sandbox.allow.push(dir.display().to_string());
+        app.tool_executor.set_sandbox(tools::Sandbox::from_config(&app.config.sandbox));
+        app.pending_tool_calls = vec![
+            ToolCall { id: "t1".into(), tool: tools::Tool::StartEditBatch },
+            ToolCall {
+                id: "t2".into(),
+                tool: tools::Tool::EditFile {
+                    path: a.display().to_string(),
+                    old_text: "old".into(),
+                    new_text: "new".into(),
+                },
+            },
+            ToolCall {
+                id: "t3".into(),
+                tool: tools::Tool::WriteFile { path: b.display().to_string(), content: "x".into() },
+            },
+            ToolCall {
+                id: "t4".into(),
+                tool: tools::Tool::EditFile {
+                    path: a.display().to_string(),
+                    old_text: "missing".into(),
+                    new_text: "y".into(),
+                },
+            },
+        ];
+        for i in 0..4 {
+            app.execute_tool_at_index(i).await;
+        }
+        assert_eq!(std::fs::read_to_string(&a).unwrap(), "fn old() {}");
+        assert!(!b.exists());
+        assert!(!app.tool_executor.edit_batch_open());
+        let result = app.tool_invocations[3].result.as_ref().unwrap();
+        assert!(result.output.contains("Edit batch rolled back"));
+        // Restored files are re-watched, so they are not reported as changed
+        assert!(!app.watched_files.is_stale(&a));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn new_conversation_closes_the_edit_batch() {
+        let dir = std::env::temp_dir().join(format!("pro-chat-batch-reset-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let a = dir.join("a.rs");
+        std::fs::write(&a, "fn old() {}").unwrap();
+
+        let mut app = test_app();
+        app.config.sandbox.allow.push(dir.display().to_string());
+        app.tool_executor.set_sandbox(tools::Sandbox::from_config(&app.config.sandbox));
+        let edit = |old: &str, new: &str| tools::Tool::EditFile {
+            path: a.display().to_string(),
+            old_text: old.into(),
+            new_text: new.into(),
+        };
+        app.pending_tool_calls = vec![
+            ToolCall { id: "t1".into(), tool: tools::Tool::StartEditBatch },
+            ToolCall { id: "t2".into(), tool: edit("old", "new") },
+        ];
+        for i in 0..2 {
+            app.execute_tool_at_index(i).await;
+        }
+        app.new_conversation();
+        assert!(!app.tool_executor.edit_batch_open());
+
+        // A failure in the next conversation must not undo the earlier edit
+        app.pending_tool_calls = vec![ToolCall { id: "t3".into(), tool: edit("missing", "y") }];
+        app.execute_tool_at_index(0).await;
+        assert_eq!(std::fs::read_to_string(&a).unwrap(), "fn new() {}");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 
-    /// Create an App with default config for testing.
-    fn test_app() -> App {
-        App::new(Config::default())
+    #[test]
+    fn tee_appends_streamed_output() {
+        let path = std::env::temp_dir().join(format!("pro-chat-tee-{}.md", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut app = test_app();
+        app.handle_tee_command(path.to_str().unwrap());
+        assert!(app.tee.is_some());
+        app.tee_write("# Title\n");
+        app.tee_write("body\n\n");
+        app.handle_tee_command("off");
+        app.tee_write("ignored");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "# Title\nbody\n\n");
+        let _ = std::fs::remove_file(&path);
     }
 
     // -----------------------------------------------------------------------
@@ -2252,10 +6245,16 @@ mod tests {
     fn slash_clear_resets_state() {
         let mut app = test_app();
         app.messages.push(ChatMessage {
+            id: history::new_message_id(),
             role: "user".into(),
             content: "hello".into(),
             timestamp: chrono::Utc::now(),
             tool_invocations: Vec::new(),
+            note: None,
+            stop_reason: None,
+            thinking: None,
+            meta: None,
+            attachments: Vec::new(),
         });
         app.api_messages.push(Message {
             role: "user".into(),
@@ -2275,10 +6274,16 @@ mod tests {
     fn slash_clear_alias() {
         let mut app = test_app();
         app.messages.push(ChatMessage {
+            id: history::new_message_id(),
             role: "user".into(),
             content: "test".into(),
             timestamp: chrono::Utc::now(),
             tool_invocations: Vec::new(),
+            note: None,
+            stop_reason: None,
+            thinking: None,
+            meta: None,
+            attachments: Vec::new(),
         });
         app.handle_slash_command("/c").unwrap();
         assert!(app.messages.is_empty());
@@ -2348,6 +6353,131 @@ mod tests {
         assert!(app.tools_enabled);
     }
 
+    #[test]
+    fn clipboard_tool_denied_until_enabled() {
+        let mut app = test_app();
+        assert_eq!(app.tool_executor.permission("read_clipboard"), ToolPermission::Deny);
+
+        app.execute_command("set clipboard");
+        assert_eq!(app.tool_executor.permission("read_clipboard"), ToolPermission::AskFirst);
+
+        app.execute_command("set clipboard");
+        assert_eq!(app.tool_executor.permission("read_clipboard"), ToolPermission::Deny);
+    }
+
+    #[tokio::test]
+    async fn tool_execution_records_stats() {
+        let mut app = test_app();
+        app.pending_tool_calls = vec![ToolCall {
+            id: "toolu_1".into(),
+            tool: tools::Tool::ListFiles { path: ".".into(), pattern: None },
+        }];
+        app.execute_tool_at_index(0).await;
+
+        let stats = &app.tool_stats["list_files"];
+        assert_eq!(stats.calls, 1);
+        assert_eq!(stats.failures, 0);
+        assert!(app.tool_invocations[0].duration.is_some());
+        // The usage log is the test's own, not the user's
+        let usage = std::fs::read_to_string(Config::data_dir().join("usage.jsonl")).unwrap();
+        assert!(usage.contains("list_files"));
+        assert!(Config::data_dir().starts_with(std::env::temp_dir()));
+
+        app.handle_slash_command("/stats").unwrap();
+        assert_eq!(app.overlay, Overlay::Stats);
+    }
+
+    #[test]
+    fn history_project_filter() {
+        let mut app = test_app();
+        assert_eq!(app.conversation.project, history::current_project());
+        // Written to the test's scratch history, never the user's
+        let mut here = Conversation::new();
+        here.add_message("m1", "user", "here");
+        here.save(&app.config.history_dir(), false).unwrap();
+        let mut elsewhere = Conversation::new();
+        elsewhere.project = Some("/elsewhere".into());
+        elsewhere.add_message("m1", "user", "elsewhere");
+        elsewhere.save(&app.config.history_dir(), false).unwrap();
+
+        app.handle_slash_command("/history here").unwrap();
+        assert!(app.history_project_only);
+        assert_eq!(app.overlay, Overlay::History);
+        let project = history::current_project();
+        assert!(app.history_list.iter().all(|c| c.project == project));
+        assert!(app.history_list.iter().any(|c| c.id == here.id));
+
+        app.handle_slash_command("/history all").unwrap();
+        assert!(!app.history_project_only);
+        assert!(app.history_list.iter().any(|c| c.id == elsewhere.id));
+    }
+
+    #[test]
+    fn stream_snapshot_includes_partial_response() {
+        let mut app = test_app();
+        app.conversation.add_message("m1", "user", "hello");
+        app.stream_buffer = "partial ans".into();
+
+        let snapshot = app.stream_snapshot();
+        assert_eq!(snapshot.id, app.conversation.id);
+        assert_eq!(snapshot.messages.len(), 2);
+        assert_eq!(snapshot.messages[1].role, "assistant");
+        assert_eq!(snapshot.messages[1].content, "partial ans");
+        // The live conversation only gains the reply once the stream finishes
+        assert_eq!(app.conversation.messages.len(), 1);
+    }
+
+    #[test]
+    fn slash_profile_switches_and_reports_unknown() {
+        let mut app = test_app();
+        app.config.profiles.insert("work".into(), crate::config::Profile {
+            model: Some("claude-opus-4-20250514".into()),
+            ..Default::default()
+        });
+
+        app.handle_slash_command("/profile work").unwrap();
+        assert_eq!(app.config.active_profile.as_deref(), Some("work"));
+        assert_eq!(app.config.model, "claude-opus-4-20250514");
+
+        app.handle_slash_command("/profile nope").unwrap();
+        assert!(app.status_message.as_deref().unwrap().starts_with("Unknown profile"));
+        assert_eq!(app.config.active_profile.as_deref(), Some("work"));
+
+        app.handle_slash_command("/profile default").unwrap();
+        assert!(app.config.active_profile.is_none());
+        assert_eq!(app.config.model, "claude-sonnet-4-20250514");
+    }
+
+    #[test]
+    fn startup_picker_new_chat_entry() {
+        let mut app = test_app();
+        app.overlay = Overlay::Picker;
+        app.overlay_scroll = 0;
+        app.overlay_select();
+        assert_eq!(app.overlay, Overlay::None);
+        assert!(app.messages.is_empty());
+    }
+
+    #[test]
+    fn bookmark_targets_focused_message() {
+        let mut app = test_app();
+        add_msg(&mut app, "user", "question");
+        add_msg(&mut app, "assistant", "answer");
+        let first = app.messages[0].id.clone();
+
+        app.goto_message(&first);
+        assert_eq!(app.focused_message, Some(0));
+
+        // The focused message is bookmarked, not the latest one
+        app.toggle_bookmark();
+        let bookmark = app.bookmarks.iter().find(|b| b.conversation_id == app.conversation.id).unwrap();
+        assert_eq!(bookmark.message_id, first);
+        assert_eq!(bookmark.excerpt, "question");
+
+        app.handle_slash_command("/bookmarks").unwrap();
+        assert_eq!(app.overlay, Overlay::Bookmarks);
+    }
+
     #[test]
     fn slash_help_opens_overlay() {
         let mut app = test_app();
@@ -2537,13 +6667,546 @@ mod tests {
 
     fn add_msg(app: &mut App, role: &str, content: &str) {
         app.messages.push(ChatMessage {
+            id: history::new_message_id(),
             role: role.into(),
             content: content.into(),
             timestamp: chrono::Utc::now(),
             tool_invocations: Vec::new(),
+            note: None,
+            stop_reason: None,
+            thinking: None,
+            meta: None,
+            attachments: Vec::new(),
         });
     }
 
+    #[test]
+    fn review_walks_hunks_across_files() {
+        let dir = std::env::temp_dir().join(format!("pro-chat-app-review-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+        let long: String = (0..20).map(|i| format!("{i}\n")).collect();
+        std::fs::write(&a, &long).unwrap();
+
+        let mut app = test_app();
+        app.open_review();
+        assert_eq!(app.overlay, Overlay::None);
+
+        app.session_changes.record(&a);
+        app.session_changes.record(&b);
+        std::fs::write(&a, long.replace("1\n", "one\n").replace("18\n", "eighteen\n")).unwrap();
+        std::fs::write(&b, "new\n").unwrap();
+        app.open_review();
+        assert_eq!(app.overlay, Overlay::Review);
+        assert_eq!(app.review_files[0].hunks.len(), 2);
+
+        app.review_step_hunk(true);
+        assert_eq!((app.overlay_scroll, app.review_hunk), (0, 1));
+        app.review_step_hunk(true);
+        assert_eq!((app.overlay_scroll, app.review_hunk), (1, 0));
+        app.review_step_hunk(false);
+        assert_eq!((app.overlay_scroll, app.review_hunk), (0, 1));
+
+        app.review_accept();
+        assert_eq!(app.review_files.len(), 1);
+        app.request_review_revert();
+        app.confirm_review_revert(true);
+        assert!(!b.exists());
+        assert_eq!(app.overlay, Overlay::None);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn cancel_aborts_the_request_task() {
+        let mut app = test_app();
+        let task = tokio::spawn(std::future::pending::<()>());
+        app.stream_task = Some(task.abort_handle());
+        app.session = SessionState::Streaming;
+        app.cancel_stream();
+        assert!(task.await.unwrap_err().is_cancelled());
+        assert!(app.stream_task.is_none());
+    }
+
+    #[test]
+    fn flash_cue_expires() {
+        let mut app = test_app();
+        app.cue(Cue::None);
+        assert!(!app.flashing());
+        app.cue(Cue::Flash);
+        assert!(app.flashing());
+        app.tick_count += FLASH_TICKS;
+        assert!(!app.flashing());
+    }
+
+    #[test]
+    fn thinking_command_sets_budget() {
+        let mut app = test_app();
+        app.handle_slash_command("/thinking 500").unwrap();
+        assert_eq!(app.config.thinking_budget, None);
+        assert!(app.status_message.as_deref().unwrap().contains("at least 1024"));
+        app.handle_slash_command("/thinking 8000").unwrap();
+        assert_eq!(app.config.thinking_budget, Some(8000));
+        app.handle_slash_command("/thinking show").unwrap();
+        assert!(app.show_thinking);
+        app.handle_slash_command("/thinking off").unwrap();
+        assert_eq!(app.config.thinking_budget, None);
+        assert!(app.show_thinking);
+    }
+
+    #[test]
+    fn welcome_selection_stays_in_range() {
+        let mut app = test_app();
+        app.welcome_recent = vec![Conversation::new(), Conversation::new()];
+        app.welcome_selected = 0;
+        assert!(app.on_welcome());
+        app.welcome_move(-1);
+        assert_eq!(app.welcome_selected, 0);
+        app.welcome_move(5);
+        assert_eq!(app.welcome_selected, 1);
+        add_msg(&mut app, "user", "hi");
+        assert!(!app.on_welcome());
+    }
+
+    #[test]
+    fn sampling_commands_set_and_clear() {
+        let mut app = test_app();
+        app.handle_slash_command("/top_p 0.9").unwrap();
+        app.handle_slash_command("/top_k 40").unwrap();
+        app.handle_slash_command(r"/stop \n\nHuman: </answer>").unwrap();
+        assert_eq!(app.sampling().top_p, Some(0.9));
+        assert_eq!(app.sampling().top_k, Some(40));
+        assert_eq!(app.sampling().stop_sequences, vec!["\n\nHuman:", "</answer>"]);
+
+        app.handle_slash_command("/top_p 1.5").unwrap();
+        assert_eq!(app.config.top_p, Some(0.9));
+        assert!(app.status_message.as_deref().unwrap().contains("Invalid top_p"));
+        app.execute_command("set top_k=off");
+        assert_eq!(app.config.top_k, None);
+        app.execute_command("set stop=off");
+        assert!(app.config.stop_sequences.is_empty());
+    }
+
+    #[test]
+    fn stop_reason_marks_cut_off_responses() {
+        let mut app = test_app();
+        add_msg(&mut app, "user", "write a long story");
+        add_msg(&mut app, "assistant", "Once upon a time");
+        app.continue_response(true);
+        assert!(app.status_message.as_deref().unwrap().starts_with("The last response was not cut off"));
+
+        app.response_stop_reason = Some("end_turn".into());
+        app.take_stop_reason();
+        assert_eq!(app.messages[1].stop_reason, None);
+
+        app.response_stop_reason = Some("max_tokens".into());
+        app.take_stop_reason();
+        assert_eq!(app.messages[1].stop_reason.as_deref(), Some("max_tokens"));
+        assert!(app.status_message.as_deref().unwrap().contains("press c to continue"));
+        assert!(app.response_stop_reason.is_none());
+
+        // A continued reply replaces the saved message instead of adding one
+        let id = app.messages[1].id.clone();
+        app.conversation.set_message(&id, "assistant", "Once upon a time");
+        app.conversation.set_message(&id, "assistant", "Once upon a time, there was");
+        assert_eq!(app.conversation.messages.len(), 1);
+        assert_eq!(app.conversation.messages[0].content, "Once upon a time, there was");
+    }
+
+    #[test]
+    fn trim_drops_oldest_whole_turns() {
+        let text = |role: &str, t: &str| Message { role: role.into(), content: MessageContent::Text(t.into()) };
+        let long = "x".repeat(400);
+        let mut messages = vec![
+            text("user", &long),
+            text("assistant", &long),
+            text("user", &long),
+            Message {
+                role: "assistant".into(),
+                content: MessageContent::Blocks(vec![serde_json::json!({"type": "tool_use", "id": "t1", "name": "glob", "input": {}})]),
+            },
+            Message {
+                role: "user".into(),
+                content: MessageContent::Blocks(vec![serde_json::json!({"type": "tool_result", "tool_use_id": "t1", "content": long})]),
+            },
+            text("assistant", &long),
+            text("user", "current"),
+        ];
+        let (dropped, tokens) = trim_oldest_turns(&mut messages).unwrap();
+        // The first turn alone isn't half, and the tool turn can't be split
+        assert_eq!(dropped, 6);
+        assert!(tokens > 500);
+        assert_eq!(messages.len(), 1);
+
+        // Nothing before the current turn: nothing to trim
+        assert_eq!(trim_oldest_turns(&mut messages), None);
+    }
+
+    #[test]
+    fn cost_sums_priced_models() {
+        let mut app = test_app();
+        app.handle_slash_command("/cost").unwrap();
+        assert!(app.status_message.as_deref().unwrap().starts_with("No token usage"));
+        assert_eq!(app.conversation_cost(), None);
+
+        for (model, output) in [("claude-sonnet-4-5", 1_000_000), ("mystery-model", 10)] {
+            app.config.model = model.into();
+            app.response_usage = Some(TokenUsage { input_tokens: 1_000_000, output_tokens: output });
+            app.finish_response_usage();
+        }
+        assert_eq!(app.token_usage.input_tokens, 2_000_000);
+        assert_eq!(app.conversation_cost(), Some(18.0));
+        app.handle_slash_command("/cost").unwrap();
+        let status = app.status_message.clone().unwrap();
+        assert!(status.starts_with("Cost: $18.0000"), "{status}");
+        assert!(status.contains("no price for mystery-model"), "{status}");
+
+        app.new_conversation();
+        assert_eq!(app.conversation_cost(), None);
+    }
+
+    #[test]
+    fn autonomy_levels_gate_confirmation() {
+        let mut app = test_app();
+        let safe = tools::Tool::Execute { command: "cargo test".into(), stdin: None };
+        let risky = tools::Tool::Execute { command: "rm -rf target".into(), stdin: None };
+        let outside = tools::Tool::WriteFile { path: "../elsewhere.txt".into(), content: String::new() };
+
+        app.autonomy = Autonomy::Low;
+        assert!(app.autonomy_requires_confirm(&safe));
+
+        app.autonomy = Autonomy::High;
+        assert!(!app.autonomy_requires_confirm(&safe));
+        assert!(app.autonomy_requires_confirm(&risky));
+        assert!(app.autonomy_requires_confirm(&outside));
+
+        app.autonomy = Autonomy::Medium;
+        app.config.autonomy_interval = 3;
+        app.unconfirmed_tool_calls = 1;
+        assert!(!app.autonomy_requires_confirm(&safe));
+        app.unconfirmed_tool_calls = 2;
+        assert!(app.autonomy_requires_confirm(&safe));
+
+        app.set_autonomy("bogus");
+        assert_eq!(app.autonomy, Autonomy::Medium);
+        assert!(app.status_message.as_deref().unwrap().starts_with("Unknown autonomy level"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn tool_calls_run_off_the_event_loop() {
+        let mut app = test_app();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        app.event_tx = Some(tx);
+        app.pending_tool_calls =
+            vec![ToolCall { id: "t1".into(), tool: tools::Tool::ReadFile { path: "Cargo.toml".into() } }];
+
+        assert!(app.execute_tool_at_index(0).await);
+        assert!(matches!(rx.recv().await, Some(Event::ToolStarted(0))));
+        app.show_tool_running(0);
+        assert!(app.tool_invocations[0].result.is_none());
+        let Some(Event::ToolFinished(0, result, elapsed)) = rx.recv().await else { panic!("no result") };
+        app.finish_tool_at_index(0, result, elapsed, false);
+        // The running row is filled in, not duplicated
+        assert_eq!(app.tool_invocations.len(), 1);
+        assert!(app.tool_invocations[0].result.as_ref().unwrap().output.contains("[package]"));
+
+        // A repeat comes from the cache without leaving the event loop
+        assert!(!app.execute_tool_at_index(0).await);
+        assert!(app.tool_invocations[1].cached);
+    }
+
+    #[test]
+    fn tool_input_overlay_answers_the_command() {
+        use crossterm::event::{KeyCode, KeyEvent};
+        let mut app = test_app();
+        let (reply, mut answer) = tokio::sync::oneshot::channel();
+        app.tool_input = Some(ToolInputPrompt { prompt: "[sudo] password for me:".into(), answer: String::new(), reply });
+        app.overlay = Overlay::ToolInput;
+        for c in "hunter2x".chars() {
+            app.handle_tool_input_key(KeyEvent::from(KeyCode::Char(c)));
+        }
+        app.handle_tool_input_key(KeyEvent::from(KeyCode::Backspace));
+        assert_eq!(app.tool_input.as_ref().unwrap().answer, "hunter2");
+        app.handle_tool_input_key(KeyEvent::from(KeyCode::Enter));
+        assert_eq!(answer.try_recv().unwrap().as_deref(), Some("hunter2"));
+        assert!(app.tool_input.is_none());
+        assert_eq!(app.overlay, Overlay::None);
+
+        let (reply, mut answer) = tokio::sync::oneshot::channel();
+        app.tool_input = Some(ToolInputPrompt { prompt: "Continue? [y/N]".into(), answer: String::new(), reply });
+        app.overlay = Overlay::ToolInput;
+        app.handle_tool_input_key(KeyEvent::from(KeyCode::Esc));
+        assert_eq!(answer.try_recv().unwrap(), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn execute_output_streams_into_the_running_row() {
+        let mut app = test_app();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        app.event_tx = Some(tx);
+        let command = "echo first; sleep 0.3; echo second".into();
+        app.pending_tool_calls = vec![ToolCall { id: "t1".into(), tool: tools::Tool::Execute { command, stdin: None } }];
+
+        assert!(app.execute_tool_at_index(0).await);
+        assert!(matches!(rx.recv().await, Some(Event::ToolStarted(0))));
+        app.show_tool_running(0);
+        let Some(Event::ToolOutputChunk(0, chunk)) = rx.recv().await else { panic!("no output") };
+        app.append_tool_output(&chunk);
+        assert_eq!(app.tool_invocations[0].live_output, "first\n");
+        assert!(app.tool_invocations[0].result.is_none());
+
+        loop {
+            match rx.recv().await {
+                Some(Event::ToolOutputChunk(0, chunk)) => app.append_tool_output(&chunk),
+                Some(Event::ToolFinished(0, result, elapsed)) => {
+                    assert_eq!(app.tool_invocations[0].live_output, "first\nsecond\n");
+                    app.finish_tool_at_index(0, result, elapsed, false);
+                    break;
+                }
+                other => panic!("unexpected event: {other:?}"),
+            }
+        }
+        assert_eq!(app.tool_invocations.len(), 1);
+        assert!(app.tool_invocations[0].result.as_ref().unwrap().output.contains("second"));
+    }
+
+    #[test]
+    fn idle_timeout_locks_the_screen() {
+        let mut app = test_app();
+        let two_minutes_ago = std::time::Instant::now() - std::time::Duration::from_secs(120);
+        app.last_activity = two_minutes_ago;
+        app.check_idle_lock();
+        assert!(!app.locked, "off by default");
+
+        app.config.idle_lock_minutes = 5;
+        app.check_idle_lock();
+        assert!(!app.locked);
+        app.config.idle_lock_minutes = 1;
+        add_msg(&mut app, "user", "unsaved draft");
+        app.save_pending = true;
+        app.check_idle_lock();
+        assert!(app.locked);
+        // Locking saved the conversation, into the test's scratch data dir
+        assert!(!app.save_pending);
+        let saved = app.config.history_dir().join(format!("{}.json", app.conversation.id));
+        assert!(saved.starts_with(std::env::temp_dir()) && saved.exists());
+    }
+
+    #[test]
+    fn session_state_transitions() {
+        use SessionState::*;
+        assert!(Idle.can_become(Streaming));
+        assert!(Streaming.can_become(RunningTools));
+        assert!(RunningTools.can_become(AwaitingToolConfirm));
+        assert!(RunningTools.can_become(Continuing));
+        assert!(AwaitingToolConfirm.can_become(Idle));
+        assert!(!Idle.can_become(Continuing));
+        assert!(!Streaming.can_become(AwaitingToolConfirm));
+        assert!(Continuing.is_streaming() && !RunningTools.is_streaming());
+    }
+
+    #[tokio::test]
+    async fn tool_confirm_moves_through_session_states() {
+        let mut app = test_app();
+        app.session = SessionState::Streaming;
+        let body = serde_json::json!({"content": [{
+            "type": "tool_use", "id": "t1", "name": "write_file",
+            "input": {"path": "never-written.txt", "content": "x"},
+        }]});
+        app.handle_tool_use_response(&body.to_string()).await;
+        assert_eq!(app.session, SessionState::AwaitingToolConfirm);
+        assert_eq!(app.overlay, Overlay::ToolConfirm);
+
+        let deny = crossterm::event::KeyEvent::from(crossterm::event::KeyCode::Char('n'));
+        app.handle_tool_confirm_key(deny).await;
+        // No request to continue with: back to idle rather than stuck
+        assert_eq!(app.session, SessionState::Idle);
+        assert_eq!(app.overlay, Overlay::None);
+        assert!(!std::path::Path::new("never-written.txt").exists());
+        assert!(!app.status_message.as_deref().unwrap_or_default().starts_with("Unexpected state change"));
+    }
+
+    #[test]
+    fn tool_loop_keeps_its_request_parameters() {
+        let mut app = test_app();
+        app.config.model = "model-a".into();
+        app.chain_request = Some(app.build_request(String::new()));
+        assert!(!app.settings_changed_mid_chain());
+
+        app.execute_command("set temperature=0.1");
+        assert!(app.settings_changed_mid_chain());
+        app.handle_slash_command("/model model-b").unwrap();
+        assert_eq!(app.response_meta().model, "model-a");
+
+        app.chain_request = Some(app.build_request(String::new()));
+        assert!(!app.settings_changed_mid_chain());
+        assert_eq!(app.response_meta().model, "model-b");
+    }
+
+    #[test]
+    fn saved_tool_permissions_override_defaults() {
+        let mut config = Config::default();
+        config.tools = toml::from_str("[permissions]\nexecute = \"allow\"\nread_clipboard = \"ask\"\nread_file = \"deny\"\n").unwrap();
+        let mut app = App::new(config);
+        assert_eq!(app.tool_executor.permission("execute"), ToolPermission::AutoAllow);
+        assert_eq!(app.tool_executor.permission("read_clipboard"), ToolPermission::AskFirst);
+        assert_eq!(app.tool_executor.permission("read_file"), ToolPermission::Deny);
+        assert_eq!(app.tool_executor.permission("list_files"), ToolPermission::AutoAllow);
+
+        app.handle_slash_command("/tools allow nope").unwrap();
+        assert_eq!(app.status_message.as_deref(), Some("Unknown tool: nope"));
+        app.handle_slash_command("/tools maybe").unwrap();
+        assert!(app.status_message.as_deref().unwrap().starts_with("Usage: /tools"));
+    }
+
+    #[test]
+    fn project_settings_override_global_ones() {
+        let dir = std::env::temp_dir().join(format!("pro-chat-project-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join(".prochat")).unwrap();
+        std::fs::create_dir_all(dir.join("fixtures")).unwrap();
+        std::fs::write(
+            ProjectSettings::path(&dir),
+            "system_prompt = \"Project rules\"\n\n[tools.permissions]\nexecute = \"deny\"\nedit_file = \"allow\"\n\n\
+             [sandbox]\nallow = [\"fixtures\"]\n",
+        )
+        .unwrap();
+
+        let mut config = Config::default();
+        config.system_prompt = Some("Global rules".into());
+        config.tools = toml::from_str("[permissions]\nexecute = \"allow\"\nwrite_file = \"allow\"\n").unwrap();
+        let mut app = App::new(config);
+        app.project_settings = ProjectSettings::load(&dir).unwrap();
+        assert!(app.project_settings.as_ref().unwrap().needs_trust());
+
+        // Trust holds only for the file as it was trusted
+        let root = dir.display().to_string();
+        let hash = app.project_settings.as_ref().unwrap().hash.clone();
+        ProjectSettings::trust(&root, &hash).unwrap();
+        assert_eq!(ProjectSettings::trusted_hash(&root), Some(hash.clone()));
+        let content = std::fs::read_to_string(ProjectSettings::path(&dir)).unwrap();
+        std::fs::write(ProjectSettings::path(&dir), format!("{content}\n")).unwrap();
+        assert_ne!(ProjectSettings::load(&dir).unwrap().unwrap().hash, hash);
+        std::fs::write(ProjectSettings::path(&dir), content).unwrap();
+
+        // Untrusted: tightening applies, loosening doesn't
+        app.apply_project_settings();
+        assert_eq!(app.system_prompt(), Some("Global rules"));
+        assert_eq!(app.tool_executor.permission("execute"), ToolPermission::Deny);
+        assert_eq!(app.tool_executor.permission("edit_file"), ToolPermission::AskFirst);
+        assert!(!app.tool_executor.sandbox().unwrap().contains(&dir.join("fixtures").join("a.txt")));
+
+        app.project_trusted = true;
+        app.apply_project_settings();
+        assert_eq!(app.system_prompt(), Some("Project rules"));
+        assert_eq!(app.tool_executor.permission("execute"), ToolPermission::Deny);
+        assert_eq!(app.tool_executor.permission("edit_file"), ToolPermission::AutoAllow);
+        assert_eq!(app.tool_executor.permission("write_file"), ToolPermission::AutoAllow);
+        assert_eq!(app.tool_executor.permission("read_file"), ToolPermission::AutoAllow);
+        assert!(app.tool_executor.sandbox().unwrap().contains(&dir.join("fixtures").join("a.txt")));
+
+        // A choice made this session survives rebuilding the permissions
+        app.execute_command("set clipboard");
+        assert_eq!(app.tool_executor.permission("read_clipboard"), ToolPermission::AskFirst);
+        app.apply_project_settings();
+        assert_eq!(app.tool_executor.permission("read_clipboard"), ToolPermission::AskFirst);
+
+        // Editing the prompt replaces the project's for the session
+        app.handle_slash_command("/system Session rules").unwrap();
+        assert_eq!(app.system_prompt(), Some("Session rules"));
+
+        app.project_settings = None;
+        app.apply_project_settings();
+        assert_eq!(app.tool_executor.permission("execute"), ToolPermission::AutoAllow);
+        assert!(!app.tool_executor.sandbox().unwrap().contains(&dir.join("fixtures")));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn sandbox_command_edits_allowlist() {
+        let mut app = test_app();
+        let tmp = std::env::temp_dir().display().to_string();
+        app.handle_slash_command(&format!("/sandbox add {tmp}")).unwrap();
+        assert_eq!(app.config.sandbox.allow, [tmp.as_str()]);
+        assert!(app.tool_executor.sandbox().unwrap().contains(&std::env::temp_dir().join("x")));
+        assert!(app.status_message.as_deref().unwrap().ends_with("(/save to keep)"));
+
+        app.handle_slash_command("/sandbox add /no/such/dir").unwrap();
+        assert_eq!(app.status_message.as_deref(), Some("Not a directory: /no/such/dir"));
+
+        app.handle_slash_command(&format!("/sandbox remove {tmp}")).unwrap();
+        assert!(app.config.sandbox.allow.is_empty());
+        app.handle_slash_command("/sandbox off").unwrap();
+        assert!(app.tool_executor.sandbox().is_none());
+        assert!(app.status_message.as_deref().unwrap().starts_with("Sandbox off"));
+    }
+
+    #[test]
+    fn viewer_is_read_only() {
+        let mut app = test_app();
+        let mut conv = Conversation::new();
+        conv.title = "Agent session".into();
+        conv.add_message("m1", "user", "hi");
+        conv.add_message("m2", "assistant", "hello");
+        app.open_viewer(conv);
+        assert!(app.viewer);
+        assert_eq!(app.messages.len(), 2);
+        assert!(app.status_message.as_deref().unwrap().contains("Agent session"));
+
+        app.handle_slash_command("/clear").unwrap();
+        assert_eq!(app.messages.len(), 2);
+        assert!(app.status_message.as_deref().unwrap().starts_with("Read-only viewer"));
+
+        app.execute_command("clear");
+        assert_eq!(app.messages.len(), 2);
+        assert!(!app.send_user_text("more"));
+
+        app.handle_slash_command("/stats").unwrap();
+        assert_eq!(app.overlay, Overlay::Stats);
+        app.overlay = Overlay::None;
+
+        // Keys that would bookmark, annotate or start over do nothing
+        use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+        app.input_mode = InputMode::Normal;
+        app.focused_message = Some(1);
+        for key in [
+            KeyEvent::from(KeyCode::Char('b')),
+            KeyEvent::new(KeyCode::Char('n'), KeyModifiers::CONTROL),
+            KeyEvent::new(KeyCode::Char('l'), KeyModifiers::CONTROL),
+        ] {
+            crate::keybinds::handle_key(&mut app, key);
+        }
+        app.begin_note();
+        // Other tests share the scratch bookmarks file, so only look at this conversation
+        assert!(!app.bookmarks.iter().any(|b| b.conversation_id == app.conversation.id));
+        assert_eq!(app.messages.len(), 2);
+        assert_eq!(app.input_mode, InputMode::Normal);
+        assert!(app.status_message.as_deref().unwrap().starts_with("Read-only viewer"));
+
+        app.overlay = Overlay::History;
+        crate::keybinds::handle_key(&mut app, KeyEvent::from(KeyCode::Char('d')));
+        assert!(app.history_confirm.is_none());
+    }
+
+    #[test]
+    fn goto_message_by_id_prefix() {
+        let mut app = test_app();
+        add_msg(&mut app, "user", "first");
+        add_msg(&mut app, "assistant", "second");
+        app.messages[0].id = "abc12345".into();
+        app.messages[1].id = "abd67890".into();
+
+        app.handle_slash_command("/goto #abd6").unwrap();
+        assert_eq!(app.scroll_offset, 4);
+        assert_eq!(app.status_message.as_deref(), Some("Message #abd67890"));
+
+        app.handle_slash_command("/goto ab").unwrap();
+        assert!(app.status_message.as_deref().unwrap().starts_with("Ambiguous"));
+
+        app.handle_slash_command("/goto zzz").unwrap();
+        assert!(app.status_message.as_deref().unwrap().starts_with("No message"));
+    }
+
     #[test]
     fn search_finds_matching_messages() {
         let mut app = test_app();
@@ -2650,6 +7313,379 @@ mod tests {
         assert!(app.input.is_empty());
     }
 
+    #[test]
+    fn invalid_network_config_falls_back_with_status() {
+        let mut config = Config::default();
+        config.network.ca_bundle = Some("/nonexistent/ca.pem".into());
+        let app = App::new(config);
+        assert!(app.status_message.as_deref().unwrap_or("").contains("network.ca_bundle"));
+    }
+
+    #[test]
+    fn code_mode_strips_prose_and_writes_file() {
+        let mut app = test_app();
+        let out = std::env::temp_dir().join(format!("pro-chat-code-{}.py", std::process::id()));
+        app.handle_slash_command(&format!("/code file {}", out.display())).unwrap();
+        assert!(app.assembled_system_prompt(None).unwrap().contains("single fenced code block"));
+
+        let reply = "Here you go:\n```python\nprint('hi')\n```\nHope that helps!";
+        add_msg(&mut app, "assistant", reply);
+        app.stream_buffer = reply.into();
+        app.format_code_reply();
+
+        assert_eq!(app.messages[0].content, "```python\nprint('hi')\n```");
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "print('hi')\n");
+        std::fs::remove_file(&out).unwrap();
+
+        // JSON mode and code mode are exclusive
+        app.handle_slash_command("/json on").unwrap();
+        assert!(app.code_mode.is_none());
+    }
+
+    #[test]
+    fn continuation_is_stitched_at_the_seam() {
+        let partial = "Here:\n```rust\nfn main() {\n    let x = 1;";
+        assert_eq!(open_fence(partial), Some("```rust"));
+        assert_eq!(open_fence("```rust\nfn a() {}\n```\ndone"), None);
+        assert!(continuation_prompt(partial).contains("inside a ```rust code block"));
+        assert!(!continuation_prompt("Plain prose").contains("code block"));
+
+        // A prefilled continuation joins as is
+        assert_eq!(stitch_continuation(partial, "\n    let y = 2;\n}\n```"), "\n    let y = 2;\n}\n```");
+        // A reopened fence is dropped
+        assert_eq!(stitch_continuation(partial, "```rust\n    let y = 2;\n}\n```"), "\n    let y = 2;\n}\n```");
+        // A bare fence closes the block, so it stays
+        assert_eq!(stitch_continuation(partial, "\n```\nDone."), "\n```\nDone.");
+        // A repeat of the line it stopped on is dropped
+        assert_eq!(stitch_continuation(partial, "    let x = 1;\n    let y = 2;"), "\n    let y = 2;");
+    }
+
+    #[test]
+    fn fenced_blocks_extracts_language_and_code() {
+        let blocks = fenced_blocks("a\n```rust\nfn main() {}\n```\nb\n```\nplain\n```\n```open");
+        assert_eq!(blocks, vec![
+            ("rust".to_string(), "fn main() {}".to_string()),
+            (String::new(), "plain".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn recipe_message_attaches_outputs() {
+        let attachments = vec![
+            ("Output of `git diff`".to_string(), String::new(), "+added\n".to_string()),
+            ("Contents of `a.rs`".to_string(), "rs".to_string(), "fn a() {}".to_string()),
+        ];
+        let text = recipe_message("Review this diff.", "focus on errors", &attachments);
+        assert_eq!(
+            text,
+            "Review this diff.\n\nfocus on errors\n\nOutput of `git diff`:\n```\n+added\n```\n\nContents of `a.rs`:\n```rs\nfn a() {}\n```"
+        );
+        assert_eq!(recipe_message("", "", &[]), "");
+    }
+
+    #[test]
+    fn unknown_recipe_reports_error() {
+        let mut app = test_app();
+        app.handle_slash_command("/recipe nope").unwrap();
+        assert_eq!(app.status_message.as_deref(), Some("Unknown recipe: nope"));
+        assert!(app.messages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn recipe_commands_attach_output_and_time_out() {
+        let env = crate::config::ToolEnvConfig::default();
+        let outputs = recipe_outputs(&["echo hi".into()], &env, std::time::Duration::from_secs(5)).await;
+        assert_eq!(outputs.unwrap(), [("Output of `echo hi`".to_string(), String::new(), "hi".to_string())]);
+
+        let outputs = recipe_outputs(&["sleep 5".into()], &env, std::time::Duration::from_millis(100)).await;
+        assert_eq!(outputs.unwrap_err(), "`sleep 5` timed out after 0 seconds");
+    }
+
+    #[test]
+    fn pack_recipe_asks_first_and_stays_in_sandbox() {
+        let mut app = test_app();
+        app.config.recipes.insert("leak".into(), Recipe {
+            run: vec!["echo hi".into()],
+            files: vec!["/etc/hostname".into()],
+            ..Recipe::default()
+        });
+        app.config.pack_entries.recipes.insert("leak".into(), "team".into());
+
+        app.handle_slash_command("/recipe leak").unwrap();
+        assert_eq!(app.overlay, Overlay::RecipeConfirm);
+        assert_eq!(app.recipe_confirm.as_ref().unwrap().pack, "team");
+        app.confirm_recipe(false);
+        assert_eq!(app.overlay, Overlay::None);
+        assert_eq!(app.status_message.as_deref(), Some("Recipe leak not run"));
+
+        app.handle_slash_command("/recipe leak").unwrap();
+        app.confirm_recipe(true);
+        assert_eq!(app.status_message.as_deref(), Some("Recipe leak: /etc/hostname is outside the sandbox"));
+        assert!(app.messages.is_empty());
+    }
+
+    #[test]
+    fn json_mode_validates_and_pretty_prints_reply() {
+        let mut app = test_app();
+        let schema_path = std::env::temp_dir().join(format!("pro-chat-schema-{}.json", std::process::id()));
+        std::fs::write(&schema_path, r#"{"type": "object", "required": ["answer"]}"#).unwrap();
+        app.handle_slash_command(&format!("/json schema {}", schema_path.display())).unwrap();
+        std::fs::remove_file(&schema_path).unwrap();
+        assert!(app.json_mode.as_ref().unwrap().schema.is_some());
+
+        let system = app.assembled_system_prompt(None).unwrap();
+        assert!(system.contains("--- Output Format ---"));
+        assert!(system.contains("\"required\""));
+
+        add_msg(&mut app, "assistant", r#"{"result": 42}"#);
+        app.stream_buffer = r#"{"result": 42}"#.into();
+        app.format_json_reply();
+        assert_eq!(app.messages[0].content, "```json\n{\n  \"result\": 42\n}\n```");
+        assert!(app.status_message.as_deref().unwrap().contains("missing required property"));
+
+        app.handle_slash_command("/json off").unwrap();
+        assert!(app.json_mode.is_none());
+    }
+
+    #[test]
+    fn request_preview_includes_pending_input_and_tools() {
+        let mut app = test_app();
+        app.api_messages.push(Message {
+            role: "assistant".into(),
+            content: MessageContent::Blocks(vec![serde_json::json!({
+                "type": "tool_use", "id": "t1", "name": "read_file", "input": {"path": "a.rs"}
+            })]),
+        });
+        app.set_input(&"line\n".repeat(10));
+
+        let preview = app.request_preview();
+        assert_eq!(preview.messages.len(), 2);
+        assert!(preview.messages[0].excerpt[0].starts_with("⚙ tool_use read_file"));
+        assert_eq!(preview.messages[1].role, "user");
+        assert_eq!(preview.messages[1].excerpt.len(), PREVIEW_LINES);
+        assert_eq!(preview.messages[1].hidden_lines, 10 - PREVIEW_LINES);
+        assert_eq!(preview.tools.is_empty(), !app.tools_enabled);
+        assert!(preview.total_tokens() >= preview.system_tokens);
+
+        app.config.provider = "openai".into();
+        assert_eq!(app.request_preview().tools.is_empty(), !app.tools_enabled);
+        app.config.provider = "xai".into();
+        assert!(app.request_preview().tools.is_empty());
+    }
+
+    #[test]
+    fn prompt_editor_moves_between_lines() {
+        let mut ed = PromptEditor::new("first line\nab");
+        assert_eq!(ed.line_col(), (1, 2));
+        ed.up();
+        assert_eq!(ed.line_col(), (0, 2));
+        ed.end();
+        assert_eq!(ed.line_col(), (0, 10));
+        ed.down();
+        assert_eq!(ed.line_col(), (1, 2), "column clamps to shorter line");
+        ed.home();
+        ed.insert('é');
+        ed.insert('\n');
+        assert_eq!(ed.text, "first line\né\nab");
+        ed.backspace();
+        ed.backspace();
+        assert_eq!(ed.text, "first line\nab");
+    }
+
+    #[test]
+    fn system_editor_saves_and_previews_context() {
+        let mut app = test_app();
+        app.handle_slash_command("/system").unwrap();
+        assert_eq!(app.overlay, Overlay::SystemPrompt);
+
+        app.prompt_editor = PromptEditor::new("Be terse.\nUse British spelling.\n");
+        app.save_prompt_editor();
+        assert_eq!(app.config.system_prompt.as_deref(), Some("Be terse.\nUse British spelling."));
+
+        app.project_context = Some("Project directory: demo".into());
+        let assembled = app.assembled_system_prompt(app.config.system_prompt.as_deref()).unwrap();
+        assert!(assembled.starts_with("Be terse."));
+        assert!(assembled.ends_with("--- Project Context ---\nProject directory: demo"));
+        // The configured prompt itself is untouched
+        assert!(!app.config.system_prompt.as_deref().unwrap().contains("Project Context"));
+    }
+
+    #[test]
+    fn queue_holds_prompts_while_streaming() {
+        let mut app = test_app();
+        app.session = SessionState::Streaming;
+        app.handle_slash_command("/queue first question").unwrap();
+        app.handle_slash_command("/queue second question").unwrap();
+        assert_eq!(app.prompt_queue, ["first question", "second question"]);
+        assert!(app.messages.is_empty());
+
+        // Nothing is dequeued until the current response finishes
+        app.send_next_queued();
+        assert_eq!(app.prompt_queue.len(), 2);
+
+        app.handle_slash_command("/queue clear").unwrap();
+        assert!(app.prompt_queue.is_empty());
+    }
+
+    #[test]
+    fn attach_checks_pdfs_and_keeps_them_on_failed_send() {
+        let dir = std::env::temp_dir().join(format!("pro-chat-attach-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pdf = dir.join("report.pdf");
+        let text = dir.join("notes.txt");
+        std::fs::write(&pdf, b"%PDF-1.4\n%%EOF\n").unwrap();
+        std::fs::write(&text, "plain text").unwrap();
+
+        let mut app = test_app();
+        app.handle_slash_command(&format!("/attach {}", text.display())).unwrap();
+        assert!(app.status_message.as_deref().unwrap().contains("not a PDF"));
+        assert!(app.pending_attachments.is_empty());
+
+        app.handle_slash_command(&format!("/attach {}", pdf.display())).unwrap();
+        assert_eq!(app.pending_attachments.len(), 1);
+        let attachment = app.pending_attachments[0].clone();
+        assert_eq!(attachment.size(), 15);
+        assert_eq!(attachment.label(), "report.pdf (15 B)");
+
+        // A failed send keeps its PDFs for the resend
+        add_msg(&mut app, "user", "summarize");
+        app.api_messages.push(Message {
+            role: "user".into(),
+            content: MessageContent::Blocks(vec![
+                api::document_block(&attachment.name, &attachment.data),
+                serde_json::json!({"type": "text", "text": "summarize"}),
+            ]),
+        });
+        app.queue_failed_send("overloaded");
+        assert_eq!(app.failed_sends[0].content, "summarize");
+        assert_eq!(app.failed_sends[0].attachments, vec![attachment]);
+
+        app.handle_slash_command("/attach clear").unwrap();
+        assert!(app.pending_attachments.is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn failed_send_moves_to_queue() {
+        let mut app = test_app();
+        add_msg(&mut app, "user", "hello there");
+        app.conversation.add_message(&app.messages[0].id.clone(), "user", "hello there");
+        app.api_messages.push(Message {
+            role: "user".into(),
+            content: MessageContent::Text("hello there".into()),
+        });
+
+        app.queue_failed_send("connection refused");
+        assert!(app.messages.is_empty());
+        assert!(app.api_messages.is_empty());
+        assert!(app.conversation.messages.is_empty());
+        assert_eq!(app.failed_sends.len(), 1);
+        assert_eq!(app.failed_sends[0].content, "hello there");
+        assert_eq!(app.failed_sends[0].error, "connection refused");
+
+        // Typed input is never clobbered by a resend
+        app.set_input("draft");
+        app.resend_failed();
+        assert_eq!(app.failed_sends.len(), 1);
+        assert_eq!(app.input, "draft");
+    }
+
+    #[test]
+    fn failure_after_tool_results_is_not_queued() {
+        let mut app = test_app();
+        app.api_messages.push(Message {
+            role: "user".into(),
+            content: MessageContent::Blocks(Vec::new()),
+        });
+        app.queue_failed_send("overloaded");
+        assert!(app.failed_sends.is_empty());
+        assert_eq!(app.api_messages.len(), 1);
+        assert_eq!(app.error_cards.len(), 1);
+        assert_eq!(app.error_cards[0].error, "overloaded");
+    }
+
+    #[test]
+    fn notes_attach_to_target_message() {
+        let mut app = test_app();
+        add_msg(&mut app, "user", "question");
+        add_msg(&mut app, "assistant", "answer");
+
+        app.begin_note();
+        assert_eq!(app.input_mode, InputMode::Command);
+        assert_eq!(app.command_input, "note ");
+
+        app.set_note("wrong: ignores edge case");
+        assert_eq!(app.messages[1].note.as_deref(), Some("wrong: ignores edge case"));
+
+        app.focused_message = Some(0);
+        app.execute_command("note check this");
+        assert_eq!(app.messages[0].note.as_deref(), Some("check this"));
+
+        app.execute_command("notes");
+        assert_eq!(app.messages[0].note.as_deref(), Some("check this"));
+        assert_eq!(app.status_message.as_deref(), Some("Unknown command: :notes"));
+
+        app.execute_command("note");
+        assert!(app.messages[0].note.is_none());
+        // Saving the note wrote the conversation, but only to the scratch dir
+        let saved = app.config.history_dir().join(format!("{}.json", app.conversation.id));
+        assert!(saved.starts_with(std::env::temp_dir()) && saved.exists());
+    }
+
+    #[test]
+    fn replay_reveals_messages_step_by_step() {
+        let mut app = test_app();
+        add_msg(&mut app, "user", "question");
+        add_msg(&mut app, "assistant", &"x".repeat(100));
+
+        app.handle_slash_command("/replay stream").unwrap();
+        assert_eq!(app.messages.len(), 1);
+
+        app.replay_advance();
+        assert_eq!(app.messages.len(), 2);
+        assert!(app.messages[1].content.is_empty());
+
+        app.replay_tick();
+        assert_eq!(app.messages[1].content.len(), REPLAY_CHARS_PER_TICK);
+
+        // Advancing mid-stream finishes the message instead of skipping ahead
+        app.replay_advance();
+        assert_eq!(app.messages[1].content.len(), 100);
+
+        app.stop_replay();
+        assert!(app.replay.is_none());
+        assert_eq!(app.messages.len(), 2);
+    }
+
+    #[test]
+    fn word_diff_marks_changed_words() {
+        use similar::ChangeTag;
+        let diff = word_diff("the quick fox", "the slow fox");
+        assert_eq!(diff, vec![
+            (ChangeTag::Equal, "the ".to_string()),
+            (ChangeTag::Delete, "quick".to_string()),
+            (ChangeTag::Insert, "slow".to_string()),
+            (ChangeTag::Equal, " fox".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn diffresp_requires_discarded_response() {
+        let mut app = test_app();
+        add_msg(&mut app, "user", "q");
+        add_msg(&mut app, "assistant", "first answer");
+        app.handle_slash_command("/diffresp").unwrap();
+        assert_ne!(app.overlay, Overlay::ResponseDiff);
+
+        app.handle_slash_command("/retry").unwrap();
+        assert_eq!(app.discarded_response.as_deref(), Some("first answer"));
+        add_msg(&mut app, "assistant", "second answer");
+
+        app.handle_slash_command("/diffresp").unwrap();
+        assert_eq!(app.overlay, Overlay::ResponseDiff);
+        assert!(!app.response_diff.is_empty());
+    }
+
     // -----------------------------------------------------------------------
     // Common prefix helper
     // -----------------------------------------------------------------------
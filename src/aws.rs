@@ -0,0 +1,293 @@
+//! Just enough AWS for the Bedrock provider: credential lookup, SigV4 request
+//! signing and decoding of the binary event stream Bedrock responds with.
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+impl AwsCredentials {
+    /// Credentials from `AWS_ACCESS_KEY_ID`/`AWS_SECRET_ACCESS_KEY` (plus
+    /// `AWS_SESSION_TOKEN`), falling back to the `AWS_PROFILE` (or `default`)
+    /// section of the shared credentials file.
+    pub fn load() -> Option<Self> {
+        if let (Ok(access_key_id), Ok(secret_access_key)) = (
+            std::env::var("AWS_ACCESS_KEY_ID"),
+            std::env::var("AWS_SECRET_ACCESS_KEY"),
+        ) {
+            return Some(Self {
+                access_key_id,
+                secret_access_key,
+                session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+            });
+        }
+        let path = std::env::var_os("AWS_SHARED_CREDENTIALS_FILE")
+            .map(PathBuf::from)
+            .or_else(|| dirs::home_dir().map(|h| h.join(".aws").join("credentials")))?;
+        let profile = std::env::var("AWS_PROFILE").unwrap_or_else(|_| "default".into());
+        Self::from_credentials_file(&std::fs::read_to_string(path).ok()?, &profile)
+    }
+
+    /// Parse one profile out of an INI-style `~/.aws/credentials` file.
+    fn from_credentials_file(contents: &str, profile: &str) -> Option<Self> {
+        let mut in_profile = false;
+        let (mut key_id, mut secret, mut token) = (None, None, None);
+        for line in contents.lines().map(str::trim) {
+            if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                in_profile = section.trim() == profile;
+                continue;
+            }
+            if !in_profile {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else { continue };
+            let value = Some(value.trim().to_string());
+            match key.trim() {
+                "aws_access_key_id" => key_id = value,
+                "aws_secret_access_key" => secret = value,
+                "aws_session_token" => token = value,
+                _ => {}
+            }
+        }
+        Some(Self {
+            access_key_id: key_id?,
+            secret_access_key: secret?,
+            session_token: token,
+        })
+    }
+}
+
+/// Percent-encode everything except RFC 3986 unreserved characters, as SigV4
+/// requires.
+pub fn uri_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Headers that sign a request with SigV4: `x-amz-date`, the session token
+/// when there is one, and `authorization`. `path` is the already-encoded
+/// request path; `headers` are any other headers that will be sent and should
+/// be covered by the signature (lowercase names).
+#[allow(clippy::too_many_arguments)]
+pub fn sign(
+    method: &str,
+    host: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    creds: &AwsCredentials,
+    region: &str,
+    service: &str,
+    now: DateTime<Utc>,
+) -> Vec<(String, String)> {
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+
+    let mut signed: Vec<(String, String)> = headers
+        .iter()
+        .map(|(k, v)| (k.to_lowercase(), v.trim().to_string()))
+        .collect();
+    signed.push(("host".into(), host.into()));
+    signed.push(("x-amz-date".into(), amz_date.clone()));
+    if let Some(token) = &creds.session_token {
+        signed.push(("x-amz-security-token".into(), token.clone()));
+    }
+    signed.sort();
+
+    let canonical_headers: String = signed.iter().map(|(k, v)| format!("{k}:{v}\n")).collect();
+    let signed_names = signed.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>().join(";");
+    // Every service but S3 encodes the (already encoded) path segments again
+    let canonical_path = path.split('/').map(uri_encode).collect::<Vec<_>>().join("/");
+    let canonical_request = format!(
+        "{method}\n{canonical_path}\n\n{canonical_headers}\n{signed_names}\n{}",
+        hex::encode(Sha256::digest(body))
+    );
+
+    let scope = format!("{date}/{region}/{service}/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let key = [date.as_str(), region, service, "aws4_request"]
+        .iter()
+        .fold(format!("AWS4{}", creds.secret_access_key).into_bytes(), |key, part| hmac(&key, part));
+    let signature = hex::encode(hmac(&key, &string_to_sign));
+
+    let mut out = vec![("x-amz-date".to_string(), amz_date)];
+    if let Some(token) = &creds.session_token {
+        out.push(("x-amz-security-token".into(), token.clone()));
+    }
+    out.push((
+        "authorization".into(),
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_names}, Signature={signature}",
+            creds.access_key_id
+        ),
+    ));
+    out
+}
+
+/// One frame of an `application/vnd.amazon.eventstream` response.
+#[derive(Debug)]
+pub struct EventStreamMessage {
+    /// String-valued headers; other header types are skipped
+    pub headers: Vec<(String, String)>,
+    pub payload: Vec<u8>,
+}
+
+impl EventStreamMessage {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+    }
+}
+
+fn be_u32(bytes: &[u8]) -> usize {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize
+}
+
+/// Take the first complete message off the front of `buf`, or `None` while
+/// more bytes are needed. Checksums are not verified; TLS already covers that.
+pub fn decode_message(buf: &mut Vec<u8>) -> anyhow::Result<Option<EventStreamMessage>> {
+    if buf.len() < 12 {
+        return Ok(None);
+    }
+    let total = be_u32(&buf[0..4]);
+    let headers_len = be_u32(&buf[4..8]);
+    if total < 16 + headers_len {
+        anyhow::bail!("Malformed event stream frame ({total} bytes, {headers_len} of headers)");
+    }
+    if buf.len() < total {
+        return Ok(None);
+    }
+    let frame: Vec<u8> = buf.drain(..total).collect();
+
+    let mut headers = Vec::new();
+    let raw = &frame[12..12 + headers_len];
+    let mut i = 0;
+    while i < raw.len() {
+        let name_len = raw[i] as usize;
+        let name = String::from_utf8_lossy(raw.get(i + 1..i + 1 + name_len).unwrap_or_default()).to_string();
+        i += 1 + name_len;
+        let Some(&kind) = raw.get(i) else { break };
+        i += 1;
+        let value_len = match kind {
+            0 | 1 => 0,
+            2 => 1,
+            3 => 2,
+            4 => 4,
+            5 | 8 => 8,
+            9 => 16,
+            6 | 7 => {
+                let Some(len) = raw.get(i..i + 2) else { break };
+                i += 2;
+                u16::from_be_bytes([len[0], len[1]]) as usize
+            }
+            other => anyhow::bail!("Unknown event stream header type {other}"),
+        };
+        let Some(value) = raw.get(i..i + value_len) else { break };
+        if kind == 7 {
+            headers.push((name, String::from_utf8_lossy(value).to_string()));
+        }
+        i += value_len;
+    }
+
+    Ok(Some(EventStreamMessage {
+        headers,
+        payload: frame[12 + headers_len..total - 4].to_vec(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example_creds() -> AwsCredentials {
+        AwsCredentials {
+            access_key_id: "AKIDEXAMPLE".into(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".into(),
+            session_token: None,
+        }
+    }
+
+    #[test]
+    fn test_sign_matches_aws_test_suite() {
+        // "get-vanilla" from the AWS SigV4 test suite
+        let now = DateTime::parse_from_rfc3339("2015-08-30T12:36:00Z").unwrap().with_timezone(&Utc);
+        let headers = sign(
+            "GET", "example.amazonaws.com", "/", &[], b"",
+            &example_creds(), "us-east-1", "service", now,
+        );
+        assert_eq!(headers[0], ("x-amz-date".to_string(), "20150830T123600Z".to_string()));
+        assert_eq!(
+            headers[1].1,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    #[test]
+    fn test_credentials_file_profile() {
+        let file = "[default]\naws_access_key_id = AKIA1\naws_secret_access_key = s1\n\n\
+                    [work]\naws_access_key_id=AKIA2\naws_secret_access_key=s2\naws_session_token=t2\n";
+        let work = AwsCredentials::from_credentials_file(file, "work").unwrap();
+        assert_eq!(work.access_key_id, "AKIA2");
+        assert_eq!(work.session_token.as_deref(), Some("t2"));
+        assert_eq!(AwsCredentials::from_credentials_file(file, "default").unwrap().secret_access_key, "s1");
+        assert!(AwsCredentials::from_credentials_file(file, "missing").is_none());
+    }
+
+    #[test]
+    fn test_decode_event_stream_message() {
+        let payload = br#"{"bytes":"e30="}"#;
+        let mut headers = Vec::new();
+        for (name, value) in [(":event-type", "chunk"), (":message-type", "event")] {
+            headers.push(name.len() as u8);
+            headers.extend_from_slice(name.as_bytes());
+            headers.push(7);
+            headers.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            headers.extend_from_slice(value.as_bytes());
+        }
+        let total = 16 + headers.len() + payload.len();
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&(total as u32).to_be_bytes());
+        frame.extend_from_slice(&(headers.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&[0; 4]);
+        frame.extend_from_slice(&headers);
+        frame.extend_from_slice(payload);
+        frame.extend_from_slice(&[0; 4]);
+
+        // A partial frame waits for more bytes
+        let mut buf = frame[..20].to_vec();
+        assert!(decode_message(&mut buf).unwrap().is_none());
+
+        let mut buf = [frame.clone(), frame[..5].to_vec()].concat();
+        let msg = decode_message(&mut buf).unwrap().unwrap();
+        assert_eq!(msg.header(":event-type"), Some("chunk"));
+        assert_eq!(msg.header(":message-type"), Some("event"));
+        assert_eq!(msg.payload, payload);
+        assert_eq!(buf.len(), 5);
+    }
+}
@@ -0,0 +1,182 @@
+//! `pro bench`: send a short standard prompt to each configured provider and
+//! model and report time to first token and output speed, to help pick the
+//! fastest model for interactive use.
+
+use std::time::Duration;
+
+use crate::api::{ApiClient, Message, MessageContent, Request};
+use crate::config::Config;
+use crate::tools::format_duration;
+
+/// The same prompt for every model, so results are comparable.
+const PROMPT: &str = "In about 100 words, explain what a hash map is and when to use one.";
+const MAX_TOKENS: u32 = 300;
+/// Give up on a single run after this long.
+const RUN_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// One timed request.
+#[derive(Debug, Clone, Copy)]
+struct Run {
+    first_token: Duration,
+    tokens_per_sec: f64,
+}
+
+/// `config` switched to the model named by a `provider/model` spec, e.g.
+/// `openrouter/meta-llama/llama-3-70b`.
+pub fn parse_target(config: &Config, spec: &str) -> anyhow::Result<Config> {
+    let Some((provider, model)) = spec.split_once('/') else {
+        anyhow::bail!("Expected provider/model, got '{spec}'");
+    };
+    config.check_provider(provider).map_err(anyhow::Error::msg)?;
+    let mut target = config.clone();
+    target.clear_profile();
+    target.provider = provider.to_string();
+    target.model = model.to_string();
+    Ok(target)
+}
+
+/// What to benchmark: `provider/model` specs from the command line, or else
+/// the configured model followed by each profile's. Each comes with the
+/// config to send it with, so profile API keys and headers apply.
+fn targets(config: &Config, specs: &[String]) -> anyhow::Result<Vec<(String, Config)>> {
+    let mut targets: Vec<(String, Config)> = Vec::new();
+    if !specs.is_empty() {
+        for spec in specs {
+            targets.push((spec.clone(), parse_target(config, spec)?));
+        }
+        return Ok(targets);
+    }
+
+    let mut candidates = vec![config.clone()];
+    for name in config.profiles.keys() {
+        let mut target = config.clone();
+        target.apply_profile(name)?;
+        candidates.push(target);
+    }
+    for target in candidates {
+        let label = format!("{}/{}", target.provider, target.model);
+        if !targets.iter().any(|(l, _)| *l == label) {
+            targets.push((label, target));
+        }
+    }
+    Ok(targets)
+}
+
+/// Send the prompt once and time the response.
+async fn time_once(client: &ApiClient, request: &Request) -> Result<Run, String> {
+    let completion = tokio::time::timeout(RUN_TIMEOUT, client.complete(request))
+        .await
+        .map_err(|_| format!("no response within {}", format_duration(RUN_TIMEOUT)))?
+        .map_err(|e| e.to_string())?;
+    let first_token = completion.first_token.ok_or("empty response")?;
+    // Providers that don't report usage get the usual 4 characters per token
+    let tokens = match completion.usage.output_tokens {
+        0 => completion.text.chars().count() as f64 / 4.0,
+        n => n as f64,
+    };
+    let generating = completion.elapsed.saturating_sub(first_token).max(Duration::from_millis(1));
+    Ok(Run { first_token, tokens_per_sec: tokens / generating.as_secs_f64() })
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(f64::total_cmp);
+    values[values.len() / 2]
+}
+
+/// Benchmark each target `runs` times, printing results as they come in.
+pub async fn run(config: &Config, specs: &[String], runs: usize) -> anyhow::Result<()> {
+    let targets = targets(config, specs)?;
+    let client = ApiClient::new(&config.network)?;
+    let runs = runs.max(1);
+    let width = targets.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+    println!("Timing {} model(s), {runs} run(s) each: \"{PROMPT}\"\n", targets.len());
+
+    let mut results: Vec<(String, Run)> = Vec::new();
+    for (label, target) in &targets {
+        let Some(api_key) = target.api_key_from_env() else {
+            println!("{label:width$}  skipped: no API key (set {})", target.api_key_env_var());
+            continue;
+        };
+        let messages = vec![Message { role: "user".into(), content: MessageContent::Text(PROMPT.into()) }];
+        let request = Request {
+            system: None,
+            max_tokens: MAX_TOKENS,
+            thinking_budget: None,
+            ..Request::new(target, api_key, messages)
+        };
+
+        let mut timed = Vec::new();
+        let mut failure = None;
+        for _ in 0..runs {
+            match time_once(&client, &request).await {
+                Ok(run) => timed.push(run),
+                Err(e) => {
+                    failure = Some(e);
+                    break;
+                }
+            }
+        }
+        if let Some(e) = failure {
+            println!("{label:width$}  error: {}", e.lines().next().unwrap_or_default());
+            continue;
+        }
+        let run = Run {
+            first_token: Duration::from_secs_f64(median(timed.iter().map(|r| r.first_token.as_secs_f64()).collect())),
+            tokens_per_sec: median(timed.iter().map(|r| r.tokens_per_sec).collect()),
+        };
+        println!(
+            "{label:width$}  first token {:>7}  {:>6.1} tok/s",
+            format_duration(run.first_token),
+            run.tokens_per_sec
+        );
+        results.push((label.clone(), run));
+    }
+
+    if results.len() > 1 {
+        let fastest = results.iter().min_by_key(|(_, r)| r.first_token).map(|(l, _)| l);
+        let quickest = results.iter().max_by(|a, b| a.1.tokens_per_sec.total_cmp(&b.1.tokens_per_sec)).map(|(l, _)| l);
+        if let (Some(fastest), Some(quickest)) = (fastest, quickest) {
+            println!("\nFastest first token: {fastest}\nHighest throughput:  {quickest}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Profile;
+
+    #[test]
+    fn test_targets() {
+        let mut config = Config::default();
+        config.provider = "anthropic".into();
+        config.model = "claude-a".into();
+        config.profiles.insert(
+            "local".into(),
+            Profile { provider: Some("ollama".into()), model: Some("llama3:8b".into()), ..Profile::default() },
+        );
+        // Same model as the top level: listed once
+        config.profiles.insert("same".into(), Profile::default());
+        let labels: Vec<String> = targets(&config, &[]).unwrap().into_iter().map(|(l, _)| l).collect();
+        assert_eq!(labels, ["anthropic/claude-a", "ollama/llama3:8b"]);
+
+        let specs = ["openrouter/meta/llama-3".to_string()];
+        let (label, target) = targets(&config, &specs).unwrap().remove(0);
+        assert_eq!(label, "openrouter/meta/llama-3");
+        assert_eq!((target.provider.as_str(), target.model.as_str()), ("openrouter", "meta/llama-3"));
+        assert!(targets(&config, &["nope/x".to_string()]).is_err());
+        assert!(targets(&config, &["gpt-4o".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_time_mock_provider() {
+        let mut config = Config::default();
+        config.provider = "mock".into();
+        let client = ApiClient::new(&config.network).unwrap();
+        let messages = vec![Message { role: "user".into(), content: MessageContent::Text(PROMPT.into()) }];
+        let run = time_once(&client, &Request::new(&config, String::new(), messages)).await.unwrap();
+        assert!(run.first_token < Duration::from_secs(5));
+        assert!(run.tokens_per_sec > 0.0);
+    }
+}
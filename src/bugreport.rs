@@ -0,0 +1,344 @@
+//! `pro bugreport`: bundle what a maintainer needs to act on an issue --
+//! version and platform, the config with secrets removed, the latest log and
+//! the last API exchange -- into a `.tar.gz` to attach to it.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::api::Message;
+use crate::config::Config;
+
+/// Only the tail of the log goes into the bundle.
+const MAX_LOG_BYTES: usize = 1024 * 1024;
+
+/// Attachment data longer than this is replaced by its length.
+const MAX_INLINE_DATA: usize = 256;
+
+/// Secret prefixes of common API keys and tokens.
+const SECRET_PREFIXES: &[&str] = &[
+    "sk-", "xai-", "AIza", "ghp_", "gho_", "ghs_", "github_pat_", "glpat-", "lin_api_", "AKIA", "ASIA",
+];
+
+/// Config keys whose string values are secrets.
+pub fn is_secret_key(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.ends_with("key") || ["token", "secret", "password"].iter().any(|s| name.contains(s))
+}
+
+const REDACTED: &str = "[REDACTED]";
+
+/// The most recent request and how it ended, kept in the data directory.
+#[derive(Debug, Serialize, Deserialize)]
+struct Exchange {
+    time: DateTime<Utc>,
+    provider: String,
+    model: String,
+    max_tokens: u32,
+    tools: bool,
+    #[serde(default)]
+    system: Option<String>,
+    messages: Value,
+    #[serde(default)]
+    response: Option<String>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+fn exchange_path() -> PathBuf {
+    Config::data_dir().join("last-exchange.json")
+}
+
+/// Remember the request about to be sent, replacing the previous exchange.
+/// Attachment data is dropped so the file stays small.
+pub fn record_request(
+    provider: &str,
+    model: &str,
+    system: Option<&str>,
+    messages: &[Message],
+    max_tokens: u32,
+    tools: bool,
+) {
+    let mut messages = serde_json::to_value(messages).unwrap_or_default();
+    strip_attachment_data(&mut messages);
+    let exchange = Exchange {
+        time: Utc::now(),
+        provider: provider.to_string(),
+        model: model.to_string(),
+        max_tokens,
+        tools,
+        system: system.map(String::from),
+        messages,
+        response: None,
+        error: None,
+    };
+    write_exchange(&exchange);
+}
+
+/// Add the reply, or the error, to the recorded request.
+pub fn record_outcome(outcome: Result<&str, &str>) {
+    let Some(mut exchange) = std::fs::read_to_string(exchange_path())
+        .ok()
+        .and_then(|s| serde_json::from_str::<Exchange>(&s).ok())
+    else {
+        return;
+    };
+    match outcome {
+        Ok(response) => exchange.response = Some(response.to_string()),
+        Err(error) => exchange.error = Some(error.to_string()),
+    }
+    write_exchange(&exchange);
+}
+
+fn write_exchange(exchange: &Exchange) {
+    if let Ok(json) = serde_json::to_string_pretty(exchange) {
+        let _ = std::fs::create_dir_all(Config::data_dir())
+            .and_then(|_| crate::history::write_atomic(&exchange_path(), json.as_bytes()));
+    }
+}
+
+/// Replace base64 image and document payloads with their size.
+fn strip_attachment_data(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, v) in map.iter_mut() {
+                match v {
+                    Value::String(s) if matches!(key.as_str(), "data" | "file_data") && s.len() > MAX_INLINE_DATA => {
+                        *s = format!("[{} bytes of base64]", s.len());
+                    }
+                    _ => strip_attachment_data(v),
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(strip_attachment_data),
+        _ => {}
+    }
+}
+
+/// The config file with every secret replaced, plus the secrets themselves
+/// so they can be scrubbed from the other files too. `[headers.*]` and
+/// `env` tables are redacted whole since any of their values may be a
+/// credential.
+fn redact_config(contents: &str) -> (String, Vec<String>) {
+    fn walk(value: &mut toml::Value, secret: bool, found: &mut Vec<String>) {
+        match value {
+            toml::Value::String(s) if secret && !s.is_empty() => {
+                found.push(std::mem::replace(s, REDACTED.to_string()));
+            }
+            toml::Value::Table(table) => {
+                for (key, v) in table.iter_mut() {
+                    let secret = secret || matches!(key.as_str(), "headers" | "env") || is_secret_key(key);
+                    walk(v, secret, found);
+                }
+            }
+            toml::Value::Array(items) => items.iter_mut().for_each(|v| walk(v, secret, found)),
+            _ => {}
+        }
+    }
+    let mut value: toml::Value = match toml::from_str(contents) {
+        Ok(value) => value,
+        // Unparseable: fall back to dropping any line that names a secret
+        Err(e) => {
+            let lines: Vec<String> = contents
+                .lines()
+                .map(|line| match line.split_once('=') {
+                    Some((key, _)) if is_secret_key(key.trim()) => format!("{} = \"{REDACTED}\"", key.trim()),
+                    _ => line.to_string(),
+                })
+                .collect();
+            return (format!("# Config does not parse: {e}\n{}", lines.join("\n")), Vec::new());
+        }
+    };
+    let mut found = Vec::new();
+    walk(&mut value, false, &mut found);
+    (toml::to_string_pretty(&value).unwrap_or_default(), found)
+}
+
+/// Replace known secrets and anything shaped like an API key or bearer
+/// token.
+fn redact_secrets(text: &str, known: &[String]) -> String {
+    let mut text = text.to_string();
+    for secret in known.iter().filter(|s| s.len() >= 8) {
+        text = text.replace(secret.as_str(), REDACTED);
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut after_bearer = false;
+    let mut rest = text.as_str();
+    while !rest.is_empty() {
+        let is_token_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.');
+        let start = rest.find(is_token_char).unwrap_or(rest.len());
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c: char| !is_token_char(c)).unwrap_or(rest.len());
+        let token = &rest[..end];
+        let looks_secret = token.len() >= 20 && SECRET_PREFIXES.iter().any(|p| token.starts_with(p));
+        if (looks_secret || after_bearer && token.len() >= 16) && token != REDACTED {
+            out.push_str(REDACTED);
+        } else {
+            out.push_str(token);
+        }
+        after_bearer = token.eq_ignore_ascii_case("bearer");
+        rest = &rest[end..];
+    }
+    out
+}
+
+/// The newest file in the log directory.
+fn latest_log(dir: &Path) -> Option<PathBuf> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+        .max_by_key(|e| e.metadata().and_then(|m| m.modified()).ok())
+        .map(|e| e.path())
+}
+
+fn tail(text: &str, max_bytes: usize) -> &str {
+    if text.len() <= max_bytes {
+        return text;
+    }
+    let mut start = text.len() - max_bytes;
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    &text[start..]
+}
+
+fn version_info(config: &Config) -> String {
+    let env = |name: &str| std::env::var(name).unwrap_or_else(|_| "-".into());
+    format!(
+        "pro {}\nos: {} {} ({})\nprovider: {}\nmodel: {}\nconfig: {}\ndata dir: {}\nTERM: {}\nTERM_PROGRAM: {}\n",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        std::env::consts::FAMILY,
+        config.provider,
+        config.model,
+        Config::path().display(),
+        Config::data_dir().display(),
+        env("TERM"),
+        env("TERM_PROGRAM"),
+    )
+}
+
+/// Secrets from the environment, for scrubbing log lines that echo them.
+fn env_secrets() -> Vec<String> {
+    // vars() would panic on a variable that isn't UTF-8
+    std::env::vars_os()
+        .filter(|(name, _)| is_secret_key(&name.to_string_lossy()))
+        .map(|(_, value)| value.to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Write the bundle to `output` (default: `pro-bugreport-<time>.tar.gz` in
+/// the current directory) and return its path.
+pub fn create(config: &Config, output: Option<PathBuf>) -> anyhow::Result<PathBuf> {
+    let output = output.unwrap_or_else(|| {
+        PathBuf::from(format!("pro-bugreport-{}.tar.gz", Utc::now().format("%Y%m%d-%H%M%S")))
+    });
+    let (config_text, mut secrets) = match std::fs::read_to_string(Config::path()) {
+        Ok(contents) => redact_config(&contents),
+        Err(_) => ("# No config file\n".to_string(), Vec::new()),
+    };
+    secrets.extend(env_secrets());
+
+    let mut files = vec![
+        ("version.txt", version_info(config)),
+        ("config.toml", config_text),
+    ];
+    if let Some(log) = latest_log(&Config::data_dir().join("logs"))
+        && let Ok(bytes) = std::fs::read(&log)
+    {
+        let text = String::from_utf8_lossy(&bytes);
+        files.push(("log.txt", redact_secrets(tail(&text, MAX_LOG_BYTES), &secrets)));
+    }
+    if let Ok(exchange) = std::fs::read_to_string(exchange_path()) {
+        files.push(("last-exchange.json", redact_secrets(&exchange, &secrets)));
+    }
+
+    let file = std::fs::File::create(&output).with_context(|| format!("Couldn't create {}", output.display()))?;
+    write_bundle(file, &files)?;
+    Ok(output)
+}
+
+fn write_bundle(writer: impl std::io::Write, files: &[(&str, String)]) -> anyhow::Result<()> {
+    let gz = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+    let mut tar = tar::Builder::new(gz);
+    let mtime = Utc::now().timestamp().max(0) as u64;
+    for (name, contents) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        header.set_cksum();
+        tar.append_data(&mut header, format!("pro-bugreport/{name}"), contents.as_bytes())?;
+    }
+    tar.into_inner()?.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_redact_config() {
+        let (text, secrets) = redact_config(
+            "provider = \"openai\"\nopenai_api_key = \"sk-live-123456789\"\n\
+             [github]\ntoken = \"ghp_abc\"\n[headers.openai]\nX-Custom = \"opaque\"\n\
+             [mcp.fs]\ncommand = \"npx\"\nenv = { API = \"hunter2\" }\n[keybinds]\nsend = \"enter\"\n",
+        );
+        let parsed: toml::Value = toml::from_str(&text).unwrap();
+        assert_eq!(parsed["provider"].as_str(), Some("openai"));
+        assert_eq!(parsed["openai_api_key"].as_str(), Some(REDACTED));
+        assert_eq!(parsed["github"]["token"].as_str(), Some(REDACTED));
+        assert_eq!(parsed["headers"]["openai"]["X-Custom"].as_str(), Some(REDACTED));
+        assert_eq!(parsed["mcp"]["fs"]["env"]["API"].as_str(), Some(REDACTED));
+        assert_eq!(parsed["mcp"]["fs"]["command"].as_str(), Some("npx"));
+        assert_eq!(parsed["keybinds"]["send"].as_str(), Some("enter"));
+        assert_eq!(secrets.len(), 4);
+        assert!(secrets.contains(&"hunter2".to_string()));
+    }
+
+    #[test]
+    fn test_redact_secrets() {
+        let text = "key sk-ant-REDACTED used; Authorization: Bearer abcdef0123456789xyz \
+                    and opaque-token-value done. sk-short";
+        assert_eq!(
+            redact_secrets(text, &["opaque-token-value".to_string()]),
+            "key [REDACTED] used; Authorization: Bearer [REDACTED] and [REDACTED] done. sk-short"
+        );
+    }
+
+    #[test]
+    fn test_strip_attachment_data() {
+        let mut value = serde_json::json!([{"content": [
+            {"type": "image", "source": {"data": "A".repeat(1000)}},
+            {"type": "text", "text": "A".repeat(1000)},
+        ]}]);
+        strip_attachment_data(&mut value);
+        assert_eq!(value[0]["content"][0]["source"]["data"], "[1000 bytes of base64]");
+        assert_eq!(value[0]["content"][1]["text"].as_str().unwrap().len(), 1000);
+    }
+
+    #[test]
+    fn test_bundle_round_trip() {
+        let mut bytes = Vec::new();
+        write_bundle(&mut bytes, &[("version.txt", "pro 0.1.0\n".into()), ("log.txt", "line\n".into())]).unwrap();
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(bytes.as_slice()));
+        let mut names = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents).unwrap();
+            names.push((entry.path().unwrap().display().to_string(), contents));
+        }
+        assert_eq!(names[0], ("pro-bugreport/version.txt".to_string(), "pro 0.1.0\n".to_string()));
+        assert_eq!(names[1].0, "pro-bugreport/log.txt");
+        assert_eq!(tail("abcdef", 3), "def");
+    }
+}
@@ -1,6 +1,9 @@
 use ratatui::style::Color;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -16,10 +19,42 @@ pub struct Config {
     pub openrouter_api_key: Option<String>,
     #[serde(default)]
     pub xai_api_key: Option<String>,
+    #[serde(default)]
+    pub gemini_api_key: Option<String>,
+    /// `OpenAI-Organization` header for team accounts; falls back to `OPENAI_ORG_ID`
+    #[serde(default)]
+    pub openai_organization: Option<String>,
+    /// `OpenAI-Project` header; falls back to `OPENAI_PROJECT_ID`
+    #[serde(default)]
+    pub openai_project: Option<String>,
+    /// Extra request headers per provider, e.g. `[headers.anthropic]`
+    #[serde(default)]
+    pub headers: BTreeMap<String, BTreeMap<String, String>>,
+    /// Anthropic beta features to opt into, sent as the `anthropic-beta` header
+    #[serde(default)]
+    pub anthropic_beta: Vec<String>,
+    /// Address of the Ollama server for the `ollama` provider
+    #[serde(default)]
+    pub ollama_host: Option<String>,
+    /// AWS region for the `bedrock` provider
+    #[serde(default)]
+    pub bedrock_region: Option<String>,
     #[serde(default = "default_max_tokens")]
     pub max_tokens: u32,
     #[serde(default = "default_temperature")]
     pub temperature: f32,
+    /// Token budget for Claude's extended thinking; off when unset
+    #[serde(default)]
+    pub thinking_budget: Option<u32>,
+    /// Nucleus sampling cutoff; the provider's default when unset
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    /// Only sample from the k most likely tokens; the provider's default when unset
+    #[serde(default)]
+    pub top_k: Option<u32>,
+    /// Strings that end the response as soon as the model produces one
+    #[serde(default)]
+    pub stop_sequences: Vec<String>,
     #[serde(default = "default_system_prompt")]
     pub system_prompt: Option<String>,
     #[serde(default = "default_theme")]
@@ -34,6 +69,289 @@ pub struct Config {
     pub last_conversation_id: Option<String>,
     #[serde(default = "default_true")]
     pub notify_on_complete: bool,
+    /// Dim footer under each reply with its model, tokens and latency
+    #[serde(default = "default_true")]
+    pub message_footer: bool,
+    /// Bell or flash when a reply starts, a tool asks first, or a request fails
+    #[serde(default)]
+    pub cues: CuesConfig,
+    /// Queue prompts submitted while a reply is streaming (sent when it
+    /// finishes) instead of refusing them
+    #[serde(default = "default_true")]
+    pub queue_while_streaming: bool,
+    #[serde(default)]
+    pub tool_env: ToolEnvConfig,
+    /// Syntax checks run after the model edits a file
+    #[serde(default)]
+    pub validate: ValidateConfig,
+    /// Directories the file tools may read and write
+    #[serde(default)]
+    pub sandbox: SandboxConfig,
+    /// Remembered tool permissions
+    #[serde(default)]
+    pub tools: ToolsConfig,
+    /// Timeouts, stall detection, proxy and TLS settings for API requests
+    #[serde(default)]
+    pub network: NetworkConfig,
+    /// Token for the GitHub issue and pull request tools
+    #[serde(default)]
+    pub github: GithubConfig,
+    /// Jira or Linear connection for the ticket tool
+    #[serde(default)]
+    pub tracker: Option<TrackerConfig>,
+    /// Embedding model for document retrieval
+    #[serde(default)]
+    pub embeddings: EmbeddingsConfig,
+    /// Store conversations as zstd-compressed `.json.zst` files
+    #[serde(default)]
+    pub compress_history: bool,
+    /// Days deleted conversations stay restorable in the trash (0 = forever)
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u32,
+    /// Save and hide the transcript after this many minutes without a key
+    /// press (0 = never)
+    #[serde(default)]
+    pub idle_lock_minutes: u32,
+    /// How often tool calls pause for confirmation (overridden per project
+    /// by `/autonomy`)
+    #[serde(default)]
+    pub autonomy: Autonomy,
+    /// With `autonomy = "medium"`, ask before every Nth ask-first call
+    #[serde(default = "default_autonomy_interval")]
+    pub autonomy_interval: usize,
+    /// What to show on launch
+    #[serde(default)]
+    pub startup: StartupMode,
+    /// What to print to stdout on quit
+    #[serde(default)]
+    pub print_on_exit: PrintOnExit,
+    /// Per-model request settings, e.g. `[models."gpt-4o"]`
+    #[serde(default)]
+    pub models: BTreeMap<String, ModelConfig>,
+    /// Named profiles selectable with `--profile` or `/profile`
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Named command chains run with `/recipe <name>`
+    #[serde(default)]
+    pub recipes: BTreeMap<String, Recipe>,
+    /// Shared profile and recipe repositories (`[[packs]]`), fetched with `pro packs`
+    #[serde(default)]
+    pub packs: Vec<PackConfig>,
+    /// Extra OpenAI-compatible providers (`[[providers]]`)
+    #[serde(default)]
+    pub providers: Vec<CustomProvider>,
+    /// MCP servers whose tools are offered alongside the built-in ones
+    #[serde(default)]
+    pub mcp: BTreeMap<String, McpServerConfig>,
+    /// Name of the profile applied to this session, if any
+    #[serde(skip)]
+    pub active_profile: Option<String>,
+    /// Top-level values the active profile replaced, restored when saving
+    #[serde(skip)]
+    profile_base: Option<ProfileBase>,
+    /// API key supplied by the active profile, as (provider, key)
+    #[serde(skip)]
+    profile_api_key: Option<(String, String)>,
+    /// History subdirectory of the active profile
+    #[serde(skip)]
+    history_subdir: Option<String>,
+    /// Profiles and recipes merged in from packs, by name, with the pack
+    /// each came from; left out when saving
+    #[serde(skip)]
+    pub pack_entries: PackEntries,
+}
+
+/// What to print to stdout after the TUI exits, for piping into other tools.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PrintOnExit {
+    #[default]
+    None,
+    /// The final assistant response
+    Last,
+    /// Every message as markdown
+    Conversation,
+}
+
+/// A cue for something worth noticing from another pane.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Cue {
+    #[default]
+    None,
+    /// Ring the terminal bell
+    Bell,
+    /// Briefly highlight the status bar
+    Flash,
+    /// Bell and flash
+    Both,
+}
+
+/// Cues for events other than a completed reply (which rings the bell
+/// with `notify_on_complete`), e.g. `[cues] confirm = "both"`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CuesConfig {
+    /// The first text of a reply arrives
+    #[serde(default)]
+    pub stream_start: Cue,
+    /// A tool call waits for confirmation
+    #[serde(default)]
+    pub confirm: Cue,
+    /// A request fails
+    #[serde(default)]
+    pub error: Cue,
+}
+
+/// How often the tool loop stops to ask before running an ask-first tool.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Autonomy {
+    /// Ask before every call
+    #[default]
+    Low,
+    /// Ask every `autonomy_interval` calls, and before dangerous ones
+    Medium,
+    /// Ask only before dangerous calls
+    High,
+}
+
+impl Autonomy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "low" => Some(Self::Low),
+            "medium" => Some(Self::Medium),
+            "high" => Some(Self::High),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+        }
+    }
+
+    fn path() -> PathBuf {
+        Config::data_dir().join("autonomy.json")
+    }
+
+    /// The level saved for `project` with `/autonomy`, if any.
+    pub fn load_for_project(project: &str) -> Option<Self> {
+        Self::load_all().get(project).copied()
+    }
+
+    pub fn save_for_project(self, project: &str) -> anyhow::Result<()> {
+        let mut levels = Self::load_all();
+        levels.insert(project.to_string(), self);
+        Self::save_all(&levels)
+    }
+
+    /// Every project's saved level, keyed by project root.
+    pub fn load_all() -> BTreeMap<String, Self> {
+        std::fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|c| serde_json::from_str(&c).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save_all(levels: &BTreeMap<String, Self>) -> anyhow::Result<()> {
+        std::fs::create_dir_all(Config::data_dir())?;
+        crate::history::write_atomic(&Self::path(), serde_json::to_string_pretty(levels)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Which conversation the app opens with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StartupMode {
+    /// Reopen the last conversation
+    #[default]
+    Restore,
+    /// Always start a fresh conversation
+    New,
+    /// Choose from recent conversations (or a new chat) first
+    Picker,
+}
+
+/// Request settings for one model, e.g. `[models."gpt-4o"]`. Unset fields
+/// fall back to the top-level config and the provider's defaults.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct ModelConfig {
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    /// Send tool definitions, overriding whether the provider supports them
+    #[serde(default)]
+    pub supports_tools: Option<bool>,
+    /// Which tools to offer (`full` or `core`), overriding the built-in
+    /// capability table
+    #[serde(default)]
+    pub tool_set: Option<crate::tools::ToolSet>,
+    /// Context size in tokens, shown next to the conversation's estimate
+    #[serde(default)]
+    pub context_window: Option<usize>,
+    /// USD per million input tokens, for `/cost` and `/dashboard`
+    #[serde(default)]
+    pub input_price: Option<f64>,
+    /// USD per million output tokens
+    #[serde(default)]
+    pub output_price: Option<f64>,
+    /// `anthropic-beta` flags for this model, added to the top-level ones
+    #[serde(default)]
+    pub anthropic_beta: Vec<String>,
+}
+
+/// A named set of overrides, e.g. `[profiles.work]`. Unset fields fall back
+/// to the top-level config.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct Profile {
+    #[serde(default)]
+    pub provider: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    /// API key for the profile's provider
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// Environment variable to read the API key from instead
+    #[serde(default)]
+    pub api_key_env: Option<String>,
+    #[serde(default)]
+    pub system_prompt: Option<String>,
+    /// Subdirectory of the history directory for this profile's conversations
+    #[serde(default)]
+    pub history_dir: Option<String>,
+}
+
+/// A reusable prompt, e.g. `[recipes.review-pr]`: switch to `profile`, run
+/// each command in `run` and read each of `files`, then send `prompt` with
+/// all of that output attached.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct Recipe {
+    /// Shown when listing recipes
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Profile to switch to first (starts a new conversation)
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Shell commands whose output is attached
+    #[serde(default)]
+    pub run: Vec<String>,
+    /// Files whose contents are attached
+    #[serde(default)]
+    pub files: Vec<String>,
+    #[serde(default)]
+    pub prompt: String,
+}
+
+#[derive(Debug, Clone)]
+struct ProfileBase {
+    provider: String,
+    model: String,
+    system_prompt: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -60,11 +378,483 @@ pub struct NeovimConfig {
     pub send_code_blocks: bool,
 }
 
+/// HTTP settings for API requests. Timeouts are in seconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkConfig {
+    /// Time allowed to establish a connection
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+    /// Time allowed for any single read; bounds non-streaming responses
+    #[serde(default = "default_read_timeout")]
+    pub read_timeout: u64,
+    /// Abort a stream when no chunk arrives for this long
+    #[serde(default = "default_stall_timeout")]
+    pub stall_timeout: u64,
+    /// Proxy URL for all requests. When unset, `HTTPS_PROXY`/`HTTP_PROXY`/
+    /// `ALL_PROXY` (and `NO_PROXY`) from the environment are honored.
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// Extra PEM bundle of trusted root certificates, e.g. a corporate CA
+    #[serde(default)]
+    pub ca_bundle: Option<PathBuf>,
+    /// Skip TLS certificate verification entirely. Only for MITM proxies
+    /// whose CA can't be installed; this makes connections interceptable.
+    #[serde(default)]
+    pub danger_accept_invalid_certs: bool,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: default_connect_timeout(),
+            read_timeout: default_read_timeout(),
+            stall_timeout: default_stall_timeout(),
+            proxy: None,
+            ca_bundle: None,
+            danger_accept_invalid_certs: false,
+        }
+    }
+}
+
+/// A git repository of shared profiles and recipes, e.g.
+/// `[[packs]] url = "https://github.com/acme/pro-pack"`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct PackConfig {
+    pub url: String,
+    /// Branch or tag to check out (default: the repository's default branch)
+    #[serde(default)]
+    pub branch: Option<String>,
+}
+
+impl PackConfig {
+    /// The repository name, shown in messages and on its entries.
+    pub fn name(&self) -> &str {
+        let url = self.url.trim_end_matches('/');
+        let last = url.rsplit(['/', ':']).next().unwrap_or(url);
+        last.strip_suffix(".git").unwrap_or(last)
+    }
+
+    /// Its directory under `<data dir>/packs`: the name and a short hash of
+    /// the URL, so two repositories with the same name don't share one.
+    /// `None` when the URL ends in no usable name, such as `..`.
+    pub fn dir_name(&self) -> Option<String> {
+        use sha2::{Digest, Sha256};
+        let name = self.name();
+        if name.is_empty() || matches!(name, "." | "..") || name.contains('\\') {
+            return None;
+        }
+        let hash = hex::encode(Sha256::digest(self.url.as_bytes()));
+        Some(format!("{name}-{}", &hash[..8]))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PackEntries {
+    pub profiles: BTreeMap<String, String>,
+    pub recipes: BTreeMap<String, String>,
+}
+
+/// An MCP server, e.g. `[mcp.github]`: either a `command` started and spoken
+/// to over stdio, or the `url` of an SSE endpoint.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct McpServerConfig {
+    #[serde(default)]
+    pub command: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Variables added to the command's environment
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+/// Credentials for the `gh_*` tools.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct GithubConfig {
+    /// Personal access token; falls back to `GITHUB_TOKEN` / `GH_TOKEN`
+    #[serde(default)]
+    pub token: Option<String>,
+    /// REST API base, for GitHub Enterprise
+    #[serde(default = "default_github_api_url")]
+    pub api_url: String,
+}
+
+fn default_github_api_url() -> String {
+    "https://api.github.com".into()
+}
+
+impl Default for GithubConfig {
+    fn default() -> Self {
+        Self { token: None, api_url: default_github_api_url() }
+    }
+}
+
+impl GithubConfig {
+    pub fn token(&self) -> Option<String> {
+        self.token.clone()
+            .or_else(|| std::env::var("GITHUB_TOKEN").ok())
+            .or_else(|| std::env::var("GH_TOKEN").ok())
+    }
+}
+
+/// Which issue tracker `fetch_ticket` and `/ticket` talk to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrackerKind {
+    Jira,
+    Linear,
+}
+
+/// Issue tracker connection, e.g. `[tracker]` with `kind = "jira"`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TrackerConfig {
+    pub kind: TrackerKind,
+    /// Jira site, e.g. `https://acme.atlassian.net`
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Jira Cloud account email; without it the token is sent as a bearer
+    /// token (Jira Data Center personal access tokens)
+    #[serde(default)]
+    pub email: Option<String>,
+    /// API token; falls back to `JIRA_API_TOKEN` or `LINEAR_API_KEY`
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+impl TrackerConfig {
+    pub fn token(&self) -> Option<String> {
+        let var = match self.kind {
+            TrackerKind::Jira => "JIRA_API_TOKEN",
+            TrackerKind::Linear => "LINEAR_API_KEY",
+        };
+        self.token.clone().or_else(|| std::env::var(var).ok())
+    }
+}
+
+/// Embedding model used by `pro index` and the `retrieve` tool.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EmbeddingsConfig {
+    /// `openai` (any OpenAI-compatible endpoint) or `ollama` (local)
+    #[serde(default = "default_embeddings_provider")]
+    pub provider: String,
+    #[serde(default = "default_embeddings_model")]
+    pub model: String,
+    /// Endpoint override, e.g. `http://localhost:11434` for a remote Ollama
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Falls back to `openai_api_key` / `OPENAI_API_KEY`
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// File extensions indexed by `pro index`
+    #[serde(default = "default_index_extensions")]
+    pub extensions: Vec<String>,
+}
+
+fn default_embeddings_provider() -> String {
+    "openai".into()
+}
+
+fn default_embeddings_model() -> String {
+    "text-embedding-3-small".into()
+}
+
+fn default_index_extensions() -> Vec<String> {
+    ["md", "markdown", "txt", "rst", "adoc", "org", "html"]
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+impl Default for EmbeddingsConfig {
+    fn default() -> Self {
+        Self {
+            provider: default_embeddings_provider(),
+            model: default_embeddings_model(),
+            url: None,
+            api_key: None,
+            extensions: default_index_extensions(),
+        }
+    }
+}
+
+/// Providers built into the client.
+pub const BUILTIN_PROVIDERS: &[&str] = &["anthropic", "openai", "openrouter", "xai", "gemini", "ollama", "bedrock", "mock"];
+
+/// An OpenAI-compatible provider defined in config.toml:
+///
+/// ```toml
+/// [[providers]]
+/// name = "together"
+/// base_url = "https://api.together.xyz/v1"
+/// api_key_env = "TOGETHER_API_KEY"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct CustomProvider {
+    pub name: String,
+    /// API root; `/chat/completions` is appended unless already present
+    pub base_url: String,
+    /// Environment variable holding the API key
+    #[serde(default)]
+    pub api_key_env: Option<String>,
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// Extra request headers
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Send tool definitions (only for endpoints that support function calling)
+    #[serde(default)]
+    pub tools: bool,
+}
+
+impl CustomProvider {
+    pub fn chat_url(&self) -> String {
+        let base = self.base_url.trim_end_matches('/');
+        if base.ends_with("/chat/completions") {
+            base.to_string()
+        } else {
+            format!("{base}/chat/completions")
+        }
+    }
+
+    /// The endpoint's model list (`GET <root>/models`).
+    pub fn models_url(&self) -> String {
+        let chat = self.chat_url();
+        format!("{}/models", chat.trim_end_matches("/chat/completions"))
+    }
+}
+
+/// Syntax checking after `write_file`/`edit_file`, keyed by file extension.
+///
+/// JSON and TOML are parsed in-process; other languages run a checker command
+/// (argv, with `{path}` replaced by the edited file). Entries in `commands`
+/// replace the built-in checker for that extension; an empty list disables it.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct ValidateConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub commands: BTreeMap<String, Vec<String>>,
+}
+
+impl ValidateConfig {
+    /// Checker command for a file extension, if any.
+    pub fn command_for(&self, ext: &str) -> Option<Vec<String>> {
+        if let Some(argv) = self.commands.get(ext) {
+            return (!argv.is_empty()).then(|| argv.clone());
+        }
+        let argv: &[&str] = match ext {
+            "rs" => &["rustfmt", "--edition", "2024", "--emit", "stdout", "{path}"],
+            "py" => &["python3", "-c", "import ast, sys; ast.parse(open(sys.argv[1]).read(), sys.argv[1])", "{path}"],
+            "js" | "mjs" | "cjs" => &["node", "--check", "{path}"],
+            "sh" | "bash" => &["bash", "-n", "{path}"],
+            "go" => &["gofmt", "-e", "-l", "{path}"],
+            _ => return None,
+        };
+        Some(argv.iter().map(|a| a.to_string()).collect())
+    }
+}
+
+/// Where the file tools may read and write. The working directory is always
+/// allowed; `allow` adds more directories (`~` is expanded). Commands run
+/// with `execute` are not confined.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SandboxConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self { enabled: true, allow: Vec::new() }
+    }
+}
+
+/// `[tools]`: per-tool permissions that replace the built-in defaults.
+/// Written by "Always allow" / "Always deny" in the confirmation prompt and
+/// by `/tools allow|ask|deny <name>`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ToolsConfig {
+    #[serde(default)]
+    pub permissions: BTreeMap<String, crate::tools::ToolPermission>,
+}
+
+/// `.prochat/settings.toml` in a project root: overrides that apply only
+/// while working in that project. Tool permissions replace the global ones
+/// tool by tool, `system_prompt` replaces the global prompt, and sandbox
+/// `allow` directories (relative to the project root) add to the global list.
+///
+/// The file comes with the repository, so until the user trusts the root
+/// with `/trust` only the parts that make tools stricter are applied. Trust
+/// covers the file as it was when given: once it changes, the user is asked
+/// again.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectSettings {
+    pub system_prompt: Option<String>,
+    #[serde(default)]
+    pub tools: ToolsConfig,
+    #[serde(default)]
+    pub sandbox: ProjectSandbox,
+    /// SHA-256 of the file, which `/trust` records with the root
+    #[serde(skip)]
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectSandbox {
+    pub enabled: Option<bool>,
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+impl ProjectSettings {
+    pub fn path(project_root: &Path) -> PathBuf {
+        project_root.join(".prochat").join("settings.toml")
+    }
+
+    /// The settings file for `project_root`, or `None` if it has none.
+    pub fn load(project_root: &Path) -> anyhow::Result<Option<Self>> {
+        let path = Self::path(project_root);
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut settings: Self = toml::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))?;
+        settings.hash = {
+            use sha2::{Digest, Sha256};
+            hex::encode(Sha256::digest(content.as_bytes()))
+        };
+        for dir in &mut settings.sandbox.allow {
+            if Path::new(dir.as_str()).is_relative() && !dir.starts_with('~') {
+                *dir = project_root.join(&*dir).display().to_string();
+            }
+        }
+        Ok(Some(settings))
+    }
+
+    /// `base` with this project's sandbox settings applied. An untrusted
+    /// project can turn the sandbox on but not off, and adds no directories.
+    pub fn sandbox(&self, base: &SandboxConfig, trusted: bool) -> SandboxConfig {
+        let mut sandbox = base.clone();
+        if !trusted {
+            sandbox.enabled |= self.sandbox.enabled == Some(true);
+            return sandbox;
+        }
+        if let Some(enabled) = self.sandbox.enabled {
+            sandbox.enabled = enabled;
+        }
+        sandbox.allow.extend(self.sandbox.allow.iter().cloned());
+        sandbox
+    }
+
+    /// Whether the file asks for anything an untrusted project doesn't get.
+    pub fn needs_trust(&self) -> bool {
+        self.system_prompt.is_some()
+            || self.sandbox.enabled == Some(false)
+            || !self.sandbox.allow.is_empty()
+            || self.tools.permissions.values().any(|p| *p != crate::tools::ToolPermission::Deny)
+    }
+
+    fn trust_path() -> PathBuf {
+        Config::data_dir().join("trusted_projects.json")
+    }
+
+    /// Trusted roots and the hash of the settings each was trusted with.
+    fn trusted_roots() -> BTreeMap<String, String> {
+        std::fs::read_to_string(Self::trust_path())
+            .ok()
+            .and_then(|c| serde_json::from_str(&c).ok())
+            .unwrap_or_default()
+    }
+
+    /// The settings hash `project_root` was trusted with by `/trust`, if any.
+    pub fn trusted_hash(project_root: &str) -> Option<String> {
+        Self::trusted_roots().remove(project_root)
+    }
+
+    /// Trust `project_root` for as long as its settings file hashes to `hash`.
+    pub fn trust(project_root: &str, hash: &str) -> anyhow::Result<()> {
+        let mut roots = Self::trusted_roots();
+        roots.insert(project_root.to_string(), hash.to_string());
+        std::fs::create_dir_all(Config::data_dir())?;
+        crate::history::write_atomic(&Self::trust_path(), serde_json::to_string_pretty(&roots)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Environment for subprocesses started by the `execute` tool and `/run`.
+///
+/// Applied in order: `passthrough` (when non-empty, only matching variables are
+/// inherited), then `strip`, then `set`. Names in `passthrough` and `strip` may
+/// use `*` wildcards, e.g. `AWS_*`.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct ToolEnvConfig {
+    #[serde(default)]
+    pub set: BTreeMap<String, String>,
+    #[serde(default)]
+    pub strip: Vec<String>,
+    #[serde(default)]
+    pub passthrough: Vec<String>,
+}
+
+impl ToolEnvConfig {
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty() && self.strip.is_empty() && self.passthrough.is_empty()
+    }
+
+    /// Compute the child environment from the given parent environment.
+    /// Names and values needn't be UTF-8; patterns match the lossy name.
+    pub fn resolve(
+        &self,
+        parent: impl IntoIterator<Item = (OsString, OsString)>,
+    ) -> Vec<(OsString, OsString)> {
+        let matches = |patterns: &[String], name: &str| {
+            patterns.iter().any(|p| {
+                glob::Pattern::new(p)
+                    .map(|pat| pat.matches(name))
+                    .unwrap_or(p == name)
+            })
+        };
+
+        let mut env: Vec<(OsString, OsString)> = parent
+            .into_iter()
+            .filter(|(k, _)| {
+                let k = k.to_string_lossy();
+                (self.passthrough.is_empty() || matches(&self.passthrough, &k))
+                    && !matches(&self.strip, &k)
+                    && !self.set.contains_key(k.as_ref())
+            })
+            .collect();
+        env.extend(self.set.iter().map(|(k, v)| (k.into(), v.into())));
+        env
+    }
+
+    /// Apply this policy to a command, replacing its inherited environment.
+    pub fn apply(&self, cmd: &mut std::process::Command) {
+        if self.is_empty() {
+            return;
+        }
+        // vars() would panic on a variable that isn't UTF-8
+        cmd.env_clear().envs(self.resolve(std::env::vars_os()));
+    }
+}
+
 fn default_provider() -> String { "anthropic".into() }
+fn default_trash_retention_days() -> u32 { 30 }
+fn default_autonomy_interval() -> usize { 5 }
 fn default_model() -> String { "claude-sonnet-4-20250514".into() }
 fn default_max_tokens() -> u32 { 8192 }
 fn default_temperature() -> f32 { 0.7 }
 fn default_true() -> bool { true }
+fn default_connect_timeout() -> u64 { 10 }
+fn default_read_timeout() -> u64 { 300 }
+fn default_stall_timeout() -> u64 { 60 }
 fn default_system_prompt() -> Option<String> {
     Some("You are a helpful AI assistant. When writing code, you are precise and produce clean, working code. You format responses using markdown. When asked to edit files or write code, use the available tools to read, write, and edit files directly. Be concise but thorough.".into())
 }
@@ -156,12 +946,75 @@ impl Default for Theme {
     fn default() -> Self { default_theme() }
 }
 
+/// Set from `--config` / `--data-dir`; take precedence over the env vars.
+static CONFIG_PATH_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+static DATA_DIR_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Resolve a location: explicit flag, then env var, then the platform default.
+/// Empty env values are ignored.
+fn pick_path(
+    flag: Option<&PathBuf>,
+    env: Option<OsString>,
+    default: impl FnOnce() -> PathBuf,
+) -> PathBuf {
+    flag.cloned()
+        .or_else(|| env.filter(|v| !v.is_empty()).map(PathBuf::from))
+        .unwrap_or_else(default)
+}
+
+/// Under `cargo test`, config and data live in a scratch directory of the
+/// test process, so no test can read or overwrite the user's own files. It
+/// is removed when the test process exits.
+fn test_root() -> Option<PathBuf> {
+    #[cfg(test)]
+    remove_test_root_at_exit();
+    cfg!(test).then(|| std::env::temp_dir().join(format!("pro-chat-test-{}", std::process::id())))
+}
+
+#[cfg(test)]
+fn remove_test_root_at_exit() {
+    unsafe extern "C" {
+        fn atexit(callback: extern "C" fn()) -> std::ffi::c_int;
+    }
+    extern "C" fn remove_test_root() {
+        let root = std::env::temp_dir().join(format!("pro-chat-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(root);
+    }
+    static REGISTER: std::sync::Once = std::sync::Once::new();
+    REGISTER.call_once(|| unsafe {
+        atexit(remove_test_root);
+    });
+}
+
+/// `$XDG_*_HOME` if set, else the `dirs` default. `dirs` already honours XDG on
+/// Linux; this makes the override work on macOS too.
+fn xdg_or(var: &str, fallback: Option<PathBuf>) -> PathBuf {
+    pick_path(None, std::env::var_os(var), || {
+        fallback.unwrap_or_else(|| PathBuf::from("."))
+    })
+}
+
 impl Config {
+    /// Point config and data lookups at explicit locations. Must be called
+    /// before anything is loaded; later calls are ignored.
+    pub fn set_path_overrides(config: Option<PathBuf>, data_dir: Option<PathBuf>) {
+        if let Some(p) = config {
+            let _ = CONFIG_PATH_OVERRIDE.set(p);
+        }
+        if let Some(p) = data_dir {
+            let _ = DATA_DIR_OVERRIDE.set(p);
+        }
+    }
+
     pub fn path() -> PathBuf {
-        dirs::config_dir()
-            .unwrap_or_else(|| PathBuf::from("."))
-            .join("pro-chat")
-            .join("config.toml")
+        if let Some(root) = test_root() {
+            return root.join("config.toml");
+        }
+        pick_path(CONFIG_PATH_OVERRIDE.get(), std::env::var_os("PRO_CHAT_CONFIG"), || {
+            xdg_or("XDG_CONFIG_HOME", dirs::config_dir())
+                .join("pro-chat")
+                .join("config.toml")
+        })
     }
 
     pub fn load() -> anyhow::Result<Self> {
@@ -182,12 +1035,83 @@ impl Config {
         if let Some(parent) = path.parent() {
             std::fs::create_dir_all(parent)?;
         }
-        let content = toml::to_string_pretty(self)?;
-        std::fs::write(&path, content)?;
+        // Never persist profile overrides or pack entries into the top-level settings
+        let mut plain = self.clone();
+        if let Some(base) = &self.profile_base {
+            plain.provider = base.provider.clone();
+            plain.model = base.model.clone();
+            plain.system_prompt = base.system_prompt.clone();
+        }
+        plain.profiles.retain(|name, _| !self.pack_entries.profiles.contains_key(name));
+        plain.recipes.retain(|name, _| !self.pack_entries.recipes.contains_key(name));
+        std::fs::write(&path, toml::to_string_pretty(&plain)?)?;
         Ok(())
     }
 
+    /// Remember a tool permission in config.toml. Only that entry is
+    /// written, so other unsaved changes made this session stay unsaved.
+    pub fn save_tool_permission(&mut self, tool: &str, permission: crate::tools::ToolPermission) -> anyhow::Result<()> {
+        self.tools.permissions.insert(tool.to_string(), permission);
+        let mut saved = Self::load()?;
+        saved.tools.permissions.insert(tool.to_string(), permission);
+        saved.save()
+    }
+
+    /// Apply the named profile on top of the top-level settings, replacing
+    /// any profile applied before.
+    pub fn apply_profile(&mut self, name: &str) -> anyhow::Result<()> {
+        let Some(profile) = self.profiles.get(name).cloned() else {
+            let known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            anyhow::bail!("Unknown profile: {name} (available: {})", known.join(", "));
+        };
+        if let Some(dir) = &profile.history_dir
+            && !Path::new(dir).components().all(|c| matches!(c, std::path::Component::Normal(_)))
+        {
+            anyhow::bail!("Profile {name}: history_dir must be a relative path without '..', not {dir}");
+        }
+        self.clear_profile();
+
+        self.profile_base = Some(ProfileBase {
+            provider: self.provider.clone(),
+            model: self.model.clone(),
+            system_prompt: self.system_prompt.clone(),
+        });
+        if let Some(provider) = profile.provider {
+            self.provider = provider;
+        }
+        if let Some(model) = profile.model {
+            self.model = model;
+        }
+        if profile.system_prompt.is_some() {
+            self.system_prompt = profile.system_prompt;
+        }
+        let key = profile.api_key.or_else(|| {
+            profile.api_key_env.and_then(|var| std::env::var(var).ok())
+        });
+        self.profile_api_key = key.map(|k| (self.provider.clone(), k));
+        self.history_subdir = profile.history_dir;
+        self.active_profile = Some(name.to_string());
+        Ok(())
+    }
+
+    /// Drop the active profile and go back to the top-level settings.
+    pub fn clear_profile(&mut self) {
+        if let Some(base) = self.profile_base.take() {
+            self.provider = base.provider;
+            self.model = base.model;
+            self.system_prompt = base.system_prompt;
+        }
+        self.profile_api_key = None;
+        self.active_profile = None;
+        self.history_subdir = None;
+    }
+
     pub fn api_key_from_env(&self) -> Option<String> {
+        if let Some((provider, key)) = &self.profile_api_key
+            && *provider == self.provider
+        {
+            return Some(key.clone());
+        }
         match self.provider.as_str() {
             "anthropic" => self.anthropic_api_key.clone()
                 .or_else(|| std::env::var("ANTHROPIC_API_KEY").ok()),
@@ -197,8 +1121,194 @@ impl Config {
                 .or_else(|| std::env::var("OPENROUTER_API_KEY").ok()),
             "xai" => self.xai_api_key.clone()
                 .or_else(|| std::env::var("XAI_API_KEY").ok()),
-            _ => None,
+            "gemini" => self.gemini_api_key.clone()
+                .or_else(|| std::env::var("GEMINI_API_KEY").ok()),
+            // Local models and the scripted mock need no key
+            "ollama" | "mock" => Some(String::new()),
+            // Requests are signed with AWS credentials instead of a key
+            "bedrock" => crate::aws::AwsCredentials::load().map(|_| String::new()),
+            name => {
+                let custom = self.custom_provider(name)?;
+                match (&custom.api_key, &custom.api_key_env) {
+                    (Some(key), _) => Some(key.clone()),
+                    (None, Some(var)) => std::env::var(var).ok(),
+                    // Unauthenticated endpoint, e.g. a local server
+                    (None, None) => Some(String::new()),
+                }
+            }
+        }
+    }
+
+    /// A provider from the `[[providers]]` registry. Built-in names are not
+    /// looked up, so a custom entry cannot shadow them.
+    pub fn custom_provider(&self, name: &str) -> Option<&CustomProvider> {
+        if BUILTIN_PROVIDERS.contains(&name) {
+            return None;
+        }
+        self.providers.iter().find(|p| p.name == name)
+    }
+
+    /// Built-in and configured provider names, for validation and completion.
+    pub fn provider_names(&self) -> Vec<String> {
+        BUILTIN_PROVIDERS
+            .iter()
+            .map(|p| p.to_string())
+            .chain(self.providers.iter().map(|p| p.name.clone()))
+            .collect()
+    }
+
+    /// Reject provider names that are neither built in nor configured.
+    pub fn check_provider(&self, name: &str) -> Result<(), String> {
+        if BUILTIN_PROVIDERS.contains(&name) || self.custom_provider(name).is_some() {
+            return Ok(());
+        }
+        Err(format!(
+            "Unknown provider '{name}' (available: {})",
+            self.provider_names().join(", ")
+        ))
+    }
+
+    /// `[models."<id>"]` overrides for the current model.
+    pub fn model_config(&self) -> Option<&ModelConfig> {
+        self.models.get(&self.model)
+    }
+
+    /// `max_tokens` for requests to the current model.
+    pub fn effective_max_tokens(&self) -> u32 {
+        self.model_config().and_then(|m| m.max_tokens).unwrap_or(self.max_tokens)
+    }
+
+    /// Temperature for requests to the current model.
+    pub fn effective_temperature(&self) -> f32 {
+        self.model_config().and_then(|m| m.temperature).unwrap_or(self.temperature)
+    }
+
+    /// Set the temperature, on the current model's override when it has one
+    /// so the change isn't shadowed by it. Returns the clamped value.
+    pub fn set_temperature(&mut self, temperature: f32) -> f32 {
+        let temperature = clamp_temperature(temperature);
+        match self.models.get_mut(&self.model) {
+            Some(ModelConfig { temperature: Some(t), .. }) => *t = temperature,
+            _ => self.temperature = temperature,
+        }
+        temperature
+    }
+
+    /// USD per million (input, output) tokens for `model`: the configured
+    /// prices, else the built-in list prices.
+    pub fn price_per_mtok(&self, model: &str) -> Option<(f64, f64)> {
+        match self.models.get(model) {
+            Some(ModelConfig { input_price: Some(input), output_price: Some(output), .. }) => Some((*input, *output)),
+            _ => crate::usage::price_per_mtok(model),
+        }
+    }
+
+    pub fn context_window(&self) -> Option<usize> {
+        self.model_config().and_then(|m| m.context_window)
+    }
+
+    /// The tools offered to the current provider and model, or `None` when
+    /// no definitions are sent (before the session's tools toggle). `[models."..."]` settings win over the
+    /// built-in capability table, which wins over the provider default.
+    pub fn tool_set(&self) -> Option<crate::tools::ToolSet> {
+        let known = crate::tools::model_tool_capability(&self.model);
+        let model = self.model_config();
+        let supported = model.and_then(|m| m.supports_tools).unwrap_or_else(|| {
+            known != Some(None)
+                && (crate::tools::provider_supports_tools(&self.provider)
+                    || self.custom_provider(&self.provider).is_some_and(|p| p.tools))
+        });
+        supported.then(|| model.and_then(|m| m.tool_set).or(known.flatten()).unwrap_or(crate::tools::ToolSet::Full))
+    }
+
+    /// Whether the provider continues a final assistant message in the
+    /// request (a "prefill") rather than needing to be asked to go on.
+    pub fn supports_prefill(&self) -> bool {
+        self.custom_provider(&self.provider).is_none() && matches!(self.provider.as_str(), "anthropic" | "bedrock")
+    }
+
+    /// Beta flags for requests to the current model: `anthropic_beta`, then
+    /// the model's own, without duplicates.
+    pub fn anthropic_betas(&self) -> Vec<String> {
+        let mut betas = self.anthropic_beta.clone();
+        for beta in self.model_config().map(|m| m.anthropic_beta.as_slice()).unwrap_or_default() {
+            if !betas.contains(beta) {
+                betas.push(beta.clone());
+            }
         }
+        betas
+    }
+
+    /// Headers sent with every request to `provider`: OpenRouter's app
+    /// attribution, the OpenAI organization and project, Anthropic beta
+    /// flags, a custom provider's `headers`, then `[headers.<provider>]`.
+    /// Later entries replace earlier ones with the same name.
+    pub fn request_headers(&self, provider: &str) -> Vec<(String, String)> {
+        let mut headers: Vec<(String, String)> = Vec::new();
+        let mut set = |name: &str, value: &str| {
+            headers.retain(|(k, _)| !k.eq_ignore_ascii_case(name));
+            headers.push((name.to_string(), value.to_string()));
+        };
+        match self.custom_provider(provider) {
+            Some(custom) => {
+                for (name, value) in &custom.headers {
+                    set(name, value);
+                }
+            }
+            None if provider == "openrouter" => {
+                for (name, value) in crate::api::OPENROUTER_HEADERS {
+                    set(name, value);
+                }
+            }
+            None if provider == "anthropic" => {
+                let betas = self.anthropic_betas();
+                if !betas.is_empty() {
+                    set("anthropic-beta", &betas.join(","));
+                }
+            }
+            None if provider == "openai" => {
+                let env = |var: &str| std::env::var(var).ok().filter(|v| !v.is_empty());
+                if let Some(org) = self.openai_organization.clone().or_else(|| env("OPENAI_ORG_ID")) {
+                    set("OpenAI-Organization", &org);
+                }
+                if let Some(project) = self.openai_project.clone().or_else(|| env("OPENAI_PROJECT_ID")) {
+                    set("OpenAI-Project", &project);
+                }
+            }
+            None => {}
+        }
+        for (name, value) in self.headers.get(provider).into_iter().flatten() {
+            set(name, value);
+        }
+        headers
+    }
+
+    /// Ollama server URL: `ollama_host`, then `OLLAMA_HOST`, then localhost.
+    pub fn ollama_host(&self) -> String {
+        let host = self.ollama_host.clone()
+            .or_else(|| std::env::var("OLLAMA_HOST").ok())
+            .unwrap_or_else(|| crate::api::DEFAULT_OLLAMA_HOST.to_string());
+        if host.contains("://") { host } else { format!("http://{host}") }
+    }
+
+    /// Bedrock region: `bedrock_region`, then `AWS_REGION`, then
+    /// `AWS_DEFAULT_REGION`, then us-east-1.
+    pub fn bedrock_region(&self) -> String {
+        self.bedrock_region.clone()
+            .or_else(|| std::env::var("AWS_REGION").ok())
+            .or_else(|| std::env::var("AWS_DEFAULT_REGION").ok())
+            .unwrap_or_else(|| "us-east-1".to_string())
+    }
+
+    /// Embedding settings with the API key resolved from the OpenAI key when
+    /// not set explicitly.
+    pub fn embeddings(&self) -> EmbeddingsConfig {
+        let mut embeddings = self.embeddings.clone();
+        if embeddings.api_key.is_none() {
+            embeddings.api_key = self.openai_api_key.clone()
+                .or_else(|| std::env::var("OPENAI_API_KEY").ok());
+        }
+        embeddings
     }
 
     /// Return the environment variable name for the current provider's API key.
@@ -208,7 +1318,12 @@ impl Config {
             "openai" => "OPENAI_API_KEY",
             "openrouter" => "OPENROUTER_API_KEY",
             "xai" => "XAI_API_KEY",
-            _ => "API_KEY",
+            "gemini" => "GEMINI_API_KEY",
+            "bedrock" => "AWS_ACCESS_KEY_ID",
+            name => self
+                .custom_provider(name)
+                .and_then(|p| p.api_key_env.as_deref())
+                .unwrap_or("API_KEY"),
         }
     }
 
@@ -224,18 +1339,28 @@ impl Config {
             "openai" => self.openai_api_key = Some(key),
             "openrouter" => self.openrouter_api_key = Some(key),
             "xai" => self.xai_api_key = Some(key),
+            "gemini" => self.gemini_api_key = Some(key),
             _ => {}
         }
     }
 
     pub fn data_dir() -> PathBuf {
-        dirs::data_dir()
-            .unwrap_or_else(|| PathBuf::from("."))
-            .join("pro-chat")
+        if let Some(root) = test_root() {
+            return root.join("data");
+        }
+        pick_path(DATA_DIR_OVERRIDE.get(), std::env::var_os("PRO_CHAT_DATA"), || {
+            xdg_or("XDG_DATA_HOME", dirs::data_dir()).join("pro-chat")
+        })
     }
 
-    pub fn history_dir() -> PathBuf {
-        Self::data_dir().join("conversations")
+    /// Where conversations are kept: a subdirectory of the data directory,
+    /// and the active profile's `history_dir` below that.
+    pub fn history_dir(&self) -> PathBuf {
+        let dir = Self::data_dir().join("conversations");
+        match &self.history_subdir {
+            Some(sub) => dir.join(sub),
+            None => dir,
+        }
     }
 }
 
@@ -248,8 +1373,19 @@ impl Default for Config {
             openai_api_key: None,
             openrouter_api_key: None,
             xai_api_key: None,
+            gemini_api_key: None,
+            openai_organization: None,
+            openai_project: None,
+            headers: BTreeMap::new(),
+            anthropic_beta: Vec::new(),
+            ollama_host: None,
+            bedrock_region: None,
             max_tokens: default_max_tokens(),
             temperature: default_temperature(),
+            thinking_budget: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: Vec::new(),
             system_prompt: default_system_prompt(),
             theme: default_theme(),
             theme_name: default_theme_name(),
@@ -257,6 +1393,35 @@ impl Default for Config {
             vim_mode: false,
             last_conversation_id: None,
             notify_on_complete: true,
+            message_footer: true,
+            cues: CuesConfig::default(),
+            queue_while_streaming: true,
+            tool_env: ToolEnvConfig::default(),
+            validate: ValidateConfig::default(),
+            sandbox: SandboxConfig::default(),
+            tools: ToolsConfig::default(),
+            network: NetworkConfig::default(),
+            github: GithubConfig::default(),
+            tracker: None,
+            embeddings: EmbeddingsConfig::default(),
+            compress_history: false,
+            trash_retention_days: default_trash_retention_days(),
+            idle_lock_minutes: 0,
+            autonomy: Autonomy::default(),
+            autonomy_interval: default_autonomy_interval(),
+            startup: StartupMode::default(),
+            print_on_exit: PrintOnExit::default(),
+            models: BTreeMap::new(),
+            profiles: BTreeMap::new(),
+            recipes: BTreeMap::new(),
+            packs: Vec::new(),
+            providers: Vec::new(),
+            mcp: BTreeMap::new(),
+            active_profile: None,
+            profile_base: None,
+            profile_api_key: None,
+            history_subdir: None,
+            pack_entries: PackEntries::default(),
         }
     }
 }
@@ -299,6 +1464,14 @@ mod tests {
         assert!(config.notify_on_complete);
     }
 
+    #[test]
+    fn test_network_config_partial_override() {
+        let config: Config = toml::from_str("[network]\nstall_timeout = 5\n").unwrap();
+        assert_eq!(config.network.stall_timeout, 5);
+        assert_eq!(config.network.connect_timeout, 10);
+        assert_eq!(config.network.read_timeout, 300);
+    }
+
     #[test]
     fn test_api_key_from_env_without_keys() {
         let config = Config::default();
@@ -316,6 +1489,10 @@ mod tests {
 
         config.set_api_key_for_provider("openai", "sk-openai-456".into());
         assert_eq!(config.openai_api_key, Some("sk-openai-456".into()));
+
+        config.set_api_key_for_provider("openrouter", "sk-or-789".into());
+        config.provider = "openrouter".into();
+        assert_eq!(config.api_key_from_env().as_deref(), Some("sk-or-789"));
     }
 
     #[test]
@@ -332,10 +1509,316 @@ mod tests {
         config.provider = "xai".into();
         assert_eq!(config.api_key_env_var(), "XAI_API_KEY");
 
+        config.provider = "gemini".into();
+        assert_eq!(config.api_key_env_var(), "GEMINI_API_KEY");
+
+        config.provider = "ollama".into();
+        assert!(config.has_api_key());
+        config.ollama_host = Some("gpu-box:11434".into());
+        assert_eq!(config.ollama_host(), "http://gpu-box:11434");
+
+        config.provider = "bedrock".into();
+        assert_eq!(config.api_key_env_var(), "AWS_ACCESS_KEY_ID");
+        config.bedrock_region = Some("eu-central-1".into());
+        assert_eq!(config.bedrock_region(), "eu-central-1");
+
         config.provider = "unknown".into();
         assert_eq!(config.api_key_env_var(), "API_KEY");
     }
 
+    #[test]
+    fn test_tool_env_resolve() {
+        let parent: Vec<(OsString, OsString)> = vec![
+            ("PATH".into(), "/bin".into()),
+            ("AWS_SECRET_ACCESS_KEY".into(), "s3cr3t".into()),
+            ("CI".into(), "0".into()),
+        ];
+        let env = ToolEnvConfig {
+            set: BTreeMap::from([("CI".to_string(), "1".to_string())]),
+            strip: vec!["AWS_*".into()],
+            passthrough: Vec::new(),
+        };
+
+        let resolved = env.resolve(parent.clone());
+        assert!(resolved.contains(&("PATH".into(), "/bin".into())));
+        assert!(resolved.contains(&("CI".into(), "1".into())));
+        assert!(!resolved.iter().any(|(k, _)| k.to_string_lossy().starts_with("AWS_")));
+
+        let allowlist = ToolEnvConfig {
+            passthrough: vec!["PATH".into()],
+            ..Default::default()
+        };
+        assert_eq!(allowlist.resolve(parent), vec![("PATH".into(), "/bin".into())]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tool_env_keeps_non_utf8_values() {
+        use std::os::unix::ffi::OsStringExt;
+        let latin1 = OsString::from_vec(vec![b'c', 0xe9]);
+        let parent = vec![(OsString::from("LANG_NAME"), latin1.clone())];
+        let env = ToolEnvConfig { strip: vec!["AWS_*".into()], ..Default::default() };
+        assert_eq!(env.resolve(parent), vec![("LANG_NAME".into(), latin1)]);
+    }
+
+    #[test]
+    fn test_custom_provider_registry() {
+        let mut config: Config = toml::from_str(
+            "[[providers]]\nname = \"together\"\nbase_url = \"https://api.together.xyz/v1/\"\n\
+             api_key_env = \"PRO_CHAT_TEST_TOGETHER_KEY\"\n[providers.headers]\nX-Org = \"acme\"\n\
+             [[providers]]\nname = \"local\"\nbase_url = \"http://localhost:8080/v1/chat/completions\"\n",
+        ).unwrap();
+        assert!(config.check_provider("together").is_ok());
+        assert!(config.check_provider("anthropic").is_ok());
+        assert!(config.check_provider("nope").unwrap_err().contains("together, local"));
+
+        let together = config.custom_provider("together").unwrap();
+        assert_eq!(together.chat_url(), "https://api.together.xyz/v1/chat/completions");
+        assert_eq!(together.models_url(), "https://api.together.xyz/v1/models");
+        assert_eq!(together.headers.get("X-Org").map(String::as_str), Some("acme"));
+        assert_eq!(config.custom_provider("local").unwrap().chat_url(), "http://localhost:8080/v1/chat/completions");
+
+        config.provider = "together".into();
+        assert_eq!(config.api_key_env_var(), "PRO_CHAT_TEST_TOGETHER_KEY");
+        assert!(!config.has_api_key());
+        config.provider = "local".into();
+        assert_eq!(config.api_key_from_env().as_deref(), Some(""));
+    }
+
+    #[test]
+    fn test_request_headers() {
+        let config: Config = toml::from_str(
+            "openai_organization = \"org-123\"\nopenai_project = \"proj_abc\"\n\
+             [headers.openai]\nopenai-project = \"proj_override\"\n\
+             [headers.openrouter]\nX-Title = \"My Tool\"\n\
+             [headers.together]\nX-Trace = \"1\"\n\
+             [[providers]]\nname = \"together\"\nbase_url = \"https://api.together.xyz/v1\"\n\
+             [providers.headers]\nX-Org = \"acme\"\n",
+        ).unwrap();
+        let pair = |k: &str, v: &str| (k.to_string(), v.to_string());
+
+        assert_eq!(config.request_headers("openai"), vec![
+            pair("OpenAI-Organization", "org-123"),
+            pair("openai-project", "proj_override"),
+        ]);
+        assert_eq!(config.request_headers("openrouter"), vec![
+            pair("HTTP-Referer", "https://github.com/pro-chat"),
+            pair("X-Title", "My Tool"),
+        ]);
+        assert_eq!(config.request_headers("together"), vec![pair("X-Org", "acme"), pair("X-Trace", "1")]);
+        assert!(config.request_headers("anthropic").is_empty());
+    }
+
+    #[test]
+    fn test_anthropic_beta_flags() {
+        let mut config: Config = toml::from_str(
+            "anthropic_beta = [\"token-counting-2024-11-01\"]\n\
+             [models.\"claude-3-7-sonnet-latest\"]\n\
+             anthropic_beta = [\"output-128k-2025-02-19\", \"token-counting-2024-11-01\"]\n",
+        ).unwrap();
+        config.model = "claude-sonnet-4-20250514".into();
+        assert_eq!(config.request_headers("anthropic"), vec![
+            ("anthropic-beta".to_string(), "token-counting-2024-11-01".to_string()),
+        ]);
+
+        config.model = "claude-3-7-sonnet-latest".into();
+        assert_eq!(config.anthropic_betas(), vec!["token-counting-2024-11-01", "output-128k-2025-02-19"]);
+        assert_eq!(
+            config.request_headers("anthropic")[0].1,
+            "token-counting-2024-11-01,output-128k-2025-02-19"
+        );
+        // Other providers never see the flags
+        assert!(config.request_headers("openrouter").iter().all(|(k, _)| k != "anthropic-beta"));
+    }
+
+    #[test]
+    fn test_model_overrides() {
+        let mut config: Config = toml::from_str(
+            "max_tokens = 4096\ntemperature = 0.7\n\
+             [models.\"o3-mini\"]\nmax_tokens = 16384\ntemperature = 1.0\nsupports_tools = false\n\
+             context_window = 200000\n",
+        ).unwrap();
+        config.provider = "openai".into();
+        config.model = "gpt-4o".into();
+        assert_eq!(config.effective_max_tokens(), 4096);
+        assert_eq!(config.tool_set(), Some(crate::tools::ToolSet::Full));
+        assert_eq!(config.context_window(), None);
+        assert_eq!(config.set_temperature(0.2), 0.2);
+        assert_eq!(config.temperature, 0.2);
+
+        config.model = "o3-mini".into();
+        assert_eq!(config.effective_max_tokens(), 16384);
+        assert_eq!(config.effective_temperature(), 1.0);
+        assert_eq!(config.tool_set(), None);
+        assert_eq!(config.context_window(), Some(200000));
+        // Changing the temperature updates the override, not the global value
+        config.set_temperature(0.5);
+        assert_eq!(config.effective_temperature(), 0.5);
+        assert_eq!(config.temperature, 0.2);
+
+        config.models.entry("claude-sonnet-4-5".into()).or_default().input_price = Some(1.0);
+        // Both prices are needed to replace the built-in ones
+        assert_eq!(config.price_per_mtok("claude-sonnet-4-5"), Some((3.0, 15.0)));
+        config.models.get_mut("claude-sonnet-4-5").unwrap().output_price = Some(2.0);
+        assert_eq!(config.price_per_mtok("claude-sonnet-4-5"), Some((1.0, 2.0)));
+        assert_eq!(config.price_per_mtok("o3-mini"), None);
+    }
+
+    #[test]
+    fn test_tool_set_capabilities() {
+        use crate::tools::ToolSet;
+        let mut config: Config = toml::from_str(
+            "[models.\"gpt-4o-mini\"]\ntool_set = \"full\"\n[models.\"o1-mini\"]\nsupports_tools = true\n",
+        ).unwrap();
+        config.provider = "openai".into();
+        config.model = "gpt-3.5-turbo".into();
+        assert_eq!(config.tool_set(), Some(ToolSet::Core));
+        config.model = "o1-preview".into();
+        assert_eq!(config.tool_set(), None);
+        // Per-model settings override the table
+        config.model = "gpt-4o-mini".into();
+        assert_eq!(config.tool_set(), Some(ToolSet::Full));
+        config.model = "o1-mini".into();
+        assert_eq!(config.tool_set(), Some(ToolSet::Full));
+        // The table never turns tools on for a provider without them
+        config.provider = "gemini".into();
+        config.model = "gpt-3.5-turbo".into();
+        assert_eq!(config.tool_set(), None);
+        assert!(toml::from_str::<Config>("[models.x]\ntool_set = \"tiny\"\n").is_err());
+    }
+
+    #[test]
+    fn test_cues_from_toml() {
+        let config: Config = toml::from_str("[cues]\nconfirm = \"both\"\nerror = \"flash\"\n").unwrap();
+        assert_eq!(config.cues.confirm, Cue::Both);
+        assert_eq!(config.cues.error, Cue::Flash);
+        assert_eq!(config.cues.stream_start, Cue::None);
+        assert!(toml::from_str::<Config>("[cues]\nerror = \"siren\"\n").is_err());
+    }
+
+    #[test]
+    fn test_validate_commands() {
+        let config: Config = toml::from_str(
+            "[validate]\nenabled = true\n[validate.commands]\nrs = []\nrb = [\"ruby\", \"-c\", \"{path}\"]\n",
+        ).unwrap();
+        assert!(config.validate.enabled);
+        assert_eq!(config.validate.command_for("rs"), None);
+        assert_eq!(config.validate.command_for("rb").unwrap()[0], "ruby");
+        assert_eq!(config.validate.command_for("sh").unwrap(), vec!["bash", "-n", "{path}"]);
+        assert!(!Config::default().validate.enabled);
+    }
+
+    #[test]
+    fn test_tool_env_from_toml() {
+        let config: Config = toml::from_str(
+            "[tool_env]\nstrip = [\"AWS_*\"]\n[tool_env.set]\nCI = \"1\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.tool_env.strip, vec!["AWS_*".to_string()]);
+        assert_eq!(config.tool_env.set.get("CI").map(String::as_str), Some("1"));
+        assert!(Config::default().tool_env.is_empty());
+    }
+
+    #[test]
+    fn test_pick_path_precedence() {
+        let flag = PathBuf::from("/flag/config.toml");
+        let default = || PathBuf::from("/default");
+
+        assert_eq!(pick_path(Some(&flag), Some("/env".into()), default), flag);
+        assert_eq!(pick_path(None, Some("/env".into()), default), PathBuf::from("/env"));
+        assert_eq!(pick_path(None, Some("".into()), default), PathBuf::from("/default"));
+        assert_eq!(pick_path(None, None, default), PathBuf::from("/default"));
+    }
+
+    #[test]
+    fn test_tests_never_touch_real_dirs() {
+        let scratch = std::env::temp_dir().join(format!("pro-chat-test-{}", std::process::id()));
+        assert!(Config::path().starts_with(&scratch));
+        assert!(Config::data_dir().starts_with(&scratch));
+        assert!(Config::default().history_dir().starts_with(&scratch));
+    }
+
+    #[test]
+    fn test_apply_profile_overrides_and_restores() {
+        let mut config: Config = toml::from_str(
+            "[profiles.work]\nprovider = \"openai\"\nmodel = \"gpt-4o\"\napi_key = \"sk-work\"\n",
+        )
+        .unwrap();
+
+        config.apply_profile("work").unwrap();
+        assert_eq!(config.active_profile.as_deref(), Some("work"));
+        assert_eq!(config.provider, "openai");
+        assert_eq!(config.model, "gpt-4o");
+        assert_eq!(config.api_key_from_env().as_deref(), Some("sk-work"));
+
+        config.clear_profile();
+        assert_eq!(config.provider, "anthropic");
+        assert_eq!(config.model, "claude-sonnet-4-20250514");
+        assert!(config.active_profile.is_none());
+
+        assert!(config.apply_profile("missing").is_err());
+    }
+
+    #[test]
+    fn test_profile_history_dir() {
+        let mut config: Config = toml::from_str(
+            "[profiles.work]\nhistory_dir = \"work\"\n\
+             [profiles.up]\nhistory_dir = \"../elsewhere\"\n\
+             [profiles.abs]\nhistory_dir = \"/tmp/elsewhere\"\n",
+        )
+        .unwrap();
+        let base = config.history_dir();
+
+        config.apply_profile("work").unwrap();
+        assert_eq!(config.history_dir(), base.join("work"));
+        // Kept on the config, so other configs (and tests) aren't affected
+        assert_eq!(Config::default().history_dir(), base);
+        config.clear_profile();
+        assert_eq!(config.history_dir(), base);
+
+        assert!(config.apply_profile("up").is_err());
+        assert!(config.apply_profile("abs").is_err());
+        assert_eq!(config.history_dir(), base);
+    }
+
+    #[test]
+    fn test_recipe_from_toml() {
+        let config: Config = toml::from_str(
+            "[recipes.review-pr]\nrun = [\"git diff main...\"]\nprofile = \"reviewer\"\nprompt = \"Review this\"\n",
+        )
+        .unwrap();
+        let recipe = &config.recipes["review-pr"];
+        assert_eq!(recipe.run, vec!["git diff main...".to_string()]);
+        assert_eq!(recipe.profile.as_deref(), Some("reviewer"));
+        assert!(recipe.files.is_empty());
+    }
+
+    #[test]
+    fn test_tracker_from_toml() {
+        let config: Config = toml::from_str(
+            "[tracker]\nkind = \"jira\"\nurl = \"https://acme.atlassian.net\"\ntoken = \"t\"\n",
+        )
+        .unwrap();
+        let tracker = config.tracker.unwrap();
+        assert_eq!(tracker.kind, TrackerKind::Jira);
+        assert_eq!(tracker.token().as_deref(), Some("t"));
+        assert!(Config::default().tracker.is_none());
+    }
+
+    #[test]
+    fn test_print_on_exit_from_toml() {
+        let config: Config = toml::from_str("print_on_exit = \"last\"\n").unwrap();
+        assert_eq!(config.print_on_exit, PrintOnExit::Last);
+        assert_eq!(Config::default().print_on_exit, PrintOnExit::None);
+    }
+
+    #[test]
+    fn test_startup_mode_from_toml() {
+        let config: Config = toml::from_str("startup = \"picker\"\n").unwrap();
+        assert_eq!(config.startup, StartupMode::Picker);
+        assert_eq!(Config::default().startup, StartupMode::Restore);
+    }
+
     #[test]
     fn test_get_theme_known_names() {
         let _ = get_theme("tokyo-night");
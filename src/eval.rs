@@ -0,0 +1,315 @@
+//! `pro eval`: run a YAML file of test prompts against one or more models and
+//! system prompts, save every response, and write a side-by-side report so
+//! prompt changes can be compared run to run.
+//!
+//! ```yaml
+//! models: [anthropic/claude-sonnet-4-20250514, openai/gpt-4o]  # default: the configured model
+//! systems:                                                      # default: the configured system prompt
+//!   terse: Answer in at most two sentences.
+//!   tutor: Explain step by step for a beginner.
+//! cases:
+//!   - name: hashmap
+//!     prompt: What is a hash map?
+//!     expect: [key, value]      # must appear (case-insensitive)
+//!     reject: ["I'm not sure"]  # must not appear
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::api::{ApiClient, Message, MessageContent, Request};
+use crate::config::Config;
+use crate::tools::format_duration;
+
+/// Give up on a single response after this long.
+const CASE_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Suite {
+    /// `provider/model` names
+    #[serde(default)]
+    models: Vec<String>,
+    /// Named system prompts to compare; an empty one sends none
+    #[serde(default)]
+    systems: BTreeMap<String, String>,
+    #[serde(default)]
+    max_tokens: Option<u32>,
+    cases: Vec<Case>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Case {
+    name: String,
+    prompt: String,
+    #[serde(default)]
+    expect: Vec<String>,
+    #[serde(default)]
+    reject: Vec<String>,
+}
+
+/// One model and system prompt combination.
+struct Variant {
+    label: String,
+    config: Config,
+    system: Option<String>,
+}
+
+/// The result of one case against one variant.
+#[derive(Debug, Serialize)]
+struct Outcome {
+    case: String,
+    variant: String,
+    response: Option<String>,
+    error: Option<String>,
+    latency_ms: u64,
+    output_tokens: u64,
+    /// `expect` strings the response lacks
+    missing: Vec<String>,
+    /// `reject` strings the response contains
+    rejected: Vec<String>,
+}
+
+impl Outcome {
+    fn passed(&self) -> bool {
+        self.error.is_none() && self.missing.is_empty() && self.rejected.is_empty()
+    }
+
+    fn verdict(&self) -> String {
+        if let Some(e) = &self.error {
+            return format!("error: {}", e.lines().next().unwrap_or_default());
+        }
+        let mut problems = Vec::new();
+        if !self.missing.is_empty() {
+            problems.push(format!("missing {}", quoted(&self.missing)));
+        }
+        if !self.rejected.is_empty() {
+            problems.push(format!("contains {}", quoted(&self.rejected)));
+        }
+        if problems.is_empty() {
+            "pass".into()
+        } else {
+            format!("FAIL: {}", problems.join("; "))
+        }
+    }
+}
+
+fn quoted(items: &[String]) -> String {
+    items.iter().map(|s| format!("\"{s}\"")).collect::<Vec<_>>().join(", ")
+}
+
+fn load_suite(path: &Path) -> anyhow::Result<Suite> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Couldn't read {}", path.display()))?;
+    let suite: Suite = serde_yaml::from_str(&text).with_context(|| format!("Invalid eval file {}", path.display()))?;
+    if suite.cases.is_empty() {
+        anyhow::bail!("{} has no cases", path.display());
+    }
+    Ok(suite)
+}
+
+/// Every model crossed with every system prompt.
+fn variants(config: &Config, suite: &Suite) -> anyhow::Result<Vec<Variant>> {
+    let models = if suite.models.is_empty() {
+        vec![(format!("{}/{}", config.provider, config.model), config.clone())]
+    } else {
+        suite
+            .models
+            .iter()
+            .map(|spec| Ok((spec.clone(), crate::bench::parse_target(config, spec)?)))
+            .collect::<anyhow::Result<_>>()?
+    };
+    let mut variants = Vec::new();
+    for (model, config) in models {
+        if suite.systems.is_empty() {
+            let system = config.system_prompt.clone();
+            variants.push(Variant { label: model, config, system });
+            continue;
+        }
+        for (name, system) in &suite.systems {
+            variants.push(Variant {
+                label: format!("{model} · {name}"),
+                config: config.clone(),
+                system: Some(system.clone()).filter(|s| !s.trim().is_empty()),
+            });
+        }
+    }
+    Ok(variants)
+}
+
+/// Check a response against the case's `expect` and `reject` lists.
+fn check(case: &Case, response: &str) -> (Vec<String>, Vec<String>) {
+    let lower = response.to_lowercase();
+    let missing = case.expect.iter().filter(|s| !lower.contains(&s.to_lowercase())).cloned().collect();
+    let rejected = case.reject.iter().filter(|s| lower.contains(&s.to_lowercase())).cloned().collect();
+    (missing, rejected)
+}
+
+async fn run_case(client: &ApiClient, variant: &Variant, case: &Case, max_tokens: Option<u32>) -> Outcome {
+    let mut outcome = Outcome {
+        case: case.name.clone(),
+        variant: variant.label.clone(),
+        response: None,
+        error: None,
+        latency_ms: 0,
+        output_tokens: 0,
+        missing: Vec::new(),
+        rejected: Vec::new(),
+    };
+    let Some(api_key) = variant.config.api_key_from_env() else {
+        outcome.error = Some(format!("no API key (set {})", variant.config.api_key_env_var()));
+        return outcome;
+    };
+    let messages = vec![Message { role: "user".into(), content: MessageContent::Text(case.prompt.clone()) }];
+    let defaults = Request::new(&variant.config, api_key, messages);
+    let request = Request {
+        system: variant.system.clone(),
+        max_tokens: max_tokens.unwrap_or(defaults.max_tokens),
+        ..defaults
+    };
+    match tokio::time::timeout(CASE_TIMEOUT, client.complete(&request)).await {
+        Ok(Ok(completion)) => {
+            (outcome.missing, outcome.rejected) = check(case, &completion.text);
+            outcome.latency_ms = completion.elapsed.as_millis() as u64;
+            outcome.output_tokens = completion.usage.output_tokens;
+            outcome.response = Some(completion.text);
+        }
+        Ok(Err(e)) => outcome.error = Some(e.to_string()),
+        Err(_) => outcome.error = Some(format!("no response within {}", format_duration(CASE_TIMEOUT))),
+    }
+    outcome
+}
+
+/// Pass counts, latency and tokens per variant, as a Markdown table.
+fn summary(variants: &[Variant], outcomes: &[Outcome]) -> String {
+    let mut out = String::from("| Variant | Passed | Errors | Avg latency | Output tokens |\n|---|---|---|---|---|\n");
+    for variant in variants {
+        let results: Vec<&Outcome> = outcomes.iter().filter(|o| o.variant == variant.label).collect();
+        let answered: Vec<&&Outcome> = results.iter().filter(|o| o.error.is_none()).collect();
+        let avg_latency = match answered.len() {
+            0 => "-".to_string(),
+            n => format_duration(Duration::from_millis(answered.iter().map(|o| o.latency_ms).sum::<u64>() / n as u64)),
+        };
+        let _ = writeln!(
+            out,
+            "| {} | {}/{} | {} | {avg_latency} | {} |",
+            variant.label,
+            results.iter().filter(|o| o.passed()).count(),
+            results.len(),
+            results.len() - answered.len(),
+            answered.iter().map(|o| o.output_tokens).sum::<u64>(),
+        );
+    }
+    out
+}
+
+/// The full report: the summary, then each case's prompt followed by every
+/// variant's response.
+fn report(suite_path: &Path, suite: &Suite, variants: &[Variant], outcomes: &[Outcome]) -> String {
+    let mut out = format!(
+        "# Eval: {}\n\n{} · {} case(s) × {} variant(s)\n\n",
+        suite_path.display(),
+        chrono::Local::now().format("%Y-%m-%d %H:%M"),
+        suite.cases.len(),
+        variants.len()
+    );
+    out.push_str(&summary(variants, outcomes));
+    for case in &suite.cases {
+        let _ = write!(out, "\n## {}\n\n", case.name);
+        for line in case.prompt.lines() {
+            let _ = writeln!(out, "> {line}");
+        }
+        for outcome in outcomes.iter().filter(|o| o.case == case.name) {
+            let _ = write!(out, "\n### {}: {}", outcome.variant, outcome.verdict());
+            if outcome.error.is_none() {
+                let _ = write!(
+                    out,
+                    " ({}, {} tokens)",
+                    format_duration(Duration::from_millis(outcome.latency_ms)),
+                    outcome.output_tokens
+                );
+            }
+            let _ = write!(out, "\n\n{}\n", outcome.response.as_deref().unwrap_or_default().trim_end());
+        }
+    }
+    out
+}
+
+/// Run every case against every variant, then save `results.json` and
+/// `report.md` under `out` (default: `<data dir>/evals/<suite>-<time>`).
+pub async fn run(config: &Config, suite_path: &Path, out: Option<PathBuf>) -> anyhow::Result<()> {
+    let suite = load_suite(suite_path)?;
+    let variants = variants(config, &suite)?;
+    let client = ApiClient::new(&config.network)?;
+    let total = suite.cases.len() * variants.len();
+
+    let mut outcomes = Vec::new();
+    for case in &suite.cases {
+        for variant in &variants {
+            let outcome = run_case(&client, variant, case, suite.max_tokens).await;
+            println!("[{}/{total}] {} · {}: {}", outcomes.len() + 1, case.name, variant.label, outcome.verdict());
+            outcomes.push(outcome);
+        }
+    }
+
+    let out = out.unwrap_or_else(|| {
+        let stem = suite_path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_else(|| "eval".into());
+        Config::data_dir()
+            .join("evals")
+            .join(format!("{stem}-{}", chrono::Local::now().format("%Y%m%d-%H%M%S")))
+    });
+    std::fs::create_dir_all(&out).with_context(|| format!("Couldn't create {}", out.display()))?;
+    std::fs::write(out.join("results.json"), serde_json::to_string_pretty(&outcomes)?)?;
+    std::fs::write(out.join("report.md"), report(suite_path, &suite, &variants, &outcomes))?;
+    println!("\n{}\nReport: {}", summary(&variants, &outcomes), out.join("report.md").display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUITE: &str = "models: [mock/a, mock/b]\nsystems:\n  plain: ''\n  terse: Be brief.\n\
+                         cases:\n  - name: greet\n    prompt: hello\n    expect: [MOCK PROVIDER, code block]\n    reject: [error]\n";
+
+    #[test]
+    fn test_variants_and_checks() {
+        let suite: Suite = serde_yaml::from_str(SUITE).unwrap();
+        let variants = variants(&Config::default(), &suite).unwrap();
+        let labels: Vec<&str> = variants.iter().map(|v| v.label.as_str()).collect();
+        assert_eq!(labels, ["mock/a · plain", "mock/a · terse", "mock/b · plain", "mock/b · terse"]);
+        assert_eq!(variants[0].system, None);
+        assert_eq!(variants[1].system.as_deref(), Some("Be brief."));
+
+        let (missing, rejected) = check(&suite.cases[0], "From the Mock Provider: an error occurred");
+        assert_eq!(missing, ["code block"]);
+        assert_eq!(rejected, ["error"]);
+
+        assert!(serde_yaml::from_str::<Suite>("cases: []\nmodel: x\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run_writes_report() {
+        let dir = std::env::temp_dir().join(format!("pro-chat-eval-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let suite_path = dir.join("cases.yaml");
+        std::fs::write(&suite_path, SUITE.replace("models: [mock/a, mock/b]\n", "models: [mock/a]\n")).unwrap();
+
+        run(&Config::default(), &suite_path, Some(dir.join("out"))).await.unwrap();
+        let report = std::fs::read_to_string(dir.join("out/report.md")).unwrap();
+        assert!(report.contains("| mock/a · plain | 1/1 | 0 |"));
+        assert!(report.contains("### mock/a · terse: pass"));
+        assert!(report.contains("nothing left your machine"));
+        let results: Vec<serde_json::Value> =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("out/results.json")).unwrap()).unwrap();
+        assert_eq!(results.len(), 2);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
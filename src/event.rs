@@ -2,17 +2,47 @@ use crossterm::event::{self, Event as CrosstermEvent, KeyEvent, MouseEvent};
 use std::time::Duration;
 use tokio::sync::mpsc;
 
+use crate::api::{RateLimits, TokenUsage};
+use crate::tools::ToolResult;
+
+/// How many times slower ticks come while the terminal is unfocused.
+const UNFOCUSED_TICK_FACTOR: u32 = 8;
+
 #[derive(Debug)]
 pub enum Event {
     Key(KeyEvent),
     Mouse(MouseEvent),
     Resize(#[allow(dead_code)] u16, u16),
+    /// The terminal (or tmux pane) gained or lost focus
+    Focus(bool),
     Tick,
     ApiChunk(String),
+    /// Extended thinking text, shown apart from the answer
+    ThinkingChunk(String),
     ApiDone,
+    /// Token counts reported for the response in flight so far
+    Usage(TokenUsage),
+    /// Why the model stopped, e.g. `end_turn` or `max_tokens`
+    StopReason(String),
+    /// Rate limits from the response headers
+    RateLimits(RateLimits),
     ApiError(String),
     /// The API returned tool_use blocks. Contains the full response JSON.
     ToolUseRequest(String),
+    /// An MCP server finished connecting: its tool count, or why it failed
+    McpConnected(String, Result<usize, String>),
+    /// The pending tool call at this index started running on its own task
+    ToolStarted(usize),
+    /// Output the running `execute` call at this index just printed
+    ToolOutputChunk(usize, String),
+    /// That call stopped at a prompt; the user's answer (or `None`) goes
+    /// back on the channel
+    ToolInput(usize, String, tokio::sync::oneshot::Sender<Option<String>>),
+    /// That call finished, with how long it took
+    ToolFinished(usize, ToolResult, Duration),
+    /// The commands of this recipe finished: one (heading, lang, output)
+    /// attachment each, or why one failed
+    RecipeOutput(String, Result<Vec<(String, String, String)>, String>),
 }
 
 pub struct EventHandler {
@@ -28,25 +58,28 @@ impl EventHandler {
         let tick_rate = Duration::from_millis(tick_rate);
 
         tokio::spawn(async move {
+            // Nothing on screen changes much while nobody is looking, so
+            // idle panes tick (and redraw) less often
+            let mut focused = true;
             loop {
-                if event::poll(tick_rate).unwrap_or(false) {
-                    match event::read() {
-                        Ok(CrosstermEvent::Key(key)) => {
-                            if tx.send(Event::Key(key)).is_err() {
-                                break;
-                            }
-                        }
-                        Ok(CrosstermEvent::Mouse(mouse)) => {
-                            if tx.send(Event::Mouse(mouse)).is_err() {
-                                break;
-                            }
+                let timeout = if focused { tick_rate } else { tick_rate * UNFOCUSED_TICK_FACTOR };
+                if event::poll(timeout).unwrap_or(false) {
+                    let event = match event::read() {
+                        Ok(CrosstermEvent::Key(key)) => Event::Key(key),
+                        Ok(CrosstermEvent::Mouse(mouse)) => Event::Mouse(mouse),
+                        Ok(CrosstermEvent::Resize(w, h)) => Event::Resize(w, h),
+                        Ok(CrosstermEvent::FocusGained) => {
+                            focused = true;
+                            Event::Focus(true)
                         }
-                        Ok(CrosstermEvent::Resize(w, h)) => {
-                            if tx.send(Event::Resize(w, h)).is_err() {
-                                break;
-                            }
+                        Ok(CrosstermEvent::FocusLost) => {
+                            focused = false;
+                            Event::Focus(false)
                         }
-                        _ => {}
+                        _ => continue,
+                    };
+                    if tx.send(event).is_err() {
+                        break;
                     }
                 } else if tx.send(Event::Tick).is_err() {
                     break;
@@ -1,6 +1,6 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
 use crate::config::Config;
@@ -11,14 +11,45 @@ pub struct Conversation {
     pub title: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// Git root (or working directory) the conversation was started in.
+    #[serde(default)]
+    pub project: Option<String>,
     pub messages: Vec<SavedMessage>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SavedMessage {
+    /// Short stable identifier, used by `/goto` and in exports. Files saved
+    /// before IDs existed get one assigned on load, which sticks on next save.
+    #[serde(default = "new_message_id")]
+    pub id: String,
     pub role: String,
     pub content: String,
     pub timestamp: DateTime<Utc>,
+    /// Private annotation, kept locally and never sent to the API
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Which model produced an assistant message, and how
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<ResponseMeta>,
+    /// Names and sizes of PDFs sent with a user message (the files
+    /// themselves are not stored)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<String>,
+}
+
+/// Details of the request behind an assistant message, shown in its footer.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResponseMeta {
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
 }
 
 impl Conversation {
@@ -28,31 +59,79 @@ impl Conversation {
             title: "New conversation".into(),
             created_at: Utc::now(),
             updated_at: Utc::now(),
+            project: current_project(),
             messages: Vec::new(),
         }
     }
 
-    fn path(&self) -> PathBuf {
-        Config::history_dir().join(format!("{}.json", self.id))
+    /// Short display name for the project (the directory's basename).
+    pub fn project_name(&self) -> Option<&str> {
+        let project = self.project.as_deref()?;
+        Path::new(project).file_name()?.to_str()
     }
 
-    pub fn save(&self) -> anyhow::Result<()> {
-        let dir = Config::history_dir();
-        std::fs::create_dir_all(&dir)?;
-        let content = serde_json::to_string_pretty(self)?;
-        std::fs::write(self.path(), content)?;
+    /// Path of the plain JSON file for conversation `id` in history `dir`.
+    fn json_path(dir: &Path, id: &str) -> PathBuf {
+        dir.join(format!("{id}.json"))
+    }
+
+    /// Path of the zstd-compressed file for conversation `id` in history `dir`.
+    fn zst_path(dir: &Path, id: &str) -> PathBuf {
+        dir.join(format!("{id}.json.zst"))
+    }
+
+    /// Save into history `dir` as pretty JSON, or as zstd-compressed compact
+    /// JSON when `compress` is set. The file in the other format, if any, is
+    /// removed.
+    pub fn save(&self, dir: &Path, compress: bool) -> anyhow::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        let (path, stale) = if compress {
+            let content = serde_json::to_vec(self)?;
+            let packed = zstd::encode_all(content.as_slice(), ZSTD_LEVEL)?;
+            write_atomic(&Self::zst_path(dir, &self.id), &packed)?;
+            (Self::zst_path(dir, &self.id), Self::json_path(dir, &self.id))
+        } else {
+            let content = serde_json::to_string_pretty(self)?;
+            write_atomic(&Self::json_path(dir, &self.id), content.as_bytes())?;
+            (Self::json_path(dir, &self.id), Self::zst_path(dir, &self.id))
+        };
+        if stale != path && stale.exists() {
+            std::fs::remove_file(stale)?;
+        }
         Ok(())
     }
 
-    pub fn load(id: &str) -> anyhow::Result<Self> {
-        let path = Config::history_dir().join(format!("{id}.json"));
-        let content = std::fs::read_to_string(path)?;
-        let conv: Conversation = serde_json::from_str(&content)?;
-        Ok(conv)
+    /// Load a conversation by id. If an interrupted save left both formats,
+    /// the newer copy wins, as in `list_all`; the next save removes the other.
+    pub fn load(dir: &Path, id: &str) -> anyhow::Result<Self> {
+        let zst = Self::zst_path(dir, id);
+        let json = Self::json_path(dir, id);
+        if !zst.exists() {
+            return read_conversation_file(&json);
+        }
+        if !json.exists() {
+            return read_conversation_file(&zst);
+        }
+        match (read_conversation_file(&zst), read_conversation_file(&json)) {
+            (Ok(a), Ok(b)) => Ok(if b.updated_at > a.updated_at { b } else { a }),
+            (Ok(conv), Err(_)) | (Err(_), Ok(conv)) => Ok(conv),
+            (Err(e), Err(_)) => Err(e),
+        }
     }
 
-    pub fn list_all() -> anyhow::Result<Vec<Conversation>> {
-        let dir = Config::history_dir();
+    /// Load a conversation by id from history `dir`, or from a
+    /// `.json`/`.json.zst` file path.
+    pub fn open(dir: &Path, id_or_path: &str) -> anyhow::Result<Self> {
+        let path = Path::new(id_or_path);
+        if path.is_file() {
+            return read_conversation_file(path);
+        }
+        Self::load(dir, id_or_path)
+            .map_err(|e| anyhow::anyhow!("No conversation or file '{id_or_path}': {e}"))
+    }
+
+    /// Every conversation in history `dir`, most recent first.
+    pub fn list_all(dir: &Path) -> anyhow::Result<Vec<Conversation>> {
         if !dir.exists() {
             return Ok(Vec::new());
         }
@@ -60,50 +139,598 @@ impl Conversation {
         for entry in std::fs::read_dir(dir)? {
             let entry = entry?;
             let path = entry.path();
-            if path.extension().is_some_and(|e| e == "json") {
-                if let Ok(content) = std::fs::read_to_string(&path) {
-                    if let Ok(conv) = serde_json::from_str::<Conversation>(&content) {
-                        convs.push(conv);
-                    }
-                }
+            if path.extension().is_some_and(|e| e == "json" || e == "zst")
+                && let Ok(conv) = read_conversation_file(&path)
+            {
+                convs.push(conv);
             }
         }
-        convs.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+        convs.sort_by_key(|c| std::cmp::Reverse(c.updated_at));
+        // A save interrupted between writing one format and removing the other
+        // leaves both; list the newer copy once
+        let mut seen = std::collections::HashSet::new();
+        convs.retain(|c| seen.insert(c.id.clone()));
+        Ok(convs)
+    }
+
+    /// Conversations started in `project`, most recent first.
+    pub fn list_for_project(dir: &Path, project: &str) -> anyhow::Result<Vec<Conversation>> {
+        let mut convs = Self::list_all(dir)?;
+        convs.retain(|c| c.project.as_deref() == Some(project));
         Ok(convs)
     }
 
     /// Returns the most recently updated conversation (by updated_at timestamp).
-    pub fn latest() -> anyhow::Result<Option<Conversation>> {
-        let convs = Self::list_all()?;
+    pub fn latest(dir: &Path) -> anyhow::Result<Option<Conversation>> {
+        let convs = Self::list_all(dir)?;
         Ok(convs.into_iter().next())
     }
 
-    pub fn delete(id: &str) -> anyhow::Result<()> {
-        let path = Config::history_dir().join(format!("{id}.json"));
-        if path.exists() {
-            std::fs::remove_file(path)?;
+    /// Move the conversation to the trash, where `/history trash` can
+    /// restore it until [`purge_trash`] removes it.
+    pub fn delete(dir: &Path, id: &str) -> anyhow::Result<()> {
+        move_to_trash(dir, id)
+    }
+
+    /// Trashed conversations, most recently deleted first.
+    pub fn list_trash(dir: &Path) -> anyhow::Result<Vec<Conversation>> {
+        list_trash_in(dir)
+    }
+
+    /// Move a trashed conversation back into history.
+    pub fn restore(dir: &Path, id: &str) -> anyhow::Result<()> {
+        restore_from_trash(dir, id)
+    }
+
+    /// Replace the content of message `id`, adding it if it isn't there yet
+    /// (a continued response keeps the id of the message it extends).
+    pub fn set_message(&mut self, id: &str, role: &str, content: &str) {
+        match self.messages.iter_mut().find(|m| m.id == id) {
+            Some(message) => {
+                message.content = content.into();
+                self.updated_at = Utc::now();
+            }
+            None => self.add_message(id, role, content),
+        }
+    }
+
+    pub fn set_meta(&mut self, id: &str, meta: ResponseMeta) {
+        if let Some(message) = self.messages.iter_mut().find(|m| m.id == id) {
+            message.meta = Some(meta);
         }
-        Ok(())
     }
 
-    pub fn add_message(&mut self, role: &str, content: &str) {
+    pub fn set_attachments(&mut self, id: &str, labels: Vec<String>) {
+        if let Some(message) = self.messages.iter_mut().find(|m| m.id == id) {
+            message.attachments = labels;
+        }
+    }
+
+    /// Append a message, keeping the ID already shown for it in the UI.
+    pub fn add_message(&mut self, id: &str, role: &str, content: &str) {
         self.messages.push(SavedMessage {
+            id: id.into(),
             role: role.into(),
             content: content.into(),
             timestamp: Utc::now(),
+            note: None,
+            meta: None,
+            attachments: Vec::new(),
         });
         self.updated_at = Utc::now();
 
         // Auto-title from first user message
-        if self.title == "New conversation" {
-            if let Some(first_user) = self.messages.iter().find(|m| m.role == "user") {
-                let title: String = first_user.content.chars().take(60).collect();
-                self.title = if title.len() < first_user.content.len() {
-                    format!("{title}...")
-                } else {
-                    title
-                };
+        if self.title == "New conversation"
+            && let Some(first_user) = self.messages.iter().find(|m| m.role == "user")
+        {
+            let title: String = first_user.content.chars().take(60).collect();
+            self.title = if title.len() < first_user.content.len() {
+                format!("{title}...")
+            } else {
+                title
+            };
+        }
+    }
+}
+
+/// A bookmarked message, stored across all conversations in `bookmarks.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub conversation_id: String,
+    pub conversation_title: String,
+    pub message_id: String,
+    pub role: String,
+    /// First line of the message, for display
+    pub excerpt: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Bookmark {
+    fn path() -> PathBuf {
+        Config::data_dir().join("bookmarks.json")
+    }
+
+    /// All bookmarks, newest first.
+    pub fn load_all() -> anyhow::Result<Vec<Bookmark>> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let content = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn save_all(bookmarks: &[Bookmark]) -> anyhow::Result<()> {
+        std::fs::create_dir_all(Config::data_dir())?;
+        let content = serde_json::to_string_pretty(bookmarks)?;
+        write_atomic(&Self::path(), content.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Add `bookmark`, or remove it if that message is already bookmarked.
+/// Returns whether the message is bookmarked afterwards.
+pub fn toggle_bookmark(bookmarks: &mut Vec<Bookmark>, bookmark: Bookmark) -> bool {
+    let existing = bookmarks.iter().position(|b| {
+        b.conversation_id == bookmark.conversation_id && b.message_id == bookmark.message_id
+    });
+    match existing {
+        Some(pos) => {
+            bookmarks.remove(pos);
+            false
+        }
+        None => {
+            bookmarks.insert(0, bookmark);
+            true
+        }
+    }
+}
+
+/// Generate a short message ID (8 hex chars, unique enough within a chat).
+pub fn new_message_id() -> String {
+    Uuid::new_v4().simple().to_string()[..8].to_string()
+}
+
+/// zstd level used for conversation files: fast, and JSON shrinks ~10x anyway.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Magic number at the start of every zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Read a conversation file, decompressing it if it starts with the zstd
+/// magic number regardless of its extension.
+fn read_conversation_file(path: &Path) -> anyhow::Result<Conversation> {
+    let raw = std::fs::read(path)?;
+    let json = if raw.starts_with(&ZSTD_MAGIC) {
+        zstd::decode_all(raw.as_slice())?
+    } else {
+        raw
+    };
+    Ok(serde_json::from_slice(&json)?)
+}
+
+/// Subdirectory of the history directory holding deleted conversations.
+const TRASH_SUBDIR: &str = "trash";
+
+/// File names a conversation may be stored under.
+fn conversation_file_names(id: &str) -> [String; 2] {
+    [format!("{id}.json"), format!("{id}.json.zst")]
+}
+
+fn move_to_trash(dir: &Path, id: &str) -> anyhow::Result<()> {
+    let trash = dir.join(TRASH_SUBDIR);
+    std::fs::create_dir_all(&trash)?;
+    for name in conversation_file_names(id) {
+        let src = dir.join(&name);
+        if !src.exists() {
+            continue;
+        }
+        let dst = trash.join(&name);
+        std::fs::rename(&src, &dst)?;
+        // Retention counts from the deletion, not the last edit
+        std::fs::File::options()
+            .write(true)
+            .open(&dst)?
+            .set_modified(std::time::SystemTime::now())?;
+    }
+    Ok(())
+}
+
+fn restore_from_trash(dir: &Path, id: &str) -> anyhow::Result<()> {
+    let trash = dir.join(TRASH_SUBDIR);
+    let mut restored = false;
+    for name in conversation_file_names(id) {
+        let src = trash.join(&name);
+        if !src.exists() {
+            continue;
+        }
+        let dst = dir.join(&name);
+        if dst.exists() {
+            anyhow::bail!("A conversation with id {id} already exists");
+        }
+        std::fs::rename(&src, &dst)?;
+        restored = true;
+    }
+    if !restored {
+        anyhow::bail!("Conversation {id} is not in the trash");
+    }
+    Ok(())
+}
+
+/// Trash files with their deletion time.
+fn trash_files(dir: &Path) -> Vec<(PathBuf, std::time::SystemTime)> {
+    let Ok(entries) = std::fs::read_dir(dir.join(TRASH_SUBDIR)) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|e| {
+            let path = e.path();
+            let deleted = e.metadata().ok()?.modified().ok()?;
+            path.extension().is_some_and(|x| x == "json" || x == "zst").then_some((path, deleted))
+        })
+        .collect()
+}
+
+fn list_trash_in(dir: &Path) -> anyhow::Result<Vec<Conversation>> {
+    let mut files = trash_files(dir);
+    files.sort_by_key(|(_, deleted)| std::cmp::Reverse(*deleted));
+    Ok(files
+        .iter()
+        .filter_map(|(path, _)| read_conversation_file(path).ok())
+        .collect())
+}
+
+fn purge_trash_in(dir: &Path, retention: std::time::Duration) -> usize {
+    let Some(cutoff) = std::time::SystemTime::now().checked_sub(retention) else {
+        return 0;
+    };
+    trash_files(dir)
+        .into_iter()
+        .filter(|(_, deleted)| *deleted < cutoff)
+        .filter(|(path, _)| std::fs::remove_file(path).is_ok())
+        .count()
+}
+
+/// Permanently remove conversations trashed more than `retention_days` ago.
+/// Zero keeps them forever. Returns how many files were removed.
+pub fn purge_trash(dir: &Path, retention_days: u32) -> usize {
+    if retention_days == 0 {
+        return 0;
+    }
+    let retention = std::time::Duration::from_secs(u64::from(retention_days) * 24 * 60 * 60);
+    purge_trash_in(dir, retention)
+}
+
+/// Totals reported by [`compact_all`].
+#[derive(Debug, Default)]
+pub struct CompactStats {
+    pub files: usize,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+/// Compress every plain JSON conversation in history `dir`.
+pub fn compact_all(dir: &Path) -> anyhow::Result<CompactStats> {
+    let mut stats = CompactStats::default();
+    if !dir.exists() {
+        return Ok(stats);
+    }
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|e| e != "json") {
+            continue;
+        }
+        let before = std::fs::metadata(&path)?.len();
+        let conv = match read_conversation_file(&path) {
+            Ok(c) => c,
+            Err(e) => {
+                tracing::warn!("Skipping {}: {e}", path.display());
+                continue;
+            }
+        };
+        conv.save(dir, true)?;
+        stats.files += 1;
+        stats.bytes_before += before;
+        stats.bytes_after += std::fs::metadata(Conversation::zst_path(dir, &conv.id))?.len();
+    }
+    Ok(stats)
+}
+
+/// Advisory lock marking a conversation as open in this process, so a second
+/// `pro` instance opens it read-only instead of clobbering its saves. The lock
+/// file holds the owner's PID; locks left behind by a crashed process are
+/// detected as stale and taken over. A file without a PID yet is another
+/// instance between creating and writing it, so it counts as held until it
+/// is older than `LOCK_WRITE_GRACE`.
+#[derive(Debug)]
+pub struct ConversationLock {
+    id: String,
+    path: PathBuf,
+    /// False when the lock file could not be created for reasons other than
+    /// contention (e.g. a read-only disk); such locks are never removed
+    owned: bool,
+}
+
+impl ConversationLock {
+    /// Lock conversation `id` in history `dir`, or return the PID of the
+    /// live process holding it (0 if it hasn't written its PID yet).
+    pub fn acquire(dir: &Path, id: &str) -> Result<Self, u32> {
+        let _ = std::fs::create_dir_all(dir);
+        Self::acquire_at(id, dir.join(format!("{id}.lock")))
+    }
+
+    fn acquire_at(id: &str, path: PathBuf) -> Result<Self, u32> {
+        use std::io::Write;
+        let me = std::process::id();
+        for _ in 0..2 {
+            match std::fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    let _ = write!(file, "{me}");
+                    return Ok(Self { id: id.to_string(), path, owned: true });
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    let holder = std::fs::read_to_string(&path)
+                        .ok()
+                        .and_then(|s| s.trim().parse::<u32>().ok());
+                    match holder {
+                        Some(pid) if pid != me && process_alive(pid) => return Err(pid),
+                        // Being written by an instance we can't name yet
+                        None if !lock_abandoned(&path) => return Err(0),
+                        // Stale (crashed owner), abandoned, or our own: take it over
+                        _ => {
+                            let _ = std::fs::remove_file(&path);
+                        }
+                    }
+                }
+                Err(_) => break,
             }
         }
+        Ok(Self { id: id.to_string(), path, owned: false })
+    }
+
+    /// The locked conversation.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl Drop for ConversationLock {
+    fn drop(&mut self) {
+        if self.owned {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// How long a lock file may go without a PID before it is taken to be left
+/// by a process that died between creating and writing it.
+const LOCK_WRITE_GRACE: std::time::Duration = std::time::Duration::from_secs(10);
+
+fn lock_abandoned(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .is_ok_and(|t| t.elapsed().is_ok_and(|age| age > LOCK_WRITE_GRACE))
+}
+
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    // Signal 0 only checks for existence; EPERM means it exists but belongs
+    // to another user
+    let Ok(pid) = libc::pid_t::try_from(pid) else { return false };
+    let exists = unsafe { libc::kill(pid, 0) } == 0;
+    exists || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn process_alive(_pid: u32) -> bool {
+    true
+}
+
+/// Write `contents` to a sibling temp file, fsync it, then rename it over
+/// `path`, so a crash or power loss leaves either the old or the new file and
+/// never a truncated one.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp = path.with_file_name(tmp_name);
+
+    let mut file = std::fs::File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&tmp, path)
+}
+
+/// The project the current process runs in: the enclosing git root if there
+/// is one, otherwise the working directory.
+pub fn current_project() -> Option<String> {
+    let cwd = std::env::current_dir().ok()?;
+    let root = cwd
+        .ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .unwrap_or(&cwd);
+    Some(root.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toggle_bookmark() {
+        let bookmark = |msg: &str| Bookmark {
+            conversation_id: "c1".into(),
+            conversation_title: "Chat".into(),
+            message_id: msg.into(),
+            role: "assistant".into(),
+            excerpt: String::new(),
+            created_at: Utc::now(),
+        };
+        let mut list = Vec::new();
+
+        assert!(toggle_bookmark(&mut list, bookmark("m1")));
+        assert!(toggle_bookmark(&mut list, bookmark("m2")));
+        assert_eq!(list[0].message_id, "m2");
+
+        assert!(!toggle_bookmark(&mut list, bookmark("m1")));
+        assert_eq!(list.len(), 1);
+    }
+
+    #[test]
+    fn test_response_meta_round_trips() {
+        let mut conv = Conversation::new();
+        conv.add_message("u1", "user", "hi");
+        conv.add_message("a1", "assistant", "hello");
+        conv.set_meta("a1", ResponseMeta {
+            model: "claude-sonnet-4-5".into(),
+            stop_reason: Some("end_turn".into()),
+            input_tokens: Some(12),
+            output_tokens: Some(3),
+            latency_ms: Some(840),
+        });
+
+        let json = serde_json::to_string(&conv).unwrap();
+        // Messages without metadata don't grow a field
+        assert_eq!(json.matches("\"meta\"").count(), 1);
+        let loaded: Conversation = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.messages[0].meta, None);
+        assert_eq!(loaded.messages[1].meta.as_ref().unwrap().latency_ms, Some(840));
+    }
+
+    #[test]
+    fn test_list_all_lists_a_conversation_once() {
+        let dir = std::env::temp_dir().join(format!("pro-chat-dupes-{}", Uuid::new_v4()));
+        let mut conv = Conversation::new();
+        conv.add_message("m1", "user", "old");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(format!("{}.json", conv.id)), serde_json::to_string(&conv).unwrap()).unwrap();
+        conv.updated_at = Utc::now() + chrono::Duration::seconds(1);
+        conv.set_message("m1", "user", "new");
+        let packed = zstd::encode_all(serde_json::to_vec(&conv).unwrap().as_slice(), ZSTD_LEVEL).unwrap();
+        std::fs::write(dir.join(format!("{}.json.zst", conv.id)), packed).unwrap();
+
+        let convs = Conversation::list_all(&dir).unwrap();
+        assert_eq!(convs.len(), 1);
+        assert_eq!(convs[0].messages[0].content, "new");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_load_prefers_newer_copy_and_save_drops_the_other() {
+        let dir = std::env::temp_dir().join(format!("pro-chat-dupes-{}", Uuid::new_v4()));
+        let mut conv = Conversation::new();
+        conv.add_message("m1", "user", "old");
+        std::fs::create_dir_all(&dir).unwrap();
+        let packed = zstd::encode_all(serde_json::to_vec(&conv).unwrap().as_slice(), ZSTD_LEVEL).unwrap();
+        std::fs::write(Conversation::zst_path(&dir, &conv.id), packed).unwrap();
+        conv.updated_at = Utc::now() + chrono::Duration::seconds(1);
+        conv.set_message("m1", "user", "new");
+        std::fs::write(Conversation::json_path(&dir, &conv.id), serde_json::to_string(&conv).unwrap()).unwrap();
+
+        let loaded = Conversation::load(&dir, &conv.id).unwrap();
+        assert_eq!(loaded.messages[0].content, "new");
+        loaded.save(&dir, false).unwrap();
+        assert!(!Conversation::zst_path(&dir, &conv.id).exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_trash_restore_and_purge() {
+        let dir = std::env::temp_dir().join(format!("pro-chat-trash-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let conv = Conversation::new();
+        std::fs::write(dir.join(format!("{}.json", conv.id)), serde_json::to_string(&conv).unwrap()).unwrap();
+
+        move_to_trash(&dir, &conv.id).unwrap();
+        assert!(!dir.join(format!("{}.json", conv.id)).exists());
+        let trashed = list_trash_in(&dir).unwrap();
+        assert_eq!(trashed.len(), 1);
+        assert_eq!(trashed[0].id, conv.id);
+
+        restore_from_trash(&dir, &conv.id).unwrap();
+        assert!(dir.join(format!("{}.json", conv.id)).exists());
+        assert!(restore_from_trash(&dir, &conv.id).is_err());
+
+        move_to_trash(&dir, &conv.id).unwrap();
+        assert_eq!(purge_trash_in(&dir, std::time::Duration::from_secs(3600)), 0);
+        let old = std::time::SystemTime::now() - std::time::Duration::from_secs(7200);
+        let trashed_file = dir.join(TRASH_SUBDIR).join(format!("{}.json", conv.id));
+        std::fs::File::options().write(true).open(&trashed_file).unwrap().set_modified(old).unwrap();
+        assert_eq!(purge_trash_in(&dir, std::time::Duration::from_secs(3600)), 1);
+        assert!(list_trash_in(&dir).unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_conversation_lock_contention_and_stale_takeover() {
+        let dir = std::env::temp_dir().join(format!("pro-chat-lock-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("c1.lock");
+
+        let lock = ConversationLock::acquire_at("c1", path.clone()).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), std::process::id().to_string());
+        drop(lock);
+        assert!(!path.exists());
+
+        // A PID that cannot exist is a crashed owner: the lock is taken over
+        std::fs::write(&path, u32::MAX.to_string()).unwrap();
+        assert!(ConversationLock::acquire_at("c1", path.clone()).is_ok());
+
+        #[cfg(unix)]
+        {
+            // PID 1 is always alive
+            std::fs::write(&path, "1").unwrap();
+            assert_eq!(ConversationLock::acquire_at("c1", path.clone()).unwrap_err(), 1);
+        }
+
+        // An empty file is an owner that hasn't written its PID yet
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(ConversationLock::acquire_at("c1", path.clone()).unwrap_err(), 0);
+        let old = std::time::SystemTime::now() - LOCK_WRITE_GRACE * 2;
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(old).unwrap();
+        assert!(ConversationLock::acquire_at("c1", path.clone()).is_ok());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_write_atomic_replaces_file() {
+        let dir = std::env::temp_dir().join(format!("pro-chat-atomic-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("conv.json");
+
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second");
+        assert!(!dir.join("conv.json.tmp").exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_read_conversation_file_detects_zstd() {
+        let dir = std::env::temp_dir().join(format!("pro-chat-zstd-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut conv = Conversation::new();
+        conv.add_message(&new_message_id(), "user", "compress me");
+        let json = serde_json::to_vec(&conv).unwrap();
+
+        // Detection goes by magic number, not by extension
+        let packed = dir.join("packed.json");
+        std::fs::write(&packed, zstd::encode_all(json.as_slice(), ZSTD_LEVEL).unwrap()).unwrap();
+        let plain = dir.join("plain.json");
+        std::fs::write(&plain, &json).unwrap();
+
+        for path in [packed, plain] {
+            let loaded = read_conversation_file(&path).unwrap();
+            assert_eq!(loaded.id, conv.id);
+            assert_eq!(loaded.messages[0].content, "compress me");
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
     }
 }
@@ -1,6 +1,6 @@
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
-use crate::app::{App, InputMode, Overlay};
+use crate::app::{App, HistoryBulkAction, InputMode, Overlay};
 
 /// Result of handling a key event
 pub enum KeyAction {
@@ -18,6 +18,36 @@ pub enum KeyAction {
     RetryMessage,
     /// Edit last user message
     EditLastMessage,
+    /// Resend the last message whose request failed
+    ResendFailed,
+    /// Continue a response cut off at max_tokens
+    ContinueResponse,
+}
+
+/// One continuation of a prefix key, e.g. the second `g` of `gg`.
+pub struct PrefixBinding {
+    pub key: char,
+    pub description: &'static str,
+    action: fn(&mut App),
+}
+
+/// Normal-mode keys that wait for a second key, and what each continuation
+/// does. The key handler and the which-key popup both read this table, so
+/// the popup always lists exactly what the keys do.
+const PREFIX_KEYMAP: &[(char, &[PrefixBinding])] = &[(
+    'g',
+    &[
+        PrefixBinding { key: 'g', description: "Scroll to top", action: App::scroll_to_top },
+        PrefixBinding { key: 'n', description: "Annotate the focused message", action: App::begin_note },
+    ],
+)];
+
+/// Continuations of `prefix`, empty if it isn't a prefix key.
+pub fn prefix_bindings(prefix: char) -> &'static [PrefixBinding] {
+    PREFIX_KEYMAP
+        .iter()
+        .find(|(p, _)| *p == prefix)
+        .map_or(&[], |(_, bindings)| bindings)
 }
 
 pub fn handle_key(app: &mut App, key: KeyEvent) -> KeyAction {
@@ -47,7 +77,32 @@ pub fn handle_key(app: &mut App, key: KeyEvent) -> KeyAction {
 }
 
 fn handle_normal_mode(app: &mut App, key: KeyEvent) -> KeyAction {
+    // Second key of a prefixed sequence; anything else is handled as usual
+    if let Some(prefix) = app.pending_prefix.take() {
+        if let KeyCode::Char(c) = key.code
+            && let Some(binding) = prefix_bindings(prefix).iter().find(|b| b.key == c)
+        {
+            (binding.action)(app);
+            return KeyAction::Consumed;
+        }
+        if key.code == KeyCode::Esc {
+            return KeyAction::Consumed;
+        }
+    }
+
     match (key.modifiers, key.code) {
+        // The read-only viewer quits like a pager
+        (KeyModifiers::NONE, KeyCode::Char('q')) if app.viewer => KeyAction::Quit,
+        // ...and leaves bookmarks, notes and the conversation alone
+        (KeyModifiers::NONE, KeyCode::Char('b')) | (KeyModifiers::CONTROL, KeyCode::Char('n' | 'l'))
+            if app.viewer =>
+        {
+            app.status_message = Some("Read-only viewer: the transcript can't be changed".into());
+            KeyAction::Consumed
+        }
+
+        (KeyModifiers::NONE, KeyCode::Esc) if app.is_streaming() => KeyAction::CancelStream,
+
         // Mode switching
         (KeyModifiers::NONE, KeyCode::Char('i')) => {
             app.input_mode = InputMode::Insert;
@@ -80,6 +135,20 @@ fn handle_normal_mode(app: &mut App, key: KeyEvent) -> KeyAction {
             KeyAction::Consumed
         }
 
+        // Welcome screen: pick a recent conversation
+        (KeyModifiers::NONE, KeyCode::Char('j') | KeyCode::Down) if app.on_welcome() => {
+            app.welcome_move(1);
+            KeyAction::Consumed
+        }
+        (KeyModifiers::NONE, KeyCode::Char('k') | KeyCode::Up) if app.on_welcome() => {
+            app.welcome_move(-1);
+            KeyAction::Consumed
+        }
+        (KeyModifiers::NONE, KeyCode::Enter) if app.on_welcome() => {
+            app.open_welcome_selection();
+            KeyAction::Consumed
+        }
+
         // Navigation
         (KeyModifiers::NONE, KeyCode::Char('j')) | (KeyModifiers::NONE, KeyCode::Down) => {
             app.scroll_down(1);
@@ -101,9 +170,9 @@ fn handle_normal_mode(app: &mut App, key: KeyEvent) -> KeyAction {
             app.scroll_to_bottom();
             KeyAction::Consumed
         }
-        (_, KeyCode::Char('g')) => {
-            // gg to top - simplified: single g goes to top
-            app.scroll_to_top();
+        (_, KeyCode::Char(c)) if !prefix_bindings(c).is_empty() => {
+            // Shows the which-key popup until the next key
+            app.pending_prefix = Some(c);
             KeyAction::Consumed
         }
 
@@ -120,6 +189,11 @@ fn handle_normal_mode(app: &mut App, key: KeyEvent) -> KeyAction {
             app.cursor_word_forward();
             KeyAction::Consumed
         }
+        // Bookmark the focused message (only when input is empty; otherwise word back)
+        (KeyModifiers::NONE, KeyCode::Char('b')) if app.input.is_empty() => {
+            app.toggle_bookmark();
+            KeyAction::Consumed
+        }
         (KeyModifiers::NONE, KeyCode::Char('b')) => {
             app.cursor_word_back();
             KeyAction::Consumed
@@ -165,6 +239,7 @@ fn handle_normal_mode(app: &mut App, key: KeyEvent) -> KeyAction {
         }
         (KeyModifiers::CONTROL, KeyCode::Char('h')) => {
             app.overlay = Overlay::History;
+            app.history_trash = false;
             app.load_history_list();
             KeyAction::Consumed
         }
@@ -194,12 +269,33 @@ fn handle_normal_mode(app: &mut App, key: KeyEvent) -> KeyAction {
 
         // Retry/regenerate last response
         (KeyModifiers::CONTROL, KeyCode::Char('r')) => {
-            return KeyAction::RetryMessage;
+            KeyAction::RetryMessage
+        }
+
+        // Preview the request the next send would make
+        (KeyModifiers::CONTROL, KeyCode::Char('o')) => {
+            app.show_request_preview();
+            KeyAction::Consumed
+        }
+
+        // Resend the last failed message
+        (KeyModifiers::SHIFT, KeyCode::Char('R')) => {
+            KeyAction::ResendFailed
+        }
+
+        (KeyModifiers::SHIFT, KeyCode::Char('T')) => {
+            app.show_thinking = !app.show_thinking;
+            KeyAction::Consumed
+        }
+
+        // Continue a response that hit max_tokens
+        (KeyModifiers::NONE, KeyCode::Char('c')) if app.input.is_empty() => {
+            KeyAction::ContinueResponse
         }
 
         // Edit last user message (only when input is empty to avoid conflicts)
         (KeyModifiers::NONE, KeyCode::Char('e')) if app.input.is_empty() => {
-            return KeyAction::EditLastMessage;
+            KeyAction::EditLastMessage
         }
 
         // Yank (copy) last response
@@ -256,9 +352,18 @@ fn handle_insert_mode(app: &mut App, key: KeyEvent) -> KeyAction {
             KeyAction::Consumed
         }
 
-        // Send message
+        // Preview the request including the message being typed
+        (KeyModifiers::CONTROL, KeyCode::Char('o')) => {
+            app.show_request_preview();
+            KeyAction::Consumed
+        }
+
+        // Send message, or open the highlighted recent chat from the welcome screen
         (KeyModifiers::NONE, KeyCode::Enter) => {
-            if app.input.trim().is_empty() {
+            if app.input.is_empty() && app.on_welcome() {
+                app.open_welcome_selection();
+                KeyAction::Consumed
+            } else if app.input.trim().is_empty() {
                 KeyAction::None
             } else {
                 KeyAction::SendMessage
@@ -388,7 +493,25 @@ fn handle_search_mode(app: &mut App, key: KeyEvent) -> KeyAction {
 }
 
 fn handle_overlay_key(app: &mut App, key: KeyEvent) -> KeyAction {
+    if app.overlay == Overlay::SystemPrompt {
+        return handle_prompt_editor_key(app, key);
+    }
+    if app.overlay == Overlay::Review {
+        return handle_review_key(app, key);
+    }
+    if app.overlay == Overlay::RecipeConfirm {
+        app.confirm_recipe(matches!(key.code, KeyCode::Char('y') | KeyCode::Enter));
+        return KeyAction::Consumed;
+    }
+    if app.overlay == Overlay::History && app.history_confirm.is_some() {
+        app.confirm_history_bulk(matches!(key.code, KeyCode::Char('y') | KeyCode::Enter));
+        return KeyAction::Consumed;
+    }
     match key.code {
+        KeyCode::Esc if app.overlay == Overlay::History && app.has_history_selection() => {
+            app.clear_history_selection();
+            KeyAction::Consumed
+        }
         KeyCode::Esc | KeyCode::Char('q') => {
             app.overlay = Overlay::None;
             KeyAction::Consumed
@@ -405,10 +528,79 @@ fn handle_overlay_key(app: &mut App, key: KeyEvent) -> KeyAction {
             app.overlay_select();
             KeyAction::Consumed
         }
-        KeyCode::Char('d') if app.overlay == Overlay::History => {
-            app.delete_history_entry();
+        KeyCode::Char('d') if app.viewer && matches!(app.overlay, Overlay::History | Overlay::Bookmarks) => {
+            app.status_message = Some("Read-only viewer: nothing can be deleted".into());
+            KeyAction::Consumed
+        }
+        KeyCode::Char('d') if app.overlay == Overlay::History && !app.history_trash => {
+            app.request_history_bulk(HistoryBulkAction::Delete);
+            KeyAction::Consumed
+        }
+        KeyCode::Char('e') if app.overlay == Overlay::History && !app.history_trash => {
+            app.request_history_bulk(HistoryBulkAction::Export);
+            KeyAction::Consumed
+        }
+        KeyCode::Char(' ') if app.overlay == Overlay::History && !app.history_trash => {
+            app.toggle_history_mark();
+            KeyAction::Consumed
+        }
+        KeyCode::Char('v') if app.overlay == Overlay::History && !app.history_trash => {
+            app.toggle_history_visual();
+            KeyAction::Consumed
+        }
+        KeyCode::Char('a') if app.overlay == Overlay::Recall => {
+            app.attach_recall_hit();
+            KeyAction::Consumed
+        }
+        KeyCode::Char('d') if app.overlay == Overlay::Bookmarks => {
+            app.delete_selected_bookmark();
+            KeyAction::Consumed
+        }
+        KeyCode::Char('p') if app.overlay == Overlay::History && !app.history_trash => {
+            app.toggle_history_project_filter();
             KeyAction::Consumed
         }
         _ => KeyAction::None,
     }
 }
+
+/// The Review overlay: j/k pick a file, n/p walk hunks, a/r accept or revert.
+fn handle_review_key(app: &mut App, key: KeyEvent) -> KeyAction {
+    if app.review_revert_pending {
+        app.confirm_review_revert(matches!(key.code, KeyCode::Char('y') | KeyCode::Enter));
+        return KeyAction::Consumed;
+    }
+    match key.code {
+        KeyCode::Esc | KeyCode::Char('q') => app.overlay = Overlay::None,
+        KeyCode::Char('j') | KeyCode::Down => app.review_select_file(1),
+        KeyCode::Char('k') | KeyCode::Up => app.review_select_file(-1),
+        KeyCode::Char('n') => app.review_step_hunk(true),
+        KeyCode::Char('p') => app.review_step_hunk(false),
+        KeyCode::Char('a') => app.review_accept(),
+        KeyCode::Char('r') => app.request_review_revert(),
+        _ => return KeyAction::None,
+    }
+    KeyAction::Consumed
+}
+
+/// The system prompt editor captures all keys so text can be typed freely.
+fn handle_prompt_editor_key(app: &mut App, key: KeyEvent) -> KeyAction {
+    let ed = &mut app.prompt_editor;
+    match (key.modifiers, key.code) {
+        (_, KeyCode::Esc) => app.overlay = Overlay::None,
+        (KeyModifiers::CONTROL, KeyCode::Char('s')) => app.save_prompt_editor(),
+        (_, KeyCode::Tab) => ed.preview = !ed.preview,
+        _ if ed.preview => {}
+        (_, KeyCode::Enter) => ed.insert('\n'),
+        (_, KeyCode::Backspace) => ed.backspace(),
+        (_, KeyCode::Left) => ed.left(),
+        (_, KeyCode::Right) => ed.right(),
+        (_, KeyCode::Up) => ed.up(),
+        (_, KeyCode::Down) => ed.down(),
+        (_, KeyCode::Home) => ed.home(),
+        (_, KeyCode::End) => ed.end(),
+        (KeyModifiers::NONE | KeyModifiers::SHIFT, KeyCode::Char(c)) => ed.insert(c),
+        _ => return KeyAction::None,
+    }
+    KeyAction::Consumed
+}
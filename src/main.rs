@@ -1,18 +1,35 @@
 mod app;
+mod aws;
+mod bench;
+mod bugreport;
 mod config;
+mod eval;
 mod event;
 mod api;
 mod ui;
 mod keybinds;
 mod markdown;
+mod mcp;
 mod neovim;
+mod packs;
+mod patch;
+mod structured;
 mod history;
+mod metrics;
+mod rag;
+mod state;
+mod review;
 mod tools;
+mod tracker;
+mod tutor;
+mod update;
+mod usage;
+mod web;
 
 use std::io;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use crossterm::{
-    event::{DisableMouseCapture, EnableMouseCapture},
+    event::{DisableFocusChange, DisableMouseCapture, EnableFocusChange, EnableMouseCapture},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
@@ -33,7 +50,8 @@ struct Cli {
     #[arg(short, long)]
     model: Option<String>,
 
-    /// API provider (anthropic, openai, openrouter, xai)
+    /// API provider (anthropic, openai, openrouter, xai, gemini, ollama,
+    /// bedrock, mock, or a name from [[providers]] in config.toml)
     #[arg(long)]
     provider: Option<String>,
 
@@ -41,6 +59,10 @@ struct Cli {
     #[arg(short, long)]
     conversation: Option<String>,
 
+    /// Only show and resume conversations from the current project (git root or cwd)
+    #[arg(long)]
+    here: bool,
+
     /// Neovim socket path for integration
     #[arg(long)]
     nvim_socket: Option<String>,
@@ -48,17 +70,119 @@ struct Cli {
     /// Print config path and exit
     #[arg(long)]
     config_path: bool,
+
+    /// Config file to use instead of the default (env: PRO_CHAT_CONFIG)
+    #[arg(long, value_name = "PATH")]
+    config: Option<std::path::PathBuf>,
+
+    /// Config profile to use (a [profiles.<name>] section)
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Directory for history and logs (env: PRO_CHAT_DATA)
+    #[arg(long, value_name = "PATH")]
+    data_dir: Option<std::path::PathBuf>,
+
+    /// Print the last response or the whole conversation to stdout on quit
+    #[arg(long, value_enum, value_name = "WHAT")]
+    print_on_exit: Option<config::PrintOnExit>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compress all saved conversations with zstd
+    CompactHistory,
+    /// Serve usage counters and latency histograms for Prometheus scraping
+    Metrics {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:9464")]
+        listen: std::net::SocketAddr,
+    },
+    /// Open a saved conversation read-only, without connecting to any API
+    View {
+        /// Conversation id or path to a .json/.json.zst file
+        conversation: String,
+    },
+    /// Chunk and embed documents under a directory for the retrieve tool
+    Index {
+        /// Directory to index (re-indexing replaces its previous chunks)
+        dir: std::path::PathBuf,
+    },
+    /// Time a short standard prompt against each configured model and
+    /// report time to first token and tokens per second
+    Bench {
+        /// provider/model to time, e.g. openai/gpt-4o (default: the configured
+        /// model and each profile's)
+        targets: Vec<String>,
+        /// Requests per model; the median is reported
+        #[arg(long, default_value_t = 3)]
+        runs: usize,
+    },
+    /// Run the prompts in a YAML file against one or more models and system
+    /// prompts, and write a comparison report
+    Eval {
+        /// Cases file (see README for the format)
+        cases: std::path::PathBuf,
+        /// Directory for results.json and report.md (default: <data dir>/evals/<name>-<time>)
+        #[arg(short, long, value_name = "DIR")]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Bundle version info, the redacted config, the latest log and the last
+    /// API exchange into a .tar.gz for a GitHub issue
+    Bugreport {
+        /// Where to write the bundle (default: pro-bugreport-<time>.tar.gz)
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Export or import settings, tool permissions and per-project autonomy
+    /// levels as one file (API keys and other secrets are left out)
+    State {
+        #[command(subcommand)]
+        action: StateAction,
+    },
+    /// Clone or update the shared profile and recipe packs listed under
+    /// [[packs]] in config.toml
+    Packs,
+    /// Learn the basics in a guided walkthrough against a mock model
+    Tutor,
+    /// Install the latest release from GitHub in place of this binary
+    Update {
+        /// Only report whether a newer release exists
+        #[arg(long)]
+        check: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum StateAction {
+    /// Write the current setup to a file
+    Export {
+        /// Where to write it
+        path: std::path::PathBuf,
+    },
+    /// Merge a setup written by `pro state export` into this machine's
+    Import {
+        /// The exported file
+        path: std::path::PathBuf,
+    },
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
+    // The tutorial gets a scratch config and history of its own
+    let tutor_dir = matches!(cli.command, Some(Command::Tutor))
+        .then(|| std::env::temp_dir().join(format!("pro-chat-tutor-{}", std::process::id())));
+    match &tutor_dir {
+        Some(dir) => Config::set_path_overrides(Some(dir.join("config.toml")), Some(dir.clone())),
+        None => Config::set_path_overrides(cli.config.clone(), cli.data_dir.clone()),
+    }
 
     // Set up file logging
-    let log_dir = dirs::data_dir()
-        .unwrap_or_else(|| std::path::PathBuf::from("."))
-        .join("pro-chat")
-        .join("logs");
+    let log_dir = Config::data_dir().join("logs");
     std::fs::create_dir_all(&log_dir)?;
     let file_appender = tracing_appender::rolling::daily(&log_dir, "pro-chat.log");
     let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
@@ -70,31 +194,130 @@ async fn main() -> anyhow::Result<()> {
         )
         .init();
 
-    let config = Config::load()?;
+    let mut config = Config::load()?;
+    // Before --profile, so it can name a profile from a pack
+    let pack_warnings = packs::merge_into(&mut config);
+    if tutor_dir.is_some() {
+        config.provider = "mock".into();
+        config.model = "tutor".into();
+        config.startup = config::StartupMode::New;
+    }
+    if let Some(ref profile) = cli.profile {
+        config.apply_profile(profile)?;
+    }
+    // Checked before the terminal is taken over so the error stays readable
+    if let Some(ref provider) = cli.provider {
+        config.check_provider(provider).map_err(anyhow::Error::msg)?;
+    }
 
     if cli.config_path {
         println!("{}", Config::path().display());
         return Ok(());
     }
 
+    if let Some(Command::Bugreport { output }) = cli.command {
+        let path = bugreport::create(&config, output)?;
+        println!("Wrote {}", path.display());
+        println!("Secrets are redacted, but it includes your last request's messages: look it over before attaching it.");
+        return Ok(());
+    }
+
+    if let Some(Command::State { action }) = &cli.command {
+        return match action {
+            StateAction::Export { path } => state::export(path),
+            StateAction::Import { path } => state::import(path),
+        };
+    }
+
+    if let Some(Command::Packs) = cli.command {
+        return packs::update_all(&config);
+    }
+
+    if let Some(Command::Update { check }) = cli.command {
+        api::use_network(&config.network);
+        return update::run(check).await;
+    }
+
+    if let Some(Command::Metrics { listen }) = cli.command {
+        return metrics::serve(listen).await;
+    }
+
+    if let Some(Command::Bench { targets, runs }) = &cli.command {
+        return bench::run(&config, targets, *runs).await;
+    }
+
+    if let Some(Command::Eval { cases, output }) = &cli.command {
+        return eval::run(&config, cases, output.clone()).await;
+    }
+
+    if let Some(Command::Index { dir }) = &cli.command {
+        let stats = rag::index_dir(&config.embeddings(), dir).await?;
+        println!("Indexed {} chunks from {} files", stats.chunks, stats.files);
+        return Ok(());
+    }
+
+    if let Some(Command::CompactHistory) = cli.command {
+        let stats = history::compact_all(&config.history_dir())?;
+        println!(
+            "Compressed {} conversations: {} KB -> {} KB",
+            stats.files,
+            stats.bytes_before / 1024,
+            stats.bytes_after / 1024
+        );
+        return Ok(());
+    }
+
+    // Loaded before the terminal is taken over so a bad id stays readable
+    let viewed = match &cli.command {
+        Some(Command::View { conversation }) => Some(history::Conversation::open(&config.history_dir(), conversation)?),
+        _ => None,
+    };
+    if viewed.is_some() {
+        // Don't restore (and lock) the last session underneath the viewer
+        config.startup = config::StartupMode::New;
+    }
+
     // Terminal setup
     enable_raw_mode()?;
     let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture, EnableFocusChange)?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
+    let print_on_exit = cli.print_on_exit.unwrap_or(config.print_on_exit);
+
+    history::purge_trash(&config.history_dir(), config.trash_retention_days);
+
     // Create app
     let mut app = App::new(config);
+    if tutor_dir.is_some() {
+        app.tutor = Some(tutor::Tutor::default());
+    }
+    if let Some(conv) = viewed {
+        app.open_viewer(conv);
+    }
 
     if let Some(model) = cli.model {
         app.set_model(&model);
     }
     if let Some(provider) = cli.provider {
-        app.set_provider(&provider);
+        app.set_provider(&provider).map_err(anyhow::Error::msg)?;
     }
     if let Some(conv) = cli.conversation {
         app.load_conversation(&conv)?;
+    } else if cli.here {
+        app.scope_to_project();
+    }
+    if cli.here {
+        app.history_project_only = true;
+    }
+    app.launch_count = app::record_launch();
+    app.load_welcome_recent();
+    if !pack_warnings.is_empty() {
+        app.status_message = Some(pack_warnings.join("; "));
+    }
+    if let Some(notes) = update::take_notes() {
+        app.status_message = Some(notes);
     }
     if let Some(socket) = cli.nvim_socket {
         app.set_nvim_socket(&socket);
@@ -117,14 +340,23 @@ async fn main() -> anyhow::Result<()> {
     execute!(
         terminal.backend_mut(),
         LeaveAlternateScreen,
-        DisableMouseCapture
+        DisableMouseCapture,
+        DisableFocusChange
     )?;
     terminal.show_cursor()?;
 
+    if let Some(dir) = tutor_dir {
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
     if let Err(err) = res {
         eprintln!("Error: {err:?}");
         std::process::exit(1);
     }
 
+    if let Some(output) = app.exit_output(print_on_exit) {
+        println!("{}", output.trim_end());
+    }
+
     Ok(())
 }
@@ -29,8 +29,9 @@ const MIN_CODE_WIDTH: usize = 40;
 
 /// Parse markdown text into styled ratatui Lines.
 /// Supports: bold, italic, code blocks (with syntax highlighting), inline code,
-/// headers, lists, links.
-pub fn parse_markdown(text: &str) -> Vec<Line<'static>> {
+/// headers, lists, links. `boxed` draws code blocks inside a border; narrow
+/// terminals turn it off so long lines don't break the box.
+pub fn parse_markdown(text: &str, boxed: bool) -> Vec<Line<'static>> {
     let mut lines: Vec<Line<'static>> = Vec::new();
     let mut in_code_block = false;
     let mut code_lang = String::new();
@@ -40,7 +41,7 @@ pub fn parse_markdown(text: &str) -> Vec<Line<'static>> {
         if line.starts_with("```") {
             if in_code_block {
                 // End code block -- render the accumulated code with highlighting.
-                render_code_block(&code_lang, &code_lines, &mut lines);
+                render_code_block(&code_lang, &code_lines, boxed, &mut lines);
                 code_lines.clear();
                 code_lang.clear();
                 in_code_block = false;
@@ -61,7 +62,7 @@ pub fn parse_markdown(text: &str) -> Vec<Line<'static>> {
 
     // Handle unclosed code block (e.g. streaming partial response).
     if in_code_block {
-        render_code_block(&code_lang, &code_lines, &mut lines);
+        render_code_block(&code_lang, &code_lines, boxed, &mut lines);
     }
 
     lines
@@ -81,43 +82,37 @@ pub fn parse_markdown(text: &str) -> Vec<Line<'static>> {
 ///   |  }                               |
 ///   +----------------------------------+
 /// ```
-fn render_code_block(lang: &str, code_lines: &[String], out: &mut Vec<Line<'static>>) {
+///
+/// Without `boxed` only the language label and an indent remain.
+fn render_code_block(lang: &str, code_lines: &[String], boxed: bool, out: &mut Vec<Line<'static>>) {
     let ss = &*SYNTAX_SET;
     let ts = &*THEME_SET;
 
-    // Determine the content width: max of all code lines, the language label, or MIN_CODE_WIDTH.
-    let label_width = if lang.is_empty() { 0 } else { lang.len() + 2 }; // " lang "
-    let max_line_len = code_lines
-        .iter()
-        .map(|l| l.len())
-        .max()
-        .unwrap_or(0);
-    let content_width = max_line_len.max(label_width).max(MIN_CODE_WIDTH);
-
-    // --- Top border ---
-    let top_border = if lang.is_empty() {
-        let bar = "\u{2500}".repeat(content_width + 2); // +2 for padding inside box
-        Line::from(Span::styled(
-            format!("  \u{250c}{bar}\u{2510}"),
-            Style::default().fg(BORDER_COLOR),
-        ))
+    let (prefix, content_width) = if boxed {
+        ("  \u{2502} ", code_block_width(lang, code_lines))
     } else {
-        // "  +-  lang  ---...---+"
-        let remaining = content_width + 2 - lang.len() - 2; // subtract " lang "
-        let bar_tail = "\u{2500}".repeat(remaining);
-        Line::from(vec![
-            Span::styled("  \u{250c}\u{2500} ", Style::default().fg(BORDER_COLOR)),
-            Span::styled(
-                lang.to_string(),
+        if !lang.is_empty() {
+            out.push(Line::from(Span::styled(
+                format!("  {lang}"),
                 Style::default().fg(LANG_LABEL_COLOR).add_modifier(Modifier::BOLD),
-            ),
+            )));
+        }
+        ("  ", 0)
+    };
+    // Right-hand padding and border, only drawn around boxed blocks
+    let close = |visible_len: usize| {
+        if boxed {
             Span::styled(
-                format!(" {bar_tail}\u{2510}"),
+                format!("{} \u{2502}", " ".repeat(content_width.saturating_sub(visible_len))),
                 Style::default().fg(BORDER_COLOR),
-            ),
-        ])
+            )
+        } else {
+            Span::raw("")
+        }
     };
-    out.push(top_border);
+    if boxed {
+        out.push(top_border(lang, content_width));
+    }
 
     // --- Code lines (highlighted) ---
     // Try to find a syntax definition for the declared language.
@@ -140,10 +135,7 @@ fn render_code_block(lang: &str, code_lines: &[String], out: &mut Vec<Line<'stat
             for src_line in LinesWithEndings::from(&source) {
                 let ranges = h.highlight_line(src_line, ss).unwrap_or_default();
                 let mut spans: Vec<Span<'static>> = Vec::new();
-                spans.push(Span::styled(
-                    "  \u{2502} ".to_string(),
-                    Style::default().fg(BORDER_COLOR),
-                ));
+                spans.push(Span::styled(prefix, Style::default().fg(BORDER_COLOR)));
 
                 let mut visible_len: usize = 0;
                 for (style, fragment) in &ranges {
@@ -164,15 +156,7 @@ fn render_code_block(lang: &str, code_lines: &[String], out: &mut Vec<Line<'stat
                 }
 
                 // Pad to content_width and close the right border.
-                let pad = if visible_len < content_width {
-                    " ".repeat(content_width - visible_len)
-                } else {
-                    String::new()
-                };
-                spans.push(Span::styled(
-                    format!("{pad} \u{2502}"),
-                    Style::default().fg(BORDER_COLOR),
-                ));
+                spans.push(close(visible_len));
 
                 out.push(Line::from(spans));
             }
@@ -180,33 +164,59 @@ fn render_code_block(lang: &str, code_lines: &[String], out: &mut Vec<Line<'stat
         None => {
             // No syntax found -- render monochrome.
             for code_line in code_lines {
-                let visible_len = code_line.len();
-                let pad = if visible_len < content_width {
-                    " ".repeat(content_width - visible_len)
-                } else {
-                    String::new()
-                };
                 out.push(Line::from(vec![
-                    Span::styled(
-                        "  \u{2502} ".to_string(),
-                        Style::default().fg(BORDER_COLOR),
-                    ),
+                    Span::styled(prefix, Style::default().fg(BORDER_COLOR)),
                     Span::styled(code_line.to_string(), Style::default().fg(CODE_FG)),
-                    Span::styled(
-                        format!("{pad} \u{2502}"),
-                        Style::default().fg(BORDER_COLOR),
-                    ),
+                    close(code_line.len()),
                 ]));
             }
         }
     }
 
     // --- Bottom border ---
-    let bar = "\u{2500}".repeat(content_width + 2);
-    out.push(Line::from(Span::styled(
-        format!("  \u{2514}{bar}\u{2518}"),
-        Style::default().fg(BORDER_COLOR),
-    )));
+    if boxed {
+        let bar = "\u{2500}".repeat(content_width + 2);
+        out.push(Line::from(Span::styled(
+            format!("  \u{2514}{bar}\u{2518}"),
+            Style::default().fg(BORDER_COLOR),
+        )));
+    }
+}
+
+/// Content width of a boxed block: max of all code lines, the language label, or MIN_CODE_WIDTH.
+fn code_block_width(lang: &str, code_lines: &[String]) -> usize {
+    let label_width = if lang.is_empty() { 0 } else { lang.len() + 2 }; // " lang "
+    let max_line_len = code_lines
+        .iter()
+        .map(|l| l.len())
+        .max()
+        .unwrap_or(0);
+    max_line_len.max(label_width).max(MIN_CODE_WIDTH)
+}
+
+fn top_border(lang: &str, content_width: usize) -> Line<'static> {
+    if lang.is_empty() {
+        let bar = "\u{2500}".repeat(content_width + 2); // +2 for padding inside box
+        Line::from(Span::styled(
+            format!("  \u{250c}{bar}\u{2510}"),
+            Style::default().fg(BORDER_COLOR),
+        ))
+    } else {
+        // "  +-  lang  ---...---+"
+        let remaining = content_width + 2 - lang.len() - 2; // subtract " lang "
+        let bar_tail = "\u{2500}".repeat(remaining);
+        Line::from(vec![
+            Span::styled("  \u{250c}\u{2500} ", Style::default().fg(BORDER_COLOR)),
+            Span::styled(
+                lang.to_string(),
+                Style::default().fg(LANG_LABEL_COLOR).add_modifier(Modifier::BOLD),
+            ),
+            Span::styled(
+                format!(" {bar_tail}\u{2510}"),
+                Style::default().fg(BORDER_COLOR),
+            ),
+        ])
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -217,25 +227,25 @@ fn parse_inline(line: &str) -> Line<'static> {
     let line = line.to_string();
 
     // Headers
-    if line.starts_with("### ") {
+    if let Some(rest) = line.strip_prefix("### ") {
         return Line::from(Span::styled(
-            line[4..].to_string(),
+            rest.to_string(),
             Style::default()
                 .fg(Color::Rgb(122, 162, 247))
                 .add_modifier(Modifier::BOLD),
         ));
     }
-    if line.starts_with("## ") {
+    if let Some(rest) = line.strip_prefix("## ") {
         return Line::from(Span::styled(
-            line[3..].to_string(),
+            rest.to_string(),
             Style::default()
                 .fg(Color::Rgb(122, 162, 247))
                 .add_modifier(Modifier::BOLD),
         ));
     }
-    if line.starts_with("# ") {
+    if let Some(rest) = line.strip_prefix("# ") {
         return Line::from(Span::styled(
-            line[2..].to_string(),
+            rest.to_string(),
             Style::default()
                 .fg(Color::Rgb(122, 162, 247))
                 .add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
@@ -253,16 +263,16 @@ fn parse_inline(line: &str) -> Line<'static> {
     }
 
     // Numbered lists
-    if let Some(rest) = line.strip_prefix(|c: char| c.is_ascii_digit()) {
-        if let Some(rest) = rest.strip_prefix(". ") {
-            let num: String = line.chars().take_while(|c| c.is_ascii_digit()).collect();
-            let mut spans = vec![Span::styled(
-                format!("  {num}. "),
-                Style::default().fg(Color::Rgb(122, 162, 247)),
-            )];
-            spans.extend(parse_inline_spans(rest));
-            return Line::from(spans);
-        }
+    if let Some(rest) = line.strip_prefix(|c: char| c.is_ascii_digit())
+        && let Some(rest) = rest.strip_prefix(". ")
+    {
+        let num: String = line.chars().take_while(|c| c.is_ascii_digit()).collect();
+        let mut spans = vec![Span::styled(
+            format!("  {num}. "),
+            Style::default().fg(Color::Rgb(122, 162, 247)),
+        )];
+        spans.extend(parse_inline_spans(rest));
+        return Line::from(spans);
     }
 
     // Regular text with inline formatting
@@ -275,53 +285,53 @@ fn parse_inline_spans(text: &str) -> Vec<Span<'static>> {
 
     while !remaining.is_empty() {
         // Inline code
-        if let Some(start) = remaining.find('`') {
-            if let Some(end) = remaining[start + 1..].find('`') {
-                if start > 0 {
-                    spans.push(Span::raw(remaining[..start].to_string()));
-                }
-                let code = &remaining[start + 1..start + 1 + end];
-                spans.push(Span::styled(
-                    code.to_string(),
-                    Style::default()
-                        .fg(Color::Rgb(224, 175, 104))
-                        .add_modifier(Modifier::BOLD),
-                ));
-                remaining = remaining[start + 1 + end + 1..].to_string();
-                continue;
+        if let Some(start) = remaining.find('`')
+            && let Some(end) = remaining[start + 1..].find('`')
+        {
+            if start > 0 {
+                spans.push(Span::raw(remaining[..start].to_string()));
             }
+            let code = &remaining[start + 1..start + 1 + end];
+            spans.push(Span::styled(
+                code.to_string(),
+                Style::default()
+                    .fg(Color::Rgb(224, 175, 104))
+                    .add_modifier(Modifier::BOLD),
+            ));
+            remaining = remaining[start + 1 + end + 1..].to_string();
+            continue;
         }
 
         // Bold **text**
-        if let Some(start) = remaining.find("**") {
-            if let Some(end) = remaining[start + 2..].find("**") {
-                if start > 0 {
-                    spans.push(Span::raw(remaining[..start].to_string()));
-                }
-                let bold_text = &remaining[start + 2..start + 2 + end];
-                spans.push(Span::styled(
-                    bold_text.to_string(),
-                    Style::default().add_modifier(Modifier::BOLD),
-                ));
-                remaining = remaining[start + 2 + end + 2..].to_string();
-                continue;
+        if let Some(start) = remaining.find("**")
+            && let Some(end) = remaining[start + 2..].find("**")
+        {
+            if start > 0 {
+                spans.push(Span::raw(remaining[..start].to_string()));
             }
+            let bold_text = &remaining[start + 2..start + 2 + end];
+            spans.push(Span::styled(
+                bold_text.to_string(),
+                Style::default().add_modifier(Modifier::BOLD),
+            ));
+            remaining = remaining[start + 2 + end + 2..].to_string();
+            continue;
         }
 
         // Italic *text*
-        if let Some(start) = remaining.find('*') {
-            if let Some(end) = remaining[start + 1..].find('*') {
-                if start > 0 {
-                    spans.push(Span::raw(remaining[..start].to_string()));
-                }
-                let italic_text = &remaining[start + 1..start + 1 + end];
-                spans.push(Span::styled(
-                    italic_text.to_string(),
-                    Style::default().add_modifier(Modifier::ITALIC),
-                ));
-                remaining = remaining[start + 1 + end + 1..].to_string();
-                continue;
+        if let Some(start) = remaining.find('*')
+            && let Some(end) = remaining[start + 1..].find('*')
+        {
+            if start > 0 {
+                spans.push(Span::raw(remaining[..start].to_string()));
             }
+            let italic_text = &remaining[start + 1..start + 1 + end];
+            spans.push(Span::styled(
+                italic_text.to_string(),
+                Style::default().add_modifier(Modifier::ITALIC),
+            ));
+            remaining = remaining[start + 1 + end + 1..].to_string();
+            continue;
         }
 
         // No more formatting
@@ -0,0 +1,406 @@
+//! Model Context Protocol client: starts (or connects to) the servers declared
+//! under `[mcp.<name>]`, lists their tools so the model is offered them next to
+//! the built-in ones, and forwards the model's calls to the owning server.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use eventsource_stream::Eventsource;
+use futures::StreamExt;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::config::McpServerConfig;
+use crate::event::Event;
+use crate::tools::{ToolImage, ToolResult};
+
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// Time allowed for the handshake and each page of the tool list.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time allowed for a single tool call.
+const CALL_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Longest tool name the model APIs accept.
+const MAX_TOOL_NAME: usize = 64;
+
+/// Connected servers by name. Global because tool definitions and tool calls
+/// are built deep inside code that never sees the app.
+static SERVERS: RwLock<BTreeMap<String, Arc<Server>>> = RwLock::new(BTreeMap::new());
+
+/// A tool offered by an MCP server.
+#[derive(Debug, Clone)]
+pub struct McpTool {
+    /// Name the model calls it by: `<server>__<tool>`
+    pub exposed: String,
+    /// Name the server knows it by
+    pub name: String,
+    pub description: String,
+    pub input_schema: Value,
+}
+
+struct Server {
+    connection: Connection,
+    tools: Vec<McpTool>,
+}
+
+/// Callers waiting for a response, by JSON-RPC id.
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Value>>>>;
+
+/// How requests reach a server. Responses arrive on a reader task either way.
+enum Transport {
+    /// Newline-delimited JSON-RPC over the child's stdin and stdout
+    Stdio {
+        stdin: tokio::sync::Mutex<ChildStdin>,
+        /// Killed when the connection is dropped
+        _child: Box<Child>,
+    },
+    /// HTTP with server-sent events: requests are POSTed to the endpoint the
+    /// event stream announced, and responses come back on the stream
+    Sse { client: reqwest::Client, endpoint: String },
+}
+
+struct Connection {
+    transport: Transport,
+    next_id: AtomicU64,
+    pending: Pending,
+}
+
+impl Connection {
+    async fn request(&self, method: &str, params: Value, timeout: Duration) -> anyhow::Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+        let message = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        if let Err(e) = self.send(&message).await {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(e);
+        }
+        let response = match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => anyhow::bail!("Server closed the connection"),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&id);
+                anyhow::bail!("No response to {method} after {}s", timeout.as_secs());
+            }
+        };
+        if let Some(error) = response.get("error") {
+            anyhow::bail!("{}", error["message"].as_str().map_or_else(|| error.to_string(), String::from));
+        }
+        Ok(response["result"].clone())
+    }
+
+    async fn notify(&self, method: &str) -> anyhow::Result<()> {
+        self.send(&json!({"jsonrpc": "2.0", "method": method})).await
+    }
+
+    async fn send(&self, message: &Value) -> anyhow::Result<()> {
+        match &self.transport {
+            Transport::Stdio { stdin, .. } => {
+                let mut line = message.to_string();
+                line.push('\n');
+                let mut stdin = stdin.lock().await;
+                stdin.write_all(line.as_bytes()).await?;
+                stdin.flush().await?;
+            }
+            Transport::Sse { client, endpoint } => {
+                let response = client.post(endpoint).json(message).send().await?;
+                if !response.status().is_success() {
+                    anyhow::bail!("{endpoint} answered {}", response.status());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Hand a response from the server to the request waiting on its id.
+/// Notifications and requests from the server are ignored.
+fn dispatch(pending: &Pending, message: &str) {
+    let Ok(value) = serde_json::from_str::<Value>(message) else { return };
+    if value.get("method").is_some() {
+        return;
+    }
+    if let Some(id) = value["id"].as_u64()
+        && let Some(tx) = pending.lock().unwrap().remove(&id)
+    {
+        let _ = tx.send(value);
+    }
+}
+
+fn spawn_stdio(config: &McpServerConfig, command: &str) -> anyhow::Result<Connection> {
+    let mut cmd = Command::new(command);
+    cmd.args(&config.args)
+        .envs(&config.env)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        // Anything it logs would scribble over the TUI
+        .stderr(std::process::Stdio::null())
+        .kill_on_drop(true);
+    crate::tools::detach_from_terminal(&mut cmd);
+    let mut child = cmd.spawn().map_err(|e| anyhow::anyhow!("Cannot start {command}: {e}"))?;
+    let stdin = child.stdin.take().expect("stdin is piped");
+    let stdout = child.stdout.take().expect("stdout is piped");
+
+    let pending = Pending::default();
+    let reader = Arc::clone(&pending);
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stdout).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            dispatch(&reader, &line);
+        }
+        // Wake anyone still waiting: the server is gone
+        reader.lock().unwrap().clear();
+    });
+
+    Ok(Connection {
+        transport: Transport::Stdio { stdin: tokio::sync::Mutex::new(stdin), _child: Box::new(child) },
+        next_id: AtomicU64::new(0),
+        pending,
+    })
+}
+
+async fn open_sse(url: &str) -> anyhow::Result<Connection> {
+    let client = crate::api::streaming_http_client()?;
+    let response = client
+        .get(url)
+        .header("accept", "text/event-stream")
+        .send()
+        .await
+        .map_err(|e| anyhow::anyhow!("Cannot reach {url}: {e}"))?;
+    if !response.status().is_success() {
+        anyhow::bail!("{url} answered {}", response.status());
+    }
+    let mut events = response.bytes_stream().eventsource();
+
+    // The first event says where to POST requests
+    let endpoint = loop {
+        match tokio::time::timeout(CONNECT_TIMEOUT, events.next()).await {
+            Ok(Some(Ok(event))) if event.event == "endpoint" => {
+                break reqwest::Url::parse(url)?.join(event.data.trim())?.to_string();
+            }
+            Ok(Some(Ok(_))) => continue,
+            _ => anyhow::bail!("{url} did not announce a message endpoint"),
+        }
+    };
+
+    let pending = Pending::default();
+    let reader = Arc::clone(&pending);
+    tokio::spawn(async move {
+        while let Some(Ok(event)) = events.next().await {
+            if event.event == "message" {
+                dispatch(&reader, &event.data);
+            }
+        }
+        reader.lock().unwrap().clear();
+    });
+
+    Ok(Connection {
+        transport: Transport::Sse { client, endpoint },
+        next_id: AtomicU64::new(0),
+        pending,
+    })
+}
+
+/// Connect to one server, run the handshake and list its tools.
+async fn connect(name: &str, config: &McpServerConfig) -> anyhow::Result<Server> {
+    let connection = match (&config.command, &config.url) {
+        (Some(command), _) => spawn_stdio(config, command)?,
+        (None, Some(url)) => open_sse(url).await?,
+        (None, None) => anyhow::bail!("set command (stdio) or url (SSE)"),
+    };
+
+    connection.request("initialize", json!({
+        "protocolVersion": PROTOCOL_VERSION,
+        "capabilities": {},
+        "clientInfo": {"name": "pro-chat", "version": env!("CARGO_PKG_VERSION")},
+    }), CONNECT_TIMEOUT).await?;
+    connection.notify("notifications/initialized").await?;
+
+    let mut tools = Vec::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let params = match &cursor {
+            Some(cursor) => json!({"cursor": cursor}),
+            None => json!({}),
+        };
+        let page = connection.request("tools/list", params, CONNECT_TIMEOUT).await?;
+        for tool in page["tools"].as_array().into_iter().flatten() {
+            let Some(tool_name) = tool["name"].as_str() else { continue };
+            tools.push(McpTool {
+                exposed: exposed_name(name, tool_name),
+                name: tool_name.to_string(),
+                description: tool["description"].as_str().unwrap_or("").to_string(),
+                input_schema: match &tool["inputSchema"] {
+                    Value::Null => json!({"type": "object", "properties": {}}),
+                    schema => schema.clone(),
+                },
+            });
+        }
+        cursor = page["nextCursor"].as_str().map(String::from);
+        if cursor.is_none() {
+            break;
+        }
+    }
+
+    Ok(Server { connection, tools })
+}
+
+/// Connect to every configured server in the background. Each reports back
+/// with an [`Event::McpConnected`]; its tools are offered from then on.
+pub fn start(servers: &BTreeMap<String, McpServerConfig>, tx: mpsc::UnboundedSender<Event>) {
+    for (name, config) in servers {
+        let (name, config, tx) = (name.clone(), config.clone(), tx.clone());
+        tokio::spawn(async move {
+            let result = match connect(&name, &config).await {
+                Ok(server) => {
+                    let count = server.tools.len();
+                    if let Ok(mut servers) = SERVERS.write() {
+                        servers.insert(name.clone(), Arc::new(server));
+                    }
+                    Ok(count)
+                }
+                Err(e) => Err(e.to_string()),
+            };
+            let _ = tx.send(Event::McpConnected(name, result));
+        });
+    }
+}
+
+/// `<server>__<tool>`, reduced to the characters and length tool names allow.
+pub fn exposed_name(server: &str, tool: &str) -> String {
+    format!("{server}__{tool}")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+        .take(MAX_TOOL_NAME)
+        .collect()
+}
+
+/// Tools of every connected server, as (server, tool) pairs.
+pub fn tools() -> Vec<(String, McpTool)> {
+    let Ok(servers) = SERVERS.read() else { return Vec::new() };
+    servers
+        .iter()
+        .flat_map(|(name, server)| server.tools.iter().map(move |t| (name.clone(), t.clone())))
+        .collect()
+}
+
+/// Definitions for the connected servers' tools, in the Anthropic format.
+pub fn tool_definitions() -> Vec<Value> {
+    tools()
+        .into_iter()
+        .map(|(server, tool)| json!({
+            "name": tool.exposed,
+            "description": format!("[MCP server {server}] {}", tool.description),
+            "input_schema": tool.input_schema,
+        }))
+        .collect()
+}
+
+/// The (server, tool) behind a name the model called, if it is an MCP tool.
+pub fn resolve(exposed: &str) -> Option<(String, String)> {
+    tools()
+        .into_iter()
+        .find(|(_, tool)| tool.exposed == exposed)
+        .map(|(server, tool)| (server, tool.name))
+}
+
+/// Run `tool` on `server` with the model's `input`.
+pub async fn call(server: &str, tool: &str, input: &Value) -> ToolResult {
+    let Some(connection) = SERVERS.read().ok().and_then(|s| s.get(server).cloned()) else {
+        return ToolResult::err(format!("MCP server {server} is not connected"));
+    };
+    let params = json!({"name": tool, "arguments": input});
+    match connection.connection.request("tools/call", params, CALL_TIMEOUT).await {
+        Ok(result) => tool_result(&result),
+        Err(e) => ToolResult::err(format!("MCP {server}/{tool}: {e}")),
+    }
+}
+
+/// Map a `tools/call` result to a [`ToolResult`]: text parts joined, the first
+/// image attached, embedded text resources inlined.
+fn tool_result(result: &Value) -> ToolResult {
+    let mut text = Vec::new();
+    let mut image = None;
+    for part in result["content"].as_array().into_iter().flatten() {
+        match part["type"].as_str() {
+            Some("text") => text.push(part["text"].as_str().unwrap_or("").to_string()),
+            Some("image") if image.is_none() => {
+                image = Some(ToolImage {
+                    media_type: part["mimeType"].as_str().unwrap_or("image/png").to_string(),
+                    data: part["data"].as_str().unwrap_or("").to_string(),
+                });
+            }
+            Some("resource") if let Some(body) = part["resource"]["text"].as_str() => {
+                text.push(body.to_string());
+            }
+            Some(other) => text.push(format!("[{other} content omitted]")),
+            None => {}
+        }
+    }
+    let output = text.join("\n");
+    let mut result = if result["isError"] == true { ToolResult::err(output) } else { ToolResult::ok(output) };
+    result.image = image;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exposed_name() {
+        assert_eq!(exposed_name("github", "create_issue"), "github__create_issue");
+        assert_eq!(exposed_name("my server", "do.thing"), "my_server__do_thing");
+        assert_eq!(exposed_name(&"s".repeat(40), &"t".repeat(40)).len(), MAX_TOOL_NAME);
+    }
+
+    #[test]
+    fn test_tool_result_mapping() {
+        let result = tool_result(&json!({"content": [
+            {"type": "text", "text": "line one"},
+            {"type": "image", "data": "AAA", "mimeType": "image/jpeg"},
+            {"type": "resource", "resource": {"uri": "file:///a", "text": "body"}},
+        ]}));
+        assert!(result.success);
+        assert_eq!(result.output, "line one\nbody");
+        assert_eq!(result.image.unwrap().media_type, "image/jpeg");
+
+        let failed = tool_result(&json!({"content": [{"type": "text", "text": "bad input"}], "isError": true}));
+        assert!(!failed.success);
+        assert_eq!(failed.output, "bad input");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stdio_handshake_and_call() {
+        // A stand-in server answering requests by id: 0 initialize, 1
+        // tools/list, 2 tools/call
+        let script = r#"while read -r line; do
+  case "$line" in
+    *'"method":"initialize"'*) echo '{"jsonrpc":"2.0","id":0,"result":{"protocolVersion":"2024-11-05","capabilities":{}}}' ;;
+    *'"method":"tools/list"'*) echo '{"jsonrpc":"2.0","id":1,"result":{"tools":[{"name":"echo","description":"Echo text","inputSchema":{"type":"object"}}]}}' ;;
+    *'"method":"tools/call"'*) echo '{"jsonrpc":"2.0","method":"notifications/progress"}'; echo '{"jsonrpc":"2.0","id":2,"result":{"content":[{"type":"text","text":"hi"}]}}' ;;
+  esac
+done"#;
+        let config = McpServerConfig {
+            command: Some("sh".into()),
+            args: vec!["-c".into(), script.into()],
+            ..Default::default()
+        };
+        let server = connect("fake", &config).await.unwrap();
+        assert_eq!(server.tools.len(), 1);
+        assert_eq!(server.tools[0].exposed, "fake__echo");
+
+        let result = server.connection
+            .request("tools/call", json!({"name": "echo", "arguments": {}}), CALL_TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(tool_result(&result).output, "hi");
+    }
+}
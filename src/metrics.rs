@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use crate::usage::{self, UsageEvent, UsageRecord};
+
+/// Upper bounds (seconds) of the latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 11] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+/// Cumulative histogram in the Prometheus sense.
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        for (bucket, &bound) in self.buckets.iter_mut().zip(&LATENCY_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+
+    fn write(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        for (count, bound) in self.buckets.iter().zip(&LATENCY_BUCKETS) {
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {count}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {}", self.count);
+        let _ = writeln!(out, "{name}_sum {}", self.sum);
+        let _ = writeln!(out, "{name}_count {}", self.count);
+    }
+}
+
+/// Escape a label value per the Prometheus text format.
+fn label(value: &str) -> String {
+    value.replace('\\', r"\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Render the usage log in the Prometheus text exposition format.
+pub fn render(records: &[UsageRecord]) -> String {
+    let mut requests: BTreeMap<(&str, &str), u64> = BTreeMap::new();
+    let mut tokens: BTreeMap<(&str, &str), u64> = BTreeMap::new();
+    let mut tools: BTreeMap<(&str, &str), u64> = BTreeMap::new();
+    let mut response_latency = Histogram::default();
+    let mut tool_latency = Histogram::default();
+
+    for record in records {
+        match &record.event {
+            UsageEvent::Response { provider, model, input_tokens, output_tokens, latency_ms } => {
+                *requests.entry((provider, model)).or_default() += 1;
+                *tokens.entry((model, "input")).or_default() += input_tokens;
+                *tokens.entry((model, "output")).or_default() += output_tokens;
+                if let Some(ms) = latency_ms {
+                    response_latency.observe(*ms as f64 / 1000.0);
+                }
+            }
+            UsageEvent::Tool { name, success, duration_ms } => {
+                let status = if *success { "success" } else { "failure" };
+                *tools.entry((name, status)).or_default() += 1;
+                tool_latency.observe(*duration_ms as f64 / 1000.0);
+            }
+        }
+    }
+
+    let mut out = String::new();
+    out.push_str("# HELP pro_chat_requests_total Completed model responses.\n");
+    out.push_str("# TYPE pro_chat_requests_total counter\n");
+    for ((provider, model), n) in &requests {
+        let _ = writeln!(
+            out,
+            "pro_chat_requests_total{{provider=\"{}\",model=\"{}\"}} {n}",
+            label(provider),
+            label(model)
+        );
+    }
+    out.push_str("# HELP pro_chat_tokens_total Estimated tokens sent and received.\n");
+    out.push_str("# TYPE pro_chat_tokens_total counter\n");
+    for ((model, direction), n) in &tokens {
+        let _ = writeln!(
+            out,
+            "pro_chat_tokens_total{{model=\"{}\",direction=\"{direction}\"}} {n}",
+            label(model)
+        );
+    }
+    out.push_str("# HELP pro_chat_tool_executions_total Tool executions by outcome.\n");
+    out.push_str("# TYPE pro_chat_tool_executions_total counter\n");
+    for ((tool, status), n) in &tools {
+        let _ = writeln!(
+            out,
+            "pro_chat_tool_executions_total{{tool=\"{}\",status=\"{status}\"}} {n}",
+            label(tool)
+        );
+    }
+    response_latency.write(
+        &mut out,
+        "pro_chat_response_latency_seconds",
+        "Time from send to end of stream.",
+    );
+    tool_latency.write(&mut out, "pro_chat_tool_latency_seconds", "Tool execution time.");
+    out
+}
+
+/// Serve `/metrics` on `addr` until the process is killed. The usage log is
+/// re-read on every scrape, so any number of `pro` sessions sharing the data
+/// directory are reported together.
+pub async fn serve(addr: SocketAddr) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    println!("Serving metrics on http://{}/metrics", listener.local_addr()?);
+
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            let n = stream.read(&mut buf).await.unwrap_or(0);
+            let request = String::from_utf8_lossy(&buf[..n]);
+            let path = request.split_whitespace().nth(1).unwrap_or("");
+
+            let (status, body) = if path == "/metrics" {
+                ("200 OK", render(&usage::load_all()))
+            } else {
+                ("404 Not Found", "not found\n".to_string())
+            };
+            let response = format!(
+                "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn render_emits_counters_and_histograms() {
+        let records = vec![
+            UsageRecord {
+                timestamp: Utc::now(),
+                event: UsageEvent::Response {
+                    provider: "anthropic".into(),
+                    model: "claude-sonnet".into(),
+                    input_tokens: 100,
+                    output_tokens: 20,
+                    latency_ms: Some(1_500),
+                },
+            },
+            UsageRecord {
+                timestamp: Utc::now(),
+                event: UsageEvent::Tool { name: "execute".into(), success: false, duration_ms: 40 },
+            },
+        ];
+        let text = render(&records);
+
+        assert!(text.contains(r#"pro_chat_requests_total{provider="anthropic",model="claude-sonnet"} 1"#));
+        assert!(text.contains(r#"pro_chat_tokens_total{model="claude-sonnet",direction="output"} 20"#));
+        assert!(text.contains(r#"pro_chat_tool_executions_total{tool="execute",status="failure"} 1"#));
+        assert!(text.contains(r#"pro_chat_response_latency_seconds_bucket{le="1"} 0"#));
+        assert!(text.contains(r#"pro_chat_response_latency_seconds_bucket{le="2.5"} 1"#));
+        assert!(text.contains("pro_chat_tool_latency_seconds_count 1"));
+    }
+}
@@ -28,15 +28,15 @@ impl NeovimClient {
         // Check XDG runtime dir
         if let Ok(runtime) = std::env::var("XDG_RUNTIME_DIR") {
             let nvim_dir = std::path::Path::new(&runtime).join("nvim");
-            if nvim_dir.exists() {
-                if let Ok(entries) = std::fs::read_dir(&nvim_dir) {
-                    for entry in entries.flatten() {
-                        let path = entry.path();
-                        if path.is_dir() {
-                            let socket = path.join("0");
-                            if socket.exists() {
-                                return Some(socket.to_string_lossy().to_string());
-                            }
+            if nvim_dir.exists()
+                && let Ok(entries) = std::fs::read_dir(&nvim_dir)
+            {
+                for entry in entries.flatten() {
+                    let path = entry.path();
+                    if path.is_dir() {
+                        let socket = path.join("0");
+                        if socket.exists() {
+                            return Some(socket.to_string_lossy().to_string());
                         }
                     }
                 }
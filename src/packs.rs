@@ -0,0 +1,209 @@
+//! Team-shared packs: git repositories listed under `[[packs]]`, cloned into
+//! `<data dir>/packs/<name>-<hash>` by `pro packs`. Each holds a `pack.toml` with
+//! `[profiles.*]` (personas) and `[recipes.*]` tables in the config.toml
+//! format. They are merged in at startup, and the user's own entries win on a
+//! name clash.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::config::{Config, PackConfig, Profile, Recipe};
+
+#[derive(Debug, Default, Deserialize)]
+struct PackFile {
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+    #[serde(default)]
+    recipes: BTreeMap<String, Recipe>,
+}
+
+fn packs_dir() -> PathBuf {
+    Config::data_dir().join("packs")
+}
+
+/// `pro packs`: clone each configured pack that isn't on disk yet and
+/// fast-forward the rest.
+pub fn update_all(config: &Config) -> anyhow::Result<()> {
+    if config.packs.is_empty() {
+        println!("No packs configured (add [[packs]] url = \"...\" to config.toml)");
+        return Ok(());
+    }
+    let root = packs_dir();
+    std::fs::create_dir_all(&root)?;
+    let mut failed = 0;
+    for pack in &config.packs {
+        let Some(dir) = pack.dir_name().map(|d| root.join(d)) else {
+            failed += 1;
+            eprintln!("{}: no repository name in the URL", pack.url);
+            continue;
+        };
+        match update(pack, &dir) {
+            Ok(summary) => println!("{}: {summary}", pack.name()),
+            Err(e) => {
+                failed += 1;
+                eprintln!("{}: {e}", pack.name());
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{failed} of {} packs failed to update", config.packs.len());
+    }
+    Ok(())
+}
+
+fn update(pack: &PackConfig, dir: &Path) -> anyhow::Result<String> {
+    // Neither may pass for one of git's options
+    if pack.url.starts_with('-') || pack.branch.as_deref().is_some_and(|b| b.starts_with('-')) {
+        anyhow::bail!("the url and branch may not start with '-'");
+    }
+    let mut git = Command::new("git");
+    let action = if dir.join(".git").exists() {
+        if let Some(branch) = &pack.branch {
+            git.arg("-C").arg(dir).args(["pull", "--ff-only", "origin", branch]);
+        } else {
+            git.arg("-C").arg(dir).args(["pull", "--ff-only"]);
+        }
+        "updated"
+    } else {
+        git.args(["clone", "--depth", "1"]);
+        if let Some(branch) = &pack.branch {
+            git.args(["--branch", branch]);
+        }
+        git.arg("--").arg(&pack.url).arg(dir);
+        "cloned"
+    };
+    let output = git.output().map_err(|e| anyhow::anyhow!("cannot run git: {e}"))?;
+    if !output.status.success() {
+        anyhow::bail!("git failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    let file = load(dir)?;
+    Ok(format!("{action} ({} profiles, {} recipes)", file.profiles.len(), file.recipes.len()))
+}
+
+fn load(dir: &Path) -> anyhow::Result<PackFile> {
+    let path = dir.join("pack.toml");
+    let content = std::fs::read_to_string(&path)
+        .map_err(|e| anyhow::anyhow!("cannot read {}: {e}", path.display()))?;
+    toml::from_str(&content).map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))
+}
+
+/// Add the profiles and recipes of every fetched pack to `config`. Returns
+/// a warning for each pack that couldn't be used.
+pub fn merge_into(config: &mut Config) -> Vec<String> {
+    merge_from(config, &packs_dir())
+}
+
+fn merge_from(config: &mut Config, root: &Path) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for pack in config.packs.clone() {
+        let name = pack.name();
+        let Some(dir) = pack.dir_name().map(|d| root.join(d)) else {
+            warnings.push(format!("Pack {} ignored: no repository name in the URL", pack.url));
+            continue;
+        };
+        if !dir.exists() {
+            warnings.push(format!("Pack {name} not fetched yet; run `pro packs`"));
+            continue;
+        }
+        let file = match load(&dir) {
+            Ok(file) => file,
+            Err(e) => {
+                warnings.push(format!("Pack {name} ignored: {e}"));
+                continue;
+            }
+        };
+        for (key, mut profile) in file.profiles {
+            if config.profiles.contains_key(&key) {
+                continue;
+            }
+            // A shared repository is no place for a key; use api_key_env
+            profile.api_key = None;
+            config.profiles.insert(key.clone(), profile);
+            config.pack_entries.profiles.insert(key, name.to_string());
+        }
+        for (key, recipe) in file.recipes {
+            if config.recipes.contains_key(&key) {
+                continue;
+            }
+            config.recipes.insert(key.clone(), recipe);
+            config.pack_entries.recipes.insert(key, name.to_string());
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_name_from_url() {
+        let pack = |url: &str| PackConfig { url: url.into(), branch: None };
+        assert_eq!(pack("https://github.com/acme/pro-pack.git").name(), "pro-pack");
+        assert_eq!(pack("git@github.com:acme/team-prompts").name(), "team-prompts");
+        assert_eq!(pack("https://example.com/packs/review/").name(), "review");
+
+        let dir = pack("https://github.com/acme/pro-pack.git").dir_name().unwrap();
+        assert!(dir.starts_with("pro-pack-"));
+        assert_ne!(Some(dir), pack("https://github.com/other/pro-pack.git").dir_name());
+        for url in ["", "https://example.com/..", "https://example.com/."] {
+            assert_eq!(pack(url).dir_name(), None, "{url}");
+        }
+    }
+
+    #[test]
+    fn test_update_rejects_option_like_arguments() {
+        let dir = std::env::temp_dir().join(format!("pro-chat-pack-opt-{}", std::process::id()));
+        let pack = PackConfig { url: "--upload-pack=touch /tmp/x".into(), branch: None };
+        assert!(update(&pack, &dir).is_err());
+        let pack = PackConfig { url: "https://example.com/p.git".into(), branch: Some("--upload-pack=x".into()) };
+        assert!(update(&pack, &dir).is_err());
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn test_merge_keeps_local_entries() {
+        let root = std::env::temp_dir().join(format!("pro-chat-packs-{}", std::process::id()));
+        let team = PackConfig { url: "https://example.com/team.git".into(), branch: None };
+        let dir = root.join(team.dir_name().unwrap());
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("pack.toml"),
+            r#"
+[profiles.reviewer]
+model = "gpt-4o"
+system_prompt = "Review like a senior engineer."
+api_key = "sk-should-not-load"
+
+[profiles.work]
+model = "pack-model"
+
+[recipes.standup]
+description = "Summarize yesterday's commits"
+run = ["git log --since=yesterday --oneline"]
+prompt = "Write my standup update."
+"#,
+        )
+        .unwrap();
+
+        let mut config = Config::default();
+        config.packs = vec![
+            team,
+            PackConfig { url: "https://example.com/missing.git".into(), branch: None },
+        ];
+        config.profiles.insert("work".into(), Profile { model: Some("mine".into()), ..Default::default() });
+
+        let warnings = merge_from(&mut config, &root);
+        assert_eq!(warnings, ["Pack missing not fetched yet; run `pro packs`"]);
+        assert_eq!(config.profiles["work"].model.as_deref(), Some("mine"));
+        assert_eq!(config.profiles["reviewer"].model.as_deref(), Some("gpt-4o"));
+        assert_eq!(config.profiles["reviewer"].api_key, None);
+        assert_eq!(config.recipes["standup"].prompt, "Write my standup update.");
+        assert_eq!(config.pack_entries.profiles.keys().collect::<Vec<_>>(), ["reviewer"]);
+        assert_eq!(config.pack_entries.recipes["standup"], "team");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
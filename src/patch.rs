@@ -0,0 +1,247 @@
+//! Unified diffs for the `apply_patch` tool: parsing, and applying hunks
+//! with context validation so that a patch lands completely or not at all.
+
+/// One file's part of a patch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilePatch {
+    /// `None` for a new file (`--- /dev/null`)
+    pub old_path: Option<String>,
+    /// `None` when the file is deleted (`+++ /dev/null`)
+    pub new_path: Option<String>,
+    pub hunks: Vec<Hunk>,
+}
+
+impl FilePatch {
+    /// The file that ends up written (or deleted).
+    pub fn path(&self) -> &str {
+        self.new_path.as_deref().or(self.old_path.as_deref()).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hunk {
+    /// The `@@ -l,s +l,s @@` line
+    pub header: String,
+    /// 1-based line the hunk claims to start at in the original
+    pub old_start: usize,
+    /// (`' '`, `'-'` or `'+'`, line without its newline)
+    pub lines: Vec<(char, String)>,
+}
+
+impl Hunk {
+    /// Context and removed lines: what must be in the file.
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines.iter().filter(|(op, _)| *op != '+').map(|(_, l)| l.as_str()).collect()
+    }
+
+    /// Context and added lines: what replaces them.
+    fn new_lines(&self) -> Vec<&str> {
+        self.lines.iter().filter(|(op, _)| *op != '-').map(|(_, l)| l.as_str()).collect()
+    }
+}
+
+/// `a/src/x.rs` -> `src/x.rs`; `/dev/null` -> `None`. A trailing tab and
+/// timestamp, as written by `diff -u`, are dropped.
+fn header_path(raw: &str) -> Option<String> {
+    let path = raw.split('\t').next().unwrap_or_default().trim();
+    if path == "/dev/null" {
+        return None;
+    }
+    let path = path.strip_prefix("a/").or_else(|| path.strip_prefix("b/")).unwrap_or(path);
+    Some(path.to_string())
+}
+
+/// The old start line from `@@ -12,7 +12,9 @@ fn context`.
+fn hunk_start(header: &str) -> Option<usize> {
+    let old = header.strip_prefix("@@ -")?.split_whitespace().next()?;
+    old.split(',').next()?.parse().ok()
+}
+
+/// Parse a unified diff. Line counts in hunk headers are not trusted (they
+/// are often off in hand- or model-written patches); a hunk runs until the
+/// next hunk or file header.
+pub fn parse(diff: &str) -> Result<Vec<FilePatch>, String> {
+    let lines: Vec<&str> = diff.trim_end_matches(['\n', '\r']).lines().collect();
+    let mut files: Vec<FilePatch> = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if let Some(old) = line.strip_prefix("--- ")
+            && let Some(new) = lines.get(i + 1).and_then(|l| l.strip_prefix("+++ "))
+        {
+            files.push(FilePatch { old_path: header_path(old), new_path: header_path(new), hunks: Vec::new() });
+            i += 2;
+            continue;
+        }
+        if line.starts_with("@@") {
+            let Some(file) = files.last_mut() else {
+                return Err("Hunk before any ---/+++ file header".into());
+            };
+            let old_start = hunk_start(line).ok_or_else(|| format!("Malformed hunk header: {line}"))?;
+            let mut hunk = Hunk { header: line.trim_end().to_string(), old_start, lines: Vec::new() };
+            i += 1;
+            while i < lines.len() {
+                let body = lines[i];
+                let next_is_file = body.starts_with("--- ") && lines.get(i + 1).is_some_and(|l| l.starts_with("+++ "));
+                if body.starts_with("@@") || body.starts_with("diff ") || next_is_file {
+                    break;
+                }
+                match body.chars().next() {
+                    Some(op @ (' ' | '-' | '+')) => hunk.lines.push((op, body[1..].trim_end_matches('\r').to_string())),
+                    // Editors and models often drop the space on blank context lines
+                    None => hunk.lines.push((' ', String::new())),
+                    Some('\\') => {} // "\ No newline at end of file"
+                    Some(_) => return Err(format!("Unexpected line in hunk {}: {body}", hunk.header)),
+                }
+                i += 1;
+            }
+            file.hunks.push(hunk);
+            continue;
+        }
+        // `diff --git`, `index`, mode lines and commentary
+        i += 1;
+    }
+    if files.is_empty() {
+        return Err("No file headers (---/+++) found; expected a unified diff".into());
+    }
+    if let Some(file) = files.iter().find(|f| f.hunks.is_empty() && f.new_path.is_some()) {
+        return Err(format!("No hunks for {}", file.path()));
+    }
+    Ok(files)
+}
+
+/// Where each hunk landed relative to its header, or why it didn't.
+pub type HunkOutcome = Result<isize, String>;
+
+/// Apply `hunks` to `contents` in order. Each hunk's context and removed
+/// lines must match exactly (trailing whitespace aside); the nearest match
+/// to the line the header names is used. Returns the new contents only if
+/// every hunk applied.
+pub fn apply(contents: &str, hunks: &[Hunk]) -> (Option<String>, Vec<HunkOutcome>) {
+    let eol = if contents.contains("\r\n") { "\r\n" } else { "\n" };
+    let trailing_newline = contents.is_empty() || contents.ends_with('\n');
+    let mut lines: Vec<String> = contents.lines().map(String::from).collect();
+    let mut outcomes = Vec::new();
+    // Lines added minus removed by earlier hunks, and where the last one ended
+    let mut delta: isize = 0;
+    let mut floor = 0;
+
+    for hunk in hunks {
+        let old = hunk.old_lines();
+        let new = hunk.new_lines();
+        let expected = (hunk.old_start.saturating_sub(1) as isize + delta).max(0) as usize;
+        let found = if old.is_empty() {
+            // Pure insertion; `-0,0` means before the first line
+            let at = if hunk.old_start == 0 { 0 } else { expected + 1 };
+            Some(at.clamp(floor, lines.len()))
+        } else {
+            find_block(&lines, &old, expected, floor)
+        };
+        match found {
+            Some(at) => {
+                lines.splice(at..at + old.len(), new.iter().map(|l| l.to_string()));
+                let shift = if old.is_empty() { 0 } else { at as isize - expected as isize };
+                outcomes.push(Ok(shift));
+                delta += new.len() as isize - old.len() as isize;
+                floor = at + new.len();
+            }
+            None => outcomes.push(Err(mismatch(&lines, &old, expected))),
+        }
+    }
+
+    if outcomes.iter().any(Result::is_err) {
+        return (None, outcomes);
+    }
+    let mut out = lines.join(eol);
+    if trailing_newline && !out.is_empty() {
+        out.push_str(eol);
+    }
+    (Some(out), outcomes)
+}
+
+fn block_matches(lines: &[String], old: &[&str], at: usize) -> bool {
+    lines.len() >= at + old.len() && old.iter().zip(&lines[at..]).all(|(a, b)| a.trim_end() == b.trim_end())
+}
+
+/// The match for `old` at or after `floor` closest to `expected`.
+fn find_block(lines: &[String], old: &[&str], expected: usize, floor: usize) -> Option<usize> {
+    let last = lines.len().checked_sub(old.len())?;
+    (floor..=last)
+        .filter(|&at| block_matches(lines, old, at))
+        .min_by_key(|&at| at.abs_diff(expected))
+}
+
+/// Why a hunk's lines aren't at the expected place, for the model to fix
+/// its patch.
+fn mismatch(lines: &[String], old: &[&str], expected: usize) -> String {
+    for (i, want) in old.iter().enumerate() {
+        match lines.get(expected + i) {
+            Some(got) if got.trim_end() == want.trim_end() => continue,
+            Some(got) => {
+                return format!(
+                    "context not found; line {} is `{}` but the patch expects `{}`",
+                    expected + i + 1,
+                    got.trim(),
+                    want.trim()
+                );
+            }
+            None => return format!("context not found; the file has only {} lines", lines.len()),
+        }
+    }
+    "context not found".into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &str = "one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten\n";
+
+    #[test]
+    fn test_parse_multi_file_patch() {
+        let diff = "diff --git a/src/a.rs b/src/a.rs\nindex 1..2 100644\n--- a/src/a.rs\n+++ b/src/a.rs\n\
+                    @@ -1,2 +1,2 @@\n-one\n+ONE\n two\n@@ -9,2 +9,3 @@ fn tail\n nine\n+nine and a half\n ten\n\
+                    --- /dev/null\n+++ b/new.txt\n@@ -0,0 +1 @@\n+hello\n";
+        let files = parse(diff).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path(), "src/a.rs");
+        assert_eq!(files[0].hunks.len(), 2);
+        assert_eq!(files[0].hunks[1].old_start, 9);
+        assert_eq!(files[0].hunks[1].lines[1], ('+', "nine and a half".to_string()));
+        assert_eq!(files[1].old_path, None);
+        assert_eq!(files[1].path(), "new.txt");
+
+        assert!(parse("just some text").is_err());
+        assert!(parse("@@ -1 +1 @@\n-a\n+b\n").is_err());
+    }
+
+    #[test]
+    fn test_apply_hunks_with_offsets() {
+        let files = parse(
+            "--- a/f\n+++ b/f\n@@ -2,2 +2,2 @@\n two\n-three\n+THREE\n@@ -6,3 +6,4 @@\n seven\n+seven and a half\n eight\n",
+        )
+        .unwrap();
+        let (out, outcomes) = apply(ORIGINAL, &files[0].hunks);
+        assert_eq!(outcomes, vec![Ok(0), Ok(1)]);
+        assert_eq!(
+            out.unwrap(),
+            "one\ntwo\nTHREE\nfour\nfive\nsix\nseven\nseven and a half\neight\nnine\nten\n"
+        );
+
+        // A later hunk that doesn't match leaves the file alone
+        let files = parse("--- a/f\n+++ b/f\n@@ -1 +1 @@\n-one\n+1\n@@ -5 +5 @@\n-FIVE\n+5\n").unwrap();
+        let (out, outcomes) = apply(ORIGINAL, &files[0].hunks);
+        assert!(out.is_none());
+        assert_eq!(outcomes[0], Ok(0));
+        assert!(outcomes[1].as_ref().unwrap_err().contains("line 5 is `five` but the patch expects `FIVE`"));
+    }
+
+    #[test]
+    fn test_apply_creates_and_keeps_line_endings() {
+        let files = parse("--- /dev/null\n+++ b/new\n@@ -0,0 +1,2 @@\n+a\n+b\n").unwrap();
+        assert_eq!(apply("", &files[0].hunks).0.unwrap(), "a\nb\n");
+
+        let files = parse("--- a/f\n+++ b/f\n@@ -1 +1 @@\n-x\n+y\n").unwrap();
+        assert_eq!(apply("x\r\nz\r\n", &files[0].hunks).0.unwrap(), "y\r\nz\r\n");
+    }
+}
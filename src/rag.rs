@@ -0,0 +1,498 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::config::{Config, EmbeddingsConfig};
+use crate::history::Conversation;
+
+/// Target chunk size in characters; chunks end on a line boundary.
+const CHUNK_CHARS: usize = 1500;
+
+/// Lines repeated at the start of the next chunk so context isn't cut mid-thought.
+const OVERLAP_LINES: usize = 3;
+
+/// Files larger than this are skipped.
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+
+/// Texts sent per embedding request.
+const EMBED_BATCH: usize = 64;
+
+const EMBED_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Message text beyond this is not embedded.
+const MAX_MESSAGE_CHARS: usize = 4000;
+
+/// Directories never descended into while indexing.
+const SKIP_DIRS: &[&str] = &["node_modules", "target", "vendor", "dist", "build"];
+
+/// One embedded piece of a document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chunk {
+    pub path: PathBuf,
+    /// 1-based, inclusive
+    pub start_line: usize,
+    pub end_line: usize,
+    pub text: String,
+    pub embedding: Vec<f32>,
+}
+
+/// Every indexed chunk, stored as `index.json` in the data dir.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DocIndex {
+    /// Embedding model the vectors came from; vectors from different models
+    /// are not comparable
+    pub model: String,
+    pub chunks: Vec<Chunk>,
+}
+
+impl DocIndex {
+    fn path() -> PathBuf {
+        Config::data_dir().join("index.json")
+    }
+
+    pub fn load() -> Self {
+        std::fs::read(Self::path())
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        std::fs::create_dir_all(Config::data_dir())?;
+        crate::history::write_atomic(&Self::path(), &serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    /// The `k` chunks most similar to `query`, best first.
+    pub fn search(&self, query: &[f32], k: usize) -> Vec<(&Chunk, f32)> {
+        let mut scored: Vec<(&Chunk, f32)> = self
+            .chunks
+            .iter()
+            .map(|c| (c, cosine(&c.embedding, query)))
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scored.truncate(k);
+        scored
+    }
+}
+
+/// Totals reported by [`index_dir`].
+#[derive(Debug, Default)]
+pub struct IndexStats {
+    pub files: usize,
+    pub chunks: usize,
+}
+
+pub fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let denom = norm(a) * norm(b);
+    if denom == 0.0 { 0.0 } else { dot / denom }
+}
+
+/// Split text into overlapping chunks of roughly [`CHUNK_CHARS`], returning
+/// (start_line, end_line, text) with 1-based line numbers.
+pub fn chunk_text(text: &str) -> Vec<(usize, usize, String)> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < lines.len() {
+        let mut end = start;
+        let mut len = 0;
+        while end < lines.len() && (len < CHUNK_CHARS || end == start) {
+            len += lines[end].len() + 1;
+            end += 1;
+        }
+        let body = lines[start..end].join("\n");
+        if !body.trim().is_empty() {
+            chunks.push((start + 1, end, body));
+        }
+        if end == lines.len() {
+            break;
+        }
+        start = end.saturating_sub(OVERLAP_LINES).max(start + 1);
+    }
+    chunks
+}
+
+/// Embed `texts` with the configured provider, one vector per text.
+pub async fn embed(config: &EmbeddingsConfig, texts: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+    let client = crate::api::http_client(EMBED_TIMEOUT)?;
+    let mut vectors = Vec::with_capacity(texts.len());
+
+    for batch in texts.chunks(EMBED_BATCH) {
+        let body: Value = match config.provider.as_str() {
+            "ollama" => {
+                let base = config.url.as_deref().unwrap_or("http://localhost:11434");
+                let response = client
+                    .post(format!("{}/api/embed", base.trim_end_matches('/')))
+                    .json(&json!({ "model": config.model, "input": batch }))
+                    .send()
+                    .await
+                    .context("Ollama embedding request failed")?;
+                error_for_status(response).await?
+            }
+            "openai" => {
+                let base = config.url.as_deref().unwrap_or("https://api.openai.com/v1");
+                let key = config
+                    .api_key
+                    .as_deref()
+                    .context("No embeddings API key: set embeddings.api_key or OPENAI_API_KEY")?;
+                let response = client
+                    .post(format!("{}/embeddings", base.trim_end_matches('/')))
+                    .bearer_auth(key)
+                    .json(&json!({ "model": config.model, "input": batch }))
+                    .send()
+                    .await
+                    .context("Embedding request failed")?;
+                error_for_status(response).await?
+            }
+            other => bail!("Unknown embeddings provider: {other}"),
+        };
+
+        let batch_vectors = parse_embeddings(&body);
+        if batch_vectors.len() != batch.len() {
+            bail!("Embedding response had {} vectors for {} inputs", batch_vectors.len(), batch.len());
+        }
+        vectors.extend(batch_vectors);
+    }
+    Ok(vectors)
+}
+
+async fn error_for_status(response: reqwest::Response) -> anyhow::Result<Value> {
+    let status = response.status();
+    let body: Value = response.json().await.unwrap_or(Value::Null);
+    if !status.is_success() {
+        let message = body["error"]["message"]
+            .as_str()
+            .or_else(|| body["error"].as_str())
+            .unwrap_or("request rejected");
+        bail!("Embedding API error ({status}): {message}");
+    }
+    Ok(body)
+}
+
+/// Vectors from an OpenAI (`data[].embedding`) or Ollama (`embeddings`) response.
+fn parse_embeddings(body: &Value) -> Vec<Vec<f32>> {
+    let to_vec = |v: &Value| -> Vec<f32> {
+        v.as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_f64)
+            .map(|x| x as f32)
+            .collect()
+    };
+    if let Some(data) = body["data"].as_array() {
+        let mut items: Vec<(u64, Vec<f32>)> = data
+            .iter()
+            .map(|d| (d["index"].as_u64().unwrap_or(0), to_vec(&d["embedding"])))
+            .collect();
+        items.sort_by_key(|(i, _)| *i);
+        return items.into_iter().map(|(_, v)| v).collect();
+    }
+    body["embeddings"].as_array().into_iter().flatten().map(to_vec).collect()
+}
+
+/// Text files under `dir` with one of `extensions`, skipping hidden and
+/// build directories.
+fn collect_files(dir: &Path, extensions: &[String]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(current) = stack.pop() {
+        let Ok(entries) = std::fs::read_dir(&current) else { continue };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') {
+                continue;
+            }
+            if path.is_dir() {
+                if !SKIP_DIRS.contains(&name.as_str()) {
+                    stack.push(path);
+                }
+            } else if path
+                .extension()
+                .is_some_and(|e| extensions.iter().any(|x| x.eq_ignore_ascii_case(&e.to_string_lossy())))
+                && entry.metadata().is_ok_and(|m| m.len() <= MAX_FILE_BYTES)
+            {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Chunk and embed every document under `dir`, replacing anything previously
+/// indexed from that directory.
+pub async fn index_dir(config: &EmbeddingsConfig, dir: &Path) -> anyhow::Result<IndexStats> {
+    let dir = dir
+        .canonicalize()
+        .with_context(|| format!("Cannot index {}", dir.display()))?;
+    let mut index = DocIndex::load();
+    if index.model != config.model {
+        index = DocIndex { model: config.model.clone(), chunks: Vec::new() };
+    }
+    index.chunks.retain(|c| !c.path.starts_with(&dir));
+
+    let mut stats = IndexStats::default();
+    let mut pending: Vec<(PathBuf, usize, usize, String)> = Vec::new();
+    for path in collect_files(&dir, &config.extensions) {
+        let Ok(text) = std::fs::read_to_string(&path) else { continue };
+        stats.files += 1;
+        for (start, end, body) in chunk_text(&text) {
+            pending.push((path.clone(), start, end, body));
+        }
+    }
+
+    let texts: Vec<String> = pending.iter().map(|(_, _, _, t)| t.clone()).collect();
+    let vectors = embed(config, &texts).await?;
+    for ((path, start_line, end_line, text), embedding) in pending.into_iter().zip(vectors) {
+        index.chunks.push(Chunk { path, start_line, end_line, text, embedding });
+        stats.chunks += 1;
+    }
+    index.save()?;
+    Ok(stats)
+}
+
+/// Top-`k` chunks for `query`, formatted for the `retrieve` tool.
+pub async fn retrieve(config: &EmbeddingsConfig, query: &str, k: usize) -> anyhow::Result<String> {
+    let index = DocIndex::load();
+    if index.chunks.is_empty() {
+        bail!("The document index is empty. Run `pro index <dir>` first.");
+    }
+    if index.model != config.model {
+        bail!(
+            "The index was built with {} but embeddings.model is {}; re-run `pro index`",
+            index.model,
+            config.model
+        );
+    }
+    let query_vector = embed(config, &[query.to_string()]).await?.pop().unwrap_or_default();
+    let results = index.search(&query_vector, k);
+
+    let mut out = String::new();
+    for (chunk, score) in results {
+        out.push_str(&format!(
+            "{}:{}-{} (score {score:.2})\n```\n{}\n```\n\n",
+            chunk.path.display(),
+            chunk.start_line,
+            chunk.end_line,
+            chunk.text
+        ));
+    }
+    Ok(out.trim_end().to_string())
+}
+
+/// Embedded messages of one saved conversation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexedConversation {
+    updated_at: DateTime<Utc>,
+    title: String,
+    messages: Vec<IndexedMessage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexedMessage {
+    message_id: String,
+    role: String,
+    excerpt: String,
+    embedding: Vec<f32>,
+}
+
+/// Message embeddings for saved conversations, stored as
+/// `history_index.json` and refreshed incrementally by [`recall`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct HistoryIndex {
+    model: String,
+    conversations: BTreeMap<String, IndexedConversation>,
+}
+
+impl HistoryIndex {
+    fn path() -> PathBuf {
+        Config::data_dir().join("history_index.json")
+    }
+
+    fn load() -> Self {
+        std::fs::read(Self::path())
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    /// Best-matching message per conversation, best conversation first.
+    fn search(&self, query: &[f32], k: usize) -> Vec<RecallHit> {
+        let mut hits: Vec<RecallHit> = self
+            .conversations
+            .iter()
+            .filter_map(|(id, conv)| {
+                conv.messages
+                    .iter()
+                    .map(|m| (m, cosine(&m.embedding, query)))
+                    .max_by(|a, b| a.1.total_cmp(&b.1))
+                    .map(|(m, score)| RecallHit {
+                        conversation_id: id.clone(),
+                        title: conv.title.clone(),
+                        message_id: m.message_id.clone(),
+                        role: m.role.clone(),
+                        excerpt: m.excerpt.clone(),
+                        score,
+                    })
+            })
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(k);
+        hits
+    }
+}
+
+/// A past conversation found by `/recall`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecallHit {
+    pub conversation_id: String,
+    pub title: String,
+    /// The message that matched best
+    pub message_id: String,
+    pub role: String,
+    pub excerpt: String,
+    pub score: f32,
+}
+
+/// Find the `k` saved conversations most similar in meaning to `query`.
+/// Conversations that changed since they were last embedded are embedded
+/// first, so the first call after a model change (or ever) is the slowest.
+pub async fn recall(
+    config: &EmbeddingsConfig,
+    conversations: &[Conversation],
+    query: &str,
+    k: usize,
+) -> anyhow::Result<Vec<RecallHit>> {
+    let mut index = HistoryIndex::load();
+    if index.model != config.model {
+        index = HistoryIndex { model: config.model.clone(), conversations: BTreeMap::new() };
+    }
+    index
+        .conversations
+        .retain(|id, _| conversations.iter().any(|c| &c.id == id));
+
+    let stale: Vec<&Conversation> = conversations
+        .iter()
+        .filter(|c| index.conversations.get(&c.id).is_none_or(|i| i.updated_at != c.updated_at))
+        .collect();
+    let mut texts = Vec::new();
+    for conv in &stale {
+        for msg in conv.messages.iter().filter(|m| !m.content.trim().is_empty()) {
+            texts.push(msg.content.chars().take(MAX_MESSAGE_CHARS).collect::<String>());
+        }
+    }
+    let mut vectors = embed(config, &texts).await?.into_iter();
+    for conv in stale {
+        let messages = conv
+            .messages
+            .iter()
+            .filter(|m| !m.content.trim().is_empty())
+            .filter_map(|m| {
+                Some(IndexedMessage {
+                    message_id: m.id.clone(),
+                    role: m.role.clone(),
+                    excerpt: m.content.lines().find(|l| !l.trim().is_empty())
+                        .unwrap_or("").chars().take(80).collect(),
+                    embedding: vectors.next()?,
+                })
+            })
+            .collect();
+        index.conversations.insert(
+            conv.id.clone(),
+            IndexedConversation { updated_at: conv.updated_at, title: conv.title.clone(), messages },
+        );
+    }
+    if !texts.is_empty() {
+        std::fs::create_dir_all(Config::data_dir())?;
+        crate::history::write_atomic(&HistoryIndex::path(), &serde_json::to_vec(&index)?)?;
+    }
+
+    let query_vector = embed(config, &[query.to_string()]).await?.pop().unwrap_or_default();
+    Ok(index.search(&query_vector, k))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_text_overlaps_on_line_boundaries() {
+        let line = "x".repeat(100);
+        let text = vec![line.as_str(); 40].join("\n");
+        let chunks = chunk_text(&text);
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks[0].0, 1);
+        assert_eq!(chunks[1].0, chunks[0].1 - OVERLAP_LINES + 1);
+        assert_eq!(chunks.last().unwrap().1, 40);
+        assert!(chunk_text("").is_empty());
+    }
+
+    #[test]
+    fn search_ranks_by_cosine_similarity() {
+        let chunk = |text: &str, embedding: Vec<f32>| Chunk {
+            path: PathBuf::from("doc.md"),
+            start_line: 1,
+            end_line: 1,
+            text: text.into(),
+            embedding,
+        };
+        let index = DocIndex {
+            model: "m".into(),
+            chunks: vec![chunk("far", vec![0.0, 1.0]), chunk("near", vec![1.0, 0.1])],
+        };
+        let results = index.search(&[1.0, 0.0], 1);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0.text, "near");
+        assert_eq!(cosine(&[1.0], &[1.0, 2.0]), 0.0);
+    }
+
+    #[test]
+    fn history_search_keeps_best_message_per_conversation() {
+        let message = |id: &str, embedding: Vec<f32>| IndexedMessage {
+            message_id: id.into(),
+            role: "user".into(),
+            excerpt: id.into(),
+            embedding,
+        };
+        let conversation = |messages| IndexedConversation {
+            updated_at: Utc::now(),
+            title: "t".into(),
+            messages,
+        };
+        let mut index = HistoryIndex::default();
+        index.conversations.insert(
+            "a".into(),
+            conversation(vec![message("a1", vec![0.0, 1.0]), message("a2", vec![1.0, 0.0])]),
+        );
+        index.conversations.insert("b".into(), conversation(vec![message("b1", vec![1.0, 1.0])]));
+
+        let hits = index.search(&[1.0, 0.0], 5);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].conversation_id, "a");
+        assert_eq!(hits[0].message_id, "a2");
+        assert_eq!(hits[1].message_id, "b1");
+    }
+
+    #[test]
+    fn parse_embeddings_handles_both_formats() {
+        let openai = json!({ "data": [
+            { "index": 1, "embedding": [0.5] },
+            { "index": 0, "embedding": [0.25] }
+        ] });
+        assert_eq!(parse_embeddings(&openai), vec![vec![0.25], vec![0.5]]);
+        let ollama = json!({ "embeddings": [[1.0, 2.0]] });
+        assert_eq!(parse_embeddings(&ollama), vec![vec![1.0, 2.0]]);
+    }
+}
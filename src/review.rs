@@ -0,0 +1,215 @@
+//! Session change review (`/review`): remembers what each file looked like
+//! before a tool first modified it, and diffs that against what is on disk
+//! now so changes can be accepted or reverted per file.
+
+use similar::{ChangeTag, TextDiff};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::tools::Tool;
+
+/// Lines of unchanged context around each hunk.
+const CONTEXT_LINES: usize = 3;
+
+/// Contents of files as they were before the first tool write this session.
+#[derive(Debug, Default)]
+pub struct SessionChanges {
+    /// `None` when the file did not exist yet
+    originals: BTreeMap<PathBuf, Option<Vec<u8>>>,
+}
+
+impl SessionChanges {
+    /// Snapshot `path` before a tool writes it. Only the first write of the
+    /// session counts, so the review always diffs against the starting point.
+    pub fn record(&mut self, path: &Path) {
+        self.originals
+            .entry(path.to_path_buf())
+            .or_insert_with(|| std::fs::read(path).ok());
+    }
+
+    /// Files whose contents differ from their snapshot, in path order.
+    pub fn files(&self) -> Vec<FileReview> {
+        self.originals
+            .iter()
+            .filter_map(|(path, original)| FileReview::new(path, original.as_deref()))
+            .collect()
+    }
+
+    /// Keep the current contents: the file drops out of the review.
+    pub fn accept(&mut self, path: &Path) {
+        self.originals.remove(path);
+    }
+
+    /// Put the snapshot back, deleting files that tools created.
+    pub fn revert(&mut self, path: &Path) -> std::io::Result<()> {
+        match self.originals.get(path) {
+            Some(Some(contents)) => std::fs::write(path, contents)?,
+            Some(None) if path.exists() => std::fs::remove_file(path)?,
+            _ => {}
+        }
+        self.originals.remove(path);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+#[derive(Debug, Clone)]
+pub struct Hunk {
+    /// Unified diff header, e.g. `@@ -12,7 +12,9 @@`
+    pub header: String,
+    pub lines: Vec<(ChangeTag, String)>,
+}
+
+/// One changed file in the review overlay.
+#[derive(Debug, Clone)]
+pub struct FileReview {
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+    pub added: usize,
+    pub removed: usize,
+    pub hunks: Vec<Hunk>,
+}
+
+impl FileReview {
+    fn new(path: &Path, original: Option<&[u8]>) -> Option<Self> {
+        let current = std::fs::read(path).ok();
+        Self::between(path, original, current.as_deref())
+    }
+
+    /// The change from `original` to `current` (`None` where the file
+    /// doesn't exist), or `None` when they are the same.
+    fn between(path: &Path, original: Option<&[u8]>, current: Option<&[u8]>) -> Option<Self> {
+        if current == original {
+            return None;
+        }
+        let kind = match (original, current) {
+            (None, _) => ChangeKind::Added,
+            (_, None) => ChangeKind::Deleted,
+            _ => ChangeKind::Modified,
+        };
+        let old = String::from_utf8_lossy(original.unwrap_or_default());
+        let new = String::from_utf8_lossy(current.unwrap_or_default());
+        let diff = TextDiff::from_lines(old.as_ref(), new.as_ref());
+
+        let (mut added, mut removed) = (0, 0);
+        let mut unified = diff.unified_diff();
+        unified.context_radius(CONTEXT_LINES);
+        let hunks = unified
+            .iter_hunks()
+            .map(|hunk| Hunk {
+                header: hunk.header().to_string(),
+                lines: hunk
+                    .iter_changes()
+                    .map(|change| {
+                        match change.tag() {
+                            ChangeTag::Insert => added += 1,
+                            ChangeTag::Delete => removed += 1,
+                            ChangeTag::Equal => {}
+                        }
+                        (change.tag(), change.value().trim_end_matches(['\n', '\r']).to_string())
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Some(Self { path: path.to_path_buf(), kind, added, removed, hunks })
+    }
+
+    /// What a `write_file` or `edit_file` call would change, against the
+    /// file as it is now, for the confirmation prompt. A call that would
+    /// leave the file as it is gives no hunks.
+    pub fn proposed(tool: &Tool) -> Option<Self> {
+        let new = tool.proposed_contents()?;
+        let path = PathBuf::from(tool.written_paths().pop()?);
+        let original = std::fs::read(&path).ok();
+        Some(Self::between(&path, original.as_deref(), Some(new.as_bytes())).unwrap_or(Self {
+            path,
+            kind: ChangeKind::Modified,
+            added: 0,
+            removed: 0,
+            hunks: Vec::new(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_review_accept_and_revert() {
+        let dir = std::env::temp_dir().join(format!("pro-chat-review-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let edited = dir.join("edited.txt");
+        let created = dir.join("created.txt");
+        let untouched = dir.join("untouched.txt");
+        std::fs::write(&edited, "one\ntwo\nthree\n").unwrap();
+        std::fs::write(&untouched, "same\n").unwrap();
+
+        let mut changes = SessionChanges::default();
+        changes.record(&edited);
+        changes.record(&created);
+        changes.record(&untouched);
+        std::fs::write(&edited, "one\n2\nthree\n").unwrap();
+        // Later writes keep the first snapshot
+        changes.record(&edited);
+        std::fs::write(&created, "new\n").unwrap();
+
+        let files = changes.files();
+        assert_eq!(files.len(), 2);
+        let review = files.iter().find(|f| f.path == edited).unwrap();
+        assert_eq!(review.kind, ChangeKind::Modified);
+        assert_eq!((review.added, review.removed), (1, 1));
+        assert_eq!(review.hunks[0].header, "@@ -1,3 +1,3 @@");
+        assert!(review.hunks[0].lines.contains(&(ChangeTag::Delete, "two".to_string())));
+        assert_eq!(files.iter().find(|f| f.path == created).unwrap().kind, ChangeKind::Added);
+
+        changes.revert(&edited).unwrap();
+        assert_eq!(std::fs::read_to_string(&edited).unwrap(), "one\ntwo\nthree\n");
+        changes.revert(&created).unwrap();
+        assert!(!created.exists());
+        assert!(changes.files().is_empty());
+
+        changes.record(&untouched);
+        std::fs::write(&untouched, "changed\n").unwrap();
+        changes.accept(&untouched);
+        assert!(changes.files().is_empty());
+        assert_eq!(std::fs::read_to_string(&untouched).unwrap(), "changed\n");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_proposed_diff_for_file_tools() {
+        let dir = std::env::temp_dir().join(format!("pro-chat-proposed-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a.txt");
+        std::fs::write(&file, "one\ntwo\nthree\n").unwrap();
+        let path = file.display().to_string();
+
+        let edit = Tool::EditFile { path: path.clone(), old_text: "two".into(), new_text: "2".into() };
+        let review = FileReview::proposed(&edit).unwrap();
+        assert_eq!((review.kind, review.added, review.removed), (ChangeKind::Modified, 1, 1));
+        assert!(review.hunks[0].lines.contains(&(ChangeTag::Insert, "2".to_string())));
+        // Nothing is written by previewing
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "one\ntwo\nthree\n");
+
+        let missing = Tool::EditFile { path: path.clone(), old_text: "four".into(), new_text: "4".into() };
+        assert!(FileReview::proposed(&missing).is_none());
+        let same = Tool::WriteFile { path, content: "one\ntwo\nthree\n".into() };
+        assert!(FileReview::proposed(&same).unwrap().hunks.is_empty());
+        let new = Tool::WriteFile { path: dir.join("b.txt").display().to_string(), content: "x\n".into() };
+        assert_eq!(FileReview::proposed(&new).unwrap().kind, ChangeKind::Added);
+        assert!(FileReview::proposed(&Tool::ReadFile { path: "a.txt".into() }).is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,231 @@
+//! `pro state export` / `pro state import`: one TOML file carrying a setup to
+//! a new machine or a teammate. It holds the settings from config.toml (tool
+//! permissions, recipes, profiles and providers included) and the per-project
+//! `/autonomy` levels. API keys, tokens, passwords, request headers and `env`
+//! tables are left out; importing keeps the ones already configured locally,
+//! unless the export points that table at a different URL.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::bugreport::is_secret_key;
+use crate::config::{Autonomy, Config};
+
+const VERSION: i64 = 1;
+
+/// Settings that only mean something on the machine they were written on.
+const LOCAL_KEYS: &[&str] = &["last_conversation_id"];
+
+pub fn export(output: &Path) -> anyhow::Result<()> {
+    let config = std::fs::read_to_string(Config::path()).unwrap_or_default();
+    let (bundle, dropped) = bundle(&config, &Autonomy::load_all())?;
+    std::fs::write(output, bundle).with_context(|| format!("writing {}", output.display()))?;
+    println!("Wrote {}", output.display());
+    if dropped > 0 {
+        println!("Left out {dropped} secret settings (keys, tokens, headers, env)");
+    }
+    Ok(())
+}
+
+pub fn import(input: &Path) -> anyhow::Result<()> {
+    let text = std::fs::read_to_string(input).with_context(|| format!("reading {}", input.display()))?;
+    let bundle: toml::Value = toml::from_str(&text).with_context(|| format!("parsing {}", input.display()))?;
+    match bundle.get("version").and_then(|v| v.as_integer()) {
+        Some(v) if v > VERSION => anyhow::bail!("{} was exported by a newer pro (state version {v})", input.display()),
+        Some(_) => {}
+        None => anyhow::bail!("{} is not a pro state export", input.display()),
+    }
+
+    let path = Config::path();
+    let existing = std::fs::read_to_string(&path).unwrap_or_default();
+    let (merged, warnings) = merge_config(&existing, bundle.get("config"))?;
+    if !existing.is_empty() {
+        let backup = path.with_extension("toml.bak");
+        std::fs::write(&backup, &existing)?;
+        println!("Previous config saved to {}", backup.display());
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    crate::history::write_atomic(&path, merged.as_bytes())?;
+    println!("Imported settings into {}", path.display());
+    for warning in warnings {
+        println!("Warning: {warning}");
+    }
+
+    let incoming: BTreeMap<String, Autonomy> = match bundle.get("autonomy") {
+        Some(levels) => levels.clone().try_into().context("reading autonomy levels")?,
+        None => BTreeMap::new(),
+    };
+    if !incoming.is_empty() {
+        let mut levels = Autonomy::load_all();
+        let count = incoming.len();
+        levels.extend(incoming);
+        Autonomy::save_all(&levels)?;
+        println!("Imported autonomy levels for {count} projects");
+    }
+    Ok(())
+}
+
+/// The export file for a config file's contents and the saved autonomy
+/// levels, and how many secret settings were left out of it.
+fn bundle(config: &str, autonomy: &BTreeMap<String, Autonomy>) -> anyhow::Result<(String, usize)> {
+    fn strip(table: &mut toml::Table) -> usize {
+        let before = table.len();
+        // Only strings can hold a secret; max_tokens and friends are settings
+        table.retain(|key, value| !(matches!(key, "headers" | "env") || value.is_str() && is_secret_key(key)));
+        let mut dropped = before - table.len();
+        for (_, value) in table.iter_mut() {
+            match value {
+                toml::Value::Table(t) => dropped += strip(t),
+                toml::Value::Array(items) => {
+                    for t in items.iter_mut().filter_map(|v| v.as_table_mut()) {
+                        dropped += strip(t);
+                    }
+                }
+                _ => {}
+            }
+        }
+        dropped
+    }
+
+    let mut settings: toml::Table = toml::from_str(config).context("config.toml does not parse")?;
+    for key in LOCAL_KEYS {
+        settings.remove(*key);
+    }
+    let dropped = strip(&mut settings);
+
+    let mut out = toml::Table::new();
+    out.insert("version".into(), VERSION.into());
+    out.insert("config".into(), settings.into());
+    out.insert("autonomy".into(), toml::Value::try_from(autonomy)?);
+    Ok((toml::to_string_pretty(&out)?, dropped))
+}
+
+/// `existing` config with the bundle's settings laid over it, and a warning
+/// for each local secret dropped on the way. Tables merge key by key, and
+/// `[[providers]]` entries by name, so anything the bundle leaves out (such as
+/// API keys) is kept. When the bundle moves a table to a different URL, that
+/// table's local secrets are dropped rather than sent to the new host.
+fn merge_config(existing: &str, incoming: Option<&toml::Value>) -> anyhow::Result<(String, Vec<String>)> {
+    fn merge(base: &mut toml::Table, incoming: &toml::Table, name: &str, warnings: &mut Vec<String>) {
+        let moved = incoming.iter().find(|(key, value)| {
+            ["url", "base_url", "api_url"].contains(&key.as_str()) && base.get(*key).is_some_and(|old| old != *value)
+        });
+        if let Some((url_key, url)) = moved {
+            let secrets: Vec<String> = base
+                .iter()
+                .filter(|(key, value)| value.is_str() && is_secret_key(key) && !incoming.contains_key(*key))
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in secrets {
+                base.remove(&key);
+                warnings.push(format!(
+                    "{name}: {url_key} changed to {url}, so the local {key} was removed rather than sent there; set it again"
+                ));
+            }
+        }
+        for (key, value) in incoming {
+            match (base.get_mut(key), value) {
+                (Some(toml::Value::Table(b)), toml::Value::Table(v)) => merge(b, v, key, warnings),
+                (Some(toml::Value::Array(b)), toml::Value::Array(v)) if key == "providers" => {
+                    for item in v.iter().filter_map(|v| v.as_table()) {
+                        let same = b.iter_mut().filter_map(|v| v.as_table_mut()).find(|t| t.get("name") == item.get("name"));
+                        match same {
+                            Some(existing) => {
+                                let name = item.get("name").and_then(|n| n.as_str()).unwrap_or("providers");
+                                merge(existing, item, name, warnings)
+                            }
+                            None => b.push(item.clone().into()),
+                        }
+                    }
+                }
+                _ => {
+                    base.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+
+    let mut base: toml::Table = toml::from_str(existing).context("config.toml does not parse")?;
+    let mut warnings = Vec::new();
+    if let Some(incoming) = incoming {
+        let incoming = incoming.as_table().context("the export's [config] is not a table")?;
+        merge(&mut base, incoming, "config", &mut warnings);
+    }
+    let merged = toml::to_string_pretty(&base)?;
+    toml::from_str::<Config>(&merged).context("the imported settings are not a valid config")?;
+    Ok((merged, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_leaves_out_secrets_and_import_keeps_local_ones() {
+        let source = r#"
+model = "gpt-4o"
+provider = "openai"
+max_tokens = 8192
+openai_api_key = "sk-source"
+last_conversation_id = "abc"
+
+[tools.permissions]
+bash = "deny"
+
+[[providers]]
+name = "internal"
+base_url = "https://llm.example.com/v1"
+api_key = "internal-secret"
+headers = { "X-Team" = "t0ken" }
+"#;
+        let autonomy = BTreeMap::from([("/work/app".to_string(), Autonomy::High)]);
+        let (bundle, dropped) = bundle(source, &autonomy).unwrap();
+        assert_eq!(dropped, 3);
+        assert!(!bundle.contains("sk-source"));
+        assert!(!bundle.contains("internal-secret"));
+        assert!(!bundle.contains("t0ken"));
+        assert!(!bundle.contains("last_conversation_id"));
+        assert!(bundle.contains("llm.example.com"));
+        assert!(bundle.contains("max_tokens = 8192"));
+
+        let bundle: toml::Value = toml::from_str(&bundle).unwrap();
+        assert_eq!(bundle["autonomy"]["/work/app"].as_str(), Some("high"));
+
+        let local = r#"
+model = "claude-sonnet-4-20250514"
+openai_api_key = "sk-local"
+
+[[providers]]
+name = "internal"
+base_url = "https://old.example.com/v1"
+api_key = "internal-local"
+"#;
+        let (merged, warnings) = merge_config(local, bundle.get("config")).unwrap();
+        let merged: toml::Value = toml::from_str(&merged).unwrap();
+        assert_eq!(merged["model"].as_str(), Some("gpt-4o"));
+        assert_eq!(merged["openai_api_key"].as_str(), Some("sk-local"));
+        assert_eq!(merged["tools"]["permissions"]["bash"].as_str(), Some("deny"));
+        // The provider moved to another host, so its local key isn't carried along
+        let provider = &merged["providers"][0];
+        assert_eq!(provider["base_url"].as_str(), Some("https://llm.example.com/v1"));
+        assert_eq!(provider.get("api_key"), None);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("internal: base_url changed"));
+
+        let same_host = local.replace("old.example.com", "llm.example.com");
+        let (merged, warnings) = merge_config(&same_host, bundle.get("config")).unwrap();
+        let merged: toml::Value = toml::from_str(&merged).unwrap();
+        assert_eq!(merged["providers"][0]["api_key"].as_str(), Some("internal-local"));
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_import_rejects_an_invalid_config() {
+        let bundle: toml::Value = toml::from_str("[config]\nmax_tokens = \"lots\"\n").unwrap();
+        assert!(merge_config("", bundle.get("config")).is_err());
+    }
+}
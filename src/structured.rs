@@ -0,0 +1,221 @@
+use serde_json::Value;
+
+/// System prompt addition used while code mode is on.
+pub const CODE_INSTRUCTION: &str = "Answer with a single fenced code block containing only the code, \
+tagged with its language. No explanation before or after it; put any necessary notes in code comments.";
+
+/// System prompt addition used while JSON mode is on.
+pub fn json_instruction(schema: Option<&Value>) -> String {
+    let mut text = String::from(
+        "Respond with a single valid JSON value and nothing else: no prose, no markdown code fences.",
+    );
+    if let Some(schema) = schema {
+        text.push_str("\nThe response must conform to this JSON Schema:\n");
+        text.push_str(&serde_json::to_string_pretty(schema).unwrap_or_default());
+    }
+    text
+}
+
+/// `response_format` for OpenAI-compatible APIs.
+pub fn openai_response_format(schema: Option<&Value>) -> Value {
+    match schema {
+        Some(schema) => serde_json::json!({
+            "type": "json_schema",
+            "json_schema": { "name": "response", "schema": schema },
+        }),
+        None => serde_json::json!({ "type": "json_object" }),
+    }
+}
+
+/// Parse a reply as JSON, tolerating a surrounding ```json fence.
+pub fn extract_json(text: &str) -> Result<Value, serde_json::Error> {
+    let trimmed = text.trim();
+    let body = trimmed
+        .strip_prefix("```json")
+        .or_else(|| trimmed.strip_prefix("```"))
+        .and_then(|rest| rest.trim_end().strip_suffix("```"))
+        .unwrap_or(trimmed);
+    serde_json::from_str(body.trim())
+}
+
+/// Check `value` against the commonly used subset of JSON Schema: `type`,
+/// `enum`, `const`, `properties`, `required`, `additionalProperties: false`,
+/// `items`, `minItems`/`maxItems`, and `minimum`/`maximum`. Returns one
+/// message per violation, each prefixed with a `$.path`.
+pub fn validate(value: &Value, schema: &Value) -> Vec<String> {
+    let mut errors = Vec::new();
+    check(value, schema, "$", &mut errors);
+    errors
+}
+
+fn type_matches(value: &Value, ty: &str) -> bool {
+    match ty {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+fn check(value: &Value, schema: &Value, path: &str, errors: &mut Vec<String>) {
+    let types: Vec<&str> = match &schema["type"] {
+        Value::String(t) => vec![t.as_str()],
+        Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    if !types.is_empty() && !types.iter().any(|t| type_matches(value, t)) {
+        errors.push(format!("{path}: expected {}", types.join(" or ")));
+        return;
+    }
+    if let Some(options) = schema["enum"].as_array()
+        && !options.contains(value)
+    {
+        errors.push(format!("{path}: not one of the allowed values"));
+    }
+    if let Some(expected) = schema.get("const")
+        && expected != value
+    {
+        errors.push(format!("{path}: expected {expected}"));
+    }
+    if let Some(n) = value.as_f64() {
+        if let Some(min) = schema["minimum"].as_f64()
+            && n < min
+        {
+            errors.push(format!("{path}: below minimum {min}"));
+        }
+        if let Some(max) = schema["maximum"].as_f64()
+            && n > max
+        {
+            errors.push(format!("{path}: above maximum {max}"));
+        }
+    }
+
+    if let Some(obj) = value.as_object() {
+        for key in schema["required"].as_array().into_iter().flatten().filter_map(Value::as_str) {
+            if !obj.contains_key(key) {
+                errors.push(format!("{path}: missing required property \"{key}\""));
+            }
+        }
+        let properties = schema["properties"].as_object();
+        for (key, child) in obj {
+            match properties.and_then(|p| p.get(key)) {
+                Some(child_schema) => check(child, child_schema, &format!("{path}.{key}"), errors),
+                None if schema["additionalProperties"] == Value::Bool(false) => {
+                    errors.push(format!("{path}: unexpected property \"{key}\""));
+                }
+                None => {}
+            }
+        }
+    }
+
+    if let Some(items) = value.as_array() {
+        if let Some(min) = schema["minItems"].as_u64()
+            && (items.len() as u64) < min
+        {
+            errors.push(format!("{path}: fewer than {min} items"));
+        }
+        if let Some(max) = schema["maxItems"].as_u64()
+            && (items.len() as u64) > max
+        {
+            errors.push(format!("{path}: more than {max} items"));
+        }
+        if schema["items"].is_object() {
+            for (i, item) in items.iter().enumerate() {
+                check(item, &schema["items"], &format!("{path}[{i}]"), errors);
+            }
+        }
+    }
+}
+
+/// Pretty-print `value`, collapsing objects and arrays nested deeper than
+/// `depth` into a one-line summary like `{…3 keys}`. A depth of 0 folds
+/// nothing.
+pub fn pretty_folded(value: &Value, depth: usize) -> String {
+    if depth == 0 {
+        return serde_json::to_string_pretty(value).unwrap_or_default();
+    }
+    let mut out = String::new();
+    write_folded(value, depth, 0, &mut out);
+    out
+}
+
+fn write_folded(value: &Value, depth: usize, level: usize, out: &mut String) {
+    let indent = "  ".repeat(level + 1);
+    let close = "  ".repeat(level);
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            if level >= depth {
+                out.push_str(&format!("{{…{} keys}}", map.len()));
+                return;
+            }
+            out.push_str("{\n");
+            for (i, (key, child)) in map.iter().enumerate() {
+                out.push_str(&format!("{indent}{}: ", Value::String(key.clone())));
+                write_folded(child, depth, level + 1, out);
+                out.push_str(if i + 1 < map.len() { ",\n" } else { "\n" });
+            }
+            out.push_str(&format!("{close}}}"));
+        }
+        Value::Array(items) if !items.is_empty() => {
+            if level >= depth {
+                out.push_str(&format!("[…{} items]", items.len()));
+                return;
+            }
+            out.push_str("[\n");
+            for (i, child) in items.iter().enumerate() {
+                out.push_str(&indent);
+                write_folded(child, depth, level + 1, out);
+                out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+            }
+            out.push_str(&format!("{close}]"));
+        }
+        other => out.push_str(&other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn extract_json_strips_fences() {
+        assert_eq!(extract_json("```json\n{\"a\": 1}\n```").unwrap(), json!({"a": 1}));
+        assert_eq!(extract_json("  [1, 2] ").unwrap(), json!([1, 2]));
+        assert!(extract_json("Sure! Here it is: {}").is_err());
+    }
+
+    #[test]
+    fn validate_reports_paths() {
+        let schema = json!({
+            "type": "object",
+            "required": ["name", "tags"],
+            "additionalProperties": false,
+            "properties": {
+                "name": {"type": "string"},
+                "age": {"type": "integer", "minimum": 0},
+                "tags": {"type": "array", "items": {"enum": ["a", "b"]}}
+            }
+        });
+        assert!(validate(&json!({"name": "x", "tags": ["a"]}), &schema).is_empty());
+
+        let errors = validate(&json!({"age": -1, "tags": ["c"], "extra": true}), &schema);
+        assert!(errors.contains(&"$: missing required property \"name\"".to_string()));
+        assert!(errors.contains(&"$.age: below minimum 0".to_string()));
+        assert!(errors.contains(&"$.tags[0]: not one of the allowed values".to_string()));
+        assert!(errors.contains(&"$: unexpected property \"extra\"".to_string()));
+    }
+
+    #[test]
+    fn pretty_folded_collapses_deep_values() {
+        let value = json!({"a": {"b": {"c": 1, "d": 2}}, "list": [1, 2, 3]});
+        let folded = pretty_folded(&value, 2);
+        assert!(folded.contains("\"b\": {…2 keys}"));
+        assert!(folded.contains("\"list\": [\n    1,"));
+        assert_eq!(pretty_folded(&value, 0), serde_json::to_string_pretty(&value).unwrap());
+    }
+}
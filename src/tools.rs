@@ -1,14 +1,19 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use tokio::process::Command as TokioCommand;
 
+use crate::config::{EmbeddingsConfig, GithubConfig, SandboxConfig, ToolEnvConfig, TrackerConfig, ValidateConfig};
+use crate::rag;
+use crate::tracker;
+
 // ---------------------------------------------------------------------------
 // Tool definition
 // ---------------------------------------------------------------------------
@@ -36,7 +41,11 @@ pub enum Tool {
     },
 
     #[serde(rename = "execute")]
-    Execute { command: String },
+    Execute {
+        command: String,
+        /// Text written to the command's stdin, used to answer prompts.
+        stdin: Option<String>,
+    },
 
     #[serde(rename = "edit_file")]
     EditFile {
@@ -44,6 +53,87 @@ pub enum Tool {
         old_text: String,
         new_text: String,
     },
+
+    #[serde(rename = "edit_lines")]
+    EditLines {
+        path: String,
+        /// First line to replace, 1-based
+        start: usize,
+        /// Last line to replace (inclusive); `start - 1` inserts before `start`
+        end: usize,
+        new_text: String,
+    },
+
+    #[serde(rename = "apply_patch")]
+    ApplyPatch {
+        /// Unified diff touching one or more files
+        patch: String,
+    },
+
+    #[serde(rename = "delete_file")]
+    DeleteFile { path: String },
+
+    #[serde(rename = "move_file")]
+    MoveFile { from: String, to: String },
+
+    #[serde(rename = "read_clipboard")]
+    ReadClipboard,
+
+    #[serde(rename = "screenshot")]
+    Screenshot { window: bool },
+
+    #[serde(rename = "gh_issue_view")]
+    GhIssueView { repo: String, number: u64 },
+
+    #[serde(rename = "gh_pr_diff")]
+    GhPrDiff { repo: String, number: u64 },
+
+    #[serde(rename = "gh_pr_comment")]
+    GhPrComment {
+        repo: String,
+        number: u64,
+        body: String,
+    },
+
+    #[serde(rename = "fetch_ticket")]
+    FetchTicket { key: String },
+
+    #[serde(rename = "git_status")]
+    GitStatus { path: Option<String> },
+
+    #[serde(rename = "git_log")]
+    GitLog {
+        /// Only commits touching this file or directory
+        path: Option<String>,
+        max_count: Option<usize>,
+    },
+
+    #[serde(rename = "git_show")]
+    GitShow { rev: String, path: Option<String> },
+
+    #[serde(rename = "retrieve")]
+    Retrieve { query: String, k: Option<usize> },
+
+    #[serde(rename = "web_fetch")]
+    WebFetch {
+        url: String,
+        /// Most characters of page text to return
+        max_chars: Option<usize>,
+    },
+
+    #[serde(rename = "start_edit_batch")]
+    StartEditBatch,
+
+    #[serde(rename = "commit_edit_batch")]
+    CommitEditBatch,
+
+    /// A tool from an MCP server, called by its name on that server
+    #[serde(rename = "mcp")]
+    Mcp {
+        server: String,
+        tool: String,
+        input: Value,
+    },
 }
 
 impl Tool {
@@ -56,8 +146,437 @@ impl Tool {
             Tool::SearchFiles { .. } => "search_files",
             Tool::Execute { .. } => "execute",
             Tool::EditFile { .. } => "edit_file",
+            Tool::EditLines { .. } => "edit_lines",
+            Tool::ApplyPatch { .. } => "apply_patch",
+            Tool::DeleteFile { .. } => "delete_file",
+            Tool::MoveFile { .. } => "move_file",
+            Tool::ReadClipboard => "read_clipboard",
+            Tool::Screenshot { .. } => "screenshot",
+            Tool::GhIssueView { .. } => "gh_issue_view",
+            Tool::GhPrDiff { .. } => "gh_pr_diff",
+            Tool::GhPrComment { .. } => "gh_pr_comment",
+            Tool::FetchTicket { .. } => "fetch_ticket",
+            Tool::GitStatus { .. } => "git_status",
+            Tool::GitLog { .. } => "git_log",
+            Tool::GitShow { .. } => "git_show",
+            Tool::Retrieve { .. } => "retrieve",
+            Tool::WebFetch { .. } => "web_fetch",
+            Tool::StartEditBatch => "start_edit_batch",
+            Tool::CommitEditBatch => "commit_edit_batch",
+            Tool::Mcp { .. } => "mcp",
+        }
+    }
+
+    /// Name the call's permission is stored under: the tool name, or the
+    /// exposed `<server>__<tool>` for an MCP tool, so that allowing one MCP
+    /// tool doesn't allow every tool of every server.
+    pub fn permission_key(&self) -> String {
+        match self {
+            Tool::Mcp { server, tool, .. } => crate::mcp::exposed_name(server, tool),
+            _ => self.name().to_string(),
+        }
+    }
+
+    /// Calls that pause for confirmation at every autonomy level: shell
+    /// commands with any [`CommandRisk`], writes outside the working
+    /// directory, posting to GitHub, and MCP tools, which could do anything.
+    /// An explicit "allow" for the tool still skips the prompt.
+    pub fn is_dangerous(&self) -> bool {
+        match self {
+            Tool::Execute { command, .. } => !classify_command(command).risks.is_empty(),
+            Tool::WriteFile { .. }
+            | Tool::EditFile { .. }
+            | Tool::EditLines { .. }
+            | Tool::ApplyPatch { .. }
+            | Tool::DeleteFile { .. }
+            | Tool::MoveFile { .. } => self.written_paths().iter().any(|path| !is_inside_cwd(Path::new(path))),
+            Tool::GhPrComment { .. } | Tool::Mcp { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Files and directories this call reads from or writes to.
+    pub fn file_paths(&self) -> Vec<String> {
+        match self {
+            Tool::ReadFile { path } | Tool::ListFiles { path, .. } => vec![path.clone()],
+            Tool::SearchFiles { path, .. } => vec![path.clone().unwrap_or_else(|| ".".into())],
+            _ => self.written_paths(),
+        }
+    }
+
+    /// What `write_file` or `edit_file` would leave in the file, for a
+    /// preview before the call runs. `None` for other tools, and for an edit
+    /// that would fail because `old_text` doesn't match exactly once.
+    pub fn proposed_contents(&self) -> Option<String> {
+        match self {
+            Tool::WriteFile { content, .. } => Some(content.clone()),
+            Tool::EditFile { path, old_text, new_text } => {
+                let contents = fs::read_to_string(path).ok()?;
+                (contents.matches(old_text.as_str()).count() == 1).then(|| contents.replacen(old_text.as_str(), new_text, 1))
+            }
+            _ => None,
+        }
+    }
+
+    /// Files this call writes, creates or deletes. For a patch that doesn't
+    /// parse this is empty; applying it fails before anything is written.
+    pub fn written_paths(&self) -> Vec<String> {
+        match self {
+            Tool::WriteFile { path, .. }
+            | Tool::EditFile { path, .. }
+            | Tool::EditLines { path, .. }
+            | Tool::DeleteFile { path } => vec![path.clone()],
+            Tool::MoveFile { from, to } => vec![from.clone(), to.clone()],
+            Tool::ApplyPatch { patch } => {
+                let mut paths = Vec::new();
+                for file in crate::patch::parse(patch).unwrap_or_default() {
+                    for path in [file.old_path, file.new_path].into_iter().flatten() {
+                        if !paths.contains(&path) {
+                            paths.push(path);
+                        }
+                    }
+                }
+                paths
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Kinds of side effect an `execute` command can have, most severe first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CommandRisk {
+    PrivilegeEscalation,
+    Deletion,
+    /// Code handed to an interpreter inline, which the classifier can't see into
+    InlineCode,
+    PackageInstall,
+    Network,
+}
+
+impl CommandRisk {
+    /// Badge text in the confirm overlay.
+    pub fn label(self) -> &'static str {
+        match self {
+            CommandRisk::PrivilegeEscalation => "root",
+            CommandRisk::Deletion => "deletes",
+            CommandRisk::InlineCode => "runs code",
+            CommandRisk::PackageInstall => "installs",
+            CommandRisk::Network => "network",
+        }
+    }
+}
+
+/// What a shell command may do, shown before it is approved.
+#[derive(Debug, Default, PartialEq)]
+pub struct CommandAssessment {
+    /// Each risk found, with what it affects: paths, packages or hosts
+    pub risks: BTreeMap<CommandRisk, Vec<String>>,
+}
+
+impl CommandAssessment {
+    fn add(&mut self, risk: CommandRisk, targets: impl IntoIterator<Item = String>) {
+        let targets: Vec<String> = targets.into_iter().collect();
+        if targets.is_empty() {
+            return;
+        }
+        let entry = self.risks.entry(risk).or_default();
+        for target in targets {
+            if !entry.contains(&target) {
+                entry.push(target);
+            }
+        }
+    }
+}
+
+const PRIVILEGE_COMMANDS: &[&str] = &["sudo", "su", "doas", "pkexec"];
+const DELETE_COMMANDS: &[&str] = &["rm", "rmdir", "shred", "unlink", "truncate", "dd"];
+const NETWORK_COMMANDS: &[&str] = &["curl", "wget", "ssh", "scp", "sftp", "rsync", "nc", "ftp", "telnet"];
+/// Interpreters and the flags that run code given on the command line
+const INLINE_CODE_COMMANDS: &[(&str, &[&str])] = &[
+    ("sh", &["-c"]),
+    ("bash", &["-c"]),
+    ("zsh", &["-c"]),
+    ("dash", &["-c"]),
+    ("python", &["-c"]),
+    ("python3", &["-c"]),
+    ("node", &["-e", "--eval", "-p", "--print"]),
+    ("perl", &["-e", "-E"]),
+    ("ruby", &["-e"]),
+];
+/// Package managers and the subcommands that install with them
+const INSTALL_COMMANDS: &[(&str, &[&str])] = &[
+    ("apt", &["install"]),
+    ("apt-get", &["install"]),
+    ("dnf", &["install"]),
+    ("yum", &["install"]),
+    ("apk", &["add"]),
+    ("pacman", &["-S", "-Sy", "-Syu"]),
+    ("brew", &["install"]),
+    ("pip", &["install"]),
+    ("pip3", &["install"]),
+    ("npm", &["install", "i", "add"]),
+    ("yarn", &["add", "install"]),
+    ("pnpm", &["add", "install", "i"]),
+    ("cargo", &["install", "add"]),
+    ("go", &["install", "get"]),
+    ("gem", &["install"]),
+];
+
+/// Classify a shell command by what it may do. Heuristic: each `;`, `&&`,
+/// `||` or `|` separated part is judged by its program and arguments.
+pub fn classify_command(command: &str) -> CommandAssessment {
+    let mut assessment = CommandAssessment::default();
+    let segments = command
+        .split(['\n', ';', '|', '&'])
+        .map(|s| s.split_whitespace().map(|w| w.trim_matches(['"', '\''])).collect::<Vec<_>>());
+
+    for words in segments {
+        // Skip `VAR=value` prefixes; look through sudo and friends
+        let mut words: &[&str] = &words;
+        while let [first, rest @ ..] = words {
+            if first.contains('=') && !first.starts_with('-') {
+                words = rest;
+            } else if PRIVILEGE_COMMANDS.contains(first) {
+                assessment.add(CommandRisk::PrivilegeEscalation, [first.to_string()]);
+                words = rest;
+                while let [flag, rest @ ..] = words
+                    && flag.starts_with('-')
+                {
+                    words = rest;
+                }
+            } else {
+                break;
+            }
+        }
+        let [program, args @ ..] = words else { continue };
+        let program = program.rsplit('/').next().unwrap_or(program);
+        let operands = || args.iter().filter(|a| !a.starts_with('-')).map(|a| a.to_string());
+
+        if DELETE_COMMANDS.contains(&program) {
+            let targets: Vec<String> = if program == "dd" {
+                args.iter().filter_map(|a| a.strip_prefix("of=")).map(String::from).collect()
+            } else {
+                operands().collect()
+            };
+            assessment.add(CommandRisk::Deletion, targets);
+        }
+        // `bash -c '...'`, `bash -lc`, `python3 -c`, `node -e`: the real command is hidden in a string
+        if let Some((_, flags)) = INLINE_CODE_COMMANDS.iter().find(|(p, _)| *p == program)
+            && let Some(flag) = args.iter().find(|a| {
+                flags.contains(a) || (flags == &["-c"] && a.starts_with('-') && !a.starts_with("--") && a.contains('c'))
+            })
+        {
+            assessment.add(CommandRisk::InlineCode, [format!("{program} {flag}")]);
+        }
+        if program == "eval" {
+            assessment.add(CommandRisk::InlineCode, ["eval".to_string()]);
+        }
+        if program.starts_with("mkfs") {
+            assessment.add(CommandRisk::Deletion, operands());
+        }
+        if program == "find" && args.contains(&"-delete") {
+            assessment.add(CommandRisk::Deletion, args.first().map(|a| a.to_string()));
+        }
+        if program == "chmod" && args.iter().any(|a| a.contains("+s") || a.ends_with("777")) {
+            assessment.add(CommandRisk::PrivilegeEscalation, operands().skip(1));
+        }
+        if program == "git" {
+            match args {
+                ["clean", ..] => assessment.add(CommandRisk::Deletion, ["untracked files".to_string()]),
+                ["reset", rest @ ..] if rest.contains(&"--hard") => {
+                    assessment.add(CommandRisk::Deletion, ["uncommitted changes".to_string()])
+                }
+                [sub, rest @ ..] if matches!(*sub, "push" | "pull" | "fetch" | "clone") => {
+                    let remote = rest.iter().find(|a| !a.starts_with('-')).map_or("origin", |v| v);
+                    assessment.add(CommandRisk::Network, [remote_host(remote)]);
+                }
+                _ => {}
+            }
+        }
+        if let Some((_, verbs)) = INSTALL_COMMANDS.iter().find(|(p, _)| *p == program)
+            && let Some(pos) = args.iter().position(|a| verbs.contains(a))
+        {
+            let packages: Vec<String> =
+                args[pos + 1..].iter().filter(|a| !a.starts_with('-')).map(|a| a.to_string()).collect();
+            let packages = if packages.is_empty() { vec!["dependencies".to_string()] } else { packages };
+            assessment.add(CommandRisk::PackageInstall, packages);
+        }
+        if program == "npx" {
+            assessment.add(CommandRisk::PackageInstall, operands().take(1));
+        }
+        if NETWORK_COMMANDS.contains(&program) {
+            let hosts: Vec<String> = operands()
+                .filter(|a| a.contains("://") || a.contains('@') || (a.contains(':') && !a.starts_with(['/', '.'])))
+                .map(|a| remote_host(&a))
+                .collect();
+            assessment.add(CommandRisk::Network, if hosts.is_empty() { vec![program.to_string()] } else { hosts });
+        }
+        // URLs passed to anything else
+        assessment.add(CommandRisk::Network, args.iter().filter(|a| a.contains("://")).map(|a| remote_host(a)));
+    }
+    assessment
+}
+
+/// Host part of a URL, `user@host:path` or similar remote spec.
+fn remote_host(spec: &str) -> String {
+    let rest = spec.split_once("://").map_or(spec, |(_, r)| r);
+    let rest = rest.split_once('@').map_or(rest, |(_, r)| r);
+    rest.split(['/', ':']).next().unwrap_or(rest).to_string()
+}
+
+/// Whether `path` stays inside the working directory once `..` is resolved.
+fn is_inside_cwd(path: &Path) -> bool {
+    let Ok(cwd) = std::env::current_dir() else { return false };
+    let mut resolved = cwd.clone();
+    for part in path.components() {
+        match part {
+            std::path::Component::ParentDir => {
+                resolved.pop();
+            }
+            std::path::Component::CurDir => {}
+            other => resolved.push(other),
+        }
+    }
+    resolved.starts_with(&cwd)
+}
+
+/// Directories the file tools are confined to, canonicalized up front so a
+/// symlink can't lead outside them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sandbox {
+    roots: Vec<PathBuf>,
+}
+
+impl Sandbox {
+    /// The working directory plus `config.allow`, or `None` when disabled.
+    pub fn from_config(config: &SandboxConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        let mut roots = vec![resolve_path(Path::new("."))];
+        for dir in &config.allow {
+            let dir = match dir.strip_prefix("~/") {
+                Some(rest) => dirs::home_dir().map(|home| home.join(rest)).unwrap_or_else(|| PathBuf::from(dir)),
+                None => PathBuf::from(dir),
+            };
+            let root = resolve_path(&dir);
+            if !roots.contains(&root) {
+                roots.push(root);
+            }
+        }
+        Some(Self { roots })
+    }
+
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        let path = resolve_path(path);
+        self.roots.iter().any(|root| path.starts_with(root))
+    }
+}
+
+/// `path` made absolute with symlinks and `..` resolved. Each existing
+/// prefix is canonicalized as it is built, so `link/..` goes where the OS
+/// would; the part that doesn't exist yet (a file about to be written) is
+/// resolved lexically.
+pub fn resolve_path(path: &Path) -> PathBuf {
+    let mut resolved = match std::env::current_dir() {
+        Ok(cwd) if path.is_relative() => cwd.canonicalize().unwrap_or(cwd),
+        _ => PathBuf::new(),
+    };
+    for part in path.components() {
+        match part {
+            std::path::Component::Prefix(_) | std::path::Component::RootDir => resolved.push(part),
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                resolved.pop();
+            }
+            std::path::Component::Normal(name) => {
+                resolved.push(name);
+                if let Ok(canonical) = resolved.canonicalize() {
+                    resolved = canonical;
+                }
+            }
         }
     }
+    resolved
+}
+
+/// Names of every built-in tool, in the order they are offered to the model.
+pub const TOOL_NAMES: &[&str] = &[
+    "read_file",
+    "write_file",
+    "list_files",
+    "search_files",
+    "execute",
+    "edit_file",
+    "edit_lines",
+    "apply_patch",
+    "delete_file",
+    "move_file",
+    "read_clipboard",
+    "screenshot",
+    "gh_issue_view",
+    "gh_pr_diff",
+    "gh_pr_comment",
+    "fetch_ticket",
+    "git_status",
+    "git_log",
+    "git_show",
+    "retrieve",
+    "web_fetch",
+    "start_edit_batch",
+    "commit_edit_batch",
+];
+
+/// Which tool definitions a model is offered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolSet {
+    /// Every tool, with full descriptions
+    Full,
+    /// File and shell tools only, described in one sentence each, for small
+    /// models that stumble over the full schema
+    Core,
+}
+
+/// The built-in tools offered in [`ToolSet::Core`].
+const CORE_TOOLS: &[&str] = &["read_file", "write_file", "list_files", "search_files", "execute", "edit_file"];
+
+/// Tool capabilities of known models, matched against the model id; the
+/// first entry that matches wins. `None` marks models whose API rejects tool
+/// definitions outright. Models not listed get their provider's default and
+/// the full set.
+const MODEL_TOOL_CAPABILITIES: &[(&str, Option<ToolSet>)] = &[
+    ("o1-mini", None),
+    ("o1-preview", None),
+    ("chatgpt-4o-latest", None),
+    ("deepseek-reasoner", None),
+    ("gpt-3.5-turbo", Some(ToolSet::Core)),
+    ("gpt-4o-mini", Some(ToolSet::Core)),
+    ("gpt-4.1-nano", Some(ToolSet::Core)),
+    ("claude-3-haiku", Some(ToolSet::Core)),
+];
+
+/// The capability table entry for `model`, if it has one. Ids are matched
+/// anywhere in the name so `openai/gpt-4o-mini` (OpenRouter) and
+/// `anthropic.claude-3-haiku-...` (Bedrock) are covered too.
+pub fn model_tool_capability(model: &str) -> Option<Option<ToolSet>> {
+    MODEL_TOOL_CAPABILITIES
+        .iter()
+        .find(|(id, _)| model.contains(id))
+        .map(|(_, set)| *set)
+}
+
+/// Names of the built-in tools in `set`.
+pub fn tool_names(set: ToolSet) -> Vec<&'static str> {
+    match set {
+        ToolSet::Full => TOOL_NAMES.to_vec(),
+        ToolSet::Core => CORE_TOOLS.to_vec(),
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -69,6 +588,21 @@ impl Tool {
 pub struct ToolResult {
     pub success: bool,
     pub output: String,
+    /// Optional image attached to the result (e.g. a screenshot), sent to the
+    /// API as an image content block alongside the text output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<ToolImage>,
+    /// Suggestions for recovering from a failure (similar paths, match
+    /// locations), sent to the model after the output.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hints: Vec<String>,
+}
+
+/// A base64-encoded image produced by a tool.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolImage {
+    pub media_type: String,
+    pub data: String,
 }
 
 impl ToolResult {
@@ -76,6 +610,8 @@ impl ToolResult {
         Self {
             success: true,
             output: output.into(),
+            image: None,
+            hints: Vec::new(),
         }
     }
 
@@ -83,7 +619,190 @@ impl ToolResult {
         Self {
             success: false,
             output: output.into(),
+            image: None,
+            hints: Vec::new(),
+        }
+    }
+
+    pub fn with_hints(mut self, hints: Vec<String>) -> Self {
+        self.hints = hints;
+        self
+    }
+
+    /// The output followed by any hints, as the model sees it.
+    pub fn model_text(&self) -> String {
+        if self.hints.is_empty() {
+            return self.output.clone();
+        }
+        let hints: Vec<String> = self.hints.iter().map(|h| format!("- {h}")).collect();
+        format!("{}\n\nHints:\n{}", self.output, hints.join("\n"))
+    }
+
+    /// Build the `content` value of an Anthropic `tool_result` block.
+    /// Plain results are sent as a string; results with an image become an
+    /// array of text and image blocks.
+    pub fn api_content(&self) -> Value {
+        match &self.image {
+            None => json!(self.model_text()),
+            Some(img) => json!([
+                { "type": "text", "text": self.model_text() },
+                {
+                    "type": "image",
+                    "source": {
+                        "type": "base64",
+                        "media_type": img.media_type,
+                        "data": img.data,
+                    }
+                }
+            ]),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tool telemetry
+// ---------------------------------------------------------------------------
+
+/// Aggregated execution metrics for a single tool across the session.
+#[derive(Debug, Clone, Default)]
+pub struct ToolStats {
+    pub calls: u32,
+    pub failures: u32,
+    pub total_duration: Duration,
+    pub max_duration: Duration,
+    pub total_output_bytes: usize,
+}
+
+impl ToolStats {
+    pub fn record(&mut self, result: &ToolResult, elapsed: Duration) {
+        self.calls += 1;
+        if !result.success {
+            self.failures += 1;
+        }
+        self.total_duration += elapsed;
+        self.max_duration = self.max_duration.max(elapsed);
+        self.total_output_bytes += result.output.len();
+    }
+
+    pub fn avg_duration(&self) -> Duration {
+        if self.calls == 0 {
+            Duration::ZERO
+        } else {
+            self.total_duration / self.calls
+        }
+    }
+
+    /// Fraction of calls that succeeded, in `0.0..=1.0`.
+    pub fn success_rate(&self) -> f64 {
+        if self.calls == 0 {
+            0.0
+        } else {
+            (self.calls - self.failures) as f64 / self.calls as f64
+        }
+    }
+}
+
+/// Compact human-readable duration: `850ms`, `2.4s`, `1m05s`.
+pub fn format_duration(d: Duration) -> String {
+    let ms = d.as_millis();
+    if ms < 1000 {
+        format!("{ms}ms")
+    } else if ms < 60_000 {
+        format!("{:.1}s", d.as_secs_f64())
+    } else {
+        let secs = d.as_secs();
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tool result cache
+// ---------------------------------------------------------------------------
+
+/// Directories walked when fingerprinting a `list_files` call; larger trees
+/// are not cached.
+const MAX_FINGERPRINT_DIRS: usize = 2000;
+
+/// Modification state of everything a cached result depends on.
+type Fingerprint = Vec<(PathBuf, Option<SystemTime>, u64)>;
+
+/// Results of read-only filesystem tools, reused while the files they looked
+/// at are unchanged on disk.
+#[derive(Debug, Default)]
+pub struct ToolCache {
+    entries: HashMap<String, (Fingerprint, ToolResult)>,
+    pub hits: u64,
+}
+
+impl ToolCache {
+    /// A cached result for `tool`, if its inputs haven't changed since.
+    pub fn get(&mut self, tool: &Tool) -> Option<ToolResult> {
+        let key = cache_key(tool)?;
+        let (fingerprint, result) = self.entries.get(&key)?;
+        if fingerprint_of(tool).as_ref() != Some(fingerprint) {
+            self.entries.remove(&key);
+            return None;
+        }
+        self.hits += 1;
+        Some(result.clone())
+    }
+
+    /// Remember a successful result of a cacheable tool.
+    pub fn insert(&mut self, tool: &Tool, result: &ToolResult) {
+        if !result.success {
+            return;
+        }
+        if let (Some(key), Some(fingerprint)) = (cache_key(tool), fingerprint_of(tool)) {
+            self.entries.insert(key, (fingerprint, result.clone()));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.hits = 0;
+    }
+}
+
+fn cache_key(tool: &Tool) -> Option<String> {
+    match tool {
+        Tool::ReadFile { .. } | Tool::ListFiles { .. } => serde_json::to_string(tool).ok(),
+        _ => None,
+    }
+}
+
+fn stat(path: &Path) -> Option<(PathBuf, Option<SystemTime>, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    Some((path.to_path_buf(), meta.modified().ok(), meta.len()))
+}
+
+/// What a cached result depends on: the file for `read_file`; every
+/// directory under the root for `list_files`, since adding or removing an
+/// entry updates its directory's mtime.
+fn fingerprint_of(tool: &Tool) -> Option<Fingerprint> {
+    match tool {
+        Tool::ReadFile { path } => Some(vec![stat(Path::new(path))?]),
+        Tool::ListFiles { path, .. } => {
+            let mut fingerprint = Vec::new();
+            let mut stack = vec![PathBuf::from(path)];
+            while let Some(dir) = stack.pop() {
+                fingerprint.push(stat(&dir)?);
+                if fingerprint.len() > MAX_FINGERPRINT_DIRS {
+                    return None;
+                }
+                for entry in fs::read_dir(&dir).ok()?.flatten() {
+                    if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                        stack.push(entry.path());
+                    }
+                }
+            }
+            fingerprint.sort();
+            Some(fingerprint)
         }
+        _ => None,
     }
 }
 
@@ -92,19 +811,29 @@ impl ToolResult {
 // ---------------------------------------------------------------------------
 
 /// Controls whether a tool may run without user confirmation.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ToolPermission {
     /// Run immediately without asking.
+    #[serde(rename = "allow")]
     AutoAllow,
     /// Prompt the user before executing.
+    #[default]
+    #[serde(rename = "ask")]
     AskFirst,
     /// Never allow execution.
+    #[serde(rename = "deny")]
     Deny,
 }
 
-impl Default for ToolPermission {
-    fn default() -> Self {
-        Self::AskFirst
+impl ToolPermission {
+    /// Whichever of the two asks more of the user.
+    pub fn stricter(self, other: Self) -> Self {
+        let rank = |p: Self| match p {
+            Self::AutoAllow => 0,
+            Self::AskFirst => 1,
+            Self::Deny => 2,
+        };
+        if rank(other) > rank(self) { other } else { self }
     }
 }
 
@@ -115,7 +844,36 @@ impl Default for ToolPermission {
 /// Maximum wall-clock time for a shell command before it is killed.
 const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(120);
 
-/// Executes tools against the local filesystem and shell.
+/// How long a command may sit silently at an input prompt before the user is
+/// asked to answer it.
+const PROMPT_STALL: Duration = Duration::from_secs(5);
+
+/// How long macOS `screencapture -W` waits for the user to click the window
+/// to capture before the screenshot is given up.
+const SCREENSHOT_PICK_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Largest screenshot we will attach; the Anthropic API rejects images over 5 MB.
+const MAX_SCREENSHOT_BYTES: usize = 5 * 1024 * 1024;
+
+/// Pull request diffs beyond this are truncated before being returned.
+const MAX_DIFF_BYTES: usize = 100 * 1024;
+
+/// Default and upper bound for `git_log` entries.
+const DEFAULT_GIT_LOG_COUNT: usize = 20;
+const MAX_GIT_LOG_COUNT: usize = 200;
+
+/// Syntax checkers taking longer than this are ignored.
+const SYNTAX_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Checker output beyond this is truncated in the tool result.
+const MAX_SYNTAX_ERROR_BYTES: usize = 4 * 1024;
+
+/// Timeout for GitHub REST requests.
+const GITHUB_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Executes tools against the local filesystem and shell. Clones share the
+/// open edit batch, so a call can run on its own task.
+#[derive(Clone)]
 pub struct ToolExecutor {
     /// Per-tool permission overrides.  Keys are tool names as returned by
     /// [`Tool::name`].  Any tool not present falls back to [`ToolPermission::AskFirst`].
@@ -123,6 +881,80 @@ pub struct ToolExecutor {
 
     /// Maximum duration for shell commands.
     command_timeout: Duration,
+
+    /// Environment policy for `execute` subprocesses.
+    env: ToolEnvConfig,
+
+    /// API base and token for the `gh_*` tools.
+    github: GithubConfig,
+
+    /// Jira or Linear connection for `fetch_ticket`, if configured.
+    tracker: Option<TrackerConfig>,
+
+    /// Embedding model for `retrieve`.
+    embeddings: EmbeddingsConfig,
+
+    /// Syntax checks run after successful file edits.
+    validate: ValidateConfig,
+
+    /// Files touched since `start_edit_batch`, while a batch is open.
+    edit_batch: Arc<Mutex<Option<EditBatch>>>,
+
+    /// Directories the file tools are confined to; `None` allows any path.
+    sandbox: Option<Sandbox>,
+
+    /// Where `execute` sends output while the command is still running.
+    output_sink: Option<OutputSink>,
+
+    /// Asks the user to answer a prompt a running command stopped at.
+    input_handler: Option<InputHandler>,
+}
+
+/// Receives a running command's stdout and stderr as they arrive.
+pub type OutputSink = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Given the prompt a command is waiting at, returns a receiver for the
+/// user's answer, or `None` if they declined.
+pub type InputHandler = Arc<dyn Fn(String) -> tokio::sync::oneshot::Receiver<Option<String>> + Send + Sync>;
+
+/// Contents of files as they were before an edit batch touched them, so a
+/// multi-file change can be undone as a whole.
+#[derive(Debug, Default)]
+pub struct EditBatch {
+    /// (path, original contents or `None` if the file did not exist)
+    originals: Vec<(PathBuf, Option<Vec<u8>>)>,
+}
+
+impl EditBatch {
+    /// Remember a file's current contents the first time the batch touches it.
+    fn snapshot(&mut self, path: &Path) {
+        if self.originals.iter().any(|(p, _)| p == path) {
+            return;
+        }
+        self.originals.push((path.to_path_buf(), fs::read(path).ok()));
+    }
+
+    /// Restore every touched file, returning the restored paths and any
+    /// failures.
+    fn rollback(self) -> (Vec<PathBuf>, Vec<String>) {
+        let mut restored = Vec::new();
+        let mut failed = Vec::new();
+        for (path, original) in self.originals.into_iter().rev() {
+            let result = match &original {
+                Some(bytes) => fs::write(&path, bytes),
+                None => match fs::remove_file(&path) {
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                    other => other,
+                },
+            };
+            match result {
+                Ok(()) => restored.push(path),
+                Err(e) => failed.push(format!("{}: {e}", path.display())),
+            }
+        }
+        restored.reverse();
+        (restored, failed)
+    }
 }
 
 impl Default for ToolExecutor {
@@ -136,20 +968,77 @@ impl ToolExecutor {
         Self {
             permissions: HashMap::new(),
             command_timeout: DEFAULT_COMMAND_TIMEOUT,
+            env: ToolEnvConfig::default(),
+            github: GithubConfig::default(),
+            tracker: None,
+            embeddings: EmbeddingsConfig::default(),
+            validate: ValidateConfig::default(),
+            edit_batch: Arc::new(Mutex::new(None)),
+            sandbox: None,
+            output_sink: None,
+            input_handler: None,
         }
     }
 
     // -- configuration helpers ------------------------------------------------
 
+    /// How long a shell command may run before it is killed.
+    pub fn command_timeout(&self) -> Duration {
+        self.command_timeout
+    }
+
+    pub fn set_env(&mut self, env: ToolEnvConfig) {
+        self.env = env;
+    }
+
+    pub fn set_github(&mut self, github: GithubConfig) {
+        self.github = github;
+    }
+
+    pub fn set_tracker(&mut self, tracker: Option<TrackerConfig>) {
+        self.tracker = tracker;
+    }
+
+    pub fn set_embeddings(&mut self, embeddings: EmbeddingsConfig) {
+        self.embeddings = embeddings;
+    }
+
+    pub fn set_sandbox(&mut self, sandbox: Option<Sandbox>) {
+        self.sandbox = sandbox;
+    }
+
+    pub fn set_output_sink(&mut self, sink: Option<OutputSink>) {
+        self.output_sink = sink;
+    }
+
+    pub fn set_input_handler(&mut self, handler: Option<InputHandler>) {
+        self.input_handler = handler;
+    }
+
+    pub fn sandbox(&self) -> Option<&Sandbox> {
+        self.sandbox.as_ref()
+    }
+
+    pub fn set_validate(&mut self, validate: ValidateConfig) {
+        self.validate = validate;
+    }
+
+    pub fn clear_permissions(&mut self) {
+        self.permissions.clear();
+    }
+
     pub fn set_permission(&mut self, tool_name: &str, perm: ToolPermission) {
         self.permissions.insert(tool_name.to_string(), perm);
     }
 
     pub fn permission(&self, tool_name: &str) -> ToolPermission {
-        self.permissions
-            .get(tool_name)
-            .copied()
-            .unwrap_or_default()
+        let own = self.permissions.get(tool_name).copied().unwrap_or_default();
+        // MCP tools (`<server>__<tool>`) are allowed one at a time; the
+        // "mcp" setting can only make all of them ask or deny
+        match self.permissions.get("mcp") {
+            Some(all) if tool_name.contains("__") => own.stricter(*all),
+            _ => own,
+        }
     }
 
     // -- execution ------------------------------------------------------------
@@ -159,26 +1048,217 @@ impl ToolExecutor {
     /// The caller is responsible for checking [`ToolPermission`] *before*
     /// calling this method.
     pub async fn execute(&self, tool: &Tool) -> ToolResult {
+        if let Some(sandbox) = &self.sandbox
+            && let Some(path) = tool.file_paths().into_iter().find(|p| !sandbox.contains(Path::new(p)))
+        {
+            let roots: Vec<String> = sandbox.roots().iter().map(|r| r.display().to_string()).collect();
+            return ToolResult::err(format!(
+                "Path outside workspace: {path} is not under {}. Only the user can allow more \
+                 directories (with /sandbox add <dir>).",
+                roots.join(", ")
+            ));
+        }
         match tool {
             Tool::ReadFile { path } => self.read_file(path),
-            Tool::WriteFile { path, content } => self.write_file(path, content),
+            Tool::WriteFile { path, content } => {
+                self.snapshot_for_batch(path);
+                let result = self.write_file(path, content);
+                self.with_syntax_check(path, result).await
+            }
             Tool::ListFiles { path, pattern } => self.list_files(path, pattern.as_deref()),
             Tool::SearchFiles { pattern, path } => self.search_files(pattern, path.as_deref()).await,
-            Tool::Execute { command } => self.execute_command(command).await,
+            Tool::Execute { command, stdin } => {
+                self.execute_command(command, stdin.as_deref()).await
+            }
             Tool::EditFile {
                 path,
                 old_text,
                 new_text,
-            } => self.edit_file(path, old_text, new_text),
+            } => {
+                self.snapshot_for_batch(path);
+                let result = self.edit_file(path, old_text, new_text);
+                self.with_syntax_check(path, result).await
+            }
+            Tool::EditLines { path, start, end, new_text } => {
+                self.snapshot_for_batch(path);
+                let result = self.edit_lines(path, *start, *end, new_text);
+                self.with_syntax_check(path, result).await
+            }
+            Tool::ApplyPatch { patch } => self.apply_patch(patch).await,
+            Tool::DeleteFile { path } => {
+                self.snapshot_for_batch(path);
+                self.delete_file(path)
+            }
+            Tool::MoveFile { from, to } => {
+                self.snapshot_for_batch(from);
+                self.snapshot_for_batch(to);
+                self.move_file(from, to)
+            }
+            Tool::ReadClipboard => self.read_clipboard(),
+            Tool::Screenshot { window } => self.screenshot(*window).await,
+            Tool::GhIssueView { repo, number } => self.gh_issue_view(repo, *number).await,
+            Tool::GhPrDiff { repo, number } => self.gh_pr_diff(repo, *number).await,
+            Tool::GhPrComment { repo, number, body } => {
+                self.gh_pr_comment(repo, *number, body).await
+            }
+            Tool::FetchTicket { key } => self.fetch_ticket(key).await,
+            Tool::GitStatus { path } => self.git_status(path.as_deref()).await,
+            Tool::GitLog { path, max_count } => self.git_log(path.as_deref(), *max_count).await,
+            Tool::GitShow { rev, path } => self.git_show(rev, path.as_deref()).await,
+            Tool::Retrieve { query, k } => {
+                match rag::retrieve(&self.embeddings, query, k.unwrap_or(5).clamp(1, 20)).await {
+                    Ok(text) => ToolResult::ok(text),
+                    Err(e) => ToolResult::err(e.to_string()),
+                }
+            }
+            Tool::WebFetch { url, max_chars } => {
+                let max_chars = max_chars.unwrap_or(crate::web::DEFAULT_MAX_CHARS).clamp(1, crate::web::MAX_CHARS);
+                match crate::web::fetch(url, max_chars).await {
+                    Ok(text) if text.trim().is_empty() => ToolResult::ok(format!("(no readable text at {url})")),
+                    Ok(text) => ToolResult::ok(text),
+                    Err(e) => ToolResult::err(e),
+                }
+            }
+            Tool::StartEditBatch => self.start_edit_batch(),
+            Tool::CommitEditBatch => self.commit_edit_batch(),
+            Tool::Mcp { server, tool, input } => crate::mcp::call(server, tool, input).await,
+        }
+    }
+
+    // -- syntax checks --------------------------------------------------------
+
+    /// Append syntax errors in the edited file to a successful edit's output,
+    /// so the model sees a broken edit right away. The edit itself stands.
+    async fn with_syntax_check(&self, path: &str, mut result: ToolResult) -> ToolResult {
+        if !result.success || !self.validate.enabled {
+            return result;
+        }
+        if let Some(errors) = self.syntax_errors(Path::new(path)).await {
+            result.output.push_str(&format!(
+                "\n\nSyntax check failed -- the file was written but does not parse:\n{errors}"
+            ));
+        }
+        result
+    }
+
+    /// Parse errors in `path`, or `None` when it parses or no checker applies
+    /// (unknown extension, checker not installed, or checker timed out).
+    async fn syntax_errors(&self, path: &Path) -> Option<String> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "json" if !self.validate.commands.contains_key("json") => {
+                let text = fs::read_to_string(path).ok()?;
+                return serde_json::from_str::<Value>(&text).err().map(|e| e.to_string());
+            }
+            "toml" if !self.validate.commands.contains_key("toml") => {
+                let text = fs::read_to_string(path).ok()?;
+                return toml::from_str::<toml::Table>(&text).err().map(|e| e.to_string());
+            }
+            _ => {}
+        }
+
+        let argv = self.validate.command_for(&ext)?;
+        let path_arg = path.display().to_string();
+        let mut cmd = TokioCommand::new(&argv[0]);
+        cmd.args(argv[1..].iter().map(|a| a.replace("{path}", &path_arg)))
+            .stdin(std::process::Stdio::null())
+            .kill_on_drop(true);
+        let output = tokio::time::timeout(SYNTAX_CHECK_TIMEOUT, cmd.output()).await.ok()?.ok()?;
+        if output.status.success() {
+            return None;
+        }
+        let mut errors = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        if errors.is_empty() {
+            errors = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        }
+        if errors.len() > MAX_SYNTAX_ERROR_BYTES {
+            let mut end = MAX_SYNTAX_ERROR_BYTES;
+            while !errors.is_char_boundary(end) {
+                end -= 1;
+            }
+            errors.truncate(end);
+            errors.push_str("\n... (truncated)");
+        }
+        Some(format!("$ {}\n{errors}", argv[0]))
+    }
+
+    // -- edit batches ---------------------------------------------------------
+
+    pub fn edit_batch_open(&self) -> bool {
+        self.edit_batch.lock().unwrap().is_some()
+    }
+
+    /// Drop the open batch without restoring anything, so files written in a
+    /// finished or abandoned turn are never rolled back by a later failure.
+    pub fn close_edit_batch(&self) {
+        *self.edit_batch.lock().unwrap() = None;
+    }
+
+    fn snapshot_for_batch(&self, path: &str) {
+        if let Some(batch) = self.edit_batch.lock().unwrap().as_mut() {
+            batch.snapshot(Path::new(path));
+        }
+    }
+
+    fn start_edit_batch(&self) -> ToolResult {
+        let mut batch = self.edit_batch.lock().unwrap();
+        if batch.is_some() {
+            return ToolResult::err("An edit batch is already open; call commit_edit_batch first");
+        }
+        *batch = Some(EditBatch::default());
+        ToolResult::ok(
+            "Edit batch started. If any write_file or edit_file fails before \
+             commit_edit_batch, every file changed in the batch is restored.",
+        )
+    }
+
+    fn commit_edit_batch(&self) -> ToolResult {
+        match self.edit_batch.lock().unwrap().take() {
+            Some(batch) if batch.originals.is_empty() => {
+                ToolResult::ok("Committed edit batch (no files changed)")
+            }
+            Some(batch) => {
+                let files: Vec<String> = batch
+                    .originals
+                    .iter()
+                    .map(|(p, _)| p.display().to_string())
+                    .collect();
+                ToolResult::ok(format!(
+                    "Committed edit batch of {} file(s): {}",
+                    files.len(),
+                    files.join(", ")
+                ))
+            }
+            None => ToolResult::err("No edit batch is open"),
         }
     }
 
+    /// Close the open batch and restore every file it touched. Returns the
+    /// restored paths and a note for the model, or `None` without a batch.
+    pub fn rollback_edit_batch(&self) -> Option<(Vec<PathBuf>, String)> {
+        let batch = self.edit_batch.lock().unwrap().take()?;
+        let (restored, failed) = batch.rollback();
+        let mut note = if restored.is_empty() {
+            "Edit batch rolled back; no files had been changed yet.".to_string()
+        } else {
+            format!(
+                "Edit batch rolled back: restored {}. Re-read files before retrying.",
+                restored.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(", ")
+            )
+        };
+        if !failed.is_empty() {
+            note.push_str(&format!(" Could not restore: {}", failed.join("; ")));
+        }
+        Some((restored, note))
+    }
+
     // -- individual tool implementations --------------------------------------
 
     fn read_file(&self, path: &str) -> ToolResult {
         let path = Path::new(path);
         if !path.exists() {
-            return ToolResult::err(format!("File not found: {}", path.display()));
+            return ToolResult::err(format!("File not found: {}", path.display()))
+                .with_hints(missing_path_hints(path));
         }
         match fs::read_to_string(path) {
             Ok(contents) => {
@@ -198,15 +1278,14 @@ impl ToolExecutor {
         let path = Path::new(path);
 
         // Create parent directories if they don't exist.
-        if let Some(parent) = path.parent() {
-            if !parent.exists() {
-                if let Err(e) = fs::create_dir_all(parent) {
-                    return ToolResult::err(format!(
-                        "Failed to create directory {}: {e}",
-                        parent.display()
-                    ));
-                }
-            }
+        if let Some(parent) = path.parent()
+            && !parent.exists()
+            && let Err(e) = fs::create_dir_all(parent)
+        {
+            return ToolResult::err(format!(
+                "Failed to create directory {}: {e}",
+                parent.display()
+            ));
         }
 
         let mut file = match fs::File::create(path) {
@@ -229,7 +1308,8 @@ impl ToolExecutor {
     fn list_files(&self, path: &str, pattern: Option<&str>) -> ToolResult {
         let base = PathBuf::from(path);
         if !base.exists() {
-            return ToolResult::err(format!("Directory not found: {}", base.display()));
+            return ToolResult::err(format!("Directory not found: {}", base.display()))
+                .with_hints(missing_path_hints(&base));
         }
 
         let glob_pattern = match pattern {
@@ -308,68 +1388,169 @@ impl ToolExecutor {
         }
     }
 
-    async fn execute_command(&self, command: &str) -> ToolResult {
+    async fn execute_command(&self, command: &str, stdin: Option<&str>) -> ToolResult {
         use std::process::Stdio;
-
-        let child = match TokioCommand::new("sh")
-            .arg("-c")
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut std_cmd = Command::new("sh");
+        self.env.apply(&mut std_cmd);
+        let mut cmd = TokioCommand::from(std_cmd);
+        // Kept open when there is a user to answer prompts
+        let interactive = stdin.is_none() && self.input_handler.is_some();
+        cmd.arg("-c")
             .arg(command)
+            .stdin(if stdin.is_some() || interactive { Stdio::piped() } else { Stdio::null() })
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
-            .kill_on_drop(true)
-            .spawn()
-        {
+            .kill_on_drop(true);
+        detach_from_terminal(&mut cmd);
+
+        let mut child = match cmd.spawn() {
             Ok(c) => c,
             Err(e) => return ToolResult::err(format!("Failed to spawn command: {e}")),
         };
 
-        // Wait with async timeout — yields to the tokio runtime instead of
-        // blocking with thread::sleep.  `kill_on_drop(true)` ensures the child
-        // is killed if the future is cancelled (e.g. on timeout).
-        let result = tokio::time::timeout(self.command_timeout, child.wait_with_output()).await;
-
-        match result {
-            Ok(Ok(output)) => {
-                let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-                let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-                let code = output.status.code().unwrap_or(-1);
-
-                let mut combined = String::new();
-                if !stdout.is_empty() {
-                    combined.push_str(&stdout);
-                }
-                if !stderr.is_empty() {
-                    if !combined.is_empty() {
-                        combined.push('\n');
+        if let Some(text) = stdin
+            && let Some(mut pipe) = child.stdin.take()
+        {
+            let text = text.to_string();
+            // Dropping the pipe afterwards closes stdin so the command sees EOF.
+            tokio::spawn(async move {
+                let _ = pipe.write_all(text.as_bytes()).await;
+            });
+        }
+        let mut input = if interactive { child.stdin.take() } else { None };
+
+        // Forward both pipes through one channel so we can watch for output
+        // stalls while the command runs.
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<(bool, Vec<u8>)>();
+        if let Some(mut out) = child.stdout.take() {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 4096];
+                while let Ok(n) = out.read(&mut buf).await {
+                    if n == 0 || tx.send((false, buf[..n].to_vec())).is_err() {
+                        break;
                     }
-                    combined.push_str("[stderr]\n");
-                    combined.push_str(&stderr);
                 }
-                if combined.is_empty() {
-                    combined = "(no output)".to_string();
+            });
+        }
+        if let Some(mut err) = child.stderr.take() {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 4096];
+                while let Ok(n) = err.read(&mut buf).await {
+                    if n == 0 || tx.send((true, buf[..n].to_vec())).is_err() {
+                        break;
+                    }
                 }
-
-                if output.status.success() {
-                    ToolResult::ok(combined)
-                } else {
-                    ToolResult::err(format!("Exit code {code}\n{combined}"))
+            });
+        }
+        drop(tx);
+
+        let mut deadline = tokio::time::Instant::now() + self.command_timeout;
+        let timed_out = || {
+            ToolResult::err(format!(
+                "Command timed out after {} seconds",
+                self.command_timeout.as_secs()
+            ))
+            .with_hints(vec![
+                "If the command waits for input, pass the answers with stdin".into(),
+                "Run long jobs in the background (`cmd > /tmp/out.log 2>&1 &`) and read the log".into(),
+            ])
+        };
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        // The current unterminated output line, which is where prompts sit.
+        let mut tail = String::new();
+
+        // `kill_on_drop(true)` ensures the child is killed on every early return.
+        loop {
+            tokio::select! {
+                chunk = rx.recv() => match chunk {
+                    Some((is_stderr, bytes)) => {
+                        let text = String::from_utf8_lossy(&bytes);
+                        if let Some(sink) = &self.output_sink {
+                            sink(&text);
+                        }
+                        tail.push_str(&text);
+                        if let Some(pos) = tail.rfind('\n') {
+                            tail.drain(..=pos);
+                        }
+                        if is_stderr { stderr.extend(bytes) } else { stdout.extend(bytes) }
+                    }
+                    None => break,
+                },
+                _ = tokio::time::sleep_until(deadline) => return timed_out(),
+                _ = tokio::time::sleep(PROMPT_STALL) => {
+                    let Some(prompt) = detect_prompt(&tail, input.is_some()).map(str::to_string) else {
+                        // Quiet but not prompting: let anything reading stdin see EOF
+                        input = None;
+                        continue;
+                    };
+                    let (Some(pipe), Some(ask)) = (input.as_mut(), &self.input_handler) else {
+                        return ToolResult::err(format!(
+                            "Command is waiting for input at prompt: {prompt}\n\
+                             There is no user to answer it; re-run with a non-interactive flag.\n{}",
+                            String::from_utf8_lossy(&stdout)
+                        ));
+                    };
+                    // The user's time to answer doesn't count against the timeout
+                    let asked = tokio::time::Instant::now();
+                    let answer = ask(prompt.clone()).await.ok().flatten();
+                    deadline += asked.elapsed();
+                    let Some(answer) = answer else {
+                        return ToolResult::err(format!(
+                            "Command was waiting for input at prompt: {prompt}\n\
+                             The user declined to answer; do not supply it yourself.\n{}",
+                            String::from_utf8_lossy(&stdout)
+                        ));
+                    };
+                    if pipe.write_all(format!("{answer}\n").as_bytes()).await.is_err() {
+                        input = None;
+                    }
+                    tail.clear();
                 }
             }
-            Ok(Err(e)) => ToolResult::err(format!("Error waiting for process: {e}")),
-            Err(_) => {
-                // Timeout elapsed — child is killed automatically via kill_on_drop.
-                ToolResult::err(format!(
-                    "Command timed out after {} seconds",
-                    self.command_timeout.as_secs()
-                ))
+        }
+
+        let status = match tokio::time::timeout_at(deadline, child.wait()).await {
+            Ok(Ok(status)) => status,
+            Ok(Err(e)) => return ToolResult::err(format!("Error waiting for process: {e}")),
+            Err(_) => return timed_out(),
+        };
+
+        let stdout = String::from_utf8_lossy(&stdout).to_string();
+        let stderr = String::from_utf8_lossy(&stderr).to_string();
+        let code = status.code().unwrap_or(-1);
+
+        let mut combined = String::new();
+        if !stdout.is_empty() {
+            combined.push_str(&stdout);
+        }
+        if !stderr.is_empty() {
+            if !combined.is_empty() {
+                combined.push('\n');
             }
+            combined.push_str("[stderr]\n");
+            combined.push_str(&stderr);
+        }
+        if combined.is_empty() {
+            combined = "(no output)".to_string();
+        }
+
+        if status.success() {
+            ToolResult::ok(combined)
+        } else {
+            ToolResult::err(format!("Exit code {code}\n{combined}"))
         }
     }
 
     fn edit_file(&self, path: &str, old_text: &str, new_text: &str) -> ToolResult {
         let file_path = Path::new(path);
         if !file_path.exists() {
-            return ToolResult::err(format!("File not found: {}", file_path.display()));
+            return ToolResult::err(format!("File not found: {}", file_path.display()))
+                .with_hints(missing_path_hints(file_path));
         }
 
         let contents = match fs::read_to_string(file_path) {
@@ -384,24 +1565,508 @@ impl ToolExecutor {
             return ToolResult::err(format!(
                 "old_text not found in {}",
                 file_path.display()
-            ));
+            ))
+            .with_hints(old_text_hints(&contents, old_text));
         }
         if count > 1 {
+            let lines: Vec<String> = contents
+                .match_indices(old_text)
+                .map(|(i, _)| (contents[..i].matches('\n').count() + 1).to_string())
+                .collect();
             return ToolResult::err(format!(
                 "old_text matches {count} locations in {} -- provide more context to make it unique",
                 file_path.display()
+            ))
+            .with_hints(vec![
+                format!("Matches start at lines {}", lines.join(", ")),
+                "Include more surrounding lines in old_text, or use edit_lines with the line numbers".into(),
+            ]);
+        }
+
+        let new_contents = contents.replacen(old_text, new_text, 1);
+        match fs::write(file_path, &new_contents) {
+            Ok(()) => ToolResult::ok(format!(
+                "Applied edit to {} (replaced 1 occurrence)",
+                file_path.display()
+            )),
+            Err(e) => ToolResult::err(format!("Failed to write {}: {e}", file_path.display())),
+        }
+    }
+
+    fn edit_lines(&self, path: &str, start: usize, end: usize, new_text: &str) -> ToolResult {
+        let file_path = Path::new(path);
+        let contents = match fs::read_to_string(file_path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return ToolResult::err(format!("File not found: {}", file_path.display()))
+                    .with_hints(missing_path_hints(file_path));
+            }
+            Err(e) => {
+                return ToolResult::err(format!("Failed to read {}: {e}", file_path.display()));
+            }
+        };
+
+        let new_contents = match replace_lines(&contents, start, end, new_text) {
+            Ok(c) => c,
+            Err(e) => return ToolResult::err(format!("{e} in {}", file_path.display())),
+        };
+        let added = new_text.lines().count();
+        let summary = if end + 1 == start {
+            format!("Inserted {added} line(s) before line {start} of {}", file_path.display())
+        } else {
+            format!("Replaced lines {start}-{end} of {} with {added} line(s)", file_path.display())
+        };
+        match fs::write(file_path, &new_contents) {
+            Ok(()) => ToolResult::ok(summary),
+            Err(e) => ToolResult::err(format!("Failed to write {}: {e}", file_path.display())),
+        }
+    }
+
+    /// Apply a unified diff. Every hunk of every file is checked against the
+    /// current contents first and nothing is written unless all of them
+    /// match; a failed write part way through restores the files already
+    /// written.
+    async fn apply_patch(&self, patch: &str) -> ToolResult {
+        let files = match crate::patch::parse(patch) {
+            Ok(files) => files,
+            Err(e) => return ToolResult::err(format!("Invalid patch: {e}")),
+        };
+
+        let mut report = Vec::new();
+        let mut failed = false;
+        // (path written, new contents or `None` to delete, path removed by a rename)
+        let mut writes: Vec<(String, Option<String>, Option<String>)> = Vec::new();
+        for file in &files {
+            let path = file.path().to_string();
+            let original = match &file.old_path {
+                Some(old) => match fs::read_to_string(old) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        failed = true;
+                        report.push(format!("{old}: can't read: {e}"));
+                        continue;
+                    }
+                },
+                None if Path::new(&path).exists() => {
+                    failed = true;
+                    report.push(format!("{path}: patch creates it, but it already exists"));
+                    continue;
+                }
+                None => String::new(),
+            };
+            let (contents, outcomes) = crate::patch::apply(&original, &file.hunks);
+            report.push(path.clone());
+            for (hunk, outcome) in file.hunks.iter().zip(&outcomes) {
+                let status = match outcome {
+                    Ok(0) => "applied".to_string(),
+                    Ok(shift) => format!("applied at offset {shift:+}"),
+                    Err(e) => format!("FAILED: {e}"),
+                };
+                report.push(format!("  {}  {status}", hunk.header));
+            }
+            match contents {
+                Some(_) if file.new_path.is_none() => writes.push((file.old_path.clone().unwrap_or_default(), None, None)),
+                Some(contents) => {
+                    let renamed = file.old_path.clone().filter(|old| *old != path);
+                    writes.push((path, Some(contents), renamed));
+                }
+                None => failed = true,
+            }
+        }
+        let report = report.join("\n");
+        if failed {
+            return ToolResult::err(format!("{report}\n\nPatch not applied; no files were changed."));
+        }
+
+        let mut undo = EditBatch::default();
+        for (path, contents, renamed) in &writes {
+            self.snapshot_for_batch(path);
+            undo.snapshot(Path::new(path));
+            if let Some(old) = renamed {
+                self.snapshot_for_batch(old);
+                undo.snapshot(Path::new(old));
+            }
+            let written = match contents {
+                Some(contents) => {
+                    let parent = Path::new(path).parent().filter(|p| !p.as_os_str().is_empty());
+                    parent.map_or(Ok(()), fs::create_dir_all).and_then(|_| fs::write(path, contents))
+                }
+                None => fs::remove_file(path),
+            };
+            let result = written.and_then(|_| renamed.as_ref().map_or(Ok(()), fs::remove_file));
+            if let Err(e) = result {
+                let (_, undo_failed) = undo.rollback();
+                let mut message = format!("{report}\n\nFailed to write {path}: {e}; the patch was rolled back.");
+                if !undo_failed.is_empty() {
+                    message.push_str(&format!("\nCould not restore: {}", undo_failed.join(", ")));
+                }
+                return ToolResult::err(message);
+            }
+        }
+
+        let hunks: usize = files.iter().map(|f| f.hunks.len()).sum();
+        let mut result = ToolResult::ok(format!("Applied {hunks} hunk(s) to {} file(s):\n{report}", writes.len()));
+        for (path, contents, _) in &writes {
+            if contents.is_some() {
+                result = self.with_syntax_check(path, result).await;
+            }
+        }
+        result
+    }
+
+    fn delete_file(&self, path: &str) -> ToolResult {
+        let path = Path::new(path);
+        match fs::symlink_metadata(path) {
+            Ok(meta) if meta.is_dir() => {
+                return ToolResult::err(format!("{} is a directory; delete_file only removes files", path.display()));
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return ToolResult::err(format!("File not found: {}", path.display()))
+                    .with_hints(missing_path_hints(path));
+            }
+            Err(e) => return ToolResult::err(format!("Failed to read {}: {e}", path.display())),
+        }
+        match fs::remove_file(path) {
+            Ok(()) => ToolResult::ok(format!("Deleted {}", path.display())),
+            Err(e) => ToolResult::err(format!("Failed to delete {}: {e}", path.display())),
+        }
+    }
+
+    /// Move or rename a file. An existing destination is never overwritten.
+    fn move_file(&self, from: &str, to: &str) -> ToolResult {
+        let (from, to) = (Path::new(from), Path::new(to));
+        match fs::symlink_metadata(from) {
+            Ok(meta) if meta.is_dir() => {
+                return ToolResult::err(format!("{} is a directory; move_file only moves files", from.display()));
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return ToolResult::err(format!("File not found: {}", from.display()))
+                    .with_hints(missing_path_hints(from));
+            }
+            Err(e) => return ToolResult::err(format!("Failed to read {}: {e}", from.display())),
+        }
+        if to.exists() {
+            return ToolResult::err(format!("{} already exists; delete it first to replace it", to.display()));
+        }
+        if let Some(parent) = to.parent()
+            && !parent.as_os_str().is_empty()
+            && !parent.exists()
+            && let Err(e) = fs::create_dir_all(parent)
+        {
+            return ToolResult::err(format!("Failed to create directory {}: {e}", parent.display()));
+        }
+        // rename can't cross filesystems; fall back to copying
+        let moved = fs::rename(from, to).or_else(|_| fs::copy(from, to).and_then(|_| fs::remove_file(from)));
+        match moved {
+            Ok(()) => ToolResult::ok(format!("Moved {} to {}", from.display(), to.display())),
+            Err(e) => ToolResult::err(format!("Failed to move {} to {}: {e}", from.display(), to.display())),
+        }
+    }
+
+    fn read_clipboard(&self) -> ToolResult {
+        let mut clipboard = match arboard::Clipboard::new() {
+            Ok(c) => c,
+            Err(e) => return ToolResult::err(format!("Failed to access clipboard: {e}")),
+        };
+        match clipboard.get_text() {
+            Ok(text) if text.is_empty() => ToolResult::ok("(clipboard is empty)"),
+            Ok(text) => ToolResult::ok(text),
+            Err(e) => ToolResult::err(format!("Failed to read clipboard: {e}")),
+        }
+    }
+
+    async fn screenshot(&self, window: bool) -> ToolResult {
+        let out_path = std::env::temp_dir().join(format!(
+            "pro-chat-screenshot-{}.png",
+            uuid::Uuid::new_v4()
+        ));
+        let out = out_path.display().to_string();
+
+        let (program, args) = match screenshot_command(window, &out) {
+            Some(cmd) => cmd,
+            None => {
+                return ToolResult::err(
+                    "No screenshot utility found (tried screencapture, grim, gnome-screenshot, scrot, import)",
+                );
+            }
+        };
+
+        // There is no non-interactive way to name the focused window on macOS
+        let picks_window = window && cfg!(target_os = "macos");
+        let timeout = if picks_window { SCREENSHOT_PICK_TIMEOUT } else { self.command_timeout };
+        let status = tokio::time::timeout(
+            timeout,
+            TokioCommand::new(program).args(&args).kill_on_drop(true).status(),
+        )
+        .await;
+        match status {
+            Ok(Ok(s)) if s.success() => {}
+            Ok(Ok(s)) => {
+                let _ = fs::remove_file(&out_path);
+                return ToolResult::err(format!(
+                    "{program} exited with code {}",
+                    s.code().unwrap_or(-1)
+                ));
+            }
+            Ok(Err(e)) => return ToolResult::err(format!("Failed to run {program}: {e}")),
+            Err(_) if picks_window => {
+                let _ = fs::remove_file(&out_path);
+                return ToolResult::err(format!(
+                    "No window was clicked within {} seconds; on macOS the user picks the window to capture. \
+                     Capture the whole screen with window: false instead",
+                    SCREENSHOT_PICK_TIMEOUT.as_secs()
+                ));
+            }
+            Err(_) => {
+                let _ = fs::remove_file(&out_path);
+                return ToolResult::err(format!("{program} timed out"));
+            }
+        }
+
+        let bytes = match fs::read(&out_path) {
+            Ok(b) => b,
+            Err(e) => return ToolResult::err(format!("Failed to read screenshot: {e}")),
+        };
+        let _ = fs::remove_file(&out_path);
+
+        if bytes.len() > MAX_SCREENSHOT_BYTES {
+            return ToolResult::err(format!(
+                "Screenshot is {} KB, over the {} KB image limit",
+                bytes.len() / 1024,
+                MAX_SCREENSHOT_BYTES / 1024
             ));
         }
 
-        let new_contents = contents.replacen(old_text, new_text, 1);
-        match fs::write(file_path, &new_contents) {
-            Ok(()) => ToolResult::ok(format!(
-                "Applied edit to {} (replaced 1 occurrence)",
-                file_path.display()
-            )),
-            Err(e) => ToolResult::err(format!("Failed to write {}: {e}", file_path.display())),
-        }
+        use base64::Engine;
+        let target = if window { "active window" } else { "screen" };
+        let mut result = ToolResult::ok(format!(
+            "Captured {target} with {program} ({} KB PNG)",
+            bytes.len() / 1024
+        ));
+        result.image = Some(ToolImage {
+            media_type: "image/png".into(),
+            data: base64::engine::general_purpose::STANDARD.encode(&bytes),
+        });
+        result
+    }
+
+    /// Send a GitHub REST request, returning the response body on success.
+    async fn github_request(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        accept: &str,
+        body: Option<Value>,
+    ) -> Result<String, String> {
+        let client = crate::api::http_client(GITHUB_TIMEOUT).map_err(|e| e.to_string())?;
+        let url = format!("{}{path}", self.github.api_url.trim_end_matches('/'));
+        let mut request = client
+            .request(method, &url)
+            .header("accept", accept)
+            .header("user-agent", "pro-chat")
+            .header("x-github-api-version", "2022-11-28");
+        if let Some(token) = self.github.token() {
+            request = request.bearer_auth(token);
+        }
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+
+        let response = request.send().await.map_err(|e| format!("GitHub request failed: {e}"))?;
+        let status = response.status();
+        let text = response.text().await.map_err(|e| format!("GitHub request failed: {e}"))?;
+        if status.is_success() {
+            Ok(text)
+        } else {
+            let message = serde_json::from_str::<Value>(&text)
+                .ok()
+                .and_then(|v| v["message"].as_str().map(String::from))
+                .unwrap_or(text);
+            Err(format!("GitHub API error ({status}): {message}"))
+        }
+    }
+
+    async fn gh_issue_view(&self, repo: &str, number: u64) -> ToolResult {
+        if !is_github_repo(repo) {
+            return ToolResult::err(format!("Not a GitHub repository: {repo} (expected owner/name)"));
+        }
+        let json = "application/vnd.github+json";
+        let path = format!("/repos/{repo}/issues/{number}");
+        let issue = match self.github_request(reqwest::Method::GET, &path, json, None).await {
+            Ok(body) => body,
+            Err(e) => return ToolResult::err(e),
+        };
+        let path = format!("{path}/comments?per_page=100");
+        let comments = match self.github_request(reqwest::Method::GET, &path, json, None).await {
+            Ok(body) => body,
+            Err(e) => return ToolResult::err(e),
+        };
+        match (serde_json::from_str(&issue), serde_json::from_str(&comments)) {
+            (Ok(issue), Ok(comments)) => ToolResult::ok(format_github_issue(&issue, &comments)),
+            _ => ToolResult::err("Unexpected response from the GitHub API"),
+        }
+    }
+
+    async fn gh_pr_diff(&self, repo: &str, number: u64) -> ToolResult {
+        if !is_github_repo(repo) {
+            return ToolResult::err(format!("Not a GitHub repository: {repo} (expected owner/name)"));
+        }
+        let path = format!("/repos/{repo}/pulls/{number}");
+        match self.github_request(reqwest::Method::GET, &path, "application/vnd.github.diff", None).await {
+            Ok(diff) if diff.is_empty() => ToolResult::ok("(empty diff)"),
+            Ok(diff) => ToolResult::ok(truncate_diff(diff)),
+            Err(e) => ToolResult::err(e),
+        }
+    }
+
+    async fn gh_pr_comment(&self, repo: &str, number: u64, body: &str) -> ToolResult {
+        if !is_github_repo(repo) {
+            return ToolResult::err(format!("Not a GitHub repository: {repo} (expected owner/name)"));
+        }
+        if self.github.token().is_none() {
+            return ToolResult::err("No GitHub token: set github.token in config or GITHUB_TOKEN");
+        }
+        let path = format!("/repos/{repo}/issues/{number}/comments");
+        let payload = json!({ "body": body });
+        match self.github_request(reqwest::Method::POST, &path, "application/vnd.github+json", Some(payload)).await {
+            Ok(response) => {
+                let url = serde_json::from_str::<Value>(&response)
+                    .ok()
+                    .and_then(|v| v["html_url"].as_str().map(String::from))
+                    .unwrap_or_default();
+                ToolResult::ok(format!("Comment posted on {repo}#{number} {url}").trim_end().to_string())
+            }
+            Err(e) => ToolResult::err(e),
+        }
+    }
+
+    // -- git ----------------------------------------------------------------
+
+    /// Run a read-only git command in the working directory. Colors, pagers
+    /// and external diff drivers are off, and no index lock is taken so a
+    /// `git status` never gets in the way of the user's own git commands.
+    async fn git(&self, args: &[&str]) -> Result<String, String> {
+        let output = TokioCommand::new("git")
+            .args(["--no-pager", "-c", "color.ui=never", "-c", "core.quotepath=off"])
+            .args(args)
+            .env("GIT_OPTIONAL_LOCKS", "0")
+            .output()
+            .await
+            .map_err(|e| format!("Failed to run git: {e}"))?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        } else {
+            Err(format!("git {} failed: {}", args[0], String::from_utf8_lossy(&output.stderr).trim()))
+        }
+    }
+
+    async fn git_status(&self, path: Option<&str>) -> ToolResult {
+        let mut args = vec!["status", "--short", "--branch"];
+        args.extend(path.iter().flat_map(|p| ["--", p]));
+        match self.git(&args).await {
+            Ok(out) if out.lines().count() <= 1 => ToolResult::ok(format!("{}(working tree clean)", out)),
+            Ok(out) => ToolResult::ok(out),
+            Err(e) => ToolResult::err(e),
+        }
+    }
+
+    async fn git_log(&self, path: Option<&str>, max_count: Option<usize>) -> ToolResult {
+        let count = max_count.unwrap_or(DEFAULT_GIT_LOG_COUNT).clamp(1, MAX_GIT_LOG_COUNT).to_string();
+        let mut args = vec!["log", "--date=short", "--format=%h %ad %an%d  %s", "-n", &count];
+        args.extend(path.iter().flat_map(|p| ["--", p]));
+        match self.git(&args).await {
+            Ok(out) if out.is_empty() => ToolResult::ok("No commits found."),
+            Ok(out) => ToolResult::ok(out),
+            Err(e) => ToolResult::err(e),
+        }
+    }
+
+    async fn git_show(&self, rev: &str, path: Option<&str>) -> ToolResult {
+        // A leading dash would be read as an option, e.g. --output=<file>
+        if rev.trim().is_empty() || rev.starts_with('-') {
+            return ToolResult::err(format!("Not a revision: {rev:?}"));
+        }
+        let mut args = vec!["show", "--stat", "--patch", "--no-ext-diff", "--no-textconv", rev];
+        args.extend(path.iter().flat_map(|p| ["--", p]));
+        match self.git(&args).await {
+            Ok(out) => ToolResult::ok(truncate_diff(out)),
+            Err(e) => ToolResult::err(e),
+        }
+    }
+
+    async fn fetch_ticket(&self, key: &str) -> ToolResult {
+        let Some(config) = &self.tracker else {
+            return ToolResult::err("No issue tracker configured (add a [tracker] section to config.toml)");
+        };
+        match tracker::fetch(config, key).await {
+            Ok(ticket) => ToolResult::ok(ticket.render()),
+            Err(e) => ToolResult::err(e),
+        }
+    }
+}
+
+/// Whether `repo` is a plain `owner/name`, so a model-supplied value can't
+/// reach another API path through `..`, extra slashes or a query string.
+fn is_github_repo(repo: &str) -> bool {
+    let Some((owner, name)) = repo.split_once('/') else { return false };
+    [owner, name].iter().all(|part| {
+        !part.is_empty()
+            && !matches!(*part, "." | "..")
+            && part.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+    })
+}
+
+/// Cut a diff to [`MAX_DIFF_BYTES`], noting how much was left out.
+fn truncate_diff(diff: String) -> String {
+    if diff.len() <= MAX_DIFF_BYTES {
+        return diff;
+    }
+    let mut end = MAX_DIFF_BYTES;
+    while !diff.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!(
+        "{}\n... (diff truncated at {} KB of {} KB)",
+        &diff[..end],
+        MAX_DIFF_BYTES / 1024,
+        diff.len() / 1024
+    )
+}
+
+/// Render an issue (or pull request) and its comments as plain text.
+fn format_github_issue(issue: &Value, comments: &Value) -> String {
+    let login = |v: &Value| v["user"]["login"].as_str().unwrap_or("ghost").to_string();
+    let kind = if issue.get("pull_request").is_some() { "Pull request" } else { "Issue" };
+    let mut out = format!(
+        "{kind} #{}: {}\nState: {}  Author: {}",
+        issue["number"],
+        issue["title"].as_str().unwrap_or(""),
+        issue["state"].as_str().unwrap_or("unknown"),
+        login(issue)
+    );
+    let labels: Vec<&str> = issue["labels"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|l| l["name"].as_str())
+        .collect();
+    if !labels.is_empty() {
+        out.push_str(&format!("  Labels: {}", labels.join(", ")));
     }
+    out.push_str("\n\n");
+    out.push_str(issue["body"].as_str().unwrap_or("(no description)").trim());
+
+    for comment in comments.as_array().into_iter().flatten() {
+        out.push_str(&format!(
+            "\n\n--- {} at {} ---\n{}",
+            login(comment),
+            comment["created_at"].as_str().unwrap_or(""),
+            comment["body"].as_str().unwrap_or("").trim()
+        ));
+    }
+    out
 }
 
 // ---------------------------------------------------------------------------
@@ -513,7 +2178,11 @@ pub fn parse_tool_calls(response: &Value) -> Vec<ToolCall> {
                     .and_then(|v| v.as_str())
                     .unwrap_or("")
                     .to_string();
-                Tool::Execute { command }
+                let stdin = input
+                    .get("stdin")
+                    .and_then(|v| v.as_str())
+                    .map(String::from);
+                Tool::Execute { command, stdin }
             }
             "edit_file" => {
                 let path = input
@@ -537,7 +2206,126 @@ pub fn parse_tool_calls(response: &Value) -> Vec<ToolCall> {
                     new_text,
                 }
             }
-            _ => continue, // unknown tool -- skip
+            "edit_lines" => {
+                let line = |key: &str| input.get(key).and_then(|v| v.as_u64()).unwrap_or(0) as usize;
+                Tool::EditLines {
+                    path: input
+                        .get("path")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or("")
+                        .to_string(),
+                    start: line("start"),
+                    end: line("end"),
+                    new_text: input
+                        .get("new_text")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or("")
+                        .to_string(),
+                }
+            }
+            "delete_file" => Tool::DeleteFile {
+                path: input.get("path").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+            },
+            "move_file" => Tool::MoveFile {
+                from: input.get("from").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+                to: input.get("to").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+            },
+            "apply_patch" => Tool::ApplyPatch {
+                patch: input.get("patch").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+            },
+            "read_clipboard" => Tool::ReadClipboard,
+            "start_edit_batch" => Tool::StartEditBatch,
+            "commit_edit_batch" => Tool::CommitEditBatch,
+            "screenshot" => {
+                let window = input
+                    .get("window")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false);
+                Tool::Screenshot { window }
+            }
+            "gh_issue_view" | "gh_pr_diff" | "gh_pr_comment" => {
+                let repo = input
+                    .get("repo")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("")
+                    .to_string();
+                let number = input
+                    .get("number")
+                    .and_then(|v| v.as_u64())
+                    .unwrap_or(0);
+                match name {
+                    "gh_issue_view" => Tool::GhIssueView { repo, number },
+                    "gh_pr_diff" => Tool::GhPrDiff { repo, number },
+                    _ => {
+                        let body = input
+                            .get("body")
+                            .and_then(|v| v.as_str())
+                            .unwrap_or("")
+                            .to_string();
+                        Tool::GhPrComment { repo, number, body }
+                    }
+                }
+            }
+            "fetch_ticket" => {
+                let key = input
+                    .get("key")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("")
+                    .to_string();
+                Tool::FetchTicket { key }
+            }
+            "git_status" | "git_log" | "git_show" => {
+                let path = input
+                    .get("path")
+                    .and_then(|v| v.as_str())
+                    .map(|s| s.to_string());
+                match name {
+                    "git_status" => Tool::GitStatus { path },
+                    "git_log" => {
+                        let max_count = input
+                            .get("max_count")
+                            .and_then(|v| v.as_u64())
+                            .map(|n| n as usize);
+                        Tool::GitLog { path, max_count }
+                    }
+                    _ => {
+                        let rev = input
+                            .get("rev")
+                            .and_then(|v| v.as_str())
+                            .unwrap_or("HEAD")
+                            .to_string();
+                        Tool::GitShow { rev, path }
+                    }
+                }
+            }
+            "retrieve" => {
+                let query = input
+                    .get("query")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("")
+                    .to_string();
+                let k = input
+                    .get("k")
+                    .and_then(|v| v.as_u64())
+                    .map(|k| k as usize);
+                Tool::Retrieve { query, k }
+            }
+            "web_fetch" => {
+                let url = input
+                    .get("url")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("")
+                    .to_string();
+                let max_chars = input
+                    .get("max_chars")
+                    .and_then(|v| v.as_u64())
+                    .map(|n| n as usize);
+                Tool::WebFetch { url, max_chars }
+            }
+            other => match crate::mcp::resolve(other) {
+                Some((server, tool)) => Tool::Mcp { server, tool, input },
+                None => continue, // unknown tool -- skip
+            },
         };
 
         calls.push(ToolCall { id, tool });
@@ -546,14 +2334,139 @@ pub fn parse_tool_calls(response: &Value) -> Vec<ToolCall> {
     calls
 }
 
+/// Directories skipped when looking for similarly named paths.
+const HINT_SKIP_DIRS: &[&str] = &["target", "node_modules", ".git", "dist", "build"];
+
+/// Upper bound on directory entries scanned for path suggestions.
+const HINT_MAX_ENTRIES: usize = 5000;
+
+/// Suggestions for a path that does not exist: files with the same or a
+/// similar name under the working directory.
+fn missing_path_hints(path: &Path) -> Vec<String> {
+    let mut hints = Vec::new();
+    let similar = similar_paths(path, Path::new("."), 5);
+    if !similar.is_empty() {
+        hints.push(format!("Did you mean: {}", similar.join(", ")));
+    }
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty())
+        && !parent.exists()
+    {
+        hints.push(format!("Directory {} does not exist either", parent.display()));
+    }
+    if let Ok(cwd) = std::env::current_dir() {
+        hints.push(format!("Relative paths resolve against {}; use list_files to explore", cwd.display()));
+    }
+    hints
+}
+
+/// Paths under `root` whose file name matches or closely resembles that of
+/// `path`, best matches first.
+fn similar_paths(path: &Path, root: &Path, limit: usize) -> Vec<String> {
+    let Some(target) = path.file_name().and_then(|n| n.to_str()).map(str::to_lowercase) else {
+        return Vec::new();
+    };
+    let mut scored: Vec<(f32, String)> = Vec::new();
+    let mut stack = vec![root.to_path_buf()];
+    let mut seen = 0;
+    while let Some(dir) = stack.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else { continue };
+        for entry in entries.flatten() {
+            seen += 1;
+            if seen > HINT_MAX_ENTRIES {
+                break;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            let entry_path = entry.path();
+            if entry_path.is_dir()
+                && !name.starts_with('.')
+                && !HINT_SKIP_DIRS.contains(&name.as_str())
+            {
+                stack.push(entry_path.clone());
+            }
+            let ratio = similar::TextDiff::from_chars(target.as_str(), name.to_lowercase().as_str()).ratio();
+            if ratio >= 0.75 {
+                let shown = entry_path.strip_prefix("./").unwrap_or(&entry_path).display().to_string();
+                scored.push((ratio, shown));
+            }
+        }
+    }
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.len().cmp(&b.1.len())));
+    scored.into_iter().take(limit).map(|(_, p)| p).collect()
+}
+
+/// Why `old_text` may have failed to match: the same text with different
+/// whitespace, or the closest line in the file.
+fn old_text_hints(contents: &str, old_text: &str) -> Vec<String> {
+    let mut hints = Vec::new();
+    let squash = |s: &str| s.split_whitespace().collect::<Vec<_>>().join(" ");
+    let first = old_text.lines().map(str::trim).find(|l| !l.is_empty()).unwrap_or("");
+    let lines: Vec<&str> = contents.lines().collect();
+
+    if !first.is_empty() && squash(contents).contains(&squash(old_text)) {
+        hints.push("old_text matches if whitespace is ignored -- copy indentation exactly from read_file output".into());
+    } else if let Some(n) = lines.iter().position(|l| l.trim() == first).filter(|_| !first.is_empty()) {
+        hints.push(format!(
+            "The first line of old_text matches line {}, but later lines differ",
+            n + 1
+        ));
+    } else if !first.is_empty() {
+        let best = lines
+            .iter()
+            .enumerate()
+            .map(|(i, l)| (similar::TextDiff::from_chars(first, l.trim()).ratio(), i))
+            .filter(|(r, _)| *r >= 0.6)
+            .max_by(|a, b| a.0.total_cmp(&b.0));
+        if let Some((_, i)) = best {
+            hints.push(format!("Closest line is {}: {}", i + 1, lines[i].trim()));
+        }
+    }
+    hints.push("Re-read the file with read_file, or use edit_lines with line numbers".into());
+    hints
+}
+
+/// Replace lines `start..=end` (1-based) of `contents` with `new_text`. An
+/// `end` of `start - 1` inserts before `start`; an empty `new_text` deletes.
+pub fn replace_lines(contents: &str, start: usize, end: usize, new_text: &str) -> Result<String, String> {
+    let lines: Vec<&str> = contents.split_inclusive('\n').collect();
+    if start == 0 || end + 1 < start || end > lines.len() {
+        return Err(format!(
+            "Line range {start}-{end} is invalid (file has {} lines; lines are numbered from 1)",
+            lines.len()
+        ));
+    }
+    let mut out = lines[..start - 1].concat();
+    out.push_str(new_text);
+    let more_follows = end < lines.len() || contents.ends_with('\n');
+    if !new_text.is_empty() && !new_text.ends_with('\n') && more_follows {
+        out.push('\n');
+    }
+    out.push_str(&lines[end..].concat());
+    Ok(out)
+}
+
+/// The lines `start..=end` (1-based) of a file, for previewing an
+/// `edit_lines` call.
+pub fn line_range(path: &str, start: usize, end: usize) -> Vec<String> {
+    fs::read_to_string(path)
+        .map(|c| {
+            c.lines()
+                .skip(start.saturating_sub(1))
+                .take((end + 1).saturating_sub(start))
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
 // ---------------------------------------------------------------------------
 // Formatting tool definitions for the Anthropic API
 // ---------------------------------------------------------------------------
 
 /// Return the tool definitions array suitable for inclusion in an Anthropic
-/// Messages API request body under the `"tools"` key.
-pub fn format_tool_definitions() -> Value {
-    json!([
+/// Messages API request body under the `"tools"` key, followed by the tools
+/// of any connected MCP servers.
+pub fn format_tool_definitions(set: ToolSet) -> Value {
+    let mut defs = json!([
         {
             "name": "read_file",
             "description": "Read the contents of a file at the given path. Returns the file contents with line numbers.",
@@ -580,91 +2493,481 @@ pub fn format_tool_definitions() -> Value {
                     },
                     "content": {
                         "type": "string",
-                        "description": "The full content to write to the file."
+                        "description": "The full content to write to the file."
+                    }
+                },
+                "required": ["path", "content"]
+            }
+        },
+        {
+            "name": "list_files",
+            "description": "List files in a directory, optionally filtered by a glob pattern.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "Directory path to list files in."
+                    },
+                    "pattern": {
+                        "type": "string",
+                        "description": "Optional glob pattern to filter files (e.g. \"**/*.rs\"). If omitted, all files are listed recursively."
+                    }
+                },
+                "required": ["path"]
+            }
+        },
+        {
+            "name": "search_files",
+            "description": "Search file contents using a regular expression pattern (via ripgrep or grep). Returns matching lines with file paths and line numbers.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "pattern": {
+                        "type": "string",
+                        "description": "Regular expression pattern to search for."
+                    },
+                    "path": {
+                        "type": "string",
+                        "description": "Optional directory or file to search in. Defaults to the current directory."
+                    }
+                },
+                "required": ["pattern"]
+            }
+        },
+        {
+            "name": "execute",
+            "description": "Execute a shell command and return its stdout and stderr. The command runs under `sh -c` with a configurable timeout (default 120 seconds). There is no terminal attached: prefer non-interactive flags (e.g. `npm init -y`, `apt-get -y`). If the command stops at a prompt (a password, yes/no or other question), the user is asked to answer it. Use `stdin` only for answers you already know, never for passwords.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "command": {
+                        "type": "string",
+                        "description": "The shell command to execute."
+                    },
+                    "stdin": {
+                        "type": "string",
+                        "description": "Optional text piped to the command's stdin, e.g. answers to prompts separated by newlines."
+                    }
+                },
+                "required": ["command"]
+            }
+        },
+        {
+            "name": "edit_file",
+            "description": "Perform a precise string replacement in a file. The old_text must appear exactly once in the file; it will be replaced with new_text.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "Path to the file to edit."
+                    },
+                    "old_text": {
+                        "type": "string",
+                        "description": "The exact text to find (must be unique in the file)."
+                    },
+                    "new_text": {
+                        "type": "string",
+                        "description": "The text to replace old_text with."
+                    }
+                },
+                "required": ["path", "old_text", "new_text"]
+            }
+        },
+        {
+            "name": "edit_lines",
+            "description": "Replace a range of lines in a file, using the line numbers shown by read_file. Prefer this over edit_file when the text to replace is not unique. Set end to start - 1 to insert before start, or new_text to \"\" to delete the lines.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "Path to the file to edit."
+                    },
+                    "start": {
+                        "type": "integer",
+                        "description": "First line to replace (1-based)."
+                    },
+                    "end": {
+                        "type": "integer",
+                        "description": "Last line to replace (inclusive)."
+                    },
+                    "new_text": {
+                        "type": "string",
+                        "description": "Replacement text for the whole range."
+                    }
+                },
+                "required": ["path", "start", "end", "new_text"]
+            }
+        },
+        {
+            "name": "apply_patch",
+            "description": "Apply a unified diff (as produced by git diff) to one or more files. Use this for several edits at once. Every hunk's context and removed lines must match the file; if any hunk fails nothing is written and the result lists which hunks failed and why. Use --- /dev/null to create a file and +++ /dev/null to delete one.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "patch": {
+                        "type": "string",
+                        "description": "The unified diff, with ---/+++ file headers and @@ hunk headers."
+                    }
+                },
+                "required": ["patch"]
+            }
+        },
+        {
+            "name": "delete_file",
+            "description": "Delete a file. Use this rather than running rm through execute. Directories are not removed.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "Path to the file to delete."
+                    }
+                },
+                "required": ["path"]
+            }
+        },
+        {
+            "name": "move_file",
+            "description": "Move or rename a file, creating the destination's directory if needed. Use this rather than running mv through execute. Fails if the destination already exists.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "from": {
+                        "type": "string",
+                        "description": "Current path of the file."
+                    },
+                    "to": {
+                        "type": "string",
+                        "description": "New path for the file."
+                    }
+                },
+                "required": ["from", "to"]
+            }
+        },
+        {
+            "name": "read_clipboard",
+            "description": "Read the current text contents of the user's system clipboard. Use this when the user refers to something they just copied.",
+            "input_schema": {
+                "type": "object",
+                "properties": {}
+            }
+        },
+        {
+            "name": "screenshot",
+            "description": "Capture a screenshot of the user's screen (or only the active window) and return it as an image. Useful for debugging UI issues with the user.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "window": {
+                        "type": "boolean",
+                        "description": "Capture only the focused window instead of the whole screen (on macOS the user clicks the window). Defaults to false."
+                    }
+                }
+            }
+        },
+        {
+            "name": "gh_issue_view",
+            "description": "Fetch a GitHub issue or pull request with its description, labels, and comments.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "repo": {
+                        "type": "string",
+                        "description": "Repository as owner/name."
+                    },
+                    "number": {
+                        "type": "integer",
+                        "description": "Issue or pull request number."
+                    }
+                },
+                "required": ["repo", "number"]
+            }
+        },
+        {
+            "name": "gh_pr_diff",
+            "description": "Fetch the unified diff of a GitHub pull request.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "repo": {
+                        "type": "string",
+                        "description": "Repository as owner/name."
+                    },
+                    "number": {
+                        "type": "integer",
+                        "description": "Pull request number."
+                    }
+                },
+                "required": ["repo", "number"]
+            }
+        },
+        {
+            "name": "gh_pr_comment",
+            "description": "Post a comment on a GitHub issue or pull request. The user confirms before it is posted.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "repo": {
+                        "type": "string",
+                        "description": "Repository as owner/name."
+                    },
+                    "number": {
+                        "type": "integer",
+                        "description": "Issue or pull request number."
+                    },
+                    "body": {
+                        "type": "string",
+                        "description": "Comment text in GitHub-flavored markdown."
+                    }
+                },
+                "required": ["repo", "number", "body"]
+            }
+        },
+        {
+            "name": "fetch_ticket",
+            "description": "Fetch a ticket from the user's issue tracker (Jira or Linear) by key, with its summary, description, status, and comments.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "key": {
+                        "type": "string",
+                        "description": "Ticket key, e.g. PROJ-123."
+                    }
+                },
+                "required": ["key"]
+            }
+        },
+        {
+            "name": "git_status",
+            "description": "Show the current branch and which files are modified, staged or untracked in the git repository (git status --short --branch).",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "Only report files under this path."
                     }
-                },
-                "required": ["path", "content"]
+                }
             }
         },
         {
-            "name": "list_files",
-            "description": "List files in a directory, optionally filtered by a glob pattern.",
+            "name": "git_log",
+            "description": "List recent commits as hash, date, author, refs and subject, newest first.",
             "input_schema": {
                 "type": "object",
                 "properties": {
                     "path": {
                         "type": "string",
-                        "description": "Directory path to list files in."
+                        "description": "Only commits that touched this file or directory."
                     },
-                    "pattern": {
-                        "type": "string",
-                        "description": "Optional glob pattern to filter files (e.g. \"**/*.rs\"). If omitted, all files are listed recursively."
+                    "max_count": {
+                        "type": "integer",
+                        "description": "Number of commits to list (default 20, max 200)."
                     }
-                },
-                "required": ["path"]
+                }
             }
         },
         {
-            "name": "search_files",
-            "description": "Search file contents using a regular expression pattern (via ripgrep or grep). Returns matching lines with file paths and line numbers.",
+            "name": "git_show",
+            "description": "Show a commit's message, changed-file summary and diff. A rev:path argument such as HEAD~3:src/main.rs shows that file as of the revision instead.",
             "input_schema": {
                 "type": "object",
                 "properties": {
-                    "pattern": {
+                    "rev": {
                         "type": "string",
-                        "description": "Regular expression pattern to search for."
+                        "description": "Commit hash, branch, tag or expression like HEAD~2."
                     },
                     "path": {
                         "type": "string",
-                        "description": "Optional directory or file to search in. Defaults to the current directory."
+                        "description": "Limit the diff to this file or directory."
                     }
                 },
-                "required": ["pattern"]
+                "required": ["rev"]
             }
         },
         {
-            "name": "execute",
-            "description": "Execute a shell command and return its stdout and stderr. The command runs under `sh -c` with a configurable timeout (default 120 seconds).",
+            "name": "retrieve",
+            "description": "Search the user's indexed local documents (built with `pro index`) and return the most relevant passages with their file paths and line ranges.",
             "input_schema": {
                 "type": "object",
                 "properties": {
-                    "command": {
+                    "query": {
                         "type": "string",
-                        "description": "The shell command to execute."
+                        "description": "What to look for, phrased as a question or description."
+                    },
+                    "k": {
+                        "type": "integer",
+                        "description": "Number of passages to return (default 5, max 20)."
                     }
                 },
-                "required": ["command"]
+                "required": ["query"]
             }
         },
         {
-            "name": "edit_file",
-            "description": "Perform a precise string replacement in a file. The old_text must appear exactly once in the file; it will be replaced with new_text.",
+            "name": "web_fetch",
+            "description": "Download a web page and return its readable text, with HTML markup, scripts and styles stripped. Use it to read documentation or other pages the user points to.",
             "input_schema": {
                 "type": "object",
                 "properties": {
-                    "path": {
-                        "type": "string",
-                        "description": "Path to the file to edit."
-                    },
-                    "old_text": {
+                    "url": {
                         "type": "string",
-                        "description": "The exact text to find (must be unique in the file)."
+                        "description": "The http or https URL to fetch."
                     },
-                    "new_text": {
-                        "type": "string",
-                        "description": "The text to replace old_text with."
+                    "max_chars": {
+                        "type": "integer",
+                        "description": "Most characters of text to return (default 20000, max 100000)."
                     }
                 },
-                "required": ["path", "old_text", "new_text"]
+                "required": ["url"]
+            }
+        },
+        {
+            "name": "start_edit_batch",
+            "description": "Start a multi-file change. Until commit_edit_batch is called, a failed write_file or edit_file restores every file changed since the batch started, so a refactor is never left half-applied.",
+            "input_schema": {
+                "type": "object",
+                "properties": {}
+            }
+        },
+        {
+            "name": "commit_edit_batch",
+            "description": "Finish the edit batch started with start_edit_batch and keep all of its changes.",
+            "input_schema": {
+                "type": "object",
+                "properties": {}
             }
         }
-    ])
+    ]);
+    if let Some(list) = defs.as_array_mut() {
+        if set == ToolSet::Core {
+            list.retain(|d| d["name"].as_str().is_some_and(|name| CORE_TOOLS.contains(&name)));
+            for def in list.iter_mut() {
+                let description = def["description"].as_str().unwrap_or_default();
+                let short = description.split_once(". ").map_or(description, |(first, _)| first);
+                def["description"] = json!(short.trim_end_matches('.').to_string() + ".");
+            }
+        }
+        list.extend(crate::mcp::tool_definitions());
+    }
+    defs
+}
+
+/// The same definitions in the OpenAI chat completions `"tools"` format.
+pub fn format_openai_tool_definitions(set: ToolSet) -> Value {
+    let defs = format_tool_definitions(set);
+    let functions: Vec<Value> = defs
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|d| json!({
+            "type": "function",
+            "function": {
+                "name": d["name"],
+                "description": d["description"],
+                "parameters": d["input_schema"],
+            }
+        }))
+        .collect();
+    Value::Array(functions)
+}
+
+/// Whether tool definitions are sent to this provider.
+pub fn provider_supports_tools(provider: &str) -> bool {
+    matches!(provider, "anthropic" | "openai" | "bedrock")
 }
 
 // ---------------------------------------------------------------------------
 // Helpers
 // ---------------------------------------------------------------------------
 
+/// Run the child in its own session so it has no controlling terminal. Without
+/// this, programs that open `/dev/tty` directly (sudo, ssh, git credential
+/// prompts) would read from the terminal the TUI owns and hang.
+#[cfg(unix)]
+pub(crate) fn detach_from_terminal(cmd: &mut TokioCommand) {
+    // SAFETY: setsid is async-signal-safe and touches no parent state.
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn detach_from_terminal(_cmd: &mut TokioCommand) {}
+
+/// If the trailing, unterminated output line asks for a password or a yes/no
+/// answer, return it trimmed. While the command can still read `stdin_open`,
+/// a line ending in `:` or `?`, optionally followed by a default such as
+/// `package name: (demo)`, counts too. Other quiet lines (progress output,
+/// logs) are not treated as prompts.
+fn detect_prompt(tail: &str, stdin_open: bool) -> Option<&str> {
+    let line = tail.trim();
+    let lower = line.to_lowercase();
+    let asks = ["password", "passphrase", "(y/n)", "[y/n]", "(yes/no)", "[yes/no]", "continue?"]
+        .iter()
+        .any(|p| lower.contains(p));
+    (asks || stdin_open && ends_in_question(line)).then_some(line)
+}
+
+/// Whether `line` ends in `:` or `?`, ignoring a trailing `(default)` or
+/// `[default]`.
+fn ends_in_question(line: &str) -> bool {
+    let mut rest = line;
+    for (open, close) in [('(', ')'), ('[', ']')] {
+        if rest.ends_with(close)
+            && let Some(start) = rest.rfind(open)
+        {
+            rest = rest[..start].trim_end();
+        }
+    }
+    rest.ends_with(':') || rest.ends_with('?')
+}
+
+/// Whether `prompt` asks for something that shouldn't be echoed.
+pub fn is_secret_prompt(prompt: &str) -> bool {
+    let lower = prompt.to_lowercase();
+    lower.contains("password") || lower.contains("passphrase")
+}
+
+/// Pick a platform screenshot utility and the arguments to write a PNG to `out`.
+fn screenshot_command(window: bool, out: &str) -> Option<(&'static str, Vec<String>)> {
+    let out = out.to_string();
+    if cfg!(target_os = "macos") {
+        let mut args = vec!["-x".to_string()];
+        if window {
+            // -o drops the window shadow; -W waits for the user to click a window
+            args.extend(["-o".to_string(), "-W".to_string()]);
+        }
+        args.push(out);
+        return Some(("screencapture", args));
+    }
+
+    let wayland = std::env::var_os("WAYLAND_DISPLAY").is_some();
+    let candidates: Vec<(&'static str, Vec<String>)> = if window {
+        vec![
+            ("gnome-screenshot", vec!["-w".into(), "-f".into(), out.clone()]),
+            ("scrot", vec!["-u".into(), out.clone()]),
+        ]
+    } else {
+        let mut c = Vec::new();
+        if wayland {
+            c.push(("grim", vec![out.clone()]));
+        }
+        c.push(("gnome-screenshot", vec!["-f".into(), out.clone()]));
+        c.push(("scrot", vec![out.clone()]));
+        c.push(("import", vec!["-window".into(), "root".into(), out.clone()]));
+        c
+    };
+    candidates.into_iter().find(|(program, _)| command_exists(program))
+}
+
 /// Check whether a command is available on the system PATH.
 fn command_exists(name: &str) -> bool {
     Command::new("which")
@@ -741,11 +3044,383 @@ mod tests {
         assert!(calls.is_empty());
     }
 
+    #[test]
+    fn test_parse_tool_calls_read_clipboard() {
+        let response = json!({
+            "content": [
+                {
+                    "type": "tool_use",
+                    "id": "toolu_clip",
+                    "name": "read_clipboard",
+                    "input": {}
+                }
+            ]
+        });
+
+        let calls = parse_tool_calls(&response);
+        assert_eq!(calls.len(), 1);
+        assert!(matches!(calls[0].tool, Tool::ReadClipboard));
+        assert_eq!(calls[0].tool.name(), "read_clipboard");
+    }
+
+    #[test]
+    fn test_parse_tool_calls_screenshot() {
+        let response = json!({
+            "content": [
+                { "type": "tool_use", "id": "toolu_a", "name": "screenshot", "input": {} },
+                { "type": "tool_use", "id": "toolu_b", "name": "screenshot", "input": { "window": true } }
+            ]
+        });
+
+        let calls = parse_tool_calls(&response);
+        assert_eq!(calls.len(), 2);
+        assert!(matches!(calls[0].tool, Tool::Screenshot { window: false }));
+        assert!(matches!(calls[1].tool, Tool::Screenshot { window: true }));
+    }
+
+    #[test]
+    fn test_parse_tool_calls_github() {
+        let response = json!({
+            "content": [
+                { "type": "tool_use", "id": "toolu_a", "name": "gh_pr_diff", "input": { "repo": "o/r", "number": 7 } },
+                { "type": "tool_use", "id": "toolu_b", "name": "gh_pr_comment", "input": { "repo": "o/r", "number": 7, "body": "LGTM" } }
+            ]
+        });
+        let calls = parse_tool_calls(&response);
+        assert!(matches!(&calls[0].tool, Tool::GhPrDiff { repo, number: 7 } if repo == "o/r"));
+        assert!(matches!(&calls[1].tool, Tool::GhPrComment { body, .. } if body == "LGTM"));
+    }
+
+    #[test]
+    fn test_github_repo_is_validated() {
+        for repo in ["rust-lang/rust", "acme/pro.chat", "a_b/c-d"] {
+            assert!(is_github_repo(repo), "{repo}");
+        }
+        for repo in ["rust", "acme/", "/rust", "acme/rust/issues", "../admin", "acme/..", "acme/rust?x=1", "acme/rüst"] {
+            assert!(!is_github_repo(repo), "{repo}");
+        }
+    }
+
+    #[test]
+    fn test_format_github_issue() {
+        let issue = json!({
+            "number": 12,
+            "title": "Crash on start",
+            "state": "open",
+            "user": { "login": "alice" },
+            "labels": [{ "name": "bug" }],
+            "body": "It crashes.",
+            "pull_request": {}
+        });
+        let comments = json!([{ "user": { "login": "bob" }, "created_at": "2026-01-01T00:00:00Z", "body": "Same here" }]);
+        let text = format_github_issue(&issue, &comments);
+        assert!(text.starts_with("Pull request #12: Crash on start\nState: open  Author: alice  Labels: bug"));
+        assert!(text.contains("It crashes."));
+        assert!(text.ends_with("--- bob at 2026-01-01T00:00:00Z ---\nSame here"));
+    }
+
+    #[test]
+    fn test_tool_result_api_content_with_image() {
+        let plain = ToolResult::ok("done");
+        assert_eq!(plain.api_content(), json!("done"));
+
+        let mut with_image = ToolResult::ok("captured");
+        with_image.image = Some(ToolImage {
+            media_type: "image/png".into(),
+            data: "AAAA".into(),
+        });
+        let content = with_image.api_content();
+        assert_eq!(content[0]["text"], "captured");
+        assert_eq!(content[1]["type"], "image");
+        assert_eq!(content[1]["source"]["data"], "AAAA");
+    }
+
+    #[test]
+    fn test_tool_stats_record() {
+        let mut stats = ToolStats::default();
+        stats.record(&ToolResult::ok("abcd"), Duration::from_millis(100));
+        stats.record(&ToolResult::err("no"), Duration::from_millis(300));
+
+        assert_eq!(stats.calls, 2);
+        assert_eq!(stats.failures, 1);
+        assert_eq!(stats.total_output_bytes, 6);
+        assert_eq!(stats.avg_duration(), Duration::from_millis(200));
+        assert_eq!(stats.max_duration, Duration::from_millis(300));
+        assert!((stats.success_rate() - 0.5).abs() < f64::EPSILON);
+    }
+
+    #[tokio::test]
+    async fn test_git_tools() {
+        // Tests run from the crate root, which is a git checkout
+        let executor = ToolExecutor::new();
+        let log = executor.execute(&Tool::GitLog { path: Some("Cargo.toml".into()), max_count: Some(1) }).await;
+        assert!(log.success, "{}", log.output);
+        assert_eq!(log.output.lines().count(), 1);
+        let hash = log.output.split_whitespace().next().unwrap().to_string();
+
+        let show = executor.execute(&Tool::GitShow { rev: hash.clone(), path: Some("Cargo.toml".into()) }).await;
+        assert!(show.success, "{}", show.output);
+        assert!(show.output.starts_with("commit "));
+        let status = executor.execute(&Tool::GitStatus { path: None }).await;
+        assert!(status.output.starts_with("## "), "{}", status.output);
+
+        let injected = executor.execute(&Tool::GitShow { rev: "--output=/tmp/x".into(), path: None }).await;
+        assert!(!injected.success);
+        let missing = executor.execute(&Tool::GitShow { rev: "no-such-rev-xyz".into(), path: None }).await;
+        assert!(missing.output.starts_with("git show failed"), "{}", missing.output);
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_millis(42)), "42ms");
+        assert_eq!(format_duration(Duration::from_millis(2400)), "2.4s");
+        assert_eq!(format_duration(Duration::from_secs(65)), "1m05s");
+    }
+
+    #[test]
+    fn test_tool_definitions_match_tool_names() {
+        let defs = format_tool_definitions(ToolSet::Full);
+        let names: Vec<&str> = defs
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|d| d["name"].as_str())
+            .collect();
+        assert_eq!(names, TOOL_NAMES);
+    }
+
     #[test]
     fn test_format_tool_definitions_is_array() {
-        let defs = format_tool_definitions();
+        let defs = format_tool_definitions(ToolSet::Full);
         assert!(defs.is_array());
-        assert_eq!(defs.as_array().unwrap().len(), 6);
+        assert_eq!(defs.as_array().unwrap().len(), TOOL_NAMES.len());
+    }
+
+    #[test]
+    fn test_core_tool_set() {
+        let defs = format_tool_definitions(ToolSet::Core);
+        let names: Vec<&str> = defs.as_array().unwrap().iter().filter_map(|d| d["name"].as_str()).collect();
+        assert_eq!(names, tool_names(ToolSet::Core));
+        // Descriptions are cut to their first sentence
+        assert_eq!(defs[0]["description"], "Read the contents of a file at the given path.");
+        assert_eq!(defs[0]["input_schema"]["required"][0], "path");
+
+        assert_eq!(model_tool_capability("gpt-4o-mini-2024-07-18"), Some(Some(ToolSet::Core)));
+        assert_eq!(model_tool_capability("openai/o1-mini"), Some(None));
+        assert_eq!(model_tool_capability("gpt-4o"), None);
+    }
+
+    #[test]
+    fn test_openai_tool_definitions() {
+        let defs = format_openai_tool_definitions(ToolSet::Full);
+        let defs = defs.as_array().unwrap();
+        assert_eq!(defs.len(), TOOL_NAMES.len());
+        assert_eq!(defs[0]["type"], "function");
+        assert_eq!(defs[0]["function"]["name"], "read_file");
+        assert_eq!(defs[0]["function"]["parameters"]["required"][0], "path");
+    }
+
+    #[test]
+    fn test_old_text_hints() {
+        let contents = "fn main() {\n    let x = 1;\n    println!(\"{x}\");\n}\n";
+        let hints = old_text_hints(contents, "let x = 1;\nprintln!(\"{x}\");");
+        assert!(hints[0].contains("whitespace"));
+        let hints = old_text_hints(contents, "    let x = 1;\n    dbg!(x);");
+        assert!(hints[0].contains("line 2"));
+        let hints = old_text_hints(contents, "    let y = 1;");
+        assert!(hints[0].starts_with("Closest line is 2"));
+    }
+
+    #[test]
+    fn test_similar_paths() {
+        let dir = std::env::temp_dir().join("pro_chat_test_similar_paths");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/config.rs"), "").unwrap();
+        fs::write(dir.join("src/main.rs"), "").unwrap();
+        let found = similar_paths(Path::new("config.rs"), &dir, 5);
+        assert_eq!(found.len(), 1);
+        assert!(found[0].ends_with("src/config.rs"));
+        assert!(similar_paths(Path::new("confg.rs"), &dir, 5)[0].ends_with("config.rs"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_non_unique_old_text_lists_lines() {
+        let dir = std::env::temp_dir().join("pro_chat_test_non_unique");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a.txt");
+        fs::write(&file, "x\ny\nx\n").unwrap();
+        let result = ToolExecutor::new().execute(&Tool::EditFile {
+            path: file.to_str().unwrap().into(),
+            old_text: "x".into(),
+            new_text: "z".into(),
+        }).await;
+        assert!(!result.success);
+        assert_eq!(result.hints[0], "Matches start at lines 1, 3");
+        assert!(result.model_text().contains("Hints:\n- Matches start"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_replace_lines() {
+        let text = "a\nb\nc\n";
+        assert_eq!(replace_lines(text, 2, 2, "B").unwrap(), "a\nB\nc\n");
+        assert_eq!(replace_lines(text, 2, 3, "x\ny\nz").unwrap(), "a\nx\ny\nz\n");
+        assert_eq!(replace_lines(text, 1, 0, "top").unwrap(), "top\na\nb\nc\n");
+        assert_eq!(replace_lines(text, 4, 3, "end\n").unwrap(), "a\nb\nc\nend\n");
+        assert_eq!(replace_lines(text, 1, 2, "").unwrap(), "c\n");
+        assert_eq!(replace_lines("a\nb", 2, 2, "B").unwrap(), "a\nB");
+        assert!(replace_lines(text, 0, 1, "x").is_err());
+        assert!(replace_lines(text, 3, 4, "x").is_err());
+        assert!(replace_lines(text, 3, 1, "x").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_syntax_check_after_write() {
+        let dir = std::env::temp_dir().join("pro_chat_test_syntax_check");
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("data.json").to_str().unwrap().to_string();
+
+        let mut executor = ToolExecutor::new();
+        let write = |content: &str| Tool::WriteFile { path: path.clone(), content: content.into() };
+        let result = executor.execute(&write("{\"a\": ")).await;
+        assert!(!result.output.contains("Syntax check failed"));
+
+        executor.set_validate(ValidateConfig { enabled: true, ..Default::default() });
+        let result = executor.execute(&write("{\"a\": ")).await;
+        assert!(result.success);
+        assert!(result.output.contains("Syntax check failed"));
+        let result = executor.execute(&write("{\"a\": 1}")).await;
+        assert!(!result.output.contains("Syntax check failed"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_apply_patch_is_all_or_nothing() {
+        let dir = std::env::temp_dir().join("pro_chat_test_apply_patch");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let a = dir.join("a.txt").display().to_string();
+        let b = dir.join("b.txt").display().to_string();
+        let c = dir.join("c.txt").display().to_string();
+        fs::write(&a, "one\ntwo\nthree\n").unwrap();
+        fs::write(&b, "alpha\nbeta\n").unwrap();
+
+        let executor = ToolExecutor::new();
+        // The second file's hunk doesn't match, so the first isn't written either
+        let bad = format!("--- {a}\n+++ {a}\n@@ -2 +2 @@\n-two\n+TWO\n--- {b}\n+++ {b}\n@@ -1 +1 @@\n-gamma\n+GAMMA\n");
+        let result = executor.execute(&Tool::ApplyPatch { patch: bad }).await;
+        assert!(!result.success);
+        assert!(result.output.contains("@@ -2 +2 @@  applied"));
+        assert!(result.output.contains("@@ -1 +1 @@  FAILED"));
+        assert_eq!(fs::read_to_string(&a).unwrap(), "one\ntwo\nthree\n");
+
+        let good = format!(
+            "--- {a}\n+++ {a}\n@@ -2 +2 @@\n-two\n+TWO\n--- /dev/null\n+++ {c}\n@@ -0,0 +1 @@\n+new\n\
+             --- {b}\n+++ /dev/null\n@@ -1,2 +0,0 @@\n-alpha\n-beta\n"
+        );
+        let tool = Tool::ApplyPatch { patch: good };
+        assert_eq!(tool.written_paths(), vec![a.clone(), c.clone(), b.clone()]);
+        let result = executor.execute(&tool).await;
+        assert!(result.success, "{}", result.output);
+        assert!(result.output.starts_with("Applied 3 hunk(s) to 3 file(s)"));
+        assert_eq!(fs::read_to_string(&a).unwrap(), "one\nTWO\nthree\n");
+        assert_eq!(fs::read_to_string(&c).unwrap(), "new\n");
+        assert!(!Path::new(&b).exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_delete_and_move_file() {
+        let dir = std::env::temp_dir().join("pro_chat_test_delete_move");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).display().to_string();
+        fs::write(path("a.txt"), "a").unwrap();
+        fs::write(path("b.txt"), "b").unwrap();
+
+        let executor = ToolExecutor::new();
+        let moved = executor.execute(&Tool::MoveFile { from: path("a.txt"), to: path("sub/c.txt") }).await;
+        assert!(moved.success, "{}", moved.output);
+        assert_eq!(fs::read_to_string(path("sub/c.txt")).unwrap(), "a");
+        assert!(!dir.join("a.txt").exists());
+
+        // Never overwrites
+        let clash = executor.execute(&Tool::MoveFile { from: path("b.txt"), to: path("sub/c.txt") }).await;
+        assert!(!clash.success);
+        assert!(clash.output.contains("already exists"));
+
+        let dir_delete = executor.execute(&Tool::DeleteFile { path: path("sub") }).await;
+        assert!(!dir_delete.success);
+        assert!(executor.execute(&Tool::DeleteFile { path: path("missing.txt") }).await.output.contains("not found"));
+
+        // A batch rollback brings a deleted file back
+        executor.execute(&Tool::StartEditBatch).await;
+        assert!(executor.execute(&Tool::DeleteFile { path: path("b.txt") }).await.success);
+        assert!(!dir.join("b.txt").exists());
+        executor.rollback_edit_batch().unwrap();
+        assert_eq!(fs::read_to_string(path("b.txt")).unwrap(), "b");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_sandbox_rejects_paths_outside_roots() {
+        let dir = std::env::temp_dir().join("pro_chat_test_sandbox");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("work")).unwrap();
+        fs::create_dir_all(dir.join("outside")).unwrap();
+        fs::write(dir.join("outside/secret.txt"), "s").unwrap();
+        std::os::unix::fs::symlink(dir.join("outside"), dir.join("work/link")).unwrap();
+        let path = |rel: &str| dir.join(rel).display().to_string();
+
+        let config = SandboxConfig { enabled: true, allow: vec![path("work")] };
+        let mut executor = ToolExecutor::new();
+        executor.set_sandbox(Sandbox::from_config(&config));
+        let inside = executor.execute(&Tool::WriteFile { path: path("work/new.txt"), content: "x".into() }).await;
+        assert!(inside.success, "{}", inside.output);
+
+        for escape in ["outside/secret.txt", "work/../outside/secret.txt", "work/link/secret.txt"] {
+            let result = executor.execute(&Tool::ReadFile { path: path(escape) }).await;
+            assert!(result.output.starts_with("Path outside workspace"), "{escape}: {}", result.output);
+        }
+        let moved = executor.execute(&Tool::MoveFile { from: path("work/new.txt"), to: path("outside/new.txt") }).await;
+        assert!(!moved.success);
+        assert!(dir.join("work/new.txt").exists());
+
+        assert_eq!(Sandbox::from_config(&SandboxConfig { enabled: false, allow: Vec::new() }), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_edit_batch_commit_and_rollback() {
+        let dir = std::env::temp_dir().join("pro_chat_test_edit_batch");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a.txt");
+        fs::write(&file, "one").unwrap();
+        let path = file.to_str().unwrap().to_string();
+
+        let executor = ToolExecutor::new();
+        assert!(!executor.execute(&Tool::CommitEditBatch).await.success);
+        assert!(executor.execute(&Tool::StartEditBatch).await.success);
+        assert!(!executor.execute(&Tool::StartEditBatch).await.success);
+        executor.execute(&Tool::WriteFile { path: path.clone(), content: "two".into() }).await;
+        executor.execute(&Tool::WriteFile { path: path.clone(), content: "three".into() }).await;
+        let (restored, _) = executor.rollback_edit_batch().unwrap();
+        assert_eq!(restored, vec![file.clone()]);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "one");
+
+        executor.execute(&Tool::StartEditBatch).await;
+        executor.execute(&Tool::WriteFile { path, content: "four".into() }).await;
+        let result = executor.execute(&Tool::CommitEditBatch).await;
+        assert!(result.output.contains("1 file(s)"));
+        assert!(executor.rollback_edit_batch().is_none());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "four");
+        let _ = fs::remove_dir_all(&dir);
     }
 
     #[tokio::test]
@@ -831,11 +3506,133 @@ mod tests {
         let executor = ToolExecutor::new();
         let result = executor.execute(&Tool::Execute {
             command: "echo hello".into(),
+            stdin: None,
         }).await;
         assert!(result.success);
         assert!(result.output.contains("hello"));
     }
 
+    #[tokio::test]
+    async fn test_execute_command_with_stdin() {
+        let executor = ToolExecutor::new();
+        let result = executor.execute(&Tool::Execute {
+            command: "read name; echo \"hi $name\"".into(),
+            stdin: Some("pro\n".into()),
+        }).await;
+        assert!(result.success);
+        assert!(result.output.contains("hi pro"));
+    }
+
+    #[tokio::test]
+    async fn test_execute_command_without_stdin_sees_eof() {
+        let executor = ToolExecutor::new();
+        let result = executor.execute(&Tool::Execute {
+            command: "cat; echo done".into(),
+            stdin: None,
+        }).await;
+        assert!(result.success);
+        assert!(result.output.contains("done"));
+    }
+
+    #[tokio::test]
+    async fn test_execute_command_applies_env() {
+        let mut executor = ToolExecutor::new();
+        executor.set_env(ToolEnvConfig {
+            set: [("PRO_CHAT_TEST_VAR".to_string(), "42".to_string())].into(),
+            ..Default::default()
+        });
+        let result = executor.execute(&Tool::Execute {
+            command: "echo \"v=$PRO_CHAT_TEST_VAR\"".into(),
+            stdin: None,
+        }).await;
+        assert!(result.output.contains("v=42"));
+    }
+
+    #[tokio::test]
+    async fn test_execute_asks_the_user_at_a_prompt() {
+        let mut executor = ToolExecutor::new();
+        let asked = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = asked.clone();
+        executor.set_input_handler(Some(Arc::new(move |prompt| {
+            seen.lock().unwrap().push(prompt);
+            let (reply, answer) = tokio::sync::oneshot::channel();
+            let _ = reply.send(Some("y".into()));
+            answer
+        })));
+        let result = executor.execute(&Tool::Execute {
+            command: "printf 'Continue? [y/N] '; read answer; echo \"got $answer\"".into(),
+            stdin: None,
+        }).await;
+        assert!(result.success, "{}", result.output);
+        assert!(result.output.contains("got y"));
+        assert_eq!(*asked.lock().unwrap(), ["Continue? [y/N]"]);
+    }
+
+    #[tokio::test]
+    async fn test_execute_asks_at_a_prompt_with_a_default() {
+        let mut executor = ToolExecutor::new();
+        let asked = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = asked.clone();
+        executor.set_input_handler(Some(Arc::new(move |prompt| {
+            seen.lock().unwrap().push(prompt);
+            let (reply, answer) = tokio::sync::oneshot::channel();
+            let _ = reply.send(Some("app".into()));
+            answer
+        })));
+        let result = executor.execute(&Tool::Execute {
+            command: "printf 'package name: (demo) '; read name; echo \"name=$name\"".into(),
+            stdin: None,
+        }).await;
+        assert!(result.success, "{}", result.output);
+        assert!(result.output.contains("name=app"));
+        assert_eq!(*asked.lock().unwrap(), ["package name: (demo)"]);
+    }
+
+    #[test]
+    fn test_detect_prompt() {
+        assert_eq!(detect_prompt("package name: (demo) ", true), Some("package name: (demo)"));
+        assert_eq!(detect_prompt("Project name? ", true), Some("Project name?"));
+        assert_eq!(detect_prompt("[sudo] password for me: ", false), Some("[sudo] password for me:"));
+        assert_eq!(detect_prompt("Proceed (y/n)", false), Some("Proceed (y/n)"));
+        // Quiet progress or log lines are not prompts
+        assert_eq!(detect_prompt("Downloading [=====>    ]", true), None);
+        assert_eq!(detect_prompt("Compiling foo v0.1.0", true), None);
+        assert_eq!(detect_prompt("", true), None);
+        // Nor is a trailing colon once nothing can answer it
+        assert_eq!(detect_prompt("Waiting for lock:", false), None);
+    }
+
+    #[test]
+    fn test_tool_cache_invalidates_on_change() {
+        let dir = std::env::temp_dir().join(format!("pro-chat-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a.txt");
+        fs::write(&file, "one").unwrap();
+
+        let mut cache = ToolCache::default();
+        let read = Tool::ReadFile { path: file.display().to_string() };
+        assert!(cache.get(&read).is_none());
+        cache.insert(&read, &ToolResult::ok("one"));
+        assert_eq!(cache.get(&read).unwrap().output, "one");
+        assert_eq!(cache.hits, 1);
+
+        fs::write(&file, "three").unwrap();
+        assert!(cache.get(&read).is_none());
+        assert_eq!(cache.len(), 0);
+
+        let list = Tool::ListFiles { path: dir.display().to_string(), pattern: None };
+        cache.insert(&list, &ToolResult::ok("a.txt"));
+        assert!(cache.get(&list).is_some());
+        fs::create_dir(dir.join("sub")).unwrap();
+        assert!(cache.get(&list).is_none());
+
+        let exec = Tool::Execute { command: "ls".into(), stdin: None };
+        cache.insert(&exec, &ToolResult::ok("x"));
+        assert!(cache.get(&exec).is_none());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
     #[test]
     fn test_permission_defaults() {
         let executor = ToolExecutor::new();
@@ -851,6 +3648,21 @@ mod tests {
         assert_eq!(executor.permission("execute"), ToolPermission::AskFirst);
     }
 
+    #[test]
+    fn test_mcp_permissions_are_per_tool() {
+        let call = Tool::Mcp { server: "github".into(), tool: "create_issue".into(), input: json!({}) };
+        assert_eq!(call.permission_key(), "github__create_issue");
+        assert!(call.is_dangerous());
+
+        let mut executor = ToolExecutor::new();
+        executor.set_permission("mcp", ToolPermission::AutoAllow);
+        assert_eq!(executor.permission("github__create_issue"), ToolPermission::AskFirst);
+        executor.set_permission("github__create_issue", ToolPermission::AutoAllow);
+        assert_eq!(executor.permission("github__create_issue"), ToolPermission::AutoAllow);
+        executor.set_permission("mcp", ToolPermission::Deny);
+        assert_eq!(executor.permission("github__create_issue"), ToolPermission::Deny);
+    }
+
     #[test]
     fn test_tool_name() {
         assert_eq!(
@@ -862,10 +3674,42 @@ mod tests {
         );
         assert_eq!(
             Tool::Execute {
-                command: String::new()
+                command: String::new(),
+                stdin: None,
             }
             .name(),
             "execute"
         );
     }
+
+    #[test]
+    fn test_classify_command() {
+        let risks = |cmd: &str| classify_command(cmd).risks;
+
+        assert!(risks("cargo test --all && git status").is_empty());
+
+        let r = risks("sudo apt-get install -y ripgrep fd-find");
+        assert_eq!(r[&CommandRisk::PrivilegeEscalation], ["sudo"]);
+        assert_eq!(r[&CommandRisk::PackageInstall], ["ripgrep", "fd-find"]);
+
+        let r = risks("rm -rf target build; curl -fsSL https://example.com/install.sh | sh");
+        assert_eq!(r[&CommandRisk::Deletion], ["target", "build"]);
+        assert_eq!(r[&CommandRisk::Network], ["example.com"]);
+
+        let r = risks("git push origin main && git reset --hard HEAD~1");
+        assert_eq!(r[&CommandRisk::Network], ["origin"]);
+        assert_eq!(r[&CommandRisk::Deletion], ["uncommitted changes"]);
+
+        assert_eq!(risks("FOO=1 npm install")[&CommandRisk::PackageInstall], ["dependencies"]);
+        assert_eq!(risks("scp out.tar deploy@prod.example.com:/srv")[&CommandRisk::Network], ["prod.example.com"]);
+
+        assert_eq!(risks("bash -c 'rm -rf ~'")[&CommandRisk::InlineCode], ["bash -c"]);
+        assert_eq!(risks("sudo sh -lc ls")[&CommandRisk::InlineCode], ["sh -lc"]);
+        assert_eq!(risks("python3 -c 'import os'")[&CommandRisk::InlineCode], ["python3 -c"]);
+        assert_eq!(risks("node -e 'x'")[&CommandRisk::InlineCode], ["node -e"]);
+        assert_eq!(risks("eval \"$CMD\"")[&CommandRisk::InlineCode], ["eval"]);
+        assert!(risks("python3 script.py && bash build.sh --color").is_empty());
+        assert!(Tool::Execute { command: "sh -c true".into(), stdin: None }.is_dangerous());
+        assert!(Tool::Execute { command: "/bin/rm x".into(), stdin: None }.is_dangerous());
+    }
 }
@@ -0,0 +1,214 @@
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::config::{TrackerConfig, TrackerKind};
+
+/// Timeout for issue tracker requests.
+const TRACKER_TIMEOUT: Duration = Duration::from_secs(30);
+
+const LINEAR_API_URL: &str = "https://api.linear.app/graphql";
+
+const LINEAR_QUERY: &str = "query($id: String!) { issue(id: $id) { identifier title description url \
+state { name } assignee { name } comments { nodes { body createdAt user { name } } } } }";
+
+/// A ticket fetched from Jira or Linear.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Ticket {
+    pub key: String,
+    pub title: String,
+    pub status: String,
+    pub assignee: Option<String>,
+    pub url: Option<String>,
+    pub description: String,
+    /// (author, timestamp, body), oldest first
+    pub comments: Vec<(String, String, String)>,
+}
+
+impl Ticket {
+    /// Plain-text rendering used as tool output and chat context.
+    pub fn render(&self) -> String {
+        let mut out = format!("{}: {}\nStatus: {}", self.key, self.title, self.status);
+        if let Some(assignee) = &self.assignee {
+            out.push_str(&format!("  Assignee: {assignee}"));
+        }
+        if let Some(url) = &self.url {
+            out.push_str(&format!("\n{url}"));
+        }
+        out.push_str("\n\n");
+        out.push_str(if self.description.trim().is_empty() {
+            "(no description)"
+        } else {
+            self.description.trim()
+        });
+        for (author, at, body) in &self.comments {
+            out.push_str(&format!("\n\n--- {author} at {at} ---\n{}", body.trim()));
+        }
+        out
+    }
+}
+
+/// Fetch a ticket such as `PROJ-123` from the configured tracker.
+pub async fn fetch(config: &TrackerConfig, key: &str) -> Result<Ticket, String> {
+    let key = key.trim();
+    if key.is_empty() {
+        return Err("No ticket key given".into());
+    }
+    let client = crate::api::http_client(TRACKER_TIMEOUT).map_err(|e| e.to_string())?;
+    let token = config.token();
+
+    let request = match config.kind {
+        TrackerKind::Jira => {
+            let base = config.url.as_deref().ok_or("tracker.url is not set")?;
+            // The key goes into the URL path, so nothing but PROJ-123 gets through
+            if !is_jira_key(key) {
+                return Err(format!("Not a Jira issue key: {key} (expected e.g. PROJ-123)"));
+            }
+            let url = format!(
+                "{}/rest/api/2/issue/{key}?fields=summary,description,status,assignee,comment",
+                base.trim_end_matches('/')
+            );
+            let request = client.get(url).header("accept", "application/json");
+            match (&config.email, token) {
+                (Some(email), token) => request.basic_auth(email, token),
+                (None, Some(token)) => request.bearer_auth(token),
+                (None, None) => request,
+            }
+        }
+        TrackerKind::Linear => {
+            let token = token.ok_or("No Linear API key: set tracker.token or LINEAR_API_KEY")?;
+            client
+                .post(config.url.as_deref().unwrap_or(LINEAR_API_URL))
+                .header("authorization", token)
+                .json(&json!({ "query": LINEAR_QUERY, "variables": { "id": key } }))
+        }
+    };
+
+    let response = request.send().await.map_err(|e| format!("Tracker request failed: {e}"))?;
+    let status = response.status();
+    let body: Value = response
+        .json()
+        .await
+        .map_err(|e| format!("Tracker returned {status} with an unreadable body: {e}"))?;
+    if !status.is_success() {
+        let message = body["errorMessages"][0]
+            .as_str()
+            .or_else(|| body["errors"][0]["message"].as_str())
+            .unwrap_or("request rejected");
+        return Err(format!("Tracker error ({status}): {message}"));
+    }
+
+    match config.kind {
+        TrackerKind::Jira => Ok(parse_jira(&body)),
+        TrackerKind::Linear => parse_linear(&body).ok_or_else(|| {
+            body["errors"][0]["message"]
+                .as_str()
+                .map(|m| format!("Linear: {m}"))
+                .unwrap_or_else(|| format!("Ticket not found: {key}"))
+        }),
+    }
+}
+
+fn parse_jira(issue: &Value) -> Ticket {
+    let fields = &issue["fields"];
+    let text = |v: &Value| v.as_str().unwrap_or("").to_string();
+    Ticket {
+        key: text(&issue["key"]),
+        title: text(&fields["summary"]),
+        status: fields["status"]["name"].as_str().unwrap_or("unknown").to_string(),
+        assignee: fields["assignee"]["displayName"].as_str().map(String::from),
+        url: None,
+        description: text(&fields["description"]),
+        comments: fields["comment"]["comments"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|c| (text(&c["author"]["displayName"]), text(&c["created"]), text(&c["body"])))
+            .collect(),
+    }
+}
+
+fn parse_linear(body: &Value) -> Option<Ticket> {
+    let issue = body["data"]["issue"].as_object()?;
+    let text = |v: &Value| v.as_str().unwrap_or("").to_string();
+    Some(Ticket {
+        key: text(&issue["identifier"]),
+        title: text(&issue["title"]),
+        status: issue["state"]["name"].as_str().unwrap_or("unknown").to_string(),
+        assignee: issue["assignee"]["name"].as_str().map(String::from),
+        url: issue["url"].as_str().map(String::from),
+        description: text(&issue["description"]),
+        comments: issue["comments"]["nodes"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|c| (text(&c["user"]["name"]), text(&c["createdAt"]), text(&c["body"])))
+            .collect(),
+    })
+}
+
+/// Whether `key` has the Jira form `PROJ-123`: `[A-Z][A-Z0-9_]+-[0-9]+`.
+fn is_jira_key(key: &str) -> bool {
+    let Some((project, number)) = key.split_once('-') else { return false };
+    let mut chars = project.chars();
+    chars.next().is_some_and(|c| c.is_ascii_uppercase())
+        && project.len() > 1
+        && chars.all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+        && !number.is_empty()
+        && number.chars().all(|c| c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jira_keys_are_validated() {
+        for key in ["PROJ-123", "AB-1", "A2_B-42"] {
+            assert!(is_jira_key(key), "{key}");
+        }
+        for key in ["", "P-1", "proj-1", "PROJ-", "PROJ-12a", "PROJ-1/../../admin", "../PROJ-1", "PROJ-1?x=1", "PROJ-1-2"] {
+            assert!(!is_jira_key(key), "{key}");
+        }
+    }
+
+    #[test]
+    fn parse_jira_issue() {
+        let issue = json!({
+            "key": "PROJ-123",
+            "fields": {
+                "summary": "Add export",
+                "description": "Users want CSV.",
+                "status": { "name": "In Progress" },
+                "assignee": null,
+                "comment": { "comments": [
+                    { "author": { "displayName": "Ann" }, "created": "2026-01-02", "body": "+1" }
+                ] }
+            }
+        });
+        let ticket = parse_jira(&issue);
+        assert_eq!(ticket.status, "In Progress");
+        assert_eq!(ticket.assignee, None);
+        assert_eq!(
+            ticket.render(),
+            "PROJ-123: Add export\nStatus: In Progress\n\nUsers want CSV.\n\n--- Ann at 2026-01-02 ---\n+1"
+        );
+    }
+
+    #[test]
+    fn parse_linear_issue() {
+        let body = json!({ "data": { "issue": {
+            "identifier": "ENG-7",
+            "title": "Fix login",
+            "description": null,
+            "url": "https://linear.app/acme/issue/ENG-7",
+            "state": { "name": "Todo" },
+            "assignee": { "name": "Bo" },
+            "comments": { "nodes": [] }
+        } } });
+        let ticket = parse_linear(&body).unwrap();
+        assert_eq!(ticket.assignee.as_deref(), Some("Bo"));
+        assert!(ticket.render().contains("(no description)"));
+        assert!(parse_linear(&json!({ "data": { "issue": null } })).is_none());
+    }
+}
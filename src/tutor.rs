@@ -0,0 +1,115 @@
+//! `pro tutor`: a guided walkthrough of modes, slash commands, code yanking
+//! and tool confirmations. It talks to the `mock` provider and keeps its
+//! config and history in a scratch directory, so nothing real is touched.
+
+use crate::app::{App, InputMode, Overlay};
+
+/// One lesson: what to do, and how to tell that it was done.
+pub struct Step {
+    pub title: &'static str,
+    pub instructions: &'static str,
+    done: fn(&App) -> bool,
+}
+
+pub const STEPS: &[Step] = &[
+    Step {
+        title: "Send a message",
+        instructions: "You start in Insert mode. Type anything, e.g. hello, and press Enter. Replies come from a built-in mock model, so nothing leaves your machine.",
+        done: |app| !app.is_streaming() && app.messages.iter().any(|m| m.role == "assistant" && !m.content.is_empty()),
+    },
+    Step {
+        title: "Normal mode",
+        instructions: "Press Esc for Normal mode, where keys are commands: j/k scroll, gg and G jump to the top and bottom, and i goes back to typing.",
+        done: |app| app.input_mode == InputMode::Normal,
+    },
+    Step {
+        title: "Yank code",
+        instructions: "The reply has a code block. Press Ctrl+y to number the code blocks, then 1 to copy the first one.",
+        done: |app| {
+            app.status_message
+                .as_deref()
+                .is_some_and(|s| s.starts_with("Yanked block") || s == "Failed to access clipboard")
+        },
+    },
+    Step {
+        title: "Slash commands",
+        instructions: "Press i, type /stats and press Enter. Slash commands change settings and open panels; /help lists them all.",
+        done: |app| app.overlay == Overlay::Stats,
+    },
+    Step {
+        title: "Close panels",
+        instructions: "Press Esc to close the panel.",
+        done: |app| app.overlay == Overlay::None,
+    },
+    Step {
+        title: "Tool calls",
+        instructions: "Press i and ask the model to run a command. Tools that change things wait for you: press y to allow this one.",
+        done: |app| app.messages.iter().any(|m| m.tool_invocations.iter().any(|t| t.result.is_some())),
+    },
+    Step {
+        title: "Done",
+        instructions: "That's the tour. Press ? for the full key reference and Ctrl+q to quit. Your real config and history were not touched.",
+        done: |_| false,
+    },
+];
+
+/// Progress through [`STEPS`].
+#[derive(Debug, Default)]
+pub struct Tutor {
+    pub step: usize,
+}
+
+impl Tutor {
+    pub fn current(&self) -> &'static Step {
+        &STEPS[self.step.min(STEPS.len() - 1)]
+    }
+}
+
+/// Move on once the current step's action has been done. At most one step
+/// per call, so a state that happens to satisfy the next step too (e.g. no
+/// overlay open) doesn't skip it.
+pub fn advance(app: &mut App) {
+    let Some(tutor) = &app.tutor else { return };
+    let step = tutor.current();
+    if (step.done)(app)
+        && let Some(tutor) = &mut app.tutor
+    {
+        tutor.step = (tutor.step + 1).min(STEPS.len() - 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_steps_advance_one_at_a_time() {
+        let mut app = App::new(Config::default());
+        app.tutor = Some(Tutor::default());
+        advance(&mut app);
+        assert_eq!(app.tutor.as_ref().unwrap().current().title, "Send a message");
+
+        app.tutor.as_mut().unwrap().step = 1;
+        advance(&mut app);
+        assert_eq!(app.tutor.as_ref().unwrap().current().title, "Normal mode");
+        app.input_mode = InputMode::Normal;
+        advance(&mut app);
+        assert_eq!(app.tutor.as_ref().unwrap().current().title, "Yank code");
+
+        // Opening Stats must not also complete "Close panels" in the same pass
+        app.tutor.as_mut().unwrap().step = 3;
+        app.overlay = Overlay::Stats;
+        advance(&mut app);
+        advance(&mut app);
+        assert_eq!(app.tutor.as_ref().unwrap().current().title, "Close panels");
+        app.overlay = Overlay::None;
+        advance(&mut app);
+        assert_eq!(app.tutor.as_ref().unwrap().current().title, "Tool calls");
+
+        // The last step stays put
+        app.tutor.as_mut().unwrap().step = STEPS.len() - 1;
+        advance(&mut app);
+        assert_eq!(app.tutor.as_ref().unwrap().current().title, "Done");
+    }
+}
@@ -3,17 +3,45 @@ use ratatui::prelude::*;
 use ratatui::widgets::*;
 use chrono::Local;
 
+use crate::api;
 use crate::app::{App, InputMode, Overlay, SetupState, SetupStep};
+use crate::config::ThemeColors;
 use crate::markdown;
 
 const SPINNER_FRAMES: &[&str] = &["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
 
+/// Below this many columns the status bar abbreviates its segments, code
+/// blocks lose their borders and the mode indicator shrinks to one letter.
+const NARROW_WIDTH: u16 = 70;
+
+fn is_narrow(width: u16) -> bool {
+    width < NARROW_WIDTH
+}
+
+/// One is shown on the welcome screen, a different one each launch.
+const WELCOME_TIPS: &[&str] = &[
+    "Ctrl+O previews the exact request before you send it",
+    "/models lists every model your provider offers",
+    "Ctrl+R regenerates the last response",
+    "Ctrl+Y lists code blocks; press 1-9 to yank one",
+    "/review shows every file tools changed this session",
+    "b bookmarks a message; /bookmarks lists them",
+    "c continues a response that was cut off at max_tokens",
+    "/cost shows what this conversation has cost so far",
+    "Shift+Enter adds a newline without sending",
+    "Ctrl+H browses history; pro --here keeps it to this project",
+];
+
 fn spinner_frame(tick: u64) -> &'static str {
     SPINNER_FRAMES[(tick as usize / 2) % SPINNER_FRAMES.len()]
 }
 
 pub fn draw(f: &mut Frame, app: &mut App) {
     let area = f.area();
+    if app.locked {
+        draw_lock_screen(f, app, area);
+        return;
+    }
 
     // Main layout: messages area + input + status bar
     let line_count = app.input.lines().count()
@@ -32,6 +60,9 @@ pub fn draw(f: &mut Frame, app: &mut App) {
     draw_messages(f, app, chunks[0]);
     draw_input(f, app, chunks[1]);
     draw_status_bar(f, app, chunks[2]);
+    if let Some(tutor) = &app.tutor {
+        draw_tutor(f, app, tutor, chunks[0]);
+    }
 
     // Draw overlay if active
     match &app.overlay {
@@ -39,9 +70,87 @@ pub fn draw(f: &mut Frame, app: &mut App) {
         Overlay::History => draw_history_overlay(f, app, area),
         Overlay::Settings => draw_settings_overlay(f, app, area),
         Overlay::ToolConfirm => draw_tool_confirm_overlay(f, app, area),
+        Overlay::ToolInput => draw_tool_input_overlay(f, app, area),
+        Overlay::RecipeConfirm => draw_recipe_confirm_overlay(f, app, area),
         Overlay::Setup => draw_setup_overlay(f, app, area),
-        Overlay::None => {}
+        Overlay::Stats => draw_stats_overlay(f, app, area),
+        Overlay::Dashboard => draw_dashboard_overlay(f, app, area),
+        Overlay::SystemPrompt => draw_system_prompt_overlay(f, app, area),
+        Overlay::RequestPreview => draw_request_preview_overlay(f, app, area),
+        Overlay::Recall => draw_recall_overlay(f, app, area),
+        Overlay::Picker => draw_picker_overlay(f, app, area),
+        Overlay::Bookmarks => draw_bookmarks_overlay(f, app, area),
+        Overlay::ResponseDiff => draw_response_diff_overlay(f, app, area),
+        Overlay::Review => draw_review_overlay(f, app, area),
+        Overlay::Models => draw_models_overlay(f, app, area),
+        Overlay::None => {
+            if let Some(prefix) = app.pending_prefix {
+                draw_which_key(f, app, prefix, chunks[0]);
+            }
+        }
+    }
+}
+
+/// The current `pro tutor` lesson, in the top-right corner of the messages
+/// area.
+fn draw_tutor(f: &mut Frame, app: &App, tutor: &crate::tutor::Tutor, area: Rect) {
+    let c = app.colors();
+    let width = 48.min(area.width);
+    let step = tutor.current();
+    let wrapped = textwrap::wrap(step.instructions, width.saturating_sub(2) as usize);
+    let height = (wrapped.len() as u16 + 3).min(area.height);
+    if width < 20 || height < 4 {
+        return;
+    }
+    let popup = Rect { x: area.x + area.width - width, y: area.y, width, height };
+    let mut lines = vec![Line::from(Span::styled(
+        step.title,
+        Style::default().fg(c.accent).add_modifier(Modifier::BOLD),
+    ))];
+    lines.extend(wrapped.into_iter().map(|l| Line::from(Span::styled(l.into_owned(), Style::default().fg(c.fg)))));
+    f.render_widget(Clear, popup);
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(c.accent))
+        .title(Span::styled(
+            format!(" Tutorial {}/{} ", tutor.step + 1, crate::tutor::STEPS.len()),
+            Style::default().fg(c.dim),
+        ));
+    f.render_widget(Paragraph::new(lines).block(block), popup);
+}
+
+/// The keys that can follow a pending prefix, in the bottom-right corner
+/// of the messages area.
+fn draw_which_key(f: &mut Frame, app: &App, prefix: char, area: Rect) {
+    let c = app.colors();
+    let bindings = crate::keybinds::prefix_bindings(prefix);
+    let lines: Vec<Line> = bindings
+        .iter()
+        .map(|b| {
+            Line::from(vec![
+                Span::styled(format!(" {prefix}{} ", b.key), Style::default().fg(c.accent).add_modifier(Modifier::BOLD)),
+                Span::styled(format!(" {} ", b.description), Style::default().fg(c.fg)),
+            ])
+        })
+        .collect();
+    let width = lines.iter().map(Line::width).max().unwrap_or(0) as u16 + 2;
+    let height = lines.len() as u16 + 2;
+    if width > area.width || height > area.height {
+        return;
     }
+    let popup = Rect {
+        x: area.x + area.width - width,
+        y: area.y + area.height - height,
+        width,
+        height,
+    };
+    f.render_widget(Clear, popup);
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(c.border))
+        .title(Span::styled(format!(" {prefix}… "), Style::default().fg(c.dim)))
+        .title_bottom(Line::from(Span::styled(" esc ", Style::default().fg(c.dim))).alignment(Alignment::Right));
+    f.render_widget(Paragraph::new(lines).block(block), popup);
 }
 
 fn draw_messages(f: &mut Frame, app: &mut App, area: Rect) {
@@ -54,67 +163,25 @@ fn draw_messages(f: &mut Frame, app: &mut App, area: Rect) {
     let inner = messages_block.inner(area);
     f.render_widget(messages_block, area);
 
-    if app.messages.is_empty() {
-        // Welcome screen
-        let banner_style = Style::default().fg(c.accent).add_modifier(Modifier::BOLD);
-        let dim_accent = Style::default().fg(c.border);
-        let welcome = vec![
-            Line::from(""),
-            Line::from(""),
-            Line::from(""),
-            Line::from(Span::styled("██████╗ ██████╗  ██████╗ ",  banner_style)),
-            Line::from(Span::styled("██╔══██╗██╔══██╗██╔═══██╗", banner_style)),
-            Line::from(Span::styled("██████╔╝██████╔╝██║   ██║", banner_style)),
-            Line::from(Span::styled("██╔═══╝ ██╔══██╗██║   ██║", banner_style)),
-            Line::from(Span::styled("██║     ██║  ██║╚██████╔╝", banner_style)),
-            Line::from(Span::styled("╚═╝     ╚═╝  ╚═╝ ╚═════╝", banner_style)),
-            Line::from(""),
-            Line::from(Span::styled(
-                "Fast AI chat in your terminal",
-                Style::default().fg(c.fg),
-            )),
-            Line::from(""),
-            Line::from(vec![
-                Span::styled("  ", dim_accent),
-                Span::styled(
-                    format!("{}", app.config.provider),
-                    Style::default().fg(c.accent).add_modifier(Modifier::BOLD),
-                ),
-                Span::styled(" / ", Style::default().fg(c.dim)),
-                Span::styled(
-                    format!("{}", app.config.model),
-                    Style::default().fg(c.dim),
-                ),
-            ]),
-            Line::from(Span::styled(
-                format!("  {}", std::env::current_dir().unwrap_or_default().display()),
-                Style::default().fg(c.dim).add_modifier(Modifier::DIM),
-            )),
-            Line::from(""),
-            Line::from(Span::styled(
-                "─────────────────────────────────",
-                Style::default().fg(c.border),
-            )),
-            Line::from(""),
-            Line::from(vec![
-                Span::styled("  i", Style::default().fg(c.accent).add_modifier(Modifier::BOLD)),
-                Span::styled(" insert  ", Style::default().fg(c.dim)),
-                Span::styled("?", Style::default().fg(c.accent).add_modifier(Modifier::BOLD)),
-                Span::styled(" help  ", Style::default().fg(c.dim)),
-                Span::styled(":q", Style::default().fg(c.accent).add_modifier(Modifier::BOLD)),
-                Span::styled(" quit  ", Style::default().fg(c.dim)),
-                Span::styled("/model", Style::default().fg(c.accent).add_modifier(Modifier::BOLD)),
-                Span::styled(" switch", Style::default().fg(c.dim)),
-            ]),
-        ];
-        let p = Paragraph::new(welcome).alignment(Alignment::Center);
-        f.render_widget(p, inner);
+    if app.messages.is_empty() && app.failed_sends.is_empty() && app.error_cards.is_empty() {
+        draw_welcome(f, app, inner);
         return;
     }
 
     // Build rendered lines from messages
     let mut all_lines: Vec<Line> = Vec::new();
     let width = inner.width as usize;
+    let last_id = app.messages.last().map(|m| m.id.as_str());
+    let push_cards = |lines: &mut Vec<Line>, after: Option<&str>| {
+        for (i, card) in app.error_cards.iter().enumerate() {
+            if card.after.as_deref() == after {
+                let latest = i + 1 == app.error_cards.len() && after == last_id;
+                let hint = latest.then_some("Ctrl+R to retry");
+                push_error_card(lines, &c, width, "Request failed", &card.error, card.failed_at, hint);
+            }
+        }
+    };
+    push_cards(&mut all_lines, None);
 
     for (msg_idx, msg) in app.messages.iter().enumerate() {
         // Separator between messages
@@ -151,12 +218,49 @@ fn draw_messages(f: &mut Frame, app: &mut App, area: Rect) {
                 format!("  {time_str}"),
                 Style::default().fg(c.dim).add_modifier(Modifier::DIM),
             ),
+            Span::styled(
+                format!("  #{}", msg.id),
+                Style::default().fg(c.border),
+            ),
+            Span::styled(
+                if app.is_bookmarked(&msg.id) { "  ★" } else { "" },
+                Style::default().fg(c.warning),
+            ),
         ]));
         all_lines.push(Line::from(""));
 
+        // Extended thinking, collapsed to one line unless expanded
+        if let Some(ref thinking) = msg.thinking {
+            let style = Style::default().fg(c.dim).add_modifier(Modifier::DIM | Modifier::ITALIC);
+            if app.show_thinking {
+                all_lines.push(Line::from(Span::styled("    ▾ Thinking", style)));
+                let max_width = width.saturating_sub(8).max(20);
+                for line in thinking.lines() {
+                    let mut current = String::new();
+                    for word in line.split_whitespace() {
+                        if !current.is_empty() && current.len() + 1 + word.len() > max_width {
+                            all_lines.push(Line::from(Span::styled(format!("    │ {current}"), style)));
+                            current.clear();
+                        }
+                        if !current.is_empty() {
+                            current.push(' ');
+                        }
+                        current.push_str(word);
+                    }
+                    all_lines.push(Line::from(Span::styled(format!("    │ {current}"), style)));
+                }
+            } else {
+                all_lines.push(Line::from(Span::styled(
+                    format!("    ▸ Thinking… ({} words · T to expand)", thinking.split_whitespace().count()),
+                    style,
+                )));
+            }
+            all_lines.push(Line::from(""));
+        }
+
         // Message content
         if msg.role == "assistant" {
-            let parsed = markdown::parse_markdown(&msg.content);
+            let parsed = markdown::parse_markdown(&msg.content, !is_narrow(area.width));
             let max_width = width.saturating_sub(6);
             for line in parsed {
                 // Word-wrap long lines that are a single plain-text span
@@ -210,6 +314,12 @@ fn draw_messages(f: &mut Frame, app: &mut App, area: Rect) {
                     all_lines.push(Line::from(format!("    {line}")));
                 }
             }
+            for label in &msg.attachments {
+                all_lines.push(Line::from(Span::styled(
+                    format!("    📎 {label}"),
+                    Style::default().fg(c.accent),
+                )));
+            }
         }
 
         // Tool invocations
@@ -218,6 +328,7 @@ fn draw_messages(f: &mut Frame, app: &mut App, area: Rect) {
             let status_icon = match &inv.result {
                 Some(r) if r.success => "✓",
                 Some(_) => "✗",
+                None if app.session == crate::app::SessionState::RunningTools => spinner_frame(app.tick_count),
                 None => "⋯",
             };
             let status_color = match &inv.result {
@@ -225,7 +336,7 @@ fn draw_messages(f: &mut Frame, app: &mut App, area: Rect) {
                 Some(_) => Color::Rgb(247, 118, 142),
                 None => c.warning,
             };
-            all_lines.push(Line::from(vec![
+            let mut header = vec![
                 Span::styled("    ", Style::default()),
                 Span::styled(
                     format!("{status_icon} "),
@@ -239,7 +350,16 @@ fn draw_messages(f: &mut Frame, app: &mut App, area: Rect) {
                     format!("  {}", inv.tool_args),
                     Style::default().fg(c.dim),
                 ),
-            ]));
+            ];
+            if inv.cached {
+                header.push(Span::styled("  cached", Style::default().fg(c.border)));
+            } else if let Some(d) = inv.duration {
+                header.push(Span::styled(
+                    format!("  {}", crate::tools::format_duration(d)),
+                    Style::default().fg(c.border),
+                ));
+            }
+            all_lines.push(Line::from(header));
 
             if let Some(ref result) = inv.result {
                 if !inv.collapsed {
@@ -262,17 +382,33 @@ fn draw_messages(f: &mut Frame, app: &mut App, area: Rect) {
                             Style::default().fg(c.border),
                         )));
                     }
+                    for hint in &result.hints {
+                        all_lines.push(Line::from(Span::styled(
+                            format!("      hint: {hint}"),
+                            Style::default().fg(c.warning),
+                        )));
+                    }
                 } else {
                     all_lines.push(Line::from(Span::styled(
                         format!("      ▸ {} lines collapsed", result.output.lines().count()),
                         Style::default().fg(c.border),
                     )));
                 }
+            } else if !inv.live_output.is_empty() {
+                // Still running: the latest lines, as they arrive
+                let output_lines: Vec<&str> = inv.live_output.lines().collect();
+                let max_lines = 15;
+                for ol in &output_lines[output_lines.len().saturating_sub(max_lines)..] {
+                    all_lines.push(Line::from(Span::styled(
+                        format!("      {ol}"),
+                        Style::default().fg(c.dim).add_modifier(Modifier::DIM),
+                    )));
+                }
             }
         }
 
         // Streaming indicator with spinner
-        if msg.role == "assistant" && app.streaming {
+        if msg.role == "assistant" && app.is_streaming() {
             let frame = spinner_frame(app.tick_count);
             if msg.content.is_empty() && msg.tool_invocations.is_empty() {
                 all_lines.push(Line::from(vec![
@@ -294,6 +430,58 @@ fn draw_messages(f: &mut Frame, app: &mut App, area: Rect) {
                 }
             }
         }
+
+        // Why the model stopped, when it wasn't the end of its turn
+        if let Some(ref reason) = msg.stop_reason {
+            let cut_off = reason == "max_tokens";
+            let hint = if cut_off && msg_idx + 1 == app.messages.len() && !app.is_streaming() {
+                " · c to continue"
+            } else {
+                ""
+            };
+            all_lines.push(Line::from(Span::styled(
+                format!("    ⏹ stopped: {reason}{hint}"),
+                Style::default().fg(if cut_off { c.warning } else { c.dim }),
+            )));
+        }
+
+        // Which model produced the reply, with its tokens and latency
+        if app.config.message_footer
+            && let Some(ref meta) = msg.meta
+        {
+            all_lines.push(Line::from(Span::styled(
+                format!("    {}", message_footer(meta, msg.stop_reason.is_none())),
+                Style::default().fg(c.border),
+            )));
+        }
+
+        // Private note, rendered as a dimmed footnote
+        if let Some(ref note) = msg.note {
+            for (i, line) in note.lines().enumerate() {
+                let prefix = if i == 0 { "    ✎ " } else { "      " };
+                all_lines.push(Line::from(Span::styled(
+                    format!("{prefix}{line}"),
+                    Style::default().fg(c.dim).add_modifier(Modifier::ITALIC | Modifier::DIM),
+                )));
+            }
+        }
+
+        push_cards(&mut all_lines, Some(msg.id.as_str()));
+    }
+
+    // Messages whose request failed, waiting to be resent
+    for (i, failed) in app.failed_sends.iter().enumerate() {
+        let hint = (i + 1 == app.failed_sends.len()).then_some("R to resend");
+        push_error_card(&mut all_lines, &c, width, "Not sent", &failed.error, failed.failed_at, None);
+        for line in failed.content.lines() {
+            all_lines.push(Line::from(Span::styled(
+                format!("    › {line}"),
+                Style::default().fg(c.dim),
+            )));
+        }
+        if let Some(hint) = hint {
+            all_lines.push(Line::from(Span::styled(format!("    {hint}"), Style::default().fg(c.border))));
+        }
     }
 
     // Handle scrolling
@@ -326,29 +514,187 @@ fn draw_messages(f: &mut Frame, app: &mut App, area: Rect) {
     }
 }
 
+/// `model · ↑in ↓out tok · 2.3s · end_turn`, leaving out what wasn't
+/// reported. The stop reason is skipped when the `⏹ stopped` line shows it.
+fn message_footer(meta: &crate::history::ResponseMeta, with_stop_reason: bool) -> String {
+    let mut parts = vec![meta.model.clone()];
+    if let (Some(input), Some(output)) = (meta.input_tokens, meta.output_tokens) {
+        parts.push(format!("↑{} ↓{} tok", format_tokens(input), format_tokens(output)));
+    }
+    if let Some(ms) = meta.latency_ms {
+        parts.push(format!("{:.1}s", ms as f64 / 1000.0));
+    }
+    if with_stop_reason && let Some(ref reason) = meta.stop_reason {
+        parts.push(reason.clone());
+    }
+    parts.join(" · ")
+}
+
+/// A failed request in the transcript: a header with the HTTP status and
+/// time, the provider's error message, and how to retry.
+fn push_error_card(
+    lines: &mut Vec<Line<'_>>,
+    c: &ThemeColors,
+    width: usize,
+    title: &str,
+    error: &str,
+    failed_at: chrono::DateTime<chrono::Utc>,
+    hint: Option<&str>,
+) {
+    let red = Color::Rgb(247, 118, 142);
+    let mut header = vec![Span::styled(format!("  ✗ {title}"), Style::default().fg(red).add_modifier(Modifier::BOLD))];
+    if let Some(status) = api::error_status(error) {
+        header.push(Span::styled(format!(" · {status}"), Style::default().fg(red)));
+    }
+    header.push(Span::styled(
+        format!(" · {}", failed_at.with_timezone(&Local).format("%H:%M")),
+        Style::default().fg(c.dim),
+    ));
+    lines.push(Line::from(""));
+    lines.push(Line::from(header));
+
+    let max_width = width.saturating_sub(8).max(20);
+    for paragraph in api::error_detail(error).lines() {
+        let mut current = String::new();
+        for word in paragraph.split_whitespace() {
+            if !current.is_empty() && current.chars().count() + 1 + word.chars().count() > max_width {
+                lines.push(Line::from(Span::styled(format!("  │ {current}"), Style::default().fg(c.fg))));
+                current.clear();
+            }
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(word);
+        }
+        lines.push(Line::from(Span::styled(format!("  │ {current}"), Style::default().fg(c.fg))));
+    }
+    if let Some(hint) = hint {
+        lines.push(Line::from(Span::styled(format!("    {hint}"), Style::default().fg(c.border))));
+    }
+}
+
+/// Start screen: what requests will go to, recent conversations to pick up,
+/// and a tip that changes every launch.
+fn draw_welcome(f: &mut Frame, app: &App, area: Rect) {
+    let c = app.colors();
+    let key = |text: &'static str| Span::styled(text, Style::default().fg(c.accent).add_modifier(Modifier::BOLD));
+    let dim = |text: String| Span::styled(text, Style::default().fg(c.dim));
+
+    let mut welcome = vec![
+        Line::from(""),
+        Line::from(""),
+        Line::from(Span::styled("Pro Chat", Style::default().fg(c.accent).add_modifier(Modifier::BOLD))),
+        Line::from(""),
+        Line::from(vec![
+            Span::styled(
+                format!("{} {}", provider_icon(&app.config.provider), app.config.provider),
+                Style::default().fg(c.accent).add_modifier(Modifier::BOLD),
+            ),
+            dim(" / ".into()),
+            Span::styled(app.config.model.to_string(), Style::default().fg(c.fg)),
+            dim(" · ".into()),
+            match key_status(app) {
+                Ok(status) => Span::styled(status, Style::default().fg(c.success)),
+                Err(status) => Span::styled(status, Style::default().fg(c.warning)),
+            },
+        ]),
+        Line::from(Span::styled(
+            std::env::current_dir().unwrap_or_default().display().to_string(),
+            Style::default().fg(c.dim).add_modifier(Modifier::DIM),
+        )),
+        Line::from(""),
+    ];
+
+    if !app.welcome_recent.is_empty() {
+        welcome.push(Line::from(Span::styled("Recent", Style::default().fg(c.warning).add_modifier(Modifier::BOLD))));
+        let title_width = (area.width as usize).saturating_sub(20).clamp(10, 40);
+        for (i, conv) in app.welcome_recent.iter().enumerate() {
+            let selected = i == app.welcome_selected;
+            let style = if selected {
+                Style::default().fg(c.accent).add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(c.fg)
+            };
+            let title: String = conv.title.chars().take(title_width).collect();
+            welcome.push(Line::from(vec![
+                Span::styled(if selected { "▸ " } else { "  " }, style),
+                Span::styled(format!("{title:<title_width$}"), style),
+                dim(format!("  {}", conv.updated_at.with_timezone(&Local).format("%b %d %H:%M"))),
+            ]));
+        }
+        let hint = if app.input_mode == InputMode::Normal { "j/k select · Enter open" } else { "Enter open · Esc then j/k to select" };
+        welcome.push(Line::from(dim(hint.into())));
+        welcome.push(Line::from(""));
+    }
+
+    welcome.push(Line::from(vec![
+        key("i"),
+        dim(" insert  ".into()),
+        key("?"),
+        dim(" help  ".into()),
+        key(":q"),
+        dim(" quit  ".into()),
+        key("/model"),
+        dim(" switch".into()),
+    ]));
+    welcome.push(Line::from(""));
+    welcome.push(Line::from(vec![
+        Span::styled("Tip: ", Style::default().fg(c.warning)),
+        dim(WELCOME_TIPS[app.launch_count % WELCOME_TIPS.len()].into()),
+    ]));
+
+    let p = Paragraph::new(welcome).alignment(Alignment::Center).wrap(Wrap { trim: true });
+    f.render_widget(p, area);
+}
+
+/// Which credentials the current provider will use, or what is missing.
+fn key_status(app: &App) -> Result<String, String> {
+    match app.config.provider.as_str() {
+        "bedrock" => Ok("AWS credentials".into()),
+        _ => match app.config.api_key_from_env() {
+            Some(key) if key.is_empty() => Ok("no key needed".into()),
+            Some(key) => {
+                let tail: String = key.chars().rev().take(4).collect::<Vec<_>>().into_iter().rev().collect();
+                Ok(format!("key …{tail}"))
+            }
+            None => Err(format!("no key: set {} or /setup", app.config.api_key_env_var())),
+        },
+    }
+}
+
 fn draw_input(f: &mut Frame, app: &App, area: Rect) {
     let c = app.colors();
     let dark_bg = c.bg_dark;
 
-    let mode_indicator = match app.input_mode {
-        InputMode::Normal => Span::styled(" NOR ", Style::default().bg(c.accent).fg(dark_bg).add_modifier(Modifier::BOLD)),
-        InputMode::Insert => Span::styled(" INS ", Style::default().bg(c.user_label).fg(dark_bg).add_modifier(Modifier::BOLD)),
-        InputMode::Command => Span::styled(" CMD ", Style::default().bg(c.warning).fg(dark_bg).add_modifier(Modifier::BOLD)),
-        InputMode::Search => Span::styled(" SRC ", Style::default().bg(Color::Rgb(247, 118, 142)).fg(dark_bg).add_modifier(Modifier::BOLD)),
+    let (label, bg) = match app.input_mode {
+        InputMode::Normal => (" NOR ", c.accent),
+        InputMode::Insert => (" INS ", c.user_label),
+        InputMode::Command => (" CMD ", c.warning),
+        InputMode::Search => (" SRC ", Color::Rgb(247, 118, 142)),
     };
+    let label = if is_narrow(area.width) { &label[..2] } else { label };
+    let mode_indicator = Span::styled(label, Style::default().bg(bg).fg(dark_bg).add_modifier(Modifier::BOLD));
 
     // Build right-side title spans
     let line_count = app.input.lines().count();
     let has_trailing_newline = app.input.ends_with('\n');
     let effective_lines = if has_trailing_newline { line_count + 1 } else { line_count };
     let mut right_title_spans: Vec<Span> = Vec::new();
+    if let [only] = app.pending_attachments.as_slice() {
+        right_title_spans.push(Span::styled(format!(" 📎 {} ", only.label()), Style::default().fg(c.accent)));
+    } else if !app.pending_attachments.is_empty() {
+        right_title_spans.push(Span::styled(
+            format!(" 📎 {} PDFs ", app.pending_attachments.len()),
+            Style::default().fg(c.accent),
+        ));
+    }
     if effective_lines > 1 {
         right_title_spans.push(Span::styled(
             format!(" [{} lines] ", effective_lines),
             Style::default().fg(c.dim),
         ));
     }
-    if app.streaming {
+    if app.is_streaming() {
         let frame = spinner_frame(app.tick_count);
         right_title_spans.push(Span::styled(
             format!(" {frame} streaming... "),
@@ -436,42 +782,136 @@ fn provider_icon(provider: &str) -> &'static str {
         "openai" => "◎",
         "openrouter" => "⬡",
         "xai" => "✕",
+        "gemini" => "◆",
+        "ollama" => "◉",
+        "bedrock" => "▣",
         _ => "●",
     }
 }
 
+/// Token count for the status bar: `840`, `12.3k`.
+fn format_tokens(n: u64) -> String {
+    if n >= 1000 {
+        format!("{:.1}k", n as f64 / 1000.0)
+    } else {
+        n.to_string()
+    }
+}
+
+/// Time left until `at`, e.g. `42s` or `3m05s`; `now` once it has passed.
+pub fn format_until(at: chrono::DateTime<chrono::Utc>, now: chrono::DateTime<chrono::Utc>) -> String {
+    match (at - now).to_std() {
+        Ok(left) if !left.is_zero() => crate::tools::format_duration(left),
+        _ => "now".into(),
+    }
+}
+
 fn draw_status_bar(f: &mut Frame, app: &App, area: Rect) {
     let c = app.colors();
+    let bg = if app.flashing() { c.border } else { c.bg_dark };
 
     let icon = provider_icon(&app.config.provider);
-    let mut spans = vec![
-        Span::styled(
-            format!(" {icon} "),
-            Style::default().fg(c.accent).add_modifier(Modifier::BOLD),
-        ),
-        Span::styled(
-            format!("{}", app.config.provider),
+    let narrow = is_narrow(area.width);
+    // Full label, or the short form used on narrow terminals
+    let pick = |full: String, short: String| if narrow { short } else { full };
+    let sep = if narrow { " " } else { " │ " };
+    let mut spans = Vec::new();
+    if let Some(ref profile) = app.config.active_profile {
+        spans.push(Span::styled(
+            format!(" [{profile}]"),
+            Style::default().fg(c.warning).add_modifier(Modifier::BOLD),
+        ));
+    }
+    if app.viewer {
+        spans.push(Span::styled(
+            if narrow { " 👁" } else { " 👁 viewer (read-only)" },
+            Style::default().fg(c.warning).add_modifier(Modifier::BOLD),
+        ));
+    }
+    if let Some(pid) = app.read_only_pid() {
+        spans.push(Span::styled(
+            pick(format!(" 🔒 read-only (PID {pid})"), " 🔒".into()),
+            Style::default().fg(c.warning).add_modifier(Modifier::BOLD),
+        ));
+    }
+    spans.push(Span::styled(
+        format!(" {icon} "),
+        Style::default().fg(c.accent).add_modifier(Modifier::BOLD),
+    ));
+    if !narrow {
+        spans.push(Span::styled(
+            app.config.provider.to_string(),
             Style::default().fg(c.accent),
-        ),
-        Span::styled(" │ ", Style::default().fg(c.border)),
-        Span::styled(
-            format!("{}", app.config.model),
-            Style::default().fg(c.fg),
-        ),
-    ];
+        ));
+        spans.push(Span::styled(sep, Style::default().fg(c.border)));
+    }
+    spans.push(Span::styled(
+        app.config.model.to_string(),
+        Style::default().fg(c.fg),
+    ));
 
     // Tools status
     if app.tools_enabled {
-        spans.push(Span::styled(" │ ", Style::default().fg(c.border)));
+        spans.push(Span::styled(sep, Style::default().fg(c.border)));
         spans.push(Span::styled(
-            "⚙ tools",
+            if narrow { "⚙" } else { "⚙ tools" },
             Style::default().fg(c.success),
         ));
     }
 
+    // JSON mode status
+    if let Some(ref json) = app.json_mode {
+        spans.push(Span::styled(sep, Style::default().fg(c.border)));
+        spans.push(Span::styled(
+            match (narrow, json.schema.is_some()) {
+                (true, _) => "{}",
+                (false, true) => "{} json+schema",
+                (false, false) => "{} json",
+            },
+            Style::default().fg(c.accent),
+        ));
+    }
+
+    // Code mode status
+    if app.code_mode.is_some() {
+        spans.push(Span::styled(sep, Style::default().fg(c.border)));
+        spans.push(Span::styled(if narrow { "</>" } else { "</> code" }, Style::default().fg(c.accent)));
+    }
+
+    // Tee status
+    if app.tee.is_some() {
+        spans.push(Span::styled(sep, Style::default().fg(c.border)));
+        spans.push(Span::styled(if narrow { "⤓" } else { "⤓ tee" }, Style::default().fg(c.accent)));
+    }
+
+    // Autonomy above the default
+    if app.autonomy != crate::config::Autonomy::Low {
+        spans.push(Span::styled(sep, Style::default().fg(c.border)));
+        spans.push(Span::styled(
+            pick(format!("⚡ {}", app.autonomy.as_str()), "⚡".into()),
+            Style::default().fg(c.warning),
+        ));
+    }
+
+    // Where the turn is: streaming, waiting on a tool prompt, running tools
+    if app.session != crate::app::SessionState::Idle {
+        let frame = spinner_frame(app.tick_count);
+        spans.push(Span::styled(sep, Style::default().fg(c.border)));
+        spans.push(Span::styled(
+            pick(format!("{frame} {}", app.session.label()), frame.to_string()),
+            Style::default().fg(c.assistant_label),
+        ));
+    }
+
+    // Edit batch status
+    if app.tool_executor.edit_batch_open() {
+        spans.push(Span::styled(sep, Style::default().fg(c.border)));
+        spans.push(Span::styled(if narrow { "✎" } else { "✎ batch" }, Style::default().fg(c.warning)));
+    }
+
     // Neovim status
     if let Some(ref nvim) = app.neovim {
-        spans.push(Span::styled(" │ ", Style::default().fg(c.border)));
+        spans.push(Span::styled(sep, Style::default().fg(c.border)));
         if nvim.is_connected() {
             spans.push(Span::styled(
                 " nvim",
@@ -485,32 +925,95 @@ fn draw_status_bar(f: &mut Frame, app: &App, area: Rect) {
         }
     }
 
+    // Changed files indicator
+    if !app.watched_files.changed.is_empty() {
+        spans.push(Span::styled(sep, Style::default().fg(c.border)));
+        spans.push(Span::styled(
+            pick(
+                format!("⟳ {} changed", app.watched_files.changed.len()),
+                format!("⟳{}", app.watched_files.changed.len()),
+            ),
+            Style::default().fg(c.warning),
+        ));
+    }
+
+    // Prompt queue indicator
+    if !app.prompt_queue.is_empty() {
+        spans.push(Span::styled(sep, Style::default().fg(c.border)));
+        spans.push(Span::styled(
+            pick(format!("⧗ {} queued", app.prompt_queue.len()), format!("⧗{}", app.prompt_queue.len())),
+            Style::default().fg(c.warning),
+        ));
+    }
+
+    // Failed sends indicator
+    if !app.failed_sends.is_empty() {
+        spans.push(Span::styled(sep, Style::default().fg(c.border)));
+        spans.push(Span::styled(
+            pick(format!("✗ {} unsent", app.failed_sends.len()), format!("✗{}", app.failed_sends.len())),
+            Style::default().fg(Color::Rgb(247, 118, 142)).add_modifier(Modifier::BOLD),
+        ));
+    }
+
+    // Replay indicator
+    if let Some(ref replay) = app.replay {
+        spans.push(Span::styled(sep, Style::default().fg(c.border)));
+        spans.push(Span::styled(
+            pick(
+                format!("▶ REPLAY {}/{}", replay.shown, replay.all.len()),
+                format!("▶ {}/{}", replay.shown, replay.all.len()),
+            ),
+            Style::default().fg(c.accent).add_modifier(Modifier::BOLD),
+        ));
+        if !narrow {
+            spans.push(Span::styled(
+                " space next · s stream · q exit",
+                Style::default().fg(c.dim),
+            ));
+        }
+    }
+
     // Scroll lock indicator
     if !app.auto_scroll {
-        spans.push(Span::styled(" │ ", Style::default().fg(c.border)));
+        spans.push(Span::styled(sep, Style::default().fg(c.border)));
         spans.push(Span::styled(
-            "↕ SCROLL LOCKED",
+            if narrow { "↕" } else { "↕ SCROLL LOCKED" },
             Style::default().fg(Color::Rgb(247, 118, 142)).add_modifier(Modifier::BOLD),
         ));
     }
 
     // Status message
     if let Some(ref msg) = app.status_message {
-        spans.push(Span::styled(" │ ", Style::default().fg(c.border)));
+        spans.push(Span::styled(sep, Style::default().fg(c.border)));
         spans.push(Span::styled(
-            format!("{msg}"),
+            msg.to_string(),
             Style::default().fg(c.warning),
         ));
     }
 
-    // Right side: token estimate, timing, and message count
-    let estimated_tokens = app.estimate_tokens();
-    let token_display = if estimated_tokens >= 1000 {
-        format!("~{:.1}k", estimated_tokens as f64 / 1000.0)
+    // Right side: token usage, timing, and message count
+    let mut token_display = if app.token_usage.is_empty() {
+        format!("~{} tok", format_tokens(app.estimate_tokens() as u64))
     } else {
-        format!("~{}", estimated_tokens)
+        format!(
+            "↑{} ↓{} tok",
+            format_tokens(app.token_usage.input_tokens),
+            format_tokens(app.token_usage.output_tokens)
+        )
     };
-    let timing_display = if app.streaming {
+    if let Some(cost) = app.conversation_cost() {
+        token_display.push_str(&format!(" ${cost:.2}"));
+    }
+    if let Some(window) = app.config.context_window()
+        && !narrow
+    {
+        token_display = format!(
+            "{}/{}k ctx · {token_display}",
+            format_tokens(app.context_tokens() as u64),
+            window / 1000
+        );
+    }
+    let timing_display = if app.is_streaming() {
         if let Some(start) = app.stream_start_time {
             let elapsed = start.elapsed().as_secs_f64();
             format!(" {elapsed:.1}s")
@@ -523,18 +1026,22 @@ fn draw_status_bar(f: &mut Frame, app: &App, area: Rect) {
         String::new()
     };
     let msg_count = app.messages.len();
-    let right_text = format!(" {token_display} tok{timing_display} │ {msg_count} msgs ");
+    let right_text = if narrow {
+        format!(" {token_display}{timing_display} ")
+    } else {
+        format!(" {token_display}{timing_display} │ {msg_count} msgs ")
+    };
 
     let left = Line::from(spans);
     let right = Span::styled(right_text, Style::default().fg(c.dim));
 
     let bar = Paragraph::new(left)
-        .style(Style::default().bg(c.bg_dark));
+        .style(Style::default().bg(bg));
     f.render_widget(bar, area);
 
-    // Right-aligned text
+    // Right-aligned text, dropped entirely when it would cover most of the bar
     let right_width = right.width() as u16;
-    if area.width > right_width {
+    if area.width > right_width * 2 {
         let right_area = Rect::new(
             area.x + area.width - right_width,
             area.y,
@@ -542,11 +1049,31 @@ fn draw_status_bar(f: &mut Frame, app: &App, area: Rect) {
             1,
         );
         let right_p = Paragraph::new(Line::from(right))
-            .style(Style::default().bg(c.bg_dark));
+            .style(Style::default().bg(bg));
         f.render_widget(right_p, right_area);
     }
 }
 
+/// Shown instead of everything else after `idle_lock_minutes`, so nothing
+/// from the conversation stays on screen.
+fn draw_lock_screen(f: &mut Frame, app: &App, area: Rect) {
+    let c = app.colors();
+    f.render_widget(Clear, area);
+    let text = vec![
+        Line::from(Span::styled("Locked", Style::default().fg(c.accent).add_modifier(Modifier::BOLD))),
+        Line::from(""),
+        Line::from(Span::styled(
+            format!("Idle for {} min; the conversation was saved.", app.config.idle_lock_minutes),
+            Style::default().fg(c.dim),
+        )),
+        Line::from(Span::styled("Press any key to resume", Style::default().fg(c.dim))),
+    ];
+    let top = area.y + area.height.saturating_sub(text.len() as u16) / 2;
+    let p = Paragraph::new(text).alignment(Alignment::Center).style(Style::default().bg(c.bg_dark));
+    f.render_widget(Block::default().style(Style::default().bg(c.bg_dark)), area);
+    f.render_widget(p, Rect::new(area.x, top, area.width, area.height.saturating_sub(top - area.y)));
+}
+
 fn draw_help_overlay(f: &mut Frame, app: &App, area: Rect) {
     let c = app.colors();
     let overlay_area = centered_rect(60, 80, area);
@@ -574,7 +1101,13 @@ fn draw_help_overlay(f: &mut Frame, app: &App, area: Rect) {
         Line::from(Span::raw("  /            Search messages")),
         Line::from(Span::raw("  n/N          Next/prev match")),
         Line::from(Span::raw("  Ctrl+r       Retry/regenerate last response")),
+        Line::from(Span::raw("  R            Resend last failed message")),
+        Line::from(Span::raw("  c            Continue a response cut off at max_tokens")),
+        Line::from(Span::raw("  T            Expand/collapse extended thinking")),
+        Line::from(Span::raw("  Ctrl+o       Preview the next request")),
         Line::from(Span::raw("  e            Edit last user message")),
+        Line::from(Span::raw("  b            Bookmark message (empty input)")),
+        Line::from(Span::raw("  gn           Annotate message with a private note")),
         Line::from(Span::raw("  Ctrl+h       History")),
         Line::from(Span::raw("  Ctrl+n       New conversation")),
         Line::from(Span::raw("  Ctrl+l       Clear conversation")),
@@ -591,19 +1124,51 @@ fn draw_help_overlay(f: &mut Frame, app: &App, area: Rect) {
         Line::from(Span::styled("Commands", Style::default().fg(c.warning).add_modifier(Modifier::BOLD))),
         Line::from(Span::raw("  /clear       Clear conversation")),
         Line::from(Span::raw("  /new         New conversation")),
-        Line::from(Span::raw("  /model <m>   Set model (use /models for aliases)")),
-        Line::from(Span::raw("  /provider    Set provider (anthropic/openai/openrouter/xai)")),
-        Line::from(Span::raw("  /system      Set system prompt")),
+        Line::from(Span::raw("  /model <m>   Set model (aliases: sonnet/opus/haiku/gpt4/gpt4m/grok/...)")),
+        Line::from(Span::raw("  /models [f]  Pick from the provider's models (filtered by f)")),
+        Line::from(Span::raw("  /provider    Set provider (anthropic/openai/openrouter/xai/gemini/ollama/bedrock)")),
+        Line::from(Span::raw("  /system      Edit system prompt (or /system <text>)")),
         Line::from(Span::raw("  /temp <t>    Set temperature")),
+        Line::from(Span::raw("  /top_p /top_k <v|off>  Set nucleus / top-k sampling")),
+        Line::from(Span::raw("  /stop <s..>  Set stop sequences (\\n for newline, off to clear)")),
         Line::from(Span::raw("  /history     Browse history")),
         Line::from(Span::raw("  /nvim        Connect neovim")),
         Line::from(Span::raw("  /file <p>    Load file into input")),
+        Line::from(Span::raw("  /attach <p>  Send a PDF with the next message (clear to drop)")),
+        Line::from(Span::raw("  /mcp         MCP servers and their tools")),
         Line::from(Span::raw("  /diff        Load git diff into input")),
         Line::from(Span::raw("  /export      Export conversation to markdown")),
+        Line::from(Span::raw("  /goto <id>   Jump to a message by its #id")),
+        Line::from(Span::raw("  /bookmarks   Browse bookmarked messages")),
+        Line::from(Span::raw("  /note <t>    Attach a private note to the focused message (gn)")),
         Line::from(Span::raw("  /theme <t>   Switch color theme")),
         Line::from(Span::raw("  /retry       Regenerate last response")),
+        Line::from(Span::raw("  /resend      Resend last failed message")),
+        Line::from(Span::raw("  /queue <p>   Queue a prompt to send after the current reply")),
+        Line::from(Span::raw("  /diffresp    Diff the discarded and regenerated response")),
+        Line::from(Span::raw("  /review      Review, accept or revert files changed by tools")),
+        Line::from(Span::raw("  /autonomy    How often tools ask first (low/medium/high)")),
+        Line::from(Span::raw("  /sandbox     Directories file tools may use (on/off/add/remove)")),
+        Line::from(Span::raw("  /cd <dir>    Change directory and load .prochat/settings.toml")),
+        Line::from(Span::raw("  /trust       Apply this project's settings file in full")),
+        Line::from(Span::raw("  /replay [id] Replay a conversation step by step (add 'stream' to type out replies)")),
         Line::from(Span::raw("  /edit        Edit last user message")),
         Line::from(Span::raw("  /setup       Provider setup wizard")),
+        Line::from(Span::raw("  /stats       Tool timing and success rates")),
+        Line::from(Span::raw("  /cost        Cost of this conversation so far")),
+        Line::from(Span::raw("  /continue    Extend the last response in place")),
+        Line::from(Span::raw("  /thinking N  Claude extended thinking budget (off, show, hide)")),
+        Line::from(Span::raw("  /footer      Toggle the model/tokens/latency footer under replies")),
+        Line::from(Span::raw("  /dashboard   Usage analytics across all history")),
+        Line::from(Span::raw("  /preview     Show exactly what the next request sends")),
+        Line::from(Span::raw("  /json on|off Request strict JSON replies (schema <path>, fold <n>)")),
+        Line::from(Span::raw("  /code on|off Code-only replies, auto-copied (file <path> to save)")),
+        Line::from(Span::raw("  /recipe [name] Run a config recipe (lists recipes without a name)")),
+        Line::from(Span::raw("  /ticket <KEY> Load a Jira/Linear ticket into input")),
+        Line::from(Span::raw("  /recall <q>   Find past conversations by meaning")),
+        Line::from(Span::raw("  /tee <path>   Append streamed replies to a file (off to stop)")),
+        Line::from(Span::raw("  /cache [clear] Show or clear cached read_file/list_files results")),
+        Line::from(Span::raw("  /refresh      Load files changed on disk into input (dismiss to ignore)")),
         Line::from(Span::raw("  /save        Save config")),
         Line::from(Span::raw("  /quit        Quit")),
         Line::from(""),
@@ -612,6 +1177,9 @@ fn draw_help_overlay(f: &mut Frame, app: &App, area: Rect) {
         Line::from(Span::raw("  openai       GPT (gpt4/gpt4m)")),
         Line::from(Span::raw("  openrouter   Any model (deepseek/llama/gemini/mistral)")),
         Line::from(Span::raw("  xai          Grok (grok/grok3/grok3m/grok2)")),
+        Line::from(Span::raw("  gemini       Gemini (gemini-pro/gemini-flash)")),
+        Line::from(Span::raw("  ollama       Local models (/models lists them)")),
+        Line::from(Span::raw("  bedrock      Claude via AWS (credentials from env or ~/.aws)")),
         Line::from(""),
         Line::from(Span::styled("  Press Esc or q to close", Style::default().fg(c.dim))),
     ];
@@ -638,26 +1206,47 @@ fn draw_history_overlay(f: &mut Frame, app: &App, area: Rect) {
     let overlay_area = centered_rect(60, 70, area);
     f.render_widget(Clear, overlay_area);
 
+    let title = if app.history_trash {
+        " Trash ".to_string()
+    } else if app.history_project_only {
+        let project = crate::history::current_project().unwrap_or_default();
+        let name = std::path::Path::new(&project)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or(project);
+        format!(" History — {name} ")
+    } else {
+        " History ".to_string()
+    };
     let history_block = Block::default()
         .borders(Borders::ALL)
         .border_type(BorderType::Rounded)
         .border_style(Style::default().fg(c.border))
         .title(Line::from(Span::styled(
-            " History ",
+            title,
             Style::default().fg(c.accent).add_modifier(Modifier::BOLD),
         )))
+        .title_bottom(Line::from(Span::styled(
+            history_footer(app),
+            Style::default().fg(c.dim),
+        )))
         .style(Style::default().bg(c.bg_dark));
 
     if app.history_list.is_empty() {
+        let (empty, hint) = if app.history_trash {
+            ("Trash is empty", "Conversations deleted from history are kept here for a while.")
+        } else {
+            ("No saved conversations", "Start chatting and your conversations will appear here.")
+        };
         let empty_msg = Paragraph::new(vec![
             Line::from(""),
             Line::from(Span::styled(
-                "No saved conversations",
+                empty,
                 Style::default().fg(c.dim),
             )),
             Line::from(""),
             Line::from(Span::styled(
-                "Start chatting and your conversations will appear here.",
+                hint,
                 Style::default().fg(c.border),
             )),
         ])
@@ -674,12 +1263,20 @@ fn draw_history_overlay(f: &mut Frame, app: &App, area: Rect) {
             Style::default().fg(c.fg)
         };
         let prefix = if i == app.overlay_scroll { "▸ " } else { "  " };
+        let marked = app.is_history_marked(i);
         let date = conv.updated_at.format("%Y-%m-%d %H:%M");
-        ListItem::new(Line::from(vec![
+        let mut spans = vec![
             Span::styled(prefix, style),
+            Span::styled(if marked { "● " } else { "  " }, Style::default().fg(c.accent)),
             Span::styled(conv.title.chars().take(40).collect::<String>(), style),
             Span::styled(format!("  {date}"), Style::default().fg(c.dim)),
-        ]))
+        ];
+        if !app.history_project_only
+            && let Some(name) = conv.project_name()
+        {
+            spans.push(Span::styled(format!("  {name}"), Style::default().fg(c.border)));
+        }
+        ListItem::new(Line::from(spans))
     }).collect();
 
     let list = List::new(items).block(history_block);
@@ -687,18 +1284,384 @@ fn draw_history_overlay(f: &mut Frame, app: &App, area: Rect) {
     f.render_widget(list, overlay_area);
 }
 
-fn draw_settings_overlay(f: &mut Frame, app: &App, area: Rect) {
+fn history_footer(app: &App) -> String {
+    if app.history_trash {
+        return match app.config.trash_retention_days {
+            0 => " Enter: restore  Esc: close ".to_string(),
+            days => format!(" Enter: restore  kept {days} days  Esc: close "),
+        };
+    }
+    let scope = if app.history_project_only { "p: all projects" } else { "p: this project" };
+    let selected = app.history_selection().len();
+    match (app.history_visual.is_some(), selected) {
+        (true, n) => format!(" -- VISUAL -- {n} selected  v: keep  d: delete  e: export  Esc: clear "),
+        (false, 0) => format!(" Space/v: select  d: delete  e: export  {scope} "),
+        (false, n) => format!(" {n} selected  d: delete  e: export  Esc: clear "),
+    }
+}
+
+fn draw_picker_overlay(f: &mut Frame, app: &App, area: Rect) {
     let c = app.colors();
-    let overlay_area = centered_rect(50, 50, area);
+    let overlay_area = centered_rect(60, 60, area);
     f.render_widget(Clear, overlay_area);
 
-    let settings = vec![
-        Line::from(Span::styled("Settings", Style::default().fg(c.accent).add_modifier(Modifier::BOLD))),
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded)
+        .border_style(Style::default().fg(c.border))
+        .title(Line::from(Span::styled(
+            " Pro Chat — Recent conversations ",
+            Style::default().fg(c.accent).add_modifier(Modifier::BOLD),
+        )))
+        .title_bottom(Line::from(Span::styled(
+            " j/k: move  Enter: open  Esc: new chat ",
+            Style::default().fg(c.dim),
+        )))
+        .style(Style::default().bg(c.bg_dark));
+
+    let row_style = |i: usize| {
+        if i == app.overlay_scroll {
+            Style::default().fg(c.accent).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(c.fg)
+        }
+    };
+    let prefix = |i: usize| if i == app.overlay_scroll { "▸ " } else { "  " };
+
+    let mut items = vec![ListItem::new(Line::from(vec![
+        Span::styled(prefix(0), row_style(0)),
+        Span::styled("+ New chat", row_style(0).fg(c.success)),
+    ]))];
+    items.extend(app.history_list.iter().enumerate().map(|(i, conv)| {
+        let row = i + 1;
+        let date = conv.updated_at.format("%Y-%m-%d %H:%M");
+        ListItem::new(Line::from(vec![
+            Span::styled(prefix(row), row_style(row)),
+            Span::styled(conv.title.chars().take(40).collect::<String>(), row_style(row)),
+            Span::styled(format!("  {date}"), Style::default().fg(c.dim)),
+            Span::styled(
+                format!("  {} msgs", conv.messages.len()),
+                Style::default().fg(c.border),
+            ),
+        ]))
+    }));
+
+    f.render_widget(List::new(items).block(block), overlay_area);
+}
+
+fn draw_models_overlay(f: &mut Frame, app: &App, area: Rect) {
+    let c = app.colors();
+    let overlay_area = centered_rect(50, 70, area);
+    f.render_widget(Clear, overlay_area);
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded)
+        .border_style(Style::default().fg(c.border))
+        .title(Line::from(Span::styled(
+            format!(" Models — {} ({}) ", app.config.provider, app.model_list.len()),
+            Style::default().fg(c.accent).add_modifier(Modifier::BOLD),
+        )))
+        .title_bottom(Line::from(Span::styled(
+            " j/k: move  Enter: use  Esc: close ",
+            Style::default().fg(c.dim),
+        )))
+        .style(Style::default().bg(c.bg_dark));
+
+    let items: Vec<ListItem> = app.model_list.iter().enumerate().map(|(i, model)| {
+        let selected = i == app.overlay_scroll;
+        let style = if selected {
+            Style::default().fg(c.accent).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(c.fg)
+        };
+        let mut spans = vec![
+            Span::styled(if selected { "▸ " } else { "  " }, style),
+            Span::styled(model.clone(), style),
+        ];
+        if *model == app.config.model {
+            spans.push(Span::styled("  ● current", Style::default().fg(c.success)));
+        }
+        ListItem::new(Line::from(spans))
+    }).collect();
+
+    // Provider lists can run to hundreds of models; let the list scroll
+    let mut state = ListState::default().with_selected(Some(app.overlay_scroll));
+    f.render_stateful_widget(List::new(items).block(block), overlay_area, &mut state);
+}
+
+fn draw_bookmarks_overlay(f: &mut Frame, app: &App, area: Rect) {
+    let c = app.colors();
+    let overlay_area = centered_rect(70, 70, area);
+    f.render_widget(Clear, overlay_area);
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded)
+        .border_style(Style::default().fg(c.border))
+        .title(Line::from(Span::styled(
+            " Bookmarks ",
+            Style::default().fg(c.accent).add_modifier(Modifier::BOLD),
+        )))
+        .title_bottom(Line::from(Span::styled(
+            " Enter: jump  d: remove ",
+            Style::default().fg(c.dim),
+        )))
+        .style(Style::default().bg(c.bg_dark));
+
+    if app.bookmarks.is_empty() {
+        let empty = Paragraph::new(vec![
+            Line::from(""),
+            Line::from(Span::styled("No bookmarks yet", Style::default().fg(c.dim))),
+            Line::from(""),
+            Line::from(Span::styled(
+                "Press b in Normal mode to bookmark the focused message.",
+                Style::default().fg(c.border),
+            )),
+        ])
+        .block(block)
+        .alignment(Alignment::Center);
+        f.render_widget(empty, overlay_area);
+        return;
+    }
+
+    let items: Vec<ListItem> = app.bookmarks.iter().enumerate().map(|(i, b)| {
+        let style = if i == app.overlay_scroll {
+            Style::default().fg(c.accent).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(c.fg)
+        };
+        let prefix = if i == app.overlay_scroll { "▸ " } else { "  " };
+        let date = b.created_at.format("%Y-%m-%d");
+        ListItem::new(vec![
+            Line::from(vec![
+                Span::styled(prefix, style),
+                Span::styled(b.excerpt.clone(), style),
+            ]),
+            Line::from(Span::styled(
+                format!(
+                    "    {} · {} · #{}  {date}",
+                    b.conversation_title.chars().take(40).collect::<String>(),
+                    b.role,
+                    b.message_id
+                ),
+                Style::default().fg(c.dim),
+            )),
+        ])
+    }).collect();
+
+    f.render_widget(List::new(items).block(block), overlay_area);
+}
+
+fn draw_recall_overlay(f: &mut Frame, app: &App, area: Rect) {
+    let c = app.colors();
+    let overlay_area = centered_rect(70, 70, area);
+    f.render_widget(Clear, overlay_area);
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded)
+        .border_style(Style::default().fg(c.border))
+        .title(Line::from(Span::styled(
+            " Recall ",
+            Style::default().fg(c.accent).add_modifier(Modifier::BOLD),
+        )))
+        .title_bottom(Line::from(Span::styled(
+            " Enter: open  a: attach to input ",
+            Style::default().fg(c.dim),
+        )))
+        .style(Style::default().bg(c.bg_dark));
+
+    let items: Vec<ListItem> = app.recall_hits.iter().enumerate().map(|(i, hit)| {
+        let style = if i == app.overlay_scroll {
+            Style::default().fg(c.accent).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(c.fg)
+        };
+        let prefix = if i == app.overlay_scroll { "▸ " } else { "  " };
+        ListItem::new(vec![
+            Line::from(vec![
+                Span::styled(prefix, style),
+                Span::styled(hit.title.chars().take(60).collect::<String>(), style),
+                Span::styled(format!("  {:.0}%", hit.score * 100.0), Style::default().fg(c.dim)),
+            ]),
+            Line::from(Span::styled(
+                format!("    {} #{}: {}", hit.role, hit.message_id, hit.excerpt),
+                Style::default().fg(c.dim),
+            )),
+        ])
+    }).collect();
+
+    f.render_widget(List::new(items).block(block), overlay_area);
+}
+
+fn draw_response_diff_overlay(f: &mut Frame, app: &App, area: Rect) {
+    use similar::ChangeTag;
+
+    let c = app.colors();
+    let overlay_area = centered_rect(80, 80, area);
+    f.render_widget(Clear, overlay_area);
+
+    let removed = Style::default()
+        .fg(Color::Rgb(247, 118, 142))
+        .add_modifier(Modifier::CROSSED_OUT);
+    let added = Style::default().fg(c.success).add_modifier(Modifier::BOLD);
+
+    // Split each change on newlines so the diff keeps the answer's line layout
+    let mut lines: Vec<Line> = Vec::new();
+    let mut current: Vec<Span> = Vec::new();
+    for (tag, text) in &app.response_diff {
+        let style = match tag {
+            ChangeTag::Delete => removed,
+            ChangeTag::Insert => added,
+            ChangeTag::Equal => Style::default().fg(c.fg),
+        };
+        for (i, part) in text.split('\n').enumerate() {
+            if i > 0 {
+                lines.push(Line::from(std::mem::take(&mut current)));
+            }
+            if !part.is_empty() {
+                current.push(Span::styled(part.to_string(), style));
+            }
+        }
+    }
+    if !current.is_empty() {
+        lines.push(Line::from(current));
+    }
+
+    let p = Paragraph::new(lines)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .border_style(Style::default().fg(c.border))
+                .title(Line::from(vec![
+                    Span::styled(" Response diff ", Style::default().fg(c.accent).add_modifier(Modifier::BOLD)),
+                    Span::styled("removed ", removed),
+                    Span::styled("added ", added),
+                ]))
+                .title_bottom(Line::from(Span::styled(
+                    " j/k: scroll  Esc: close ",
+                    Style::default().fg(c.dim),
+                )))
+                .style(Style::default().bg(c.bg_dark)),
+        )
+        .wrap(Wrap { trim: false })
+        .scroll((app.overlay_scroll as u16, 0));
+
+    f.render_widget(p, overlay_area);
+}
+
+/// Whole-screen review of files changed by tools: file list on the left,
+/// the selected file's hunks on the right scrolled to the current hunk.
+fn draw_review_overlay(f: &mut Frame, app: &App, area: Rect) {
+    use crate::review::ChangeKind;
+    use similar::ChangeTag;
+
+    let c = app.colors();
+    let red = Color::Rgb(247, 118, 142);
+    f.render_widget(Clear, area);
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded)
+        .border_style(Style::default().fg(c.border))
+        .title(Line::from(Span::styled(
+            format!(" Review — {} file(s) changed this session ", app.review_files.len()),
+            Style::default().fg(c.accent).add_modifier(Modifier::BOLD),
+        )))
+        .title_bottom(Line::from(Span::styled(
+            " j/k: file  n/p: hunk  a: accept  r: revert  Esc: close ",
+            Style::default().fg(c.dim),
+        )))
+        .style(Style::default().bg(c.bg_dark));
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let cols = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(30), Constraint::Percentage(70)])
+        .split(inner);
+
+    let items: Vec<ListItem> = app.review_files.iter().enumerate().map(|(i, file)| {
+        let (mark, color) = match file.kind {
+            ChangeKind::Added => ("A", c.success),
+            ChangeKind::Modified => ("M", c.warning),
+            ChangeKind::Deleted => ("D", red),
+        };
+        let selected = i == app.overlay_scroll;
+        let name_style = if selected {
+            Style::default().fg(c.accent).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(c.fg)
+        };
+        ListItem::new(Line::from(vec![
+            Span::styled(if selected { "▸ " } else { "  " }, Style::default().fg(c.accent)),
+            Span::styled(format!("{mark} "), Style::default().fg(color).add_modifier(Modifier::BOLD)),
+            Span::styled(file.path.display().to_string(), name_style),
+            Span::styled(format!("  +{} -{}", file.added, file.removed), Style::default().fg(c.dim)),
+        ]))
+    }).collect();
+    f.render_widget(
+        List::new(items).block(
+            Block::default()
+                .borders(Borders::RIGHT)
+                .border_style(Style::default().fg(c.border)),
+        ),
+        cols[0],
+    );
+
+    let Some(file) = app.selected_review_file() else {
+        return;
+    };
+    let mut lines: Vec<Line> = Vec::new();
+    let mut offset = 0;
+    for (i, hunk) in file.hunks.iter().enumerate() {
+        let mut header = Style::default().fg(c.accent);
+        if i == app.review_hunk {
+            offset = lines.len();
+            header = header.add_modifier(Modifier::BOLD | Modifier::REVERSED);
+        }
+        lines.push(Line::from(Span::styled(
+            format!("{}  ({}/{})", hunk.header, i + 1, file.hunks.len()),
+            header,
+        )));
+        for (tag, text) in &hunk.lines {
+            let (sign, style) = match tag {
+                ChangeTag::Delete => ("-", Style::default().fg(red)),
+                ChangeTag::Insert => ("+", Style::default().fg(c.success)),
+                ChangeTag::Equal => (" ", Style::default().fg(c.fg)),
+            };
+            lines.push(Line::from(Span::styled(format!("{sign}{text}"), style)));
+        }
+        lines.push(Line::from(""));
+    }
+    f.render_widget(
+        Paragraph::new(lines)
+            .block(Block::default().padding(Padding::horizontal(1)))
+            .scroll((offset as u16, 0)),
+        cols[1],
+    );
+}
+
+fn draw_settings_overlay(f: &mut Frame, app: &App, area: Rect) {
+    let c = app.colors();
+    let overlay_area = centered_rect(50, 50, area);
+    f.render_widget(Clear, overlay_area);
+
+    let settings = vec![
+        Line::from(Span::styled("Settings", Style::default().fg(c.accent).add_modifier(Modifier::BOLD))),
         Line::from(""),
         Line::from(format!("  Provider:    {}", app.config.provider)),
         Line::from(format!("  Model:       {}", app.config.model)),
-        Line::from(format!("  Temperature: {}", app.config.temperature)),
-        Line::from(format!("  Max tokens:  {}", app.config.max_tokens)),
+        Line::from(format!("  Temperature: {}", app.config.effective_temperature())),
+        Line::from(format!(
+            "  Top p / k:   {} / {}",
+            app.config.top_p.map_or("default".into(), |p| p.to_string()),
+            app.config.top_k.map_or("default".into(), |k| k.to_string()),
+        )),
+        Line::from(format!("  Stop:        {:?}", app.config.stop_sequences)),
+        Line::from(format!("  Max tokens:  {}", app.config.effective_max_tokens())),
         Line::from(format!("  Vim mode:    {}", app.config.vim_mode)),
         Line::from(format!("  Theme:       {}", app.config.theme_name)),
         Line::from(""),
@@ -717,20 +1680,394 @@ fn draw_settings_overlay(f: &mut Frame, app: &App, area: Rect) {
     f.render_widget(p, overlay_area);
 }
 
-fn draw_tool_confirm_overlay(f: &mut Frame, app: &App, area: Rect) {
+fn draw_stats_overlay(f: &mut Frame, app: &App, area: Rect) {
+    use crate::tools::format_duration;
+
     let c = app.colors();
-    let overlay_area = centered_rect(60, 40, area);
+    let overlay_area = centered_rect(70, 60, area);
     f.render_widget(Clear, overlay_area);
 
+    let mut lines = vec![
+        Line::from(Span::styled("Session Stats", Style::default().fg(c.accent).add_modifier(Modifier::BOLD))),
+        Line::from(""),
+        Line::from(format!("  Messages:    {}", app.messages.len())),
+        Line::from(format!(
+            "  Tool calls:  {}",
+            app.tool_stats.values().map(|s| s.calls).sum::<u32>()
+        )),
+        Line::from(""),
+    ];
+
+    if app.tool_stats.is_empty() {
+        lines.push(Line::from(Span::styled(
+            "  No tools have run in this session",
+            Style::default().fg(c.dim),
+        )));
+    } else {
+        lines.push(Line::from(Span::styled(
+            format!(
+                "  {:<16}{:>6}{:>8}{:>9}{:>9}{:>10}",
+                "Tool", "Calls", "OK", "Avg", "Max", "Output"
+            ),
+            Style::default().fg(c.assistant_label).add_modifier(Modifier::BOLD),
+        )));
+        for (name, stats) in &app.tool_stats {
+            let rate = stats.success_rate();
+            let rate_color = if rate >= 0.9 {
+                c.success
+            } else if rate >= 0.5 {
+                c.warning
+            } else {
+                Color::Rgb(247, 118, 142)
+            };
+            lines.push(Line::from(vec![
+                Span::raw(format!("  {:<16}{:>6}", name, stats.calls)),
+                Span::styled(
+                    format!("{:>7.0}%", rate * 100.0),
+                    Style::default().fg(rate_color),
+                ),
+                Span::raw(format!(
+                    "{:>9}{:>9}{:>9}K",
+                    format_duration(stats.avg_duration()),
+                    format_duration(stats.max_duration),
+                    stats.total_output_bytes / 1024,
+                )),
+            ]));
+        }
+    }
+
+    if let Some(limits) = app.rate_limits {
+        let now = chrono::Utc::now();
+        lines.push(Line::from(""));
+        lines.push(Line::from(Span::styled(
+            format!("  Rate limits ({})", app.config.provider),
+            Style::default().fg(c.assistant_label).add_modifier(Modifier::BOLD),
+        )));
+        for (name, limit) in [("Requests", limits.requests), ("Tokens", limits.tokens)] {
+            let Some(limit) = limit else { continue };
+            let reset = limit
+                .reset_at
+                .map(|at| format!("  resets in {}", format_until(at, now)))
+                .unwrap_or_default();
+            lines.push(Line::from(vec![
+                Span::raw(format!("  {name:<12}")),
+                Span::styled(
+                    format!("{}/{} left", format_tokens(limit.remaining), format_tokens(limit.limit)),
+                    Style::default().fg(if limit.is_low() { c.warning } else { c.success }),
+                ),
+                Span::styled(reset, Style::default().fg(c.dim)),
+            ]));
+        }
+    }
+
+    lines.push(Line::from(""));
+    lines.push(Line::from(Span::styled("  Press Esc or q to close", Style::default().fg(c.dim))));
+
+    let p = Paragraph::new(lines)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .border_style(Style::default().fg(c.border))
+                .title(Line::from(Span::styled(
+                    " Stats ",
+                    Style::default().fg(c.accent).add_modifier(Modifier::BOLD),
+                )))
+                .style(Style::default().bg(c.bg_dark)),
+        );
+
+    f.render_widget(p, overlay_area);
+}
+
+fn draw_system_prompt_overlay(f: &mut Frame, app: &App, area: Rect) {
+    let c = app.colors();
+    let overlay_area = centered_rect(80, 80, area);
+    f.render_widget(Clear, overlay_area);
+
+    let ed = &app.prompt_editor;
+    let (title, hints) = if ed.preview {
+        (" System Prompt — assembled preview ", " Tab: edit  Ctrl+s: save  Esc: cancel ")
+    } else {
+        (" System Prompt ", " Tab: preview  Ctrl+s: save  Esc: cancel ")
+    };
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded)
+        .border_style(Style::default().fg(c.border))
+        .title(Line::from(Span::styled(
+            title,
+            Style::default().fg(c.accent).add_modifier(Modifier::BOLD),
+        )))
+        .title_bottom(Line::from(Span::styled(hints, Style::default().fg(c.dim))))
+        .style(Style::default().bg(c.bg_dark));
+    let inner = block.inner(overlay_area);
+    let height = inner.height as usize;
+
+    if ed.preview {
+        let assembled = app.assembled_system_prompt(Some(&ed.text)).unwrap_or_default();
+        let mut lines: Vec<Line> = assembled
+            .lines()
+            .map(|l| {
+                if l.starts_with("--- ") && l.ends_with(" ---") {
+                    Line::from(Span::styled(l.to_string(), Style::default().fg(c.assistant_label).add_modifier(Modifier::BOLD)))
+                } else {
+                    Line::from(Span::styled(l.to_string(), Style::default().fg(c.fg)))
+                }
+            })
+            .collect();
+        lines.push(Line::from(""));
+        let tools_note = if app.tools_enabled {
+            let set = app.config.tool_set().unwrap_or(crate::tools::ToolSet::Full);
+            format!(
+                "+ {} tool definitions sent alongside",
+                crate::tools::tool_names(set).len() + crate::mcp::tools().len()
+            )
+        } else {
+            "Tools are off: no tool definitions sent".to_string()
+        };
+        lines.push(Line::from(Span::styled(tools_note, Style::default().fg(c.dim))));
+        lines.push(Line::from(Span::styled(
+            format!("~{} tokens", assembled.len() / 4),
+            Style::default().fg(c.dim),
+        )));
+        let p = Paragraph::new(lines).block(block).wrap(Wrap { trim: false });
+        f.render_widget(p, overlay_area);
+        return;
+    }
+
+    // Keep the cursor line in view
+    let (cursor_line, cursor_col) = ed.line_col();
+    let top = cursor_line.saturating_sub(height.saturating_sub(1));
+    let lines: Vec<Line> = ed
+        .text
+        .split('\n')
+        .skip(top)
+        .take(height)
+        .map(|l| Line::from(Span::styled(l.to_string(), Style::default().fg(c.fg))))
+        .collect();
+    f.render_widget(Paragraph::new(lines).block(block), overlay_area);
+
+    let x = inner.x + (cursor_col as u16).min(inner.width.saturating_sub(1));
+    let y = inner.y + (cursor_line - top) as u16;
+    f.set_cursor_position(Position::new(x, y));
+}
+
+fn draw_request_preview_overlay(f: &mut Frame, app: &App, area: Rect) {
+    let c = app.colors();
+    let overlay_area = centered_rect(85, 85, area);
+    f.render_widget(Clear, overlay_area);
+
+    let pv = &app.request_preview;
+    let heading = |text: String| {
+        Line::from(Span::styled(text, Style::default().fg(c.accent).add_modifier(Modifier::BOLD)))
+    };
+    let dim = |text: String| Line::from(Span::styled(text, Style::default().fg(c.dim)));
+
+    let mut lines = vec![
+        Line::from(vec![
+            Span::styled(format!("{} ", pv.provider), Style::default().fg(c.accent)),
+            Span::styled(pv.model.clone(), Style::default().fg(c.fg).add_modifier(Modifier::BOLD)),
+            Span::styled(
+                format!("  max_tokens {}  temperature {:.1}", pv.max_tokens, pv.temperature),
+                Style::default().fg(c.dim),
+            ),
+        ]),
+        Line::from(""),
+        heading(format!("System prompt  ~{} tok", pv.system_tokens)),
+    ];
+    match &pv.system {
+        Some(system) => {
+            for l in system.lines().take(8) {
+                lines.push(Line::from(format!("  {l}")));
+            }
+            let hidden = system.lines().count().saturating_sub(8);
+            if hidden > 0 {
+                lines.push(dim(format!("  ⋯ {hidden} more lines")));
+            }
+        }
+        None => lines.push(dim("  (none)".into())),
+    }
+
+    lines.push(Line::from(""));
+    lines.push(heading(format!("Messages ({})", pv.messages.len())));
+    for (i, m) in pv.messages.iter().enumerate() {
+        let color = if m.role == "user" { c.user_label } else { c.assistant_label };
+        lines.push(Line::from(vec![
+            Span::styled(format!("  [{}] {}", i + 1, m.role), Style::default().fg(color).add_modifier(Modifier::BOLD)),
+            Span::styled(format!("  ~{} tok", m.tokens), Style::default().fg(c.dim)),
+        ]));
+        for l in &m.excerpt {
+            lines.push(Line::from(format!("      {l}")));
+        }
+        if m.hidden_lines > 0 {
+            lines.push(dim(format!("      ⋯ {} more lines", m.hidden_lines)));
+        }
+    }
+    if pv.messages.is_empty() {
+        lines.push(dim("  (none)".into()));
+    }
+
+    lines.push(Line::from(""));
+    if pv.tools.is_empty() {
+        lines.push(heading("Tools".into()));
+        lines.push(dim("  not sent".into()));
+    } else {
+        lines.push(heading(format!("Tools ({})  ~{} tok", pv.tools.len(), pv.tool_tokens)));
+        lines.push(Line::from(format!("  {}", pv.tools.join(", "))));
+    }
+
+    lines.push(Line::from(""));
+    lines.push(Line::from(Span::styled(
+        format!("Total ≈ {} input tokens", pv.total_tokens()),
+        Style::default().fg(c.warning).add_modifier(Modifier::BOLD),
+    )));
+
+    let p = Paragraph::new(lines)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .border_style(Style::default().fg(c.border))
+                .title(Line::from(Span::styled(
+                    " Request preview ",
+                    Style::default().fg(c.accent).add_modifier(Modifier::BOLD),
+                )))
+                .title_bottom(Line::from(Span::styled(
+                    " j/k: scroll  Esc: close ",
+                    Style::default().fg(c.dim),
+                )))
+                .style(Style::default().bg(c.bg_dark)),
+        )
+        .wrap(Wrap { trim: false })
+        .scroll((app.overlay_scroll as u16, 0));
+
+    f.render_widget(p, overlay_area);
+}
+
+fn draw_dashboard_overlay(f: &mut Frame, app: &App, area: Rect) {
+    let c = app.colors();
+    let overlay_area = centered_rect(75, 80, area);
+    f.render_widget(Clear, overlay_area);
+
+    let dash = &app.dashboard;
+    let heading = |text: &str| {
+        Line::from(Span::styled(
+            text.to_string(),
+            Style::default().fg(c.accent).add_modifier(Modifier::BOLD),
+        ))
+    };
+    let column_header = |text: String| {
+        Line::from(Span::styled(
+            text,
+            Style::default().fg(c.assistant_label).add_modifier(Modifier::BOLD),
+        ))
+    };
+    let empty = |text: &str| Line::from(Span::styled(format!("  {text}"), Style::default().fg(c.dim)));
+
+    let mut lines = vec![heading("Tokens per model per week (estimated)"), Line::from("")];
+    if dash.weekly.is_empty() {
+        lines.push(empty("No responses recorded yet"));
+    } else {
+        lines.push(column_header(format!(
+            "  {:<10}{:<28}{:>6}{:>10}{:>10}{:>10}",
+            "Week", "Model", "Resp", "In", "Out", "Cost"
+        )));
+        for ((week, model), u) in &dash.weekly {
+            let cost = u.cost.map_or("—".to_string(), |c| format!("${c:.2}"));
+            lines.push(Line::from(format!(
+                "  {:<10}{:<28}{:>6}{:>10}{:>10}{:>10}",
+                week,
+                model.chars().take(27).collect::<String>(),
+                u.responses,
+                u.input_tokens,
+                u.output_tokens,
+                cost,
+            )));
+        }
+        let total: f64 = dash.weekly.values().filter_map(|u| u.cost).sum();
+        lines.push(Line::from(Span::styled(
+            format!("  Total known cost: ${total:.2}"),
+            Style::default().fg(c.dim),
+        )));
+    }
+
+    lines.push(Line::from(""));
+    lines.push(heading("Activity by hour"));
+    lines.push(Line::from(""));
+    let peak = dash.hours.iter().copied().max().unwrap_or(0);
+    if peak == 0 {
+        lines.push(empty("No messages in history"));
+    } else {
+        for (hour, &count) in dash.hours.iter().enumerate().filter(|(_, n)| **n > 0) {
+            let width = (count * 40).div_ceil(peak) as usize;
+            let color = if Some(hour) == dash.busiest_hour() { c.warning } else { c.accent };
+            lines.push(Line::from(vec![
+                Span::raw(format!("  {hour:02}:00 ")),
+                Span::styled("█".repeat(width), Style::default().fg(color)),
+                Span::styled(format!(" {count}"), Style::default().fg(c.dim)),
+            ]));
+        }
+    }
+
+    lines.push(Line::from(""));
+    lines.push(heading("Most used tools"));
+    lines.push(Line::from(""));
+    if dash.tools.is_empty() {
+        lines.push(empty("No tool calls recorded yet"));
+    } else {
+        lines.push(column_header(format!("  {:<20}{:>8}{:>10}", "Tool", "Calls", "Failed")));
+        for (name, t) in dash.tools.iter().take(10) {
+            lines.push(Line::from(format!("  {:<20}{:>8}{:>10}", name, t.calls, t.failures)));
+        }
+    }
+
+    lines.push(Line::from(""));
+    lines.push(heading("Sessions"));
+    lines.push(Line::from(""));
+    lines.push(Line::from(format!("  Conversations:   {}", dash.sessions)));
+    if let Some(avg) = dash.avg_session {
+        lines.push(Line::from(format!(
+            "  Average length:  {}",
+            crate::tools::format_duration(avg)
+        )));
+    }
+
+    let p = Paragraph::new(lines)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .border_style(Style::default().fg(c.border))
+                .title(Line::from(Span::styled(
+                    " Dashboard ",
+                    Style::default().fg(c.accent).add_modifier(Modifier::BOLD),
+                )))
+                .title_bottom(Line::from(Span::styled(
+                    " j/k scroll · Esc close ",
+                    Style::default().fg(c.dim),
+                )))
+                .style(Style::default().bg(c.bg_dark)),
+        )
+        .scroll((app.overlay_scroll as u16, 0));
+
+    f.render_widget(p, overlay_area);
+}
+
+fn draw_tool_confirm_overlay(f: &mut Frame, app: &App, area: Rect) {
+    let c = app.colors();
     let call = match app.pending_tool_calls.get(app.pending_tool_confirm_idx) {
         Some(tc) => tc,
         None => return,
     };
+    // File writes get room for a diff of what they will change
+    let proposed = crate::review::FileReview::proposed(&call.tool);
+    let overlay_area = if proposed.is_some() { centered_rect(80, 80, area) } else { centered_rect(60, 40, area) };
+    f.render_widget(Clear, overlay_area);
 
     let tool_name = call.tool.name();
     let tool_args = crate::app::format_tool_args_public(&call.tool);
 
-    let lines = vec![
+    let mut lines = vec![
         Line::from(Span::styled(
             "Tool Execution Request",
             Style::default().fg(c.warning).add_modifier(Modifier::BOLD),
@@ -768,6 +2105,39 @@ fn draw_tool_confirm_overlay(f: &mut Frame, app: &App, area: Rect) {
             Span::styled("Deny all", Style::default().fg(c.fg)),
         ]),
     ];
+    // Show what will be posted publicly before it is allowed
+    if let crate::tools::Tool::GhPrComment { body, .. } = &call.tool {
+        let preview: Vec<Line> = body
+            .lines()
+            .take(10)
+            .map(|l| Line::from(Span::styled(format!("    {l}"), Style::default().fg(c.dim))))
+            .collect();
+        lines.splice(4..4, preview);
+    }
+    // Risk badges and what the command touches
+    if let crate::tools::Tool::Execute { command, .. } = &call.tool {
+        lines.splice(4..4, command_risk_lines(app, command));
+    }
+    // Show the range being replaced and its replacement
+    if let crate::tools::Tool::EditLines { path, start, end, new_text } = &call.tool {
+        let removed = Style::default().fg(Color::Rgb(247, 118, 142));
+        let mut preview: Vec<Line> = crate::tools::line_range(path, *start, *end)
+            .into_iter()
+            .zip(*start..)
+            .take(10)
+            .map(|(l, n)| Line::from(Span::styled(format!("  {n:>4} - {l}"), removed)))
+            .collect();
+        preview.extend(
+            new_text
+                .lines()
+                .take(10)
+                .map(|l| Line::from(Span::styled(format!("       + {l}"), Style::default().fg(c.success)))),
+        );
+        lines.splice(4..4, preview);
+    }
+    if let Some(review) = &proposed {
+        lines.splice(4..4, proposed_diff_lines(app, review));
+    }
 
     let p = Paragraph::new(lines)
         .block(
@@ -786,6 +2156,183 @@ fn draw_tool_confirm_overlay(f: &mut Frame, app: &App, area: Rect) {
     f.render_widget(p, overlay_area);
 }
 
+fn draw_tool_input_overlay(f: &mut Frame, app: &App, area: Rect) {
+    let c = app.colors();
+    let Some(input) = &app.tool_input else { return };
+    let overlay_area = centered_rect(60, 30, area);
+    f.render_widget(Clear, overlay_area);
+
+    // Passwords are typed blind
+    let answer = if crate::tools::is_secret_prompt(&input.prompt) {
+        "*".repeat(input.answer.chars().count())
+    } else {
+        input.answer.clone()
+    };
+    let lines = vec![
+        Line::from(Span::styled("  The running command is asking:", Style::default().fg(c.dim))),
+        Line::from(""),
+        Line::from(Span::styled(format!("  {}", input.prompt), Style::default().fg(c.fg).add_modifier(Modifier::BOLD))),
+        Line::from(""),
+        Line::from(vec![
+            Span::styled("  > ", Style::default().fg(c.accent)),
+            Span::styled(answer, Style::default().fg(c.fg)),
+            Span::styled("█", Style::default().fg(c.accent)),
+        ]),
+        Line::from(""),
+        Line::from(vec![
+            Span::styled("  [Enter] ", Style::default().fg(c.success).add_modifier(Modifier::BOLD)),
+            Span::styled("Send to the command  ", Style::default().fg(c.fg)),
+            Span::styled("[Esc] ", Style::default().fg(Color::Rgb(247, 118, 142)).add_modifier(Modifier::BOLD)),
+            Span::styled("Decline", Style::default().fg(c.fg)),
+        ]),
+        Line::from(""),
+        Line::from(Span::styled("  The answer goes to the command, not the model.", Style::default().fg(c.dim))),
+    ];
+
+    let p = Paragraph::new(lines)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .border_style(Style::default().fg(c.warning))
+                .title(Line::from(Span::styled(
+                    " Input ",
+                    Style::default().fg(c.warning).add_modifier(Modifier::BOLD),
+                )))
+                .style(Style::default().bg(c.bg_dark)),
+        )
+        .wrap(Wrap { trim: false });
+
+    f.render_widget(p, overlay_area);
+}
+
+fn draw_recipe_confirm_overlay(f: &mut Frame, app: &App, area: Rect) {
+    let c = app.colors();
+    let Some(pending) = &app.recipe_confirm else { return };
+    let overlay_area = centered_rect(70, 60, area);
+    f.render_widget(Clear, overlay_area);
+
+    let mut lines = vec![
+        Line::from(Span::styled(
+            format!("  Recipe {} comes from the {} pack and will:", pending.name, pending.pack),
+            Style::default().fg(c.dim),
+        )),
+        Line::from(""),
+    ];
+    for cmd in &pending.recipe.run {
+        lines.push(Line::from(vec![
+            Span::styled("  run  ", Style::default().fg(c.warning)),
+            Span::styled(cmd.clone(), Style::default().fg(c.fg).add_modifier(Modifier::BOLD)),
+        ]));
+    }
+    for file in &pending.recipe.files {
+        lines.push(Line::from(vec![
+            Span::styled("  read ", Style::default().fg(c.accent)),
+            Span::styled(file.clone(), Style::default().fg(c.fg).add_modifier(Modifier::BOLD)),
+        ]));
+    }
+    lines.extend([
+        Line::from(""),
+        Line::from(vec![
+            Span::styled("  [y] ", Style::default().fg(c.success).add_modifier(Modifier::BOLD)),
+            Span::styled("Run and send the output  ", Style::default().fg(c.fg)),
+            Span::styled("[any other key] ", Style::default().fg(Color::Rgb(247, 118, 142)).add_modifier(Modifier::BOLD)),
+            Span::styled("Cancel", Style::default().fg(c.fg)),
+        ]),
+    ]);
+
+    let p = Paragraph::new(lines)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .border_style(Style::default().fg(c.warning))
+                .title(Line::from(Span::styled(
+                    " Pack recipe ",
+                    Style::default().fg(c.warning).add_modifier(Modifier::BOLD),
+                )))
+                .style(Style::default().bg(c.bg_dark)),
+        )
+        .wrap(Wrap { trim: false });
+
+    f.render_widget(p, overlay_area);
+}
+
+/// A colored unified diff of what a `write_file` or `edit_file` call will
+/// change, cut off after `MAX_LINES` lines.
+fn proposed_diff_lines(app: &App, review: &crate::review::FileReview) -> Vec<Line<'static>> {
+    use crate::review::ChangeKind;
+    use similar::ChangeTag;
+    const MAX_LINES: usize = 40;
+
+    let c = app.colors();
+    let red = Color::Rgb(247, 118, 142);
+    let summary = match (review.kind, review.hunks.is_empty()) {
+        (_, true) => "no changes".to_string(),
+        (ChangeKind::Added, _) => format!("new file, {} lines", review.added),
+        _ => format!("+{} -{}", review.added, review.removed),
+    };
+    let mut lines = vec![
+        Line::from(""),
+        Line::from(vec![
+            Span::styled(format!("  {}  ", review.path.display()), Style::default().fg(c.fg).add_modifier(Modifier::BOLD)),
+            Span::styled(summary, Style::default().fg(c.dim)),
+        ]),
+    ];
+    let mut diff = Vec::new();
+    for hunk in &review.hunks {
+        diff.push(Line::from(Span::styled(format!("  {}", hunk.header), Style::default().fg(c.accent))));
+        for (tag, text) in &hunk.lines {
+            let (sign, color) = match tag {
+                ChangeTag::Insert => ('+', c.success),
+                ChangeTag::Delete => ('-', red),
+                ChangeTag::Equal => (' ', c.dim),
+            };
+            diff.push(Line::from(Span::styled(format!("  {sign} {text}"), Style::default().fg(color))));
+        }
+    }
+    let hidden = diff.len().saturating_sub(MAX_LINES);
+    lines.extend(diff.into_iter().take(MAX_LINES));
+    if hidden > 0 {
+        lines.push(Line::from(Span::styled(format!("  … {hidden} more diff lines"), Style::default().fg(c.dim))));
+    }
+    lines
+}
+
+/// Colored badges for an `execute` command's risks, each followed by what
+/// it affects.
+fn command_risk_lines(app: &App, command: &str) -> Vec<Line<'static>> {
+    use crate::tools::CommandRisk;
+
+    let c = app.colors();
+    let assessment = crate::tools::classify_command(command);
+    if assessment.risks.is_empty() {
+        return vec![Line::from(Span::styled(
+            "  ✓ no installs, deletions, network or privilege use detected",
+            Style::default().fg(c.success),
+        ))];
+    }
+    let mut badges = vec![Span::styled("  Risk: ", Style::default().fg(c.dim))];
+    let mut details = Vec::new();
+    for (risk, targets) in &assessment.risks {
+        let color = match risk {
+            CommandRisk::PrivilegeEscalation | CommandRisk::Deletion => Color::Rgb(247, 118, 142),
+            CommandRisk::InlineCode | CommandRisk::PackageInstall => c.warning,
+            CommandRisk::Network => c.accent,
+        };
+        badges.push(Span::styled(
+            format!(" {} ", risk.label()),
+            Style::default().fg(c.bg_dark).bg(color).add_modifier(Modifier::BOLD),
+        ));
+        badges.push(Span::raw(" "));
+        details.push(Line::from(vec![
+            Span::styled(format!("    {:<9}", risk.label()), Style::default().fg(color)),
+            Span::styled(targets.join(", "), Style::default().fg(c.fg)),
+        ]));
+    }
+    std::iter::once(Line::from(badges)).chain(details).collect()
+}
+
 fn draw_setup_overlay(f: &mut Frame, app: &App, area: Rect) {
     let c = app.colors();
     let overlay_area = centered_rect(50, 60, area);
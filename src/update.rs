@@ -0,0 +1,212 @@
+//! `pro update`: replace the running binary with the latest GitHub release.
+//! Each release carries one binary per platform, named `pro-<os>-<arch>`
+//! (`.exe` on Windows), and a `SHA256SUMS` file covering them.
+//!
+//! The checksum only proves the binary arrived intact: `SHA256SUMS` comes
+//! from the same release, so it is no evidence of who built it. That rests
+//! on TLS to GitHub, which is why the download never skips certificate
+//! checks, whatever `[network]` says.
+
+use anyhow::Context;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+
+use crate::config::Config;
+
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/teddytennant/Pro-Chat/releases/latest";
+const CHECKSUMS_ASSET: &str = "SHA256SUMS";
+/// Changelog lines kept for the summary shown after updating.
+const SUMMARY_LINES: usize = 8;
+
+/// Long enough to download a release binary over a slow connection.
+const DOWNLOAD_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(300);
+
+const USER_AGENT: &str = concat!("pro-chat/", env!("CARGO_PKG_VERSION"));
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Release {
+    fn asset(&self, name: &str) -> Option<&Asset> {
+        self.assets.iter().find(|a| a.name == name)
+    }
+}
+
+/// The release asset built for this platform.
+fn asset_name() -> String {
+    format!("pro-{}-{}{}", std::env::consts::OS, std::env::consts::ARCH, std::env::consts::EXE_SUFFIX)
+}
+
+/// `major.minor.patch` out of a tag like `v1.2.3` or `1.2.3-rc1`.
+fn parse_version(tag: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = tag.trim().trim_start_matches('v').split(['.', '-', '+']);
+    let mut next = || parts.next()?.parse().ok();
+    Some((next()?, next()?, next().unwrap_or(0)))
+}
+
+fn is_newer(tag: &str, current: &str) -> bool {
+    matches!((parse_version(tag), parse_version(current)), (Some(latest), Some(current)) if latest > current)
+}
+
+/// The expected hash for `name` in `sha256sum` output (`<hex>  <name>`, or
+/// `<hex> *<name>` for binary mode).
+fn checksum_for(sums: &str, name: &str) -> Option<String> {
+    sums.lines().find_map(|line| {
+        let (hash, file) = line.trim().split_once(char::is_whitespace)?;
+        (file.trim().trim_start_matches('*') == name).then(|| hash.to_lowercase())
+    })
+}
+
+/// The first few non-empty lines of the release notes.
+fn changelog_summary(body: &str) -> String {
+    let lines: Vec<&str> = body.lines().map(str::trim_end).filter(|l| !l.trim().is_empty()).collect();
+    let mut summary = lines.iter().take(SUMMARY_LINES).copied().collect::<Vec<_>>().join("\n");
+    if lines.len() > SUMMARY_LINES {
+        summary.push_str(&format!("\n… {} more lines in the release notes", lines.len() - SUMMARY_LINES));
+    }
+    summary
+}
+
+fn notes_path() -> PathBuf {
+    Config::data_dir().join("update-notes")
+}
+
+/// What the last `pro update` installed, for the TUI to show once on the
+/// next launch.
+pub fn take_notes() -> Option<String> {
+    let path = notes_path();
+    let notes = std::fs::read_to_string(&path).ok()?;
+    let _ = std::fs::remove_file(&path);
+    Some(notes)
+}
+
+/// Swap the running executable for `binary`. The new file is written next to
+/// the old one and renamed over it, so a failure leaves the old binary intact.
+fn replace_exe(binary: &[u8]) -> anyhow::Result<PathBuf> {
+    let exe = std::env::current_exe()?.canonicalize()?;
+    let file_name = exe.file_name().context("Executable path has no file name")?.to_string_lossy();
+    let staged = exe.with_file_name(format!("{file_name}.new"));
+    std::fs::write(&staged, binary).with_context(|| format!("Couldn't write {}", staged.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o755))?;
+    }
+    // Windows won't overwrite a running executable but lets it be renamed
+    #[cfg(windows)]
+    let old = exe.with_file_name(format!("{file_name}.old"));
+    #[cfg(windows)]
+    std::fs::rename(&exe, &old)?;
+    if let Err(e) = std::fs::rename(&staged, &exe) {
+        // Put the old binary back rather than leave no `pro` at all
+        #[cfg(windows)]
+        let _ = std::fs::rename(&old, &exe);
+        let _ = std::fs::remove_file(&staged);
+        anyhow::bail!("Couldn't replace {}: {e}", exe.display());
+    }
+    Ok(exe)
+}
+
+async fn download(client: &reqwest::Client, asset: &Asset) -> anyhow::Result<Vec<u8>> {
+    let response = client
+        .get(&asset.browser_download_url)
+        .header(reqwest::header::USER_AGENT, USER_AGENT)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(response.bytes().await?.to_vec())
+}
+
+/// Check for a newer release and, unless `check_only`, install it.
+pub async fn run(check_only: bool) -> anyhow::Result<()> {
+    let current = env!("CARGO_PKG_VERSION");
+    let client = crate::api::verified_http_client(DOWNLOAD_TIMEOUT)?;
+    let release: Release = client
+        .get(LATEST_RELEASE_URL)
+        .header("accept", "application/vnd.github+json")
+        .header(reqwest::header::USER_AGENT, USER_AGENT)
+        .send()
+        .await?
+        .error_for_status()
+        .context("Couldn't fetch the latest release")?
+        .json()
+        .await?;
+    let tag = &release.tag_name;
+    if !is_newer(tag, current) {
+        println!("pro {current} is up to date");
+        return Ok(());
+    }
+    let summary = changelog_summary(release.body.as_deref().unwrap_or_default());
+    if check_only {
+        println!("pro {tag} is available (installed: {current})\n\n{summary}");
+        return Ok(());
+    }
+
+    let name = asset_name();
+    let binary = release
+        .asset(&name)
+        .with_context(|| format!("Release {tag} has no binary for this platform ({name})"))?;
+    let sums = release
+        .asset(CHECKSUMS_ASSET)
+        .with_context(|| format!("Release {tag} has no {CHECKSUMS_ASSET}; not installing an unverified binary"))?;
+    println!("Downloading {name} from {tag}...");
+    let sums = String::from_utf8_lossy(&download(&client, sums).await?).to_string();
+    let expected = checksum_for(&sums, &name).with_context(|| format!("{CHECKSUMS_ASSET} has no entry for {name}"))?;
+    let bytes = download(&client, binary).await?;
+    let actual = hex::encode(Sha256::digest(&bytes));
+    if actual != expected {
+        anyhow::bail!("Checksum mismatch for {name}: expected {expected}, got {actual}");
+    }
+
+    let exe = replace_exe(&bytes)?;
+    let notes = format!("Updated pro {current} → {tag}\n{summary}");
+    let _ = std::fs::create_dir_all(Config::data_dir())
+        .and_then(|_| crate::history::write_atomic(&notes_path(), notes.trim_end().as_bytes()));
+    println!("Installed {tag} at {}\n\n{summary}", exe.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_comparison() {
+        assert!(is_newer("v0.2.0", "0.1.0"));
+        assert!(is_newer("1.0", "0.9.9"));
+        assert!(is_newer("v0.1.10", "0.1.9"));
+        assert!(!is_newer("v0.1.0", "0.1.0"));
+        assert!(!is_newer("v0.0.9-rc1", "0.1.0"));
+        assert!(!is_newer("nightly", "0.1.0"));
+    }
+
+    #[test]
+    fn test_checksum_lookup() {
+        let sums = "ABC123  pro-linux-x86_64\ndef456 *pro-windows-x86_64.exe\n";
+        assert_eq!(checksum_for(sums, "pro-linux-x86_64").as_deref(), Some("abc123"));
+        assert_eq!(checksum_for(sums, "pro-windows-x86_64.exe").as_deref(), Some("def456"));
+        assert_eq!(checksum_for(sums, "pro-macos-aarch64"), None);
+    }
+
+    #[test]
+    fn test_changelog_summary() {
+        assert_eq!(changelog_summary("## Changes\n\n- Faster\n- Smaller\n"), "## Changes\n- Faster\n- Smaller");
+        let long: String = (1..=10).map(|i| format!("- change {i}\n")).collect();
+        let summary = changelog_summary(&long);
+        assert_eq!(summary.lines().count(), SUMMARY_LINES + 1);
+        assert!(summary.ends_with("… 2 more lines in the release notes"));
+    }
+}
@@ -0,0 +1,237 @@
+use chrono::{DateTime, Datelike, Local, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::history::Conversation;
+
+/// One entry in the append-only usage log (`usage.jsonl` in the data dir).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageRecord {
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: UsageEvent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum UsageEvent {
+    /// A completed model response. Token counts are the provider's, or
+    /// estimates (chars / 4) when it reported none.
+    Response {
+        provider: String,
+        model: String,
+        input_tokens: u64,
+        output_tokens: u64,
+        /// Time from send to end of stream
+        #[serde(default)]
+        latency_ms: Option<u64>,
+    },
+    /// A tool execution.
+    Tool {
+        name: String,
+        success: bool,
+        duration_ms: u64,
+    },
+}
+
+fn log_path() -> PathBuf {
+    Config::data_dir().join("usage.jsonl")
+}
+
+/// Append an event to the usage log. Failures are ignored: analytics must
+/// never get in the way of chatting.
+pub fn record(event: UsageEvent) {
+    let record = UsageRecord { timestamp: Utc::now(), event };
+    let Ok(line) = serde_json::to_string(&record) else { return };
+    let _ = std::fs::create_dir_all(Config::data_dir());
+    if let Ok(mut file) = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path())
+    {
+        let _ = writeln!(file, "{line}");
+    }
+}
+
+/// Every record in the usage log, skipping lines that fail to parse.
+pub fn load_all() -> Vec<UsageRecord> {
+    std::fs::read_to_string(log_path())
+        .map(|content| {
+            content
+                .lines()
+                .filter_map(|l| serde_json::from_str(l).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Rough list prices in USD per million (input, output) tokens.
+pub fn price_per_mtok(model: &str) -> Option<(f64, f64)> {
+    let m = model.to_lowercase();
+    let price = if m.contains("opus") {
+        (15.0, 75.0)
+    } else if m.contains("sonnet") {
+        (3.0, 15.0)
+    } else if m.contains("haiku") {
+        (0.8, 4.0)
+    } else if m.contains("gpt-4o-mini") {
+        (0.15, 0.6)
+    } else if m.contains("gpt-4o") {
+        (2.5, 10.0)
+    } else if m.contains("grok") {
+        (3.0, 15.0)
+    } else {
+        return None;
+    };
+    Some(price)
+}
+
+/// Token totals for one model in one ISO week.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WeeklyUsage {
+    pub responses: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// `None` when the model has no known price
+    pub cost: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ToolUsage {
+    pub calls: u64,
+    pub failures: u64,
+}
+
+/// Aggregated view of all stored usage, shown by `/dashboard`.
+#[derive(Debug, Clone, Default)]
+pub struct Dashboard {
+    /// Keyed by (ISO week like `2026-W42`, model), newest week last
+    pub weekly: BTreeMap<(String, String), WeeklyUsage>,
+    /// Messages sent per local hour of day
+    pub hours: [u64; 24],
+    /// Most used tools first
+    pub tools: Vec<(String, ToolUsage)>,
+    pub sessions: usize,
+    /// Mean time from first to last message of a conversation
+    pub avg_session: Option<Duration>,
+}
+
+impl Dashboard {
+    /// `price` gives USD per million (input, output) tokens for a model.
+    pub fn build(
+        records: &[UsageRecord],
+        conversations: &[Conversation],
+        price: impl Fn(&str) -> Option<(f64, f64)>,
+    ) -> Self {
+        let mut dash = Dashboard::default();
+        let mut tools: BTreeMap<String, ToolUsage> = BTreeMap::new();
+
+        for record in records {
+            match &record.event {
+                UsageEvent::Response { model, input_tokens, output_tokens, .. } => {
+                    let week = record.timestamp.with_timezone(&Local).iso_week();
+                    let key = (format!("{}-W{:02}", week.year(), week.week()), model.clone());
+                    let entry = dash.weekly.entry(key).or_default();
+                    entry.responses += 1;
+                    entry.input_tokens += input_tokens;
+                    entry.output_tokens += output_tokens;
+                    entry.cost = price(model).map(|(inp, out)| {
+                        (entry.input_tokens as f64 * inp + entry.output_tokens as f64 * out) / 1e6
+                    });
+                }
+                UsageEvent::Tool { name, success, .. } => {
+                    let entry = tools.entry(name.clone()).or_default();
+                    entry.calls += 1;
+                    if !success {
+                        entry.failures += 1;
+                    }
+                }
+            }
+        }
+        dash.tools = tools.into_iter().collect();
+        dash.tools.sort_by(|a, b| b.1.calls.cmp(&a.1.calls).then(a.0.cmp(&b.0)));
+
+        let mut total = Duration::ZERO;
+        for conv in conversations {
+            for msg in conv.messages.iter().filter(|m| m.role == "user") {
+                dash.hours[msg.timestamp.with_timezone(&Local).hour() as usize] += 1;
+            }
+            if let (Some(first), Some(last)) = (conv.messages.first(), conv.messages.last()) {
+                dash.sessions += 1;
+                total += (last.timestamp - first.timestamp).to_std().unwrap_or_default();
+            }
+        }
+        if dash.sessions > 0 {
+            dash.avg_session = Some(total / dash.sessions as u32);
+        }
+        dash
+    }
+
+    /// The hour of day with the most messages, if any were sent.
+    pub fn busiest_hour(&self) -> Option<usize> {
+        let (hour, &count) = self.hours.iter().enumerate().max_by_key(|&(h, c)| (c, std::cmp::Reverse(h)))?;
+        (count > 0).then_some(hour)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(model: &str, input: u64, output: u64) -> UsageRecord {
+        UsageRecord {
+            timestamp: Utc::now(),
+            event: UsageEvent::Response {
+                provider: "anthropic".into(),
+                model: model.into(),
+                input_tokens: input,
+                output_tokens: output,
+                latency_ms: None,
+            },
+        }
+    }
+
+    fn tool(name: &str, success: bool) -> UsageRecord {
+        UsageRecord {
+            timestamp: Utc::now(),
+            event: UsageEvent::Tool { name: name.into(), success, duration_ms: 5 },
+        }
+    }
+
+    #[test]
+    fn dashboard_aggregates_tokens_and_tools() {
+        let records = vec![
+            response("claude-sonnet-4-5", 1_000_000, 0),
+            response("claude-sonnet-4-5", 0, 1_000_000),
+            response("mystery-model", 10, 10),
+            tool("read_file", true),
+            tool("execute", false),
+            tool("read_file", true),
+        ];
+        let dash = Dashboard::build(&records, &[], price_per_mtok);
+
+        assert_eq!(dash.weekly.len(), 2);
+        let sonnet = dash.weekly.iter().find(|((_, m), _)| m == "claude-sonnet-4-5").unwrap().1;
+        assert_eq!(sonnet.responses, 2);
+        assert_eq!(sonnet.cost, Some(18.0));
+        let mystery = dash.weekly.iter().find(|((_, m), _)| m == "mystery-model").unwrap().1;
+        assert_eq!(mystery.cost, None);
+
+        assert_eq!(dash.tools[0].0, "read_file");
+        assert_eq!(dash.tools[0].1.calls, 2);
+        assert_eq!(dash.tools[1].1.failures, 1);
+        assert_eq!(dash.busiest_hour(), None);
+    }
+
+    #[test]
+    fn usage_record_round_trips_as_flat_json() {
+        let json = serde_json::to_string(&tool("glob", true)).unwrap();
+        assert!(json.contains(r#""kind":"tool""#));
+        let back: UsageRecord = serde_json::from_str(&json).unwrap();
+        assert!(matches!(back.event, UsageEvent::Tool { ref name, .. } if name == "glob"));
+    }
+}
@@ -0,0 +1,272 @@
+//! The `web_fetch` tool: download a page and reduce its HTML to readable
+//! text for the model.
+
+use std::time::Duration;
+
+use futures::StreamExt;
+
+/// Timeout for the whole download.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Responses are cut off after this many bytes.
+const MAX_DOWNLOAD_BYTES: usize = 2 * 1024 * 1024;
+
+/// Default and upper bound for the text returned to the model.
+pub const DEFAULT_MAX_CHARS: usize = 20_000;
+pub const MAX_CHARS: usize = 100_000;
+
+/// Elements whose contents are never readable text.
+const SKIPPED_ELEMENTS: &[&str] = &["script", "style", "noscript", "template", "svg", "head", "iframe"];
+
+/// Elements that start a new line.
+const BLOCK_ELEMENTS: &[&str] = &[
+    "p", "div", "br", "hr", "section", "article", "header", "footer", "nav", "main", "aside", "ul", "ol",
+    "table", "tr", "pre", "blockquote", "h1", "h2", "h3", "h4", "h5", "h6", "dt", "dd", "figure", "form",
+];
+
+/// Download `url` and return its text: HTML is converted, other text types
+/// pass through, anything else is refused. At most `max_chars` characters
+/// are returned.
+pub async fn fetch(url: &str, max_chars: usize) -> Result<String, String> {
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        return Err(format!("Only http(s) URLs can be fetched, not {url}"));
+    }
+    let client = crate::api::http_client(FETCH_TIMEOUT).map_err(|e| e.to_string())?;
+    let response = client
+        .get(url)
+        .header(reqwest::header::USER_AGENT, concat!("pro-chat/", env!("CARGO_PKG_VERSION")))
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {e}"))?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("{url} returned {status}"));
+    }
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_lowercase();
+    let is_html = content_type.contains("html");
+    let is_text = content_type.is_empty()
+        || content_type.starts_with("text/")
+        || ["json", "xml", "javascript", "yaml", "toml"].iter().any(|t| content_type.contains(t));
+    if !is_html && !is_text {
+        return Err(format!("{url} is {content_type}, not a text page"));
+    }
+
+    let mut body = Vec::new();
+    let mut truncated = false;
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| format!("Download failed: {e}"))?;
+        body.extend_from_slice(&chunk);
+        if body.len() > MAX_DOWNLOAD_BYTES {
+            body.truncate(MAX_DOWNLOAD_BYTES);
+            truncated = true;
+            break;
+        }
+    }
+    let raw = String::from_utf8_lossy(&body);
+    let text = if is_html || raw.trim_start().starts_with('<') && raw.contains("<html") {
+        html_to_text(&raw)
+    } else {
+        raw.into_owned()
+    };
+
+    let total = text.chars().count();
+    let mut out: String = text.chars().take(max_chars).collect();
+    if total > max_chars {
+        out.push_str(&format!("\n... (truncated at {max_chars} of {total} characters)"));
+    } else if truncated {
+        out.push_str(&format!("\n... (page cut off after {} MB)", MAX_DOWNLOAD_BYTES / 1024 / 1024));
+    }
+    Ok(out)
+}
+
+/// Readable text from an HTML document: tags dropped, scripts and styles
+/// skipped, block elements and list items on their own lines, entities
+/// decoded and runs of whitespace collapsed.
+pub fn html_to_text(html: &str) -> String {
+    let mut out = String::new();
+    let mut rest = html;
+    // Closing tag we are skipping ahead to, e.g. `</script`
+    let mut skipping: Option<String> = None;
+    let mut pre = 0usize;
+
+    loop {
+        // Script and style bodies may contain `<`, so jump straight to the end
+        if let Some(end) = skipping.take() {
+            let close = format!("</{end}");
+            rest = match rest.to_ascii_lowercase().find(&close) {
+                Some(at) => rest[at..].find('>').map_or("", |gt| &rest[at + gt + 1..]),
+                None => "",
+            };
+            continue;
+        }
+        let Some(lt) = rest.find('<') else { break };
+        push_text(&mut out, &rest[..lt], pre > 0);
+        rest = &rest[lt..];
+        if rest.starts_with("<!--") {
+            rest = rest.find("-->").map_or("", |end| &rest[end + 3..]);
+            continue;
+        }
+        let Some(gt) = rest.find('>') else { rest = ""; break };
+        let tag = &rest[1..gt];
+        rest = &rest[gt + 1..];
+
+        let closing = tag.starts_with('/');
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if !closing && !tag.ends_with('/') && SKIPPED_ELEMENTS.contains(&name.as_str()) {
+            skipping = Some(name);
+            continue;
+        }
+        match name.as_str() {
+            "pre" if closing => pre = pre.saturating_sub(1),
+            "pre" => pre += 1,
+            _ => {}
+        }
+        if name == "li" && !closing {
+            new_line(&mut out);
+            out.push_str("- ");
+        } else if BLOCK_ELEMENTS.contains(&name.as_str()) || name == "li" {
+            new_line(&mut out);
+            if !closing && matches!(name.as_str(), "h1" | "h2" | "h3" | "p" | "ul" | "ol" | "table" | "pre") {
+                out.push('\n');
+            }
+        } else if matches!(name.as_str(), "td" | "th") && closing {
+            out.push_str(" | ");
+        }
+    }
+    push_text(&mut out, rest, false);
+
+    // Collapse the blank lines left behind by nested blocks
+    let mut text = String::new();
+    let mut blank = 0;
+    for line in out.lines().map(str::trim_end) {
+        if line.trim().is_empty() {
+            blank += 1;
+            continue;
+        }
+        if !text.is_empty() {
+            text.push_str(if blank > 0 { "\n\n" } else { "\n" });
+        }
+        text.push_str(line);
+        blank = 0;
+    }
+    text
+}
+
+fn new_line(out: &mut String) {
+    let trimmed = out.trim_end_matches(' ').len();
+    out.truncate(trimmed);
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+/// Append a text node, collapsing whitespace outside `<pre>`.
+fn push_text(out: &mut String, text: &str, preformatted: bool) {
+    let text = decode_entities(text);
+    if preformatted {
+        out.push_str(&text);
+        return;
+    }
+    for (i, word) in text.split_whitespace().enumerate() {
+        let starts_with_space = i > 0 || text.starts_with(char::is_whitespace);
+        if starts_with_space && !out.is_empty() && !out.ends_with([' ', '\n']) {
+            out.push(' ');
+        }
+        out.push_str(word);
+    }
+    if text.ends_with(char::is_whitespace) && !out.is_empty() && !out.ends_with([' ', '\n']) {
+        out.push(' ');
+    }
+}
+
+/// Decode named entities common in documentation pages and all numeric
+/// ones; anything else is left as written.
+fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest[1..].find(';').filter(|&end| end <= 10).and_then(|end| {
+            let entity = &rest[1..end + 1];
+            let c = match entity {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                "nbsp" => ' ',
+                "mdash" => '—',
+                "ndash" => '–',
+                "hellip" => '…',
+                "copy" => '©',
+                _ => {
+                    let code = match entity.strip_prefix('#') {
+                        Some(hex) if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16).ok(),
+                        Some(dec) => dec.parse().ok(),
+                        None => None,
+                    };
+                    code.and_then(char::from_u32)?
+                }
+            };
+            Some((c, end + 2))
+        });
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_html_to_text() {
+        let html = r#"<!DOCTYPE html><html><head><title>Docs</title><style>p { color: red }</style></head>
+            <body><nav><a href="/">Home</a></nav>
+            <h1>Install</h1><p>Run   the <code>installer</code>,
+            then restart.</p><!-- hidden -->
+            <ul><li>Fast &amp; small</li><li>Caf&eacute; &#8212; &#x2713;</li></ul>
+            <script>alert("x < y")</script>
+            <pre>fn main() {
+    println!("hi");
+}</pre><table><tr><th>Key</th><th>Value</th></tr></table></body></html>"#;
+        assert_eq!(
+            html_to_text(html),
+            "Home\n\nInstall\n\nRun the installer, then restart.\n\n- Fast & small\n- Caf&eacute; — ✓\n\n\
+             fn main() {\n    println!(\"hi\");\n}\n\nKey | Value |"
+        );
+    }
+
+    #[test]
+    fn test_decode_entities() {
+        assert_eq!(decode_entities("a &lt;b&gt; &quot;c&quot; &#39;d&#39;"), "a <b> \"c\" 'd'");
+        assert_eq!(decode_entities("AT&T & more;"), "AT&T & more;");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_rejects_other_schemes() {
+        assert!(fetch("file:///etc/passwd", 100).await.unwrap_err().contains("Only http(s)"));
+    }
+}